@@ -0,0 +1,61 @@
+//! Batched keccak256 throughput, scalar vs the detected SIMD implementation, at the message sizes
+//! the state trie hashes: 32-byte keys, leaf nodes and full 16-child branch nodes.
+//!
+//!     cargo bench --bench keccak
+
+use flux_engine::keccak::{keccak256_batch_with, Implementation};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const MESSAGES: usize = 100_000;
+const ROUNDS: usize = 5;
+
+fn main() {
+    let detected = Implementation::detect();
+    println!("[FLUX] keccak256 batches of {} messages, best of {} rounds", MESSAGES, ROUNDS);
+    println!("[FLUX] detected implementation: {}", detected);
+
+    for (label, len) in [("key", 32), ("leaf node", 110), ("branch node", 532)] {
+        let inputs: Vec<Vec<u8>> = (0..MESSAGES)
+            .map(|i| {
+                let mut message = vec![0xab; len];
+                message[..8].copy_from_slice(&(i as u64).to_le_bytes());
+                message
+            })
+            .collect();
+
+        let scalar = best_of(Implementation::Scalar, &inputs);
+        print!("  {:<12} {:>4} bytes: scalar {:>8.1} ns/hash", label, len, per_hash(scalar));
+        if detected != Implementation::Scalar {
+            assert_eq!(
+                keccak256_batch_with(Implementation::Scalar, &inputs),
+                keccak256_batch_with(detected, &inputs),
+                "{} disagrees with scalar",
+                detected
+            );
+            let simd = best_of(detected, &inputs);
+            print!(
+                ", {} {:>8.1} ns/hash ({:.2}x)",
+                detected,
+                per_hash(simd),
+                scalar.as_secs_f64() / simd.as_secs_f64()
+            );
+        }
+        println!();
+    }
+}
+
+fn best_of(implementation: Implementation, inputs: &[Vec<u8>]) -> Duration {
+    (0..ROUNDS)
+        .map(|_| {
+            let started = Instant::now();
+            black_box(keccak256_batch_with(implementation, black_box(inputs)));
+            started.elapsed()
+        })
+        .min()
+        .expect("at least one round")
+}
+
+fn per_hash(elapsed: Duration) -> f64 {
+    elapsed.as_nanos() as f64 / MESSAGES as f64
+}
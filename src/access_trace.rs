@@ -0,0 +1,317 @@
+/*
+ * FLUX ENGINE - ACCESS TRACES
+ * `--record-trace FILE` writes what every committed transaction read and wrote, the accounts it
+ * declared and the gas it used, one JSON line per transaction. `flux replay-trace` feeds those
+ * sets back through a strategy's scheduler with no EVM behind it: each "execution" just reads
+ * its recorded locations from the scheduler's view and writes its recorded locations back, so
+ * scheduler experiments run on a real block's conflict structure in a fraction of the time.
+ *
+ * Sets are recorded per slot and coarsened on replay to the configured `--conflicts`. The
+ * executions take no time, so what replay measures is each strategy's scheduling decisions and
+ * overhead (executions, aborts, queueing), not the speedup full execution would see.
+ *
+ * With `--zstd-level` each block's lines are written as a zstd frame of their own, so a trace cut
+ * short is still a valid stream of whole blocks. Reading takes either kind.
+ */
+
+use crate::locking;
+use crate::mvcc::{ConflictGranularity, Location};
+use crate::scheduler::{self, Strategy};
+use crate::zstd;
+use revm::db::DatabaseRef;
+use revm::primitives::{
+    Account, AccountInfo, Address, Bytecode, Bytes, Eval, ExecutionResult, Output, ResultAndState, State,
+    StorageSlot, B256, U256,
+};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Stands in for the coinbase the schedulers leave out. Recorded sets already leave the real one
+/// out, so it only has to be an address no transaction touches.
+const REPLAY_COINBASE: Address = Address::repeat_byte(0xc0);
+
+#[derive(Debug, Clone, Default)]
+pub struct TracedTx {
+    pub id: usize,
+    pub gas: u64,
+    /// Sender, target and access list accounts: what the pessimistic strategy locks.
+    pub declared: Vec<Address>,
+    pub reads: Vec<Location>,
+    pub writes: Vec<Location>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TracedBlock {
+    pub number: u64,
+    pub txs: Vec<TracedTx>,
+}
+
+// --- RECORDING ---
+
+pub struct TraceWriter {
+    out: BufWriter<File>,
+    compression: Option<u32>,
+}
+
+impl TraceWriter {
+    pub fn create(path: &Path, compression: Option<u32>) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Self {
+            out: BufWriter::new(file),
+            compression,
+        })
+    }
+
+    pub fn write_block(&mut self, block: &TracedBlock) -> Result<(), String> {
+        let locations = |locations: &[Location]| locations.iter().map(location_to_string).collect::<Vec<_>>();
+        let mut lines = Vec::new();
+        for tx in &block.txs {
+            let line = json!({
+                "block": block.number,
+                "tx": tx.id,
+                "gas": tx.gas,
+                "declared": tx.declared.iter().map(Address::to_string).collect::<Vec<_>>(),
+                "reads": locations(&tx.reads),
+                "writes": locations(&tx.writes),
+            });
+            writeln!(lines, "{}", line).map_err(|e| format!("access trace: {}", e))?;
+        }
+        if let Some(level) = self.compression {
+            lines = zstd::compress(&lines, level);
+        }
+        self.out.write_all(&lines).map_err(|e| format!("access trace: {}", e))?;
+        // Flushed per block, so a run cut short still leaves every finished block replayable.
+        self.out.flush().map_err(|e| format!("access trace: {}", e))
+    }
+}
+
+/// `0x<address>` for an account, `0x<address>:0x<slot>` for a storage slot.
+fn location_to_string(location: &Location) -> String {
+    match location {
+        Location::Account(address) => address.to_string(),
+        Location::Storage(address, slot) => format!("{}:{:#x}", address, slot),
+    }
+}
+
+fn parse_location(raw: &str) -> Result<Location, String> {
+    let bad = || format!("{:?} is not an account or slot location", raw);
+    match raw.split_once(':') {
+        Some((address, slot)) => Ok(Location::Storage(
+            address.parse().map_err(|_| bad())?,
+            slot.parse().map_err(|_| bad())?,
+        )),
+        None => Ok(Location::Account(raw.parse().map_err(|_| bad())?)),
+    }
+}
+
+// --- LOADING ---
+
+/// Reads a trace back into blocks, in the order they were recorded.
+pub fn read_trace(path: &Path) -> Result<Vec<TracedBlock>, String> {
+    let file = zstd::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut blocks: Vec<TracedBlock> = Vec::new();
+    for (i, line) in file.lines().enumerate() {
+        let at = |e: String| format!("{}:{}: {}", path.display(), i + 1, e);
+        let line = line.map_err(|e| at(e.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value: Value = serde_json::from_str(&line).map_err(|e| at(e.to_string()))?;
+        let number = value["block"].as_u64().ok_or_else(|| at("block: missing".into()))?;
+        let tx = parse_tx(&value).map_err(at)?;
+        match blocks.last_mut() {
+            Some(block) if block.number == number => block.txs.push(tx),
+            _ => blocks.push(TracedBlock { number, txs: vec![tx] }),
+        }
+    }
+    if blocks.is_empty() {
+        return Err(format!("{}: no transactions", path.display()));
+    }
+    Ok(blocks)
+}
+
+fn parse_tx(value: &Value) -> Result<TracedTx, String> {
+    let list = |name: &str| -> Result<Vec<&str>, String> {
+        value[name]
+            .as_array()
+            .ok_or_else(|| format!("{}: missing", name))?
+            .iter()
+            .map(|item| item.as_str().ok_or_else(|| format!("{}: expected strings", name)))
+            .collect()
+    };
+    let locations = |name: &str| -> Result<Vec<Location>, String> {
+        list(name)?.into_iter().map(parse_location).collect()
+    };
+    Ok(TracedTx {
+        id: value["tx"].as_u64().ok_or("tx: missing")? as usize,
+        gas: value["gas"].as_u64().ok_or("gas: missing")?,
+        declared: list("declared")?
+            .into_iter()
+            .map(|raw| raw.parse().map_err(|_| format!("declared: {:?} is not an address", raw)))
+            .collect::<Result<_, _>>()?,
+        reads: locations("reads")?,
+        writes: locations("writes")?,
+    })
+}
+
+// --- REPLAY ---
+
+/// The state under every replayed block: empty, since only which version a read sees matters.
+struct NoState;
+
+impl DatabaseRef for NoState {
+    type Error = String;
+
+    fn basic(&self, _address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        Ok(None)
+    }
+
+    fn code_by_hash(&self, _code_hash: B256) -> Result<Bytecode, Self::Error> {
+        Ok(Bytecode::default())
+    }
+
+    fn storage(&self, _address: Address, _index: U256) -> Result<U256, Self::Error> {
+        Ok(U256::ZERO)
+    }
+
+    fn block_hash(&self, _number: U256) -> Result<B256, Self::Error> {
+        Ok(B256::ZERO)
+    }
+}
+
+/// "Executes" a traced transaction against `view`: reads its read set, then returns a state
+/// holding its write set, shaped like revm's so the schedulers derive the same sets from it.
+fn run_traced<DB: DatabaseRef<Error = String>>(tx: &TracedTx, view: &DB) -> Result<ResultAndState, String> {
+    let mut state = State::default();
+    for location in &tx.reads {
+        match location {
+            Location::Account(address) => {
+                let info = view.basic(*address)?.unwrap_or_default();
+                state.entry(*address).or_insert_with(|| Account::from(info));
+            }
+            Location::Storage(address, slot) => {
+                let value = view.storage(*address, *slot)?;
+                let account = state.entry(*address).or_insert_with(|| Account::from(AccountInfo::default()));
+                account.storage.entry(*slot).or_insert(StorageSlot::new(value));
+            }
+        }
+    }
+    for location in &tx.writes {
+        let account = state.entry(location.address()).or_insert_with(|| Account::from(AccountInfo::default()));
+        account.mark_touch();
+        if let Location::Storage(_, slot) = location {
+            let slot = account.storage.entry(*slot).or_insert(StorageSlot::new(U256::ZERO));
+            // Any change will do: validation compares versions, not values.
+            slot.present_value = slot.previous_or_original_value.wrapping_add(U256::from(1));
+        }
+    }
+    let result = ExecutionResult::Success {
+        reason: Eval::Stop,
+        gas_used: tx.gas,
+        gas_refunded: 0,
+        logs: vec![],
+        output: Output::Call(Bytes::new()),
+    };
+    Ok(ResultAndState { result, state })
+}
+
+/// Replays `block` under `strategy` on the current rayon pool. Optimistic replay models spread
+/// dispatch with unbounded depth and no retry rounds: every tx speculates against the pre-block
+/// state and re-executes once if it read anything an earlier tx wrote.
+pub fn replay_block(block: &TracedBlock, strategy: Strategy, granularity: ConflictGranularity) -> ReplayStats {
+    let coarsen = |locations: &[Location]| -> Vec<Location> {
+        let mut seen = HashSet::new();
+        locations
+            .iter()
+            .map(|location| granularity.coarsen(*location))
+            .filter(|location| seen.insert(*location))
+            .collect()
+    };
+    let txs: Vec<TracedTx> = block
+        .txs
+        .iter()
+        .map(|tx| TracedTx {
+            reads: coarsen(&tx.reads),
+            writes: coarsen(&tx.writes),
+            ..tx.clone()
+        })
+        .collect();
+
+    let started = Instant::now();
+    let (executions, reexecuted) = match strategy {
+        Strategy::BlockStm => {
+            let (_, stats) = scheduler::run(&NoState, REPLAY_COINBASE, txs.len(), |i, view| run_traced(&txs[i], view));
+            (stats.executions, stats.reexecuted)
+        }
+        Strategy::Pessimistic => {
+            let locks = txs.iter().map(|tx| tx.declared.iter().copied().collect()).collect();
+            let (_, stats) = locking::run(&NoState, REPLAY_COINBASE, locks, |i, view| run_traced(&txs[i], view));
+            (txs.len() as u64 + stats.unlocked, stats.unlocked)
+        }
+        Strategy::Optimistic => {
+            let mut written = HashSet::new();
+            let mut conflicted = 0;
+            for tx in &txs {
+                conflicted += tx.reads.iter().any(|read| written.contains(read)) as u64;
+                written.extend(tx.writes.iter().copied());
+            }
+            (txs.len() as u64 + conflicted, conflicted)
+        }
+        Strategy::Serial => (txs.len() as u64, 0),
+    };
+    ReplayStats {
+        blocks: 1,
+        transactions: txs.len() as u64,
+        executions,
+        reexecuted,
+        gas: txs.iter().map(|tx| tx.gas).sum(),
+        wall: started.elapsed(),
+    }
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReplayStats {
+    pub blocks: u64,
+    pub transactions: u64,
+    pub executions: u64,
+    /// Transactions that ran more than once.
+    pub reexecuted: u64,
+    /// Recorded gas of the replayed transactions.
+    pub gas: u64,
+    pub wall: Duration,
+}
+
+impl std::ops::AddAssign for ReplayStats {
+    fn add_assign(&mut self, other: Self) {
+        self.blocks += other.blocks;
+        self.transactions += other.transactions;
+        self.executions += other.executions;
+        self.reexecuted += other.reexecuted;
+        self.gas += other.gas;
+        self.wall += other.wall;
+    }
+}
+
+impl fmt::Display for ReplayStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Trace Replay:       {} txs in {} blocks, {} executions ({:.2} per tx), {} re-executed ({:.2}%), \
+             scheduled in {:?}",
+            self.transactions,
+            self.blocks,
+            self.executions,
+            self.executions as f64 / self.transactions.max(1) as f64,
+            self.reexecuted,
+            self.reexecuted as f64 / self.transactions.max(1) as f64 * 100.0,
+            self.wall
+        )
+    }
+}
@@ -0,0 +1,112 @@
+/*
+ * FLUX ENGINE - BYTECODE ANALYSIS CACHE
+ * revm builds a contract's jumpdest bitmap every time it is handed raw code. This keeps one
+ * analysed copy per code hash, shared by every executor thread, and feeds it to revm ready-made.
+ */
+
+use dashmap::DashMap;
+use revm::interpreter::analysis::to_analysed;
+use revm::primitives::{Account, AccountInfo, Address, Bytecode, BytecodeState, HashMap, B256, KECCAK_EMPTY, U256};
+use revm::{Database, DatabaseCommit};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Unbounded: entries are keyed by code hash, so it never holds more than the deployed code set.
+#[derive(Debug, Default)]
+pub struct AnalysisCache {
+    by_hash: DashMap<B256, Bytecode>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl AnalysisCache {
+    /// `code` with its jump table, analysing it at most once per hash across all threads.
+    pub fn analysed(&self, code_hash: B256, code: Bytecode) -> Bytecode {
+        if code_hash == KECCAK_EMPTY || matches!(code.state, BytecodeState::Analysed { .. }) {
+            return code;
+        }
+        if let Some(cached) = self.by_hash.get(&code_hash) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return cached.clone();
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let analysed = to_analysed(code);
+        self.by_hash.insert(code_hash, analysed.clone());
+        analysed
+    }
+
+    pub fn stats(&self) -> AnalysisStats {
+        AnalysisStats {
+            cached: self.by_hash.len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Routes every code load of `db` through the shared cache. Everything else passes straight through.
+pub struct WithAnalysisCache<'a, DB> {
+    db: &'a mut DB,
+    cache: &'a AnalysisCache,
+}
+
+impl<'a, DB> WithAnalysisCache<'a, DB> {
+    pub fn new(db: &'a mut DB, cache: &'a AnalysisCache) -> Self {
+        Self { db, cache }
+    }
+}
+
+impl<DB: Database> Database for WithAnalysisCache<'_, DB> {
+    type Error = DB::Error;
+
+    fn basic(&mut self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        Ok(self.db.basic(address)?.map(|mut info| {
+            if let Some(code) = info.code.take() {
+                info.code = Some(self.cache.analysed(info.code_hash, code));
+            }
+            info
+        }))
+    }
+
+    fn code_by_hash(&mut self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        let code = self.db.code_by_hash(code_hash)?;
+        Ok(self.cache.analysed(code_hash, code))
+    }
+
+    fn storage(&mut self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        self.db.storage(address, index)
+    }
+
+    fn block_hash(&mut self, number: U256) -> Result<B256, Self::Error> {
+        self.db.block_hash(number)
+    }
+}
+
+impl<DB: DatabaseCommit> DatabaseCommit for WithAnalysisCache<'_, DB> {
+    fn commit(&mut self, changes: HashMap<Address, Account>) {
+        self.db.commit(changes)
+    }
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone, Default)]
+pub struct AnalysisStats {
+    pub cached: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl fmt::Display for AnalysisStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lookups = (self.hits + self.misses).max(1);
+        writeln!(
+            f,
+            "Code Analysis:      {} contracts cached, {} hits / {} misses ({:.1}% hit rate)",
+            self.cached,
+            self.hits,
+            self.misses,
+            self.hits as f64 / lookups as f64 * 100.0
+        )
+    }
+}
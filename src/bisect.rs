@@ -0,0 +1,164 @@
+/*
+ * FLUX ENGINE - BISECT
+ * What every committed transaction of a block left behind, so two runs of the block from the same
+ * pre-state can be compared transaction by transaction (`flux bisect`). From the same pre-state,
+ * the state after each transaction matches exactly as long as each one wrote the same values, so
+ * the first transaction whose writes differ is where the runs part ways.
+ */
+
+use crate::mvcc::Location;
+use revm::primitives::{Address, State, B256, U256};
+use std::fmt;
+
+/// What a committed tx left at one location.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Written {
+    /// Balance, nonce and code hash; `None` once the account self-destructed.
+    Account(Option<(U256, u64, B256)>),
+    Slot(U256),
+}
+
+impl fmt::Display for Written {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Written::Account(Some((balance, nonce, code_hash))) => {
+                write!(f, "balance {}, nonce {}, code {}", balance, nonce, code_hash)
+            }
+            Written::Account(None) => write!(f, "self-destructed"),
+            Written::Slot(value) => write!(f, "{:#x}", value),
+        }
+    }
+}
+
+/// The values one committed tx wrote, ordered by address with each account before its slots.
+#[derive(Debug, Clone)]
+pub struct TxWrites {
+    pub id: usize,
+    pub writes: Vec<(Location, Written)>,
+}
+
+impl TxWrites {
+    /// Reads them off the state a tx executed to, as the applier commits it (coinbase rebased).
+    /// Only touched accounts are committed, so only their writes count.
+    pub fn from_state(id: usize, state: &State) -> Self {
+        let mut writes = Vec::new();
+        for (address, account) in state.iter().filter(|(_, account)| account.is_touched()) {
+            if account.is_selfdestructed() {
+                writes.push((Location::Account(*address), Written::Account(None)));
+                continue;
+            }
+            let info = &account.info;
+            writes.push((
+                Location::Account(*address),
+                Written::Account(Some((info.balance, info.nonce, info.code_hash))),
+            ));
+            for (slot, value) in account.storage.iter().filter(|(_, value)| value.is_changed()) {
+                writes.push((Location::Storage(*address, *slot), Written::Slot(value.present_value)));
+            }
+        }
+        writes.sort_unstable_by_key(|(location, _)| order(location));
+        Self { id, writes }
+    }
+}
+
+/// Where two runs of a block first disagree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    /// The runs committed different txs at this position, or only one of them committed one.
+    Tx {
+        index: usize,
+        ids: (Option<usize>, Option<usize>),
+    },
+    /// Both committed tx `id` here, but left `location` with different values (`None` where a
+    /// run did not write it).
+    Write {
+        index: usize,
+        id: usize,
+        location: Location,
+        values: (Option<Written>, Option<Written>),
+    },
+}
+
+impl Divergence {
+    /// Describes it with the two runs called `names`.
+    pub fn describe(&self, names: [&str; 2]) -> String {
+        let id = |id: &Option<usize>| id.map_or("none".to_string(), |id| format!("id {}", id));
+        let value = |value: &Option<Written>| value.as_ref().map_or("not written".to_string(), Written::to_string);
+        match self {
+            Divergence::Tx { index, ids } => format!(
+                "tx #{} first diverges: {} committed in the {} run, {} in the {} run",
+                index,
+                id(&ids.0),
+                names[0],
+                id(&ids.1),
+                names[1]
+            ),
+            Divergence::Write { index, id, location, values } => format!(
+                "tx #{} (id {}) first diverges, at {}: {} in the {} run, {} in the {} run",
+                index,
+                id,
+                describe_location(location),
+                value(&values.0),
+                names[0],
+                value(&values.1),
+                names[1]
+            ),
+        }
+    }
+}
+
+/// The first committed tx, in block order, at which `a` and `b` differ, and the first location
+/// it wrote differently.
+pub fn first_divergence(a: &[TxWrites], b: &[TxWrites]) -> Option<Divergence> {
+    for index in 0..a.len().max(b.len()) {
+        let (one, two) = match (a.get(index), b.get(index)) {
+            (Some(one), Some(two)) if one.id == two.id => (one, two),
+            (one, two) => {
+                return Some(Divergence::Tx {
+                    index,
+                    ids: (one.map(|tx| tx.id), two.map(|tx| tx.id)),
+                })
+            }
+        };
+        // Both are sorted the same way, so walk them side by side.
+        let (mut i, mut j) = (0, 0);
+        while i < one.writes.len() || j < two.writes.len() {
+            let (x, y) = (one.writes.get(i), two.writes.get(j));
+            let (location, values) = match (x, y) {
+                (Some((l, v)), Some((m, w))) if l == m => {
+                    (i, j) = (i + 1, j + 1);
+                    if v == w {
+                        continue;
+                    }
+                    (*l, (Some(v.clone()), Some(w.clone())))
+                }
+                (Some((l, v)), Some((m, _))) if order(l) < order(m) => (*l, (Some(v.clone()), None)),
+                (Some((l, v)), None) => (*l, (Some(v.clone()), None)),
+                (_, Some((m, w))) => (*m, (None, Some(w.clone()))),
+                (None, None) => unreachable!("the loop stops once both are walked"),
+            };
+            return Some(Divergence::Write {
+                index,
+                id: one.id,
+                location,
+                values,
+            });
+        }
+    }
+    None
+}
+
+/// By address, an account before its slots.
+fn order(location: &Location) -> (Address, Option<U256>) {
+    match location {
+        Location::Account(address) => (*address, None),
+        Location::Storage(address, slot) => (*address, Some(*slot)),
+    }
+}
+
+fn describe_location(location: &Location) -> String {
+    match location {
+        Location::Account(address) => format!("account {}", address),
+        Location::Storage(address, slot) => format!("slot {}:{:#x}", address, slot),
+    }
+}
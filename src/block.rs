@@ -0,0 +1,102 @@
+/*
+ * FLUX ENGINE - BLOCKS
+ * Header fields the executor needs, plus the EIP-1559 fee-market arithmetic.
+ */
+
+use revm::primitives::{calc_blob_gasprice, calc_excess_blob_gas, Address, Env, B256, U256};
+
+/// EIP-1559: the base fee moves by at most 1/8 per block.
+const BASE_FEE_MAX_CHANGE_DENOMINATOR: u64 = 8;
+/// EIP-1559: blocks target half of their gas limit.
+const ELASTICITY_MULTIPLIER: u64 = 2;
+
+/// EIP-4895 beacon-chain withdrawal, credited after the block's transactions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Withdrawal {
+    pub index: u64,
+    pub validator_index: u64,
+    pub address: Address,
+    /// In gwei, as on the consensus layer.
+    pub amount: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub number: u64,
+    pub timestamp: u64,
+    pub coinbase: Address,
+    pub gas_limit: u64,
+    /// EIP-1559 base fee in wei per gas. Zero before London.
+    pub base_fee_per_gas: u64,
+    /// EIP-4844 excess blob gas carried over from the parent; sets this block's blob gas price.
+    pub excess_blob_gas: u64,
+    /// EIP-4788 root the block prologue stores in the beacon roots contract (Cancun+).
+    pub parent_beacon_block_root: Option<B256>,
+    /// Strictly part of the body, but besides the transactions it is all the engine needs from it.
+    pub withdrawals: Vec<Withdrawal>,
+    /// Canonical results when replaying a known block; `None` for synthetic blocks.
+    pub gas_used: Option<u64>,
+    pub receipts_root: Option<B256>,
+    pub state_root: Option<B256>,
+}
+
+impl Default for BlockHeader {
+    fn default() -> Self {
+        Self {
+            number: 0,
+            timestamp: 0,
+            coinbase: Address::ZERO,
+            gas_limit: 30_000_000,
+            base_fee_per_gas: 0,
+            excess_blob_gas: 0,
+            parent_beacon_block_root: None,
+            withdrawals: vec![],
+            gas_used: None,
+            receipts_root: None,
+            state_root: None,
+        }
+    }
+}
+
+impl BlockHeader {
+    /// Copies the block-level context into a revm environment.
+    pub fn apply(&self, env: &mut Env) {
+        env.block.number = U256::from(self.number);
+        env.block.timestamp = U256::from(self.timestamp);
+        env.block.coinbase = self.coinbase;
+        env.block.gas_limit = U256::from(self.gas_limit);
+        env.block.basefee = U256::from(self.base_fee_per_gas);
+        env.block.set_blob_excess_gas_and_price(self.excess_blob_gas);
+    }
+
+    /// Wei per unit of blob gas in this block (EIP-4844 fake exponential of the excess).
+    pub fn blob_gas_price(&self) -> u128 {
+        calc_blob_gasprice(self.excess_blob_gas)
+    }
+
+    /// Excess blob gas of the child block given how much blob gas this block used (EIP-4844).
+    pub fn next_excess_blob_gas(&self, blob_gas_used: u64) -> u64 {
+        calc_excess_blob_gas(self.excess_blob_gas, blob_gas_used)
+    }
+
+    /// Base fee of the child block given how much gas this block used (EIP-1559).
+    /// The benchmark blocks can exceed `gas_limit`; they count as full, so the step stays <= 1/8.
+    pub fn next_base_fee(&self, gas_used: u64) -> u64 {
+        let gas_used = gas_used.min(self.gas_limit);
+        let target = self.gas_limit / ELASTICITY_MULTIPLIER;
+        let base_fee = self.base_fee_per_gas as u128;
+        // A zero base fee means no fee market (pre-London); it stays zero.
+        if target == 0 || base_fee == 0 || gas_used == target {
+            return self.base_fee_per_gas;
+        }
+
+        let target = target as u128;
+        if gas_used as u128 > target {
+            let delta = (gas_used as u128 - target) * base_fee / target / BASE_FEE_MAX_CHANGE_DENOMINATOR as u128;
+            (base_fee + delta.max(1)) as u64
+        } else {
+            let delta = (target - gas_used as u128) * base_fee / target / BASE_FEE_MAX_CHANGE_DENOMINATOR as u128;
+            base_fee.saturating_sub(delta) as u64
+        }
+    }
+}
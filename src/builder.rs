@@ -0,0 +1,161 @@
+/*
+ * FLUX ENGINE - BLOCK BUILDER
+ * `flux build`: the engine as a proposer-side payload builder. Every block's transactions join
+ * a pool; the pool is ordered (by tip unless `--build-order` says otherwise) and handed to
+ * `FluxEngine::build_block`, which executes it against the pending state and fills the block up
+ * to its gas limit. What did not fit stays in the pool, ahead of the next block's arrivals; what
+ * was invalid leaves it.
+ *
+ * `--out FILE` writes each built block's transactions in the order they were included, in the
+ * `--txs-file` JSONL format, so a built chain can be replayed as it was built.
+ */
+
+use crate::{BlockResult, FluxTransaction};
+use revm::primitives::HashSet;
+use serde_json::json;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+pub struct BlockBuilder {
+    /// Left out of earlier blocks for lack of gas, oldest first.
+    pool: Vec<FluxTransaction>,
+    out: Option<BufWriter<File>>,
+    stats: BuildStats,
+}
+
+impl BlockBuilder {
+    pub fn new(out: Option<&Path>) -> Result<Self, String> {
+        let out = out
+            .map(|path| File::create(path).map_err(|e| format!("{}: {}", path.display(), e)))
+            .transpose()?
+            .map(BufWriter::new);
+        Ok(Self {
+            pool: Vec::new(),
+            out,
+            stats: BuildStats::default(),
+        })
+    }
+
+    /// The next block's candidates: what is still pending, then `arrivals`. Ids are renumbered
+    /// in that order, so they stay unique within the block.
+    pub fn candidates(&mut self, arrivals: Vec<FluxTransaction>) -> Vec<FluxTransaction> {
+        let mut txs = std::mem::take(&mut self.pool);
+        txs.extend(arrivals);
+        for (id, tx) in txs.iter_mut().enumerate() {
+            tx.id = id;
+        }
+        txs
+    }
+
+    /// Takes the outcome of building block `number` from `candidates`: the excluded txs go back
+    /// to the pool and the included ones, in block order, to the output.
+    pub fn settle(
+        &mut self,
+        number: u64,
+        gas_limit: u64,
+        candidates: Vec<FluxTransaction>,
+        result: &BlockResult,
+    ) -> Result<(), String> {
+        let excluded: HashSet<usize> = result.excluded.iter().copied().collect();
+        let mut by_id: Vec<Option<FluxTransaction>> = vec![None; candidates.len()];
+        let offered = candidates.len() as u64;
+        for tx in candidates {
+            if excluded.contains(&tx.id) {
+                self.pool.push(tx);
+            } else {
+                let id = tx.id;
+                by_id[id] = Some(tx);
+            }
+        }
+        self.stats += BuildStats {
+            blocks: 1,
+            offered,
+            included: result.receipts.len() as u64,
+            excluded: excluded.len() as u64,
+            dropped: result.rejected as u64,
+            gas_used: result.gas_used,
+            gas_limit,
+        };
+        println!(
+            "[FLUX] Built block {}: {} of {} txs, {} gas of {} ({:.1}%), {} wei in tips, {} left in the pool",
+            number,
+            result.receipts.len(),
+            offered,
+            result.gas_used,
+            gas_limit,
+            result.gas_used as f64 / gas_limit.max(1) as f64 * 100.0,
+            result.priority_fees,
+            self.pool.len()
+        );
+
+        let Some(out) = &mut self.out else {
+            return Ok(());
+        };
+        for receipt in &result.receipts {
+            let tx = by_id[receipt.tx_id].as_ref().expect("every receipt is for a candidate");
+            let line = json!({
+                "block": number,
+                "id": tx.id,
+                "sender": tx.caller.to_string(),
+                "to": if tx.create { String::new() } else { tx.to.to_string() },
+                "input": format!("0x{}", hex::encode(&tx.data)),
+                "gas": tx.gas_limit,
+                "value": tx.value.to_string(),
+                "nonce": tx.nonce,
+                "gas_price": tx.max_fee_per_gas.to_string(),
+            });
+            writeln!(out, "{}", line).map_err(|e| format!("built blocks: {}", e))?;
+        }
+        out.flush().map_err(|e| format!("built blocks: {}", e))
+    }
+
+    pub fn stats(&self) -> BuildStats {
+        self.stats
+    }
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BuildStats {
+    pub blocks: u64,
+    /// Candidates over all blocks; a tx waiting out several blocks counts in each.
+    pub offered: u64,
+    pub included: u64,
+    /// Left out for lack of gas, back to the pool.
+    pub excluded: u64,
+    /// Invalid, and out of the pool.
+    pub dropped: u64,
+    pub gas_used: u64,
+    pub gas_limit: u64,
+}
+
+impl std::ops::AddAssign for BuildStats {
+    fn add_assign(&mut self, other: Self) {
+        self.blocks += other.blocks;
+        self.offered += other.offered;
+        self.included += other.included;
+        self.excluded += other.excluded;
+        self.dropped += other.dropped;
+        self.gas_used += other.gas_used;
+        self.gas_limit += other.gas_limit;
+    }
+}
+
+impl fmt::Display for BuildStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Block Builder:      {} blocks, {} of {} candidates included, {} deferred for gas, {} dropped as \
+             invalid, {:.1}% of the gas limit filled",
+            self.blocks,
+            self.included,
+            self.offered,
+            self.excluded,
+            self.dropped,
+            self.gas_used as f64 / self.gas_limit.max(1) as f64 * 100.0
+        )
+    }
+}
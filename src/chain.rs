@@ -0,0 +1,192 @@
+/*
+ * FLUX ENGINE - CHAIN CONFIGURATION
+ * Fork activation schedule. Every block is executed under the revm spec its header selects,
+ * so a replay that crosses a fork boundary switches gas rules, opcodes and precompiles with it.
+ *
+ * Presets (`--chain mainnet|sepolia|bsc`) also carry what a generated chain takes from the network
+ * it imitates, the gas limit and block time, and a public endpoint `--from-block` fetches from
+ * when no `--rpc-url` is given. `bsc` is BSC-like rather than BSC: its gas limit, block time and
+ * EVM forks, without the Parlia consensus or system contracts.
+ */
+
+use crate::block::BlockHeader;
+use revm::primitives::{Env, SpecId};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForkCondition {
+    Block(u64),
+    /// Post-merge forks activate by timestamp.
+    Timestamp(u64),
+}
+
+impl ForkCondition {
+    fn active_at(self, header: &BlockHeader) -> bool {
+        match self {
+            ForkCondition::Block(number) => header.number >= number,
+            ForkCondition::Timestamp(timestamp) => header.timestamp >= timestamp,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainSpec {
+    pub name: String,
+    pub chain_id: u64,
+    /// Gas limit of generated blocks.
+    pub gas_limit: u64,
+    /// Seconds between blocks: the timestamp step of generated blocks and the slot `follow`
+    /// measures against.
+    pub block_time: u64,
+    /// Public JSON-RPC endpoint, if the network has one worth defaulting to.
+    pub rpc_url: Option<String>,
+    /// Ascending by spec. The last active entry wins.
+    forks: Vec<(SpecId, ForkCondition)>,
+}
+
+impl ChainSpec {
+    pub fn mainnet() -> Self {
+        use ForkCondition::*;
+        Self {
+            name: "mainnet".to_string(),
+            chain_id: 1,
+            gas_limit: 30_000_000,
+            block_time: 12,
+            rpc_url: Some("https://ethereum-rpc.publicnode.com".to_string()),
+            forks: vec![
+                (SpecId::FRONTIER, Block(0)),
+                (SpecId::HOMESTEAD, Block(1_150_000)),
+                (SpecId::DAO_FORK, Block(1_920_000)),
+                (SpecId::TANGERINE, Block(2_463_000)),
+                (SpecId::SPURIOUS_DRAGON, Block(2_675_000)),
+                (SpecId::BYZANTIUM, Block(4_370_000)),
+                (SpecId::PETERSBURG, Block(7_280_000)),
+                (SpecId::ISTANBUL, Block(9_069_000)),
+                (SpecId::MUIR_GLACIER, Block(9_200_000)),
+                (SpecId::BERLIN, Block(12_244_000)),
+                (SpecId::LONDON, Block(12_965_000)),
+                (SpecId::ARROW_GLACIER, Block(13_773_000)),
+                (SpecId::GRAY_GLACIER, Block(15_050_000)),
+                // Really TTD-triggered; this is the first post-merge block.
+                (SpecId::MERGE, Block(15_537_394)),
+                (SpecId::SHANGHAI, Timestamp(1_681_338_455)),
+                (SpecId::CANCUN, Timestamp(1_710_338_135)),
+            ],
+        }
+    }
+
+    /// Ethereum's long-lived testnet: London from genesis, merged at block 1,450,409.
+    pub fn sepolia() -> Self {
+        use ForkCondition::*;
+        Self {
+            name: "sepolia".to_string(),
+            chain_id: 11_155_111,
+            gas_limit: 30_000_000,
+            block_time: 12,
+            rpc_url: Some("https://ethereum-sepolia-rpc.publicnode.com".to_string()),
+            forks: vec![
+                (SpecId::LONDON, Block(0)),
+                (SpecId::MERGE, Block(1_450_409)),
+                (SpecId::SHANGHAI, Timestamp(1_677_557_088)),
+                (SpecId::CANCUN, Timestamp(1_706_655_072)),
+            ],
+        }
+    }
+
+    /// A BNB Smart Chain-like network: 3-second blocks with a 140M gas limit. Berlin and London
+    /// came together with Hertz; Shanghai with Kepler and Cancun with Haber. There is no merge.
+    pub fn bsc() -> Self {
+        use ForkCondition::*;
+        Self {
+            name: "bsc".to_string(),
+            chain_id: 56,
+            gas_limit: 140_000_000,
+            block_time: 3,
+            rpc_url: Some("https://bsc-dataseed.bnbchain.org".to_string()),
+            forks: vec![
+                (SpecId::MUIR_GLACIER, Block(0)),
+                (SpecId::BERLIN, Block(31_302_048)),
+                (SpecId::LONDON, Block(31_302_048)),
+                (SpecId::SHANGHAI, Timestamp(1_705_996_800)),
+                (SpecId::CANCUN, Timestamp(1_718_863_500)),
+            ],
+        }
+    }
+
+    /// Every block runs under `spec` (synthetic benchmarks, single-fork replays).
+    pub fn fixed(chain_id: u64, spec: SpecId) -> Self {
+        Self {
+            name: format!("{:?}", spec).to_lowercase(),
+            chain_id,
+            gas_limit: 30_000_000,
+            block_time: 12,
+            rpc_url: None,
+            forks: vec![(spec, ForkCondition::Block(0))],
+        }
+    }
+
+    /// The spec `header` executes under.
+    pub fn spec_at(&self, header: &BlockHeader) -> SpecId {
+        self.forks
+            .iter()
+            .rev()
+            .find(|(_, condition)| condition.active_at(header))
+            .map(|(spec, _)| *spec)
+            .unwrap_or(SpecId::FRONTIER)
+    }
+
+    /// Every spec this chain can select, for validating per-spec configuration up front.
+    pub fn specs(&self) -> impl Iterator<Item = SpecId> + '_ {
+        self.forks.iter().map(|(spec, _)| *spec)
+    }
+
+    /// Chain and block context for executing inside `header`'s block; tx fields are left default.
+    pub fn block_env(&self, header: &BlockHeader) -> Env {
+        let mut env = Env::default();
+        env.cfg.chain_id = self.chain_id;
+        env.cfg.spec_id = self.spec_at(header);
+        header.apply(&mut env);
+        env
+    }
+}
+
+impl Default for ChainSpec {
+    /// Single-fork dev chain on the newest spec revm fully supports.
+    fn default() -> Self {
+        let mut chain = Self::fixed(1337, SpecId::CANCUN);
+        chain.name = "dev".to_string();
+        chain
+    }
+}
+
+impl fmt::Display for ChainSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (chain id {})", self.name, self.chain_id)
+    }
+}
+
+impl FromStr for ChainSpec {
+    type Err = String;
+
+    /// A preset (`mainnet`, `sepolia`, `bsc`), `dev`, or a fork name (`london`, `shanghai`, ...)
+    /// to pin every block to it.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "mainnet" => Ok(Self::mainnet()),
+            "sepolia" => Ok(Self::sepolia()),
+            "bsc" => Ok(Self::bsc()),
+            "dev" => Ok(Self::default()),
+            fork => parse_spec(fork).map(|spec| Self::fixed(1, spec)).ok_or_else(|| {
+                format!("unknown chain {:?} (expected mainnet, sepolia, bsc, dev or a fork name)", s)
+            }),
+        }
+    }
+}
+
+/// Inverse of `SpecId`'s `Debug` rendering, case-insensitive.
+pub fn parse_spec(name: &str) -> Option<SpecId> {
+    (0..=u8::MAX)
+        .filter_map(SpecId::try_from_u8)
+        .find(|spec| format!("{:?}", spec).eq_ignore_ascii_case(name))
+}
@@ -0,0 +1,463 @@
+/*
+ * FLUX ENGINE - CHECKPOINTS
+ * Engine state plus pipeline position, written every N blocks so a long replay restarts from the
+ * last checkpoint instead of from scratch. A checkpoint is a directory: `checkpoint.meta` (the
+ * next block and the chain/backend it runs on) and `state.jsonl` (the same dump format
+ * `import-state` reads; zstd-compressed with `--zstd-level`).
+ *
+ * When the last executed block's header carried a state root, the meta records it, and resuming
+ * checks the restored state against it before executing anything.
+ *
+ * A run writes a full checkpoint first, then deltas: `state.delta` holds only the accounts and
+ * slots that changed since the checkpoint named by the meta's `base`, and loading one loads its
+ * base and patches it. After `DELTAS_PER_FULL` deltas the next checkpoint is full again, so
+ * resuming never reads a long chain.
+ *
+ * Delta layout: `FLUXDLT1` | entry count (8, LE), then per account: address (20) | kind (1), and
+ * unless the kind is `DELETED`: balance (32, BE) | nonce (8, BE) | code length (4, LE) and code,
+ * for `WITH_CODE` only | slot count (4, LE) | (slot (32, BE) | value (32, BE)) per changed slot,
+ * zero where the slot was cleared.
+ */
+
+use crate::block::BlockHeader;
+use crate::state::backend::BackendKind;
+use crate::state::snapshot::{self, SnapshotAccount};
+use revm::primitives::{AccountInfo, Address, Bytecode, Bytes, HashMap, B256, U256};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const META_FILE: &str = "checkpoint.meta";
+const STATE_FILE: &str = "state.jsonl";
+const DELTA_FILE: &str = "state.delta";
+const DELTA_MAGIC: &[u8; 8] = b"FLUXDLT1";
+/// Deltas written between two full checkpoints.
+const DELTAS_PER_FULL: usize = 15;
+
+// Delta entry kinds.
+const DELETED: u8 = 0;
+/// Same code as in the base.
+const SAME_CODE: u8 = 1;
+const WITH_CODE: u8 = 2;
+
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    /// Chain name as `--chain` accepts it.
+    pub chain: String,
+    /// Header context of the next block to execute: its number, plus the fee-market values it
+    /// inherits from the last executed block.
+    pub next: BlockHeader,
+    /// The persistent store the state was layered on, if any. Only the in-memory state is in
+    /// `accounts`, so resuming reopens the same store. A flat log keeps absorbing blocks after
+    /// the checkpoint, so with it only the most recent checkpoint is consistent.
+    pub state_backend: BackendKind,
+    pub state_dir: Option<PathBuf>,
+    /// The canonical root of this state, from the header of the last executed block.
+    pub state_root: Option<B256>,
+    pub accounts: Vec<SnapshotAccount>,
+}
+
+impl Checkpoint {
+    /// Writes `block-<n>` under `dir`, where `n` is the last executed block, and returns its path.
+    pub fn write(&self, dir: &Path, compression: Option<u32>) -> Result<PathBuf, String> {
+        let path = self.path(dir);
+        self.write_meta(&path, None).map_err(|e| format!("{}: {}", path.display(), e))?;
+        remove_stale(&path.join(DELTA_FILE))?;
+        snapshot::write_dump(&path.join(STATE_FILE), &self.accounts, compression)?;
+        Ok(path)
+    }
+
+    /// Writes `block-<n>` under `dir` as `delta` on top of the checkpoint `base`, a sibling of it.
+    fn write_delta(&self, dir: &Path, base: &str, delta: &[Delta]) -> Result<PathBuf, String> {
+        let path = self.path(dir);
+        self.write_meta(&path, Some(base)).map_err(|e| format!("{}: {}", path.display(), e))?;
+        remove_stale(&path.join(STATE_FILE))?;
+        let file = path.join(DELTA_FILE);
+        write_delta(&file, delta).map_err(|e| format!("{}: {}", file.display(), e))?;
+        Ok(path)
+    }
+
+    fn path(&self, dir: &Path) -> PathBuf {
+        dir.join(format!("block-{}", self.next.number.saturating_sub(1)))
+    }
+
+    fn write_meta(&self, path: &Path, base: Option<&str>) -> io::Result<()> {
+        fs::create_dir_all(path)?;
+        let mut out = io::BufWriter::new(fs::File::create(path.join(META_FILE))?);
+        writeln!(out, "# flux checkpoint v1")?;
+        writeln!(out, "chain: {}", self.chain)?;
+        if let Some(base) = base {
+            writeln!(out, "base: {}", base)?;
+        }
+        writeln!(out, "next.number: {}", self.next.number)?;
+        writeln!(out, "next.timestamp: {}", self.next.timestamp)?;
+        writeln!(out, "next.coinbase: {}", self.next.coinbase)?;
+        writeln!(out, "next.gas_limit: {}", self.next.gas_limit)?;
+        writeln!(out, "next.base_fee: {}", self.next.base_fee_per_gas)?;
+        writeln!(out, "next.excess_blob_gas: {}", self.next.excess_blob_gas)?;
+        if let Some(root) = &self.state_root {
+            writeln!(out, "state.root: {}", root)?;
+        }
+        if let Some(dir) = &self.state_dir {
+            writeln!(out, "state.backend: {}", self.state_backend)?;
+            writeln!(out, "state.dir: {}", dir.display())?;
+        }
+        out.flush()
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let meta = path.join(META_FILE);
+        let text = fs::read_to_string(&meta).map_err(|e| format!("{}: {}", meta.display(), e))?;
+        let mut checkpoint = Checkpoint {
+            chain: String::new(),
+            next: BlockHeader::default(),
+            state_backend: BackendKind::default(),
+            state_dir: None,
+            state_root: None,
+            accounts: vec![],
+        };
+        let mut base = None;
+
+        for (lineno, line) in text.lines().enumerate() {
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            let bad = |what: &str| format!("{}:{}: invalid {}", meta.display(), lineno + 1, what);
+            let (key, value) = line.split_once(": ").ok_or_else(|| bad("line"))?;
+            let next = &mut checkpoint.next;
+            match key {
+                "chain" => checkpoint.chain = value.to_string(),
+                "base" => base = Some(value.to_string()),
+                "next.number" => next.number = value.parse().map_err(|_| bad(key))?,
+                "next.timestamp" => next.timestamp = value.parse().map_err(|_| bad(key))?,
+                "next.coinbase" => next.coinbase = value.parse().map_err(|_| bad(key))?,
+                "next.gas_limit" => next.gas_limit = value.parse().map_err(|_| bad(key))?,
+                "next.base_fee" => next.base_fee_per_gas = value.parse().map_err(|_| bad(key))?,
+                "next.excess_blob_gas" => next.excess_blob_gas = value.parse().map_err(|_| bad(key))?,
+                "state.backend" => checkpoint.state_backend = value.parse()?,
+                "state.dir" => checkpoint.state_dir = Some(PathBuf::from(value)),
+                "state.root" => checkpoint.state_root = Some(value.parse().map_err(|_| bad(key))?),
+                _ => return Err(bad("key")),
+            }
+        }
+        if checkpoint.chain.is_empty() {
+            return Err(format!("{}: missing chain", meta.display()));
+        }
+
+        checkpoint.accounts = match base {
+            None => snapshot::read_dump(&path.join(STATE_FILE))?.collect::<Result<_, _>>()?,
+            Some(base) => {
+                let mut accounts = by_address(Checkpoint::load(&path.with_file_name(base))?.accounts);
+                read_delta(&path.join(DELTA_FILE), &mut accounts)?;
+                accounts.into_values().collect()
+            }
+        };
+        Ok(checkpoint)
+    }
+}
+
+// --- DELTAS ---
+
+/// Writes a run's checkpoints into one directory: a full one, then `DELTAS_PER_FULL` deltas,
+/// each against the checkpoint before it, then a full one again.
+pub struct CheckpointWriter {
+    dir: PathBuf,
+    compression: Option<u32>,
+    /// Name and state of the last checkpoint written, which the next delta is taken against.
+    last: Option<(String, HashMap<Address, SnapshotAccount>)>,
+    deltas: usize,
+}
+
+impl CheckpointWriter {
+    pub fn new(dir: PathBuf, compression: Option<u32>) -> Self {
+        Self {
+            dir,
+            compression,
+            last: None,
+            deltas: 0,
+        }
+    }
+
+    /// Writes `checkpoint`, as a delta when there is a recent enough base, and returns its path.
+    pub fn write(&mut self, mut checkpoint: Checkpoint) -> Result<PathBuf, String> {
+        let accounts = by_address(std::mem::take(&mut checkpoint.accounts));
+        let path = match &self.last {
+            Some((base, previous)) if self.deltas < DELTAS_PER_FULL => {
+                let path = checkpoint.write_delta(&self.dir, base, &diff(previous, &accounts))?;
+                self.deltas += 1;
+                path
+            }
+            _ => {
+                checkpoint.accounts = accounts.values().cloned().collect();
+                let path = checkpoint.write(&self.dir, self.compression)?;
+                self.deltas = 0;
+                path
+            }
+        };
+        let name = path.file_name().expect("block-<n>").to_string_lossy().into_owned();
+        self.last = Some((name, accounts));
+        Ok(path)
+    }
+}
+
+/// An account's change since the base: `None` once it is gone, otherwise its fields and every
+/// slot whose value changed. The code is left out (`None`) where it is the base's.
+type Delta = (Address, Option<SnapshotAccount>);
+
+fn by_address(accounts: Vec<SnapshotAccount>) -> HashMap<Address, SnapshotAccount> {
+    accounts.into_iter().map(|account| (account.address, account)).collect()
+}
+
+fn diff(previous: &HashMap<Address, SnapshotAccount>, current: &HashMap<Address, SnapshotAccount>) -> Vec<Delta> {
+    let mut delta: Vec<Delta> = previous
+        .keys()
+        .filter(|address| !current.contains_key(*address))
+        .map(|address| (*address, None))
+        .collect();
+    for (address, account) in current {
+        let Some(before) = previous.get(address) else {
+            delta.push((*address, Some(account.clone())));
+            continue;
+        };
+        let mut old: HashMap<U256, U256> = before.storage.iter().copied().collect();
+        let mut storage: Vec<(U256, U256)> = account
+            .storage
+            .iter()
+            .filter(|(slot, value)| old.remove(slot) != Some(*value))
+            .copied()
+            .collect();
+        storage.extend(old.into_keys().map(|slot| (slot, U256::ZERO)));
+        let info = (&account.info.balance, account.info.nonce, &account.info.code_hash);
+        if storage.is_empty() && info == (&before.info.balance, before.info.nonce, &before.info.code_hash) {
+            continue;
+        }
+        let mut info = account.info.clone();
+        info.code = if info.code_hash == before.info.code_hash {
+            None
+        } else {
+            Some(info.code.unwrap_or_default())
+        };
+        delta.push((*address, Some(SnapshotAccount { address: *address, info, storage })));
+    }
+    delta
+}
+
+fn write_delta(path: &Path, delta: &[Delta]) -> io::Result<()> {
+    let mut out = io::BufWriter::new(fs::File::create(path)?);
+    out.write_all(DELTA_MAGIC)?;
+    out.write_all(&(delta.len() as u64).to_le_bytes())?;
+    for (address, account) in delta {
+        out.write_all(address.as_slice())?;
+        let Some(account) = account else {
+            out.write_all(&[DELETED])?;
+            continue;
+        };
+        out.write_all(&[if account.info.code.is_some() { WITH_CODE } else { SAME_CODE }])?;
+        out.write_all(&account.info.balance.to_be_bytes::<32>())?;
+        out.write_all(&account.info.nonce.to_be_bytes())?;
+        if let Some(code) = &account.info.code {
+            let code = code.original_bytes();
+            out.write_all(&(code.len() as u32).to_le_bytes())?;
+            out.write_all(&code)?;
+        }
+        out.write_all(&(account.storage.len() as u32).to_le_bytes())?;
+        for (slot, value) in &account.storage {
+            out.write_all(&slot.to_be_bytes::<32>())?;
+            out.write_all(&value.to_be_bytes::<32>())?;
+        }
+    }
+    out.flush()
+}
+
+/// Patches `accounts`, the base's state, with the delta in `path`.
+fn read_delta(path: &Path, accounts: &mut HashMap<Address, SnapshotAccount>) -> Result<(), String> {
+    let data = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if data.get(..DELTA_MAGIC.len()) != Some(DELTA_MAGIC.as_slice()) {
+        return Err(format!("{}: not a checkpoint delta", path.display()));
+    }
+    let mut rest = &data[DELTA_MAGIC.len()..];
+    let mut take = |len: usize| -> Result<&[u8], String> {
+        if rest.len() < len {
+            return Err(format!("{}: truncated checkpoint delta", path.display()));
+        }
+        let (bytes, tail) = rest.split_at(len);
+        rest = tail;
+        Ok(bytes)
+    };
+
+    let count = u64::from_le_bytes(take(8)?.try_into().expect("8 bytes"));
+    for _ in 0..count {
+        let address = Address::from_slice(take(20)?);
+        let kind = take(1)?[0];
+        if kind == DELETED {
+            accounts.remove(&address);
+            continue;
+        }
+        let balance = U256::from_be_slice(take(32)?);
+        let nonce = u64::from_be_bytes(take(8)?.try_into().expect("8 bytes"));
+        let base = accounts.remove(&address);
+        let mut info = match kind {
+            SAME_CODE => base.as_ref().map_or_else(AccountInfo::default, |account| account.info.clone()),
+            WITH_CODE => match u32::from_le_bytes(take(4)?.try_into().expect("4 bytes")) as usize {
+                0 => AccountInfo::default(),
+                len => {
+                    let code = Bytecode::new_raw(Bytes::copy_from_slice(take(len)?));
+                    AccountInfo::new(U256::ZERO, 0, code.hash_slow(), code)
+                }
+            },
+            other => return Err(format!("{}: unknown delta entry kind {} for {}", path.display(), other, address)),
+        };
+        info.balance = balance;
+        info.nonce = nonce;
+        let mut storage: HashMap<U256, U256> =
+            base.map(|account| account.storage.into_iter().collect()).unwrap_or_default();
+        let slots = u32::from_le_bytes(take(4)?.try_into().expect("4 bytes"));
+        for _ in 0..slots {
+            let (slot, value) = (U256::from_be_slice(take(32)?), U256::from_be_slice(take(32)?));
+            if value == U256::ZERO {
+                storage.remove(&slot);
+            } else {
+                storage.insert(slot, value);
+            }
+        }
+        let storage = storage.into_iter().collect();
+        accounts.insert(address, SnapshotAccount { address, info, storage });
+    }
+    if !rest.is_empty() {
+        return Err(format!("{}: {} trailing bytes after {} entries", path.display(), rest.len(), count));
+    }
+    Ok(())
+}
+
+/// Drops the state file a checkpoint of the other kind left at the same path.
+fn remove_stale(path: &Path) -> Result<(), String> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(format!("{}: {}", path.display(), e)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type State = HashMap<Address, SnapshotAccount>;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("flux-checkpoint-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn checkpoint(block: u64, accounts: &State) -> Checkpoint {
+        Checkpoint {
+            chain: "mainnet".into(),
+            next: BlockHeader {
+                number: block + 1,
+                ..BlockHeader::default()
+            },
+            state_backend: BackendKind::default(),
+            state_dir: None,
+            state_root: None,
+            accounts: accounts.values().cloned().collect(),
+        }
+    }
+
+    fn account(byte: u8, balance: u64, code: &[u8], storage: &[(u64, u64)]) -> SnapshotAccount {
+        let mut info = AccountInfo {
+            balance: U256::from(balance),
+            nonce: 1,
+            ..AccountInfo::default()
+        };
+        if !code.is_empty() {
+            let code = Bytecode::new_raw(Bytes::copy_from_slice(code));
+            info.code_hash = code.hash_slow();
+            info.code = Some(code);
+        }
+        SnapshotAccount {
+            address: Address::repeat_byte(byte),
+            info,
+            storage: storage.iter().map(|&(slot, value)| (U256::from(slot), U256::from(value))).collect(),
+        }
+    }
+
+    /// Accounts in a comparable form: their sorted dump lines.
+    fn normalized(accounts: &[SnapshotAccount]) -> Vec<String> {
+        let mut lines: Vec<String> = accounts.iter().map(snapshot::dump_line).collect();
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn deltas_load_back_to_the_state_they_were_taken_from() {
+        let dir = scratch("deltas");
+        let mut writer = CheckpointWriter::new(dir.clone(), None);
+        let mut state = by_address(vec![
+            account(1, 100, &[], &[]),
+            account(2, 0, &[0x60, 0x00, 0x56], &[(1, 11), (2, 22)]),
+            account(3, 5, &[0x60, 0x01, 0x56], &[(1, 1)]),
+        ]);
+        let first = writer.write(checkpoint(10, &state)).unwrap();
+
+        // Balances, slots written and cleared, a new contract, a deleted one, one re-created
+        // without code.
+        let changes: [&dyn Fn(&mut State); 3] = [
+            &|state| {
+                state.insert(Address::repeat_byte(1), account(1, 90, &[], &[]));
+                state.insert(Address::repeat_byte(2), account(2, 0, &[0x60, 0x00, 0x56], &[(1, 12), (3, 33)]));
+                state.insert(Address::repeat_byte(4), account(4, 1, &[0x60, 0x02, 0x56], &[(7, 7)]));
+            },
+            &|state| {
+                state.remove(&Address::repeat_byte(3));
+                state.insert(Address::repeat_byte(4), account(4, 1, &[], &[]));
+            },
+            &|state| {
+                state.insert(Address::repeat_byte(3), account(3, 9, &[0x60, 0x03, 0x56], &[(1, 2)]));
+            },
+        ];
+        let mut written = vec![(first.clone(), normalized(&checkpoint(10, &state).accounts))];
+        for (i, change) in changes.iter().enumerate() {
+            change(&mut state);
+            let path = writer.write(checkpoint(11 + i as u64, &state)).unwrap();
+            assert!(path.join(DELTA_FILE).exists() && !path.join(STATE_FILE).exists());
+            written.push((path, normalized(&checkpoint(0, &state).accounts)));
+        }
+        assert!(first.join(STATE_FILE).exists() && !first.join(DELTA_FILE).exists());
+
+        for (path, accounts) in &written {
+            let loaded = Checkpoint::load(path).unwrap();
+            assert_eq!(&normalized(&loaded.accounts), accounts, "{}", path.display());
+        }
+        // Only what changed is stored: the contract's code stays in the base.
+        let delta = fs::read(written[1].0.join(DELTA_FILE)).unwrap();
+        assert!(!delta.windows(3).any(|code| code == [0x60, 0x00, 0x56]));
+        assert_eq!(fs::read_to_string(written[3].0.join(META_FILE)).unwrap().lines().nth(2), Some("base: block-12"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_a_full_checkpoint_after_a_run_of_deltas() {
+        let dir = scratch("full");
+        let mut writer = CheckpointWriter::new(dir.clone(), None);
+        let mut state = HashMap::default();
+        let mut paths = Vec::new();
+        for block in 0..2 * (DELTAS_PER_FULL as u64 + 1) {
+            state.insert(Address::repeat_byte(1), account(1, block, &[], &[(block, 1)]));
+            paths.push(writer.write(checkpoint(block, &state)).unwrap());
+        }
+        let full: Vec<bool> = paths.iter().map(|path| path.join(STATE_FILE).exists()).collect();
+        let expected: Vec<bool> = (0..full.len()).map(|i| i % (DELTAS_PER_FULL + 1) == 0).collect();
+        assert_eq!(full, expected);
+
+        let last = Checkpoint::load(paths.last().unwrap()).unwrap();
+        assert_eq!(last.next.number, full.len() as u64);
+        assert_eq!(normalized(&last.accounts), normalized(&checkpoint(0, &state).accounts));
+
+        // A cut-off delta is an error, not a silently older state.
+        let delta = paths[1].join(DELTA_FILE);
+        let bytes = fs::read(&delta).unwrap();
+        fs::write(&delta, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(Checkpoint::load(&paths[1]).unwrap_err().ends_with("truncated checkpoint delta"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
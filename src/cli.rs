@@ -0,0 +1,796 @@
+/*
+ * FLUX ENGINE - COMMAND LINE
+ * Hand-rolled flag parsing; everything maps onto EngineConfig or the run setup in main.
+ */
+
+use flux_engine::filter::TxFilter;
+use flux_engine::forensics::ForensicsConfig;
+use flux_engine::hugepages::HugePageMode;
+use flux_engine::priority::{BuildOrder, DEFAULT_MAX_WAIT};
+use flux_engine::scheduler::Strategy;
+use flux_engine::source::mempool::MempoolConfig;
+use flux_engine::source::rpc::{RpcConfig, DEFAULT_BATCH, DEFAULT_CONNECTIONS, DEFAULT_WINDOW};
+use flux_engine::source::ws::MAX_REORG_DEPTH;
+use flux_engine::source::SourceConfig;
+use flux_engine::state::history::PruneMode;
+use flux_engine::topology::Topology;
+use flux_engine::workload::{Popularity, SyntheticWorkload, TxCount, WorkloadProfile};
+use flux_engine::zstd;
+use flux_engine::EngineConfig;
+use revm::primitives::Address;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const USAGE: &str = "\
+Usage: flux [run] [OPTIONS]
+       flux build [--out <FILE>] [OPTIONS]
+       flux trace --from-capture <FILE>
+       flux replay-trace --from-trace <FILE> [--strategy <NAME>] [--conflicts <LEVEL>]
+       flux follow --ws-url <URL> [--slot-secs <S>] [--blocks <N>] [OPTIONS]
+       flux verify --self [OPTIONS]
+       flux bisect --block <N> [OPTIONS]
+       flux import-state --snapshot <FILE> --state-dir <DIR>
+       flux pack-state --snapshot <FILE> --out <FILE>
+
+Commands:
+  run                      Execute the benchmark workload (default)
+  build                    Build blocks from the workload as a pool: fill each up to its gas limit in --build-order,
+                           carrying what did not fit into the next
+  trace                    Re-run a forensic capture standalone and print its opcode trace
+  follow                   Execute new blocks as a node announces them, reporting latency against the slot time
+  replay-trace             Schedule a recorded access trace's blocks under --strategy, without executing the EVM
+  verify                   With --self: execute the blocks twice, the second time on other executor and commit thread
+                           counts, and check both passes agree on every block's roots and on the final state
+  bisect                   Run up to --block, then execute it both under --strategy and serially from the same state,
+                           comparing the state after every tx to find the first one, and key, the two runs disagree on
+  import-state             Load a `geth dump` JSON-lines snapshot into --state-dir
+  pack-state               Convert a JSON-lines snapshot into a --preload-state file
+
+Options:
+  --chain <CHAIN>          Network preset: mainnet|sepolia|bsc (chain id, fork schedule, gas limit, block time, RPC
+                           endpoint), dev, or a fork name pinning every block to it, e.g. london (default: dev)
+  --commit-threads <N>     Threads used for commit-phase validation (default: 1)
+  --topology <SPEC>        Pin pools to cores: exec=<cores>[,commit=<cores>], e.g. exec=2-27,commit=28-29. One executor
+                           (or commit thread) per core; checked against the cores this process may use
+  --state-shards <N>       Independently locked partitions of global state (default: 64)
+  --watchdog-secs <S>      Abort if no tx progress for S seconds, 0 disables (default: 30)
+  --state-dir <DIR>        Persistent state to execute on top of (default: in memory only)
+  --state-backend <KIND>   Store in --state-dir: flatlog|rocksdb|mmap (default: flatlog; rocksdb needs --features rocksdb)
+  --state-cache <N>        Accounts + slots cached between blocks, least recently read evicted first (default: unbounded)
+  --conflicts <LEVEL>      Conflict detection granularity: account|slot (default: slot)
+  --strategy <NAME>        Execution strategy: optimistic|block-stm|pessimistic|serial (default: optimistic)
+  --dispatch <MODE>        Optimistic executor lanes: spread|grouped[:<hot>[:<max chunk>]]|predicted. grouped joins
+                           consecutive txs sharing an account <hot> txs touch (grouped = grouped:4:32); predicted
+                           links txs whose access lists or learned patterns overlap (default: spread)
+  --speculation-depth <D>  Txs optimistic speculation runs ahead of the applier: unbounded|<N>|adaptive[:<min>:<max>]
+                           (default: unbounded; adaptive = adaptive:32:4096, narrowing as the abort rate climbs)
+  --serial-baseline        Re-run the same blocks under --strategy serial afterwards and report the speedup over it
+  --self                   Check the run against a second pass of itself (verify only)
+  --block <N>              Block to bisect (bisect only)
+  --retry-rounds <N>       Parallel re-runs of conflicted txs per block before falling back to serial (default: 0)
+  --prune <MODE>           State history kept in memory: keep-latest|keep-last:<N>|archive (default: keep-latest;
+                           follow: keep-last:64, so reorgs can be unwound)
+  --huge-pages <MODE>      Back large state allocations with huge pages: off|thp|2m|1g (default: off)
+  --disk-latency <MODEL>   Simulated backend read latency: none|nvme|sata|network|<median_us>[:<sigma>]
+  --prefetch-state         Read each block's senders, targets and access lists into the caches before executing it
+  --prefetch-ahead <N>     Take up to N blocks early from a block source and read their declared locations into the
+                           caches while the current block executes
+  --prefetch-ahead-txs <N> Stop taking blocks early once N txs are queued (default: unbounded)
+  --prevalidate            Reject txs revm is certain to refuse (nonce, balance, intrinsic gas, fee caps) before executing
+  --io-threads <N>         Threads backend reads are handed to, 0 = none (default: 0; needs --features async-io on Linux)
+  --commitment <KIND>      State root structure: mpt|verkle (default: mpt; verkle is experimental, needs --features verkle)
+  --emit-state-diffs <DIR> Write every block's state changes (old -> new) to DIR/block-<n>.jsonl
+  --ws-url <URL>           Node to follow: subscribes to new heads and fetches each block (ws:// only; follow only).
+                           The engine's state (--state-dir) must be the parent of the next block the node produces
+  --slot-secs <S>          Slot time follow measures each block's latency against (default: the chain's block time)
+  --record-trace <FILE>    Write every committed tx's read/write sets and gas to FILE, for replay-trace
+  --from-trace <FILE>      Access trace to replay (replay-trace only)
+  --blocks <N>             Number of consecutive blocks to execute (default: 1; follow: until the connection ends)
+  --end-block <N>          Execute through block N instead; with --rpc-url or --blocks-dir the range is checked against
+                           what the node or the files hold before anything runs
+  --checkpoint-interval <N>
+                           Write a state checkpoint every N blocks: a full one, then deltas holding what changed
+                           since the one before
+  --checkpoint-dir <DIR>   Where checkpoints go (default: checkpoints)
+  --zstd-level <N>         zstd-compress the checkpoint state dumps, --record-trace file and --rpc-cache blocks at
+                           level N (1-22; default: uncompressed). Reading detects compressed files either way
+  --resume <DIR>           Continue from a checkpoint directory (block-<n>) instead of genesis
+  --resume-from <N>        Continue at block N from the checkpoint taken after block N-1 in --checkpoint-dir, checking
+                           its state against that block's header root; block sources start at N
+  --preload-state <FILE>   Seed the accounts and storage in a pack-state file before the first block
+  --filter <EXPR>          Profile only matching txs, e.g. 'to == 0x.. || gas > 1_000_000'. The others still execute,
+                           so the state advances as the chain's did, but are left out of the reported numbers
+  --only-contract <ADDR>   Profile only txs calling ADDR or naming it in their access list (repeatable; any of them)
+  --only-sender <ADDR>     Profile only txs sent by ADDR (repeatable; any of them). Combined with each other and
+                           --filter, a tx has to pass all three. Others are marked as the block is queued and execute
+                           unprofiled, so a selected tx still sees the nonces, balances and slots they leave behind
+  --build-order <ORDER>    Order each block's txs are executed in: arrival|tip[:<max wait>]. tip = highest effective
+                           tip first, a sender's txs in arrival order, none passed over more than <max wait> times
+                           (tip = tip:1024; default: arrival, tip for build)
+  --workload <PROFILE>     Start the generated workload from a profile: transfers|dex-heavy|nft-mint|mixed. The flags
+                           below override single settings of it (default: 10k transfers over 100 accounts)
+  --txs-per-block <N>      Txs in each generated block, fixed or drawn from <min>-<max> (default: 10000)
+  --senders <N>            Accounts sending the generated txs, round-robin (default: 1, so all txs conflict)
+  --contracts <N>          Accounts the generated transfers round-robin over, and as many counter contracts
+                           (default: 100)
+  --popularity <DIST>      How generated txs pick their account: round-robin|zipf[:<skew>]. zipf draws account k with
+                           weight 1/(k+1)^skew, a few hot accounts and a long tail (zipf = zipf:1.0;
+                           default: round-robin)
+  --complexity <RATIO>     Share of generated txs calling a counter contract instead of transferring (default: 0)
+  --conflict-rate <RATIO>  Share of generated txs sent to the first account/contract instead (default: 0)
+  --seed <N>               Seed for every random choice the generator makes (default: 0)
+  --sign-txs               Sign the generated txs with a test key, so every block pays for sender recovery
+  --rpc-url <URL>          Replay real blocks fetched over JSON-RPC (http:// only) instead of generating them; their
+                           pre-state must already be in --state-dir or --preload-state
+  --from-block <N>         First block to replay (required with --rpc-url); alone, replays from the chain's public
+                           endpoint. Also --start-block
+  --rpc-window <N>         Blocks fetched ahead of the one executing (default: 8)
+  --rpc-batch <N>          Blocks per request, sent as a JSON-RPC batch when more than 1 (default: 1)
+  --rpc-connections <N>    Requests in flight at once, one connection each (default: 4)
+  --rpc-rate <R>           Max RPC requests per second, retries included (default: unlimited)
+  --rpc-cache <DIR>        Keep fetched blocks in DIR and read them from there on later runs instead of fetching
+                           them again (one DIR per chain)
+  --blocks-dir <DIR>       Replay blocks from the era1 files / RLP dumps (geth export) in DIR, in file name order,
+                           from --from-block if given; needs the matching --chain and pre-state like --rpc-url
+  --txs-file <FILE>        Run the txs listed in a JSONL or CSV file (sender, to, gas; optionally id, input, value,
+                           nonce, gas_price, block) instead of generating them; genesis funds every sender
+  --mempool-rate <TX/S>    Feed the generated txs through a simulated mempool, arriving at TX/S on average; each
+                           block takes what is pending, up to --txs-per-block
+  --mempool-replace <RATIO>
+                           Share of arrivals replacing a pending tx with a higher fee (default: 0.05)
+  --mempool-invalidate <RATIO>
+                           Chance each pending tx is dropped as invalid before a block (default: 0.01)
+  --label <KEY=VALUE>      Attach a label to this run's report (repeatable)
+  --forensics-dir <DIR>    Capture over-budget / retry-looping txs into DIR
+  --tx-budget-ms <MS>      Per-execution wall-clock budget before a capture (default: 250)
+  --max-retries <N>        Capture txs re-executed more than N times (default: 3)
+  --from-capture <FILE>    Capture file to replay (trace only)
+  --snapshot <FILE>        State snapshot to import (import-state, pack-state)
+  --out <FILE>             Where the packed state (pack-state) or the built blocks' txs, as a --txs-file (build), go
+  -h, --help               Print this help";
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    Run,
+    /// `out` receives the included txs of every built block.
+    Build { out: Option<PathBuf> },
+    Trace { capture: PathBuf },
+    ReplayTrace { trace: PathBuf },
+    /// `flux verify --self`: the blocks are executed a second time and both passes compared.
+    Verify,
+    /// `flux bisect --block <N>`: the blocks before `block` run as configured, then `block` runs both
+    /// that way and serially, and the two are compared tx by tx.
+    Bisect { block: u64 },
+    /// `blocks` is `None` to follow until the connection ends.
+    Follow { ws_url: String, slot: Duration, blocks: Option<u64> },
+    ImportState { snapshot: PathBuf, state_dir: PathBuf },
+    PackState { snapshot: PathBuf, out: PathBuf },
+}
+
+/// How far ahead of execution `--prefetch-ahead` may run: at least one block, then as many as
+/// fit in both limits.
+#[derive(Debug, Clone)]
+pub struct PrefetchLookahead {
+    pub blocks: usize,
+    pub max_txs: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckpointSchedule {
+    pub interval: u64,
+    pub dir: PathBuf,
+    /// zstd level the state dumps are written with, `None` = uncompressed.
+    pub compression: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Args {
+    pub command: Command,
+    pub config: EngineConfig,
+    /// Free-form run labels (`--label machine=epyc-9654`), in the order given.
+    pub labels: Vec<(String, String)>,
+    pub filter: Option<TxFilter>,
+    /// Block-building order the (filtered) txs are handed to the engine in.
+    pub build_order: BuildOrder,
+    /// Shape of the generated blocks.
+    pub synthetic: SyntheticWorkload,
+    /// Generate signed txs, whose senders the engine has to recover.
+    pub sign_txs: bool,
+    /// Replay blocks from a node or from files instead of generating them.
+    pub source: Option<SourceConfig>,
+    /// Let the generated txs arrive through a simulated mempool instead of a block at a time.
+    pub mempool: Option<MempoolConfig>,
+    pub blocks: u64,
+    /// Execute through this block instead of for `blocks` blocks.
+    pub end_block: Option<u64>,
+    /// Checkpoint every `interval` blocks into `dir`.
+    pub checkpoint: Option<CheckpointSchedule>,
+    pub resume: Option<PathBuf>,
+    /// Seeded after genesis (or the checkpoint), before the first block is timed.
+    pub preload_state: Option<PathBuf>,
+    pub lookahead: Option<PrefetchLookahead>,
+    /// Process-wide (it configures the global allocator), hence not part of EngineConfig.
+    pub huge_pages: HugePageMode,
+    /// Process-wide too (it builds the global rayon pool); its commit cores are in `config`.
+    pub topology: Option<Topology>,
+    /// Replay the run's blocks serially afterwards, for a speedup figure.
+    pub serial_baseline: bool,
+}
+
+pub fn parse() -> Result<Args, String> {
+    parse_from(std::env::args().skip(1))
+}
+
+fn parse_from(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut config = EngineConfig::default();
+    let mut labels: Vec<(String, String)> = Vec::new();
+    let mut filter: Option<String> = None;
+    let mut only_contracts: Vec<Address> = Vec::new();
+    let mut only_senders: Vec<Address> = Vec::new();
+    let mut build_order: Option<BuildOrder> = None;
+    let mut profile: Option<WorkloadProfile> = None;
+    let mut txs_per_block: Option<TxCount> = None;
+    let mut senders: Option<usize> = None;
+    let mut contracts: Option<usize> = None;
+    let mut popularity: Option<Popularity> = None;
+    let mut complexity: Option<f64> = None;
+    let mut conflict_rate: Option<f64> = None;
+    let mut seed: Option<u64> = None;
+    let mut sign_txs = false;
+    let mut rpc_url: Option<String> = None;
+    let mut from_block: Option<u64> = None;
+    let mut rpc_window: Option<usize> = None;
+    let mut rpc_rate: Option<f64> = None;
+    let mut rpc_batch: Option<usize> = None;
+    let mut rpc_connections: Option<usize> = None;
+    let mut rpc_cache: Option<PathBuf> = None;
+    let mut blocks_dir: Option<PathBuf> = None;
+    let mut txs_file: Option<PathBuf> = None;
+    let mut mempool_rate: Option<f64> = None;
+    let mut mempool_replace: Option<f64> = None;
+    let mut mempool_invalidate: Option<f64> = None;
+    let mut huge_pages = HugePageMode::Off;
+    let mut topology: Option<Topology> = None;
+    let mut commit_threads_given = false;
+    let mut blocks: Option<u64> = None;
+    let mut end_block: Option<u64> = None;
+    let mut ws_url: Option<String> = None;
+    let mut slot: Option<Duration> = None;
+    let mut checkpoint_interval: Option<u64> = None;
+    let mut checkpoint_dir: Option<PathBuf> = None;
+    let mut zstd_level: Option<u32> = None;
+    let mut resume: Option<PathBuf> = None;
+    let mut resume_from: Option<u64> = None;
+    let mut preload_state: Option<PathBuf> = None;
+    let mut prefetch_ahead: Option<usize> = None;
+    let mut prefetch_ahead_txs: Option<usize> = None;
+    let mut serial_baseline = false;
+    let mut self_check = false;
+    let mut bisect_block: Option<u64> = None;
+    let mut forensics_dir: Option<PathBuf> = None;
+    let mut tx_budget = None;
+    let mut max_retries = None;
+    let mut from_capture: Option<PathBuf> = None;
+    let mut from_trace: Option<PathBuf> = None;
+    let mut snapshot: Option<PathBuf> = None;
+    let mut out: Option<PathBuf> = None;
+    let mut args = args.into_iter().peekable();
+
+    let subcommand = match args.peek().map(String::as_str) {
+        Some("run") | Some("build") | Some("trace") | Some("replay-trace") | Some("follow") | Some("verify")
+        | Some("bisect") | Some("import-state") | Some("pack-state") => args.next(),
+        _ => None,
+    };
+
+    while let Some(arg) = args.next() {
+        // Accept both `--flag value` and `--flag=value`.
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg, None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} expects a value", flag))
+        };
+
+        match flag.as_str() {
+            "--chain" => config.chain = value()?.parse()?,
+            "--commit-threads" => {
+                let threads: usize = parse_value(&flag, value()?)?;
+                if threads == 0 {
+                    return Err("--commit-threads must be at least 1".into());
+                }
+                config.commit_threads = threads;
+                commit_threads_given = true;
+            }
+            "--state-shards" => {
+                let shards: usize = parse_value(&flag, value()?)?;
+                if shards == 0 {
+                    return Err("--state-shards must be at least 1".into());
+                }
+                config.state_shards = shards;
+            }
+            "--watchdog-secs" => {
+                let secs: u64 = parse_value(&flag, value()?)?;
+                config.watchdog_timeout = (secs > 0).then(|| Duration::from_secs(secs));
+            }
+            "--state-dir" => config.state_dir = Some(PathBuf::from(value()?)),
+            "--state-backend" => config.state_backend = value()?.parse()?,
+            "--state-cache" => {
+                let entries: usize = parse_value(&flag, value()?)?;
+                if entries == 0 {
+                    return Err("--state-cache must be at least 1".into());
+                }
+                config.state_cache = Some(entries);
+            }
+            "--conflicts" => config.conflict_granularity = value()?.parse()?,
+            "--strategy" => config.strategy = value()?.parse()?,
+            "--dispatch" => config.dispatch = value()?.parse()?,
+            "--speculation-depth" => config.speculation_depth = value()?.parse()?,
+            "--retry-rounds" => config.retry_rounds = parse_value(&flag, value()?)?,
+            "--prune" => config.prune = value()?.parse()?,
+            "--huge-pages" => huge_pages = value()?.parse()?,
+            "--topology" => topology = Some(value()?.parse()?),
+            "--disk-latency" => config.disk_latency = value()?.parse()?,
+            "--prefetch-state" => config.prefetch_state = true,
+            "--prefetch-ahead" => {
+                let blocks: usize = parse_value(&flag, value()?)?;
+                if blocks == 0 {
+                    return Err("--prefetch-ahead must be at least 1".into());
+                }
+                prefetch_ahead = Some(blocks);
+            }
+            "--prefetch-ahead-txs" => prefetch_ahead_txs = Some(parse_value(&flag, value()?)?),
+            "--prevalidate" => config.prevalidate = true,
+            "--io-threads" => config.io_threads = parse_value(&flag, value()?)?,
+            "--commitment" => config.commitment = value()?.parse()?,
+            "--emit-state-diffs" => config.state_diffs = Some(PathBuf::from(value()?)),
+            "--record-trace" => config.record_trace = Some(PathBuf::from(value()?)),
+            "--blocks" => {
+                let count: u64 = parse_value(&flag, value()?)?;
+                if count == 0 {
+                    return Err("--blocks must be at least 1".into());
+                }
+                blocks = Some(count);
+            }
+            "--end-block" => end_block = Some(parse_value(&flag, value()?)?),
+            "--ws-url" => ws_url = Some(value()?),
+            "--slot-secs" => {
+                let secs: f64 = parse_value(&flag, value()?)?;
+                if !(secs > 0.0 && secs.is_finite()) {
+                    return Err(format!("--slot-secs must be a positive number, got {}", secs));
+                }
+                slot = Some(Duration::from_secs_f64(secs));
+            }
+            "--checkpoint-interval" => {
+                let interval: u64 = parse_value(&flag, value()?)?;
+                if interval == 0 {
+                    return Err("--checkpoint-interval must be at least 1".into());
+                }
+                checkpoint_interval = Some(interval);
+            }
+            "--checkpoint-dir" => checkpoint_dir = Some(PathBuf::from(value()?)),
+            "--zstd-level" => {
+                let level: u32 = parse_value(&flag, value()?)?;
+                if !(1..=zstd::MAX_LEVEL).contains(&level) {
+                    return Err(format!("--zstd-level must be between 1 and {}, got {}", zstd::MAX_LEVEL, level));
+                }
+                zstd_level = Some(level);
+            }
+            "--resume" => resume = Some(PathBuf::from(value()?)),
+            "--resume-from" => resume_from = Some(parse_value(&flag, value()?)?),
+            "--preload-state" => preload_state = Some(PathBuf::from(value()?)),
+            "--serial-baseline" => serial_baseline = true,
+            "--self" => self_check = true,
+            "--block" => bisect_block = Some(parse_value(&flag, value()?)?),
+            "--filter" => filter = Some(value()?),
+            "--only-contract" => only_contracts.push(parse_value(&flag, value()?)?),
+            "--only-sender" => only_senders.push(parse_value(&flag, value()?)?),
+            "--build-order" => build_order = Some(value()?.parse()?),
+            "--workload" => profile = Some(value()?.parse()?),
+            "--txs-per-block" => txs_per_block = Some(value()?.parse()?),
+            "--senders" => {
+                let count: usize = parse_value(&flag, value()?)?;
+                if count == 0 {
+                    return Err("--senders must be at least 1".into());
+                }
+                senders = Some(count);
+            }
+            "--contracts" => {
+                let count: usize = parse_value(&flag, value()?)?;
+                if count == 0 {
+                    return Err("--contracts must be at least 1".into());
+                }
+                contracts = Some(count);
+            }
+            "--popularity" => popularity = Some(value()?.parse()?),
+            "--complexity" => complexity = Some(parse_ratio(&flag, value()?)?),
+            "--conflict-rate" => conflict_rate = Some(parse_ratio(&flag, value()?)?),
+            "--seed" => seed = Some(parse_value(&flag, value()?)?),
+            "--sign-txs" => sign_txs = true,
+            "--rpc-url" => rpc_url = Some(value()?),
+            "--from-block" | "--start-block" => from_block = Some(parse_value(&flag, value()?)?),
+            "--rpc-window" => rpc_window = Some(parse_value(&flag, value()?)?),
+            "--rpc-rate" => rpc_rate = Some(parse_value(&flag, value()?)?),
+            "--rpc-batch" => rpc_batch = Some(parse_value(&flag, value()?)?),
+            "--rpc-connections" => rpc_connections = Some(parse_value(&flag, value()?)?),
+            "--rpc-cache" => rpc_cache = Some(PathBuf::from(value()?)),
+            "--blocks-dir" => blocks_dir = Some(PathBuf::from(value()?)),
+            "--txs-file" => txs_file = Some(PathBuf::from(value()?)),
+            "--mempool-rate" => {
+                let rate: f64 = parse_value(&flag, value()?)?;
+                if !(rate > 0.0 && rate.is_finite()) {
+                    return Err(format!("--mempool-rate must be a positive number of txs per second, got {}", rate));
+                }
+                mempool_rate = Some(rate);
+            }
+            "--mempool-replace" => mempool_replace = Some(parse_ratio(&flag, value()?)?),
+            "--mempool-invalidate" => mempool_invalidate = Some(parse_ratio(&flag, value()?)?),
+            "--label" => {
+                let raw = value()?;
+                let (key, val) = raw
+                    .split_once('=')
+                    .filter(|(key, _)| !key.is_empty())
+                    .ok_or_else(|| format!("--label expects KEY=VALUE, got {:?}", raw))?;
+                if labels.iter().any(|(k, _)| k == key) {
+                    return Err(format!("duplicate --label key: {}", key));
+                }
+                labels.push((key.to_string(), val.to_string()));
+            }
+            "--forensics-dir" => forensics_dir = Some(PathBuf::from(value()?)),
+            "--tx-budget-ms" => tx_budget = Some(Duration::from_millis(parse_value(&flag, value()?)?)),
+            "--max-retries" => max_retries = Some(parse_value(&flag, value()?)?),
+            "--from-capture" => from_capture = Some(PathBuf::from(value()?)),
+            "--from-trace" => from_trace = Some(PathBuf::from(value()?)),
+            "--snapshot" => snapshot = Some(PathBuf::from(value()?)),
+            "--out" => out = Some(PathBuf::from(value()?)),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            other => return Err(format!("unknown argument: {}", other)),
+        }
+    }
+
+    match forensics_dir {
+        Some(dir) => {
+            config.forensics = Some(ForensicsConfig {
+                dir,
+                tx_budget: tx_budget.unwrap_or(Duration::from_millis(250)),
+                max_retries: max_retries.unwrap_or(3),
+            })
+        }
+        None if tx_budget.is_some() || max_retries.is_some() => {
+            return Err("--tx-budget-ms / --max-retries need --forensics-dir".into());
+        }
+        None => {}
+    }
+
+    // The shorthands become clauses of the filter, so the report echoes what was replayed.
+    let mut clauses: Vec<String> = filter.into_iter().collect();
+    for (field, addresses) in [("touches", &only_contracts), ("from", &only_senders)] {
+        if !addresses.is_empty() {
+            let any: Vec<String> = addresses.iter().map(|address| format!("{} == {}", field, address)).collect();
+            clauses.push(any.join(" || "));
+        }
+    }
+    let filter: Option<TxFilter> = match clauses.len() {
+        0 => None,
+        1 => Some(clauses[0].parse()?),
+        _ => Some(clauses.iter().map(|clause| format!("({})", clause)).collect::<Vec<_>>().join(" && ").parse()?),
+    };
+
+    let checkpoint_dir_given = checkpoint_dir.is_some();
+    let checkpoint_dir = checkpoint_dir.unwrap_or_else(|| PathBuf::from("checkpoints"));
+    if let Some(block) = resume_from {
+        if resume.is_some() {
+            return Err("--resume and --resume-from both pick a checkpoint; use one".into());
+        }
+        if block == 0 {
+            return Err("--resume-from 0 has no checkpoint before it; runs start there anyway".into());
+        }
+        resume = Some(checkpoint_dir.join(format!("block-{}", block - 1)));
+    }
+    let checkpoint = match checkpoint_interval {
+        Some(interval) => Some(CheckpointSchedule {
+            interval,
+            dir: checkpoint_dir,
+            compression: zstd_level,
+        }),
+        None if checkpoint_dir_given && resume_from.is_none() => {
+            return Err("--checkpoint-dir needs --checkpoint-interval or --resume-from".into())
+        }
+        None => None,
+    };
+
+    if let Some(topology) = topology.as_ref().filter(|topology| !topology.commit.is_empty()) {
+        if commit_threads_given {
+            return Err("--commit-threads conflicts with the commit cores in --topology (one thread per core)".into());
+        }
+        config.commit_threads = topology.commit.len();
+        config.commit_cores = topology.commit.clone();
+    }
+
+    // A bare --from-block fetches from the chain's own endpoint.
+    if rpc_url.is_none() && blocks_dir.is_none() && txs_file.is_none() && from_block.is_some() {
+        rpc_url = config.chain.rpc_url.clone();
+    }
+    // A resumed replay picks up the blocks where its checkpoint left off.
+    if let Some(block) = resume_from.filter(|_| rpc_url.is_some() || blocks_dir.is_some()) {
+        match from_block {
+            Some(first) if first != block => {
+                return Err(format!(
+                    "--from-block {} but --resume-from {}: the replay continues at the checkpoint",
+                    first, block
+                ))
+            }
+            _ => from_block = Some(block),
+        }
+    }
+    if rpc_url.is_none()
+        && (rpc_window.is_some()
+            || rpc_rate.is_some()
+            || rpc_batch.is_some()
+            || rpc_connections.is_some()
+            || rpc_cache.is_some())
+    {
+        return Err("--rpc-window / --rpc-batch / --rpc-connections / --rpc-rate / --rpc-cache need --rpc-url".into());
+    }
+    if zstd_level.is_some() && rpc_cache.is_none() && config.record_trace.is_none() && checkpoint.is_none() {
+        return Err("--zstd-level compresses --rpc-cache, --record-trace and --checkpoint-interval output; give one".into());
+    }
+    config.trace_compression = zstd_level.filter(|_| config.record_trace.is_some());
+    let source = match (rpc_url, blocks_dir, txs_file) {
+        (Some(url), None, None) => match from_block {
+            Some(first_block) => Some(SourceConfig::Rpc(RpcConfig {
+                url,
+                first_block,
+                window: rpc_window.unwrap_or(DEFAULT_WINDOW),
+                batch: rpc_batch.unwrap_or(DEFAULT_BATCH),
+                connections: rpc_connections.unwrap_or(DEFAULT_CONNECTIONS),
+                rate_limit: rpc_rate,
+                cache_compression: zstd_level.filter(|_| rpc_cache.is_some()),
+                cache: rpc_cache,
+            })),
+            None => return Err("--rpc-url needs --from-block <N>".into()),
+        },
+        (None, Some(dir), None) => Some(SourceConfig::Archive {
+            dir,
+            first_block: from_block,
+        }),
+        (None, None, Some(_)) if from_block.is_some() => {
+            return Err("--from-block selects replayed blocks; --txs-file runs on the generated chain".into());
+        }
+        (None, None, Some(path)) => Some(SourceConfig::File(path)),
+        (None, None, None) if from_block.is_some() => {
+            return Err(format!(
+                "--from-block needs --rpc-url or --blocks-dir; {} has no default endpoint",
+                config.chain.name
+            ))
+        }
+        (None, None, None) => None,
+        _ => return Err("--rpc-url, --blocks-dir and --txs-file are different sources of blocks; pick one".into()),
+    };
+    if mempool_rate.is_none() && (mempool_replace.is_some() || mempool_invalidate.is_some()) {
+        return Err("--mempool-replace / --mempool-invalidate need --mempool-rate".into());
+    }
+    let mempool = mempool_rate.map(|rate| {
+        let defaults = MempoolConfig::default();
+        MempoolConfig {
+            rate,
+            replace: mempool_replace.unwrap_or(defaults.replace),
+            invalidate: mempool_invalidate.unwrap_or(defaults.invalidate),
+        }
+    });
+    if source.is_some() && mempool.is_some() {
+        return Err("--mempool-rate feeds generated txs; it cannot replay a block source".into());
+    }
+    // The profile sets the baseline; flags given alongside it override single knobs.
+    let synthetic_given = profile.is_some()
+        || txs_per_block.is_some()
+        || senders.is_some()
+        || contracts.is_some()
+        || popularity.is_some()
+        || complexity.is_some()
+        || conflict_rate.is_some()
+        || seed.is_some();
+    let base = profile.map_or_else(SyntheticWorkload::default, WorkloadProfile::workload);
+    let synthetic = SyntheticWorkload {
+        txs_per_block: txs_per_block.unwrap_or(base.txs_per_block),
+        senders: senders.unwrap_or(base.senders),
+        contracts: contracts.unwrap_or(base.contracts),
+        popularity: popularity.unwrap_or(base.popularity),
+        complexity: complexity.unwrap_or(base.complexity),
+        conflict_rate: conflict_rate.unwrap_or(base.conflict_rate),
+        seed: seed.unwrap_or(base.seed),
+    };
+    if source.is_some() && synthetic_given {
+        return Err("--workload, --txs-per-block, --senders, --contracts, --popularity, --complexity, \
+                    --conflict-rate and --seed shape generated blocks"
+            .into());
+    }
+    if sign_txs && synthetic.senders > 1 {
+        return Err(format!(
+            "--sign-txs signs with a single key, but the workload has {} senders; add --senders 1",
+            synthetic.senders
+        ));
+    }
+    if mempool.is_some() && sign_txs {
+        return Err("--sign-txs signs whole generated blocks; the mempool builds its own from what arrives".into());
+    }
+    if source.is_some() && sign_txs {
+        return Err("--sign-txs signs generated txs; replayed blocks carry their own signatures".into());
+    }
+
+    if serial_baseline {
+        if source.is_some() || mempool.is_some() {
+            return Err("--serial-baseline re-generates the run's blocks; it cannot replay a block source or mempool".into());
+        }
+        if resume.is_some() || config.state_dir.is_some() {
+            return Err("--serial-baseline replays from an in-memory genesis; drop --resume / --resume-from / --state-dir".into());
+        }
+        if config.strategy == Strategy::Serial {
+            return Err("--serial-baseline needs a parallel --strategy to compare against".into());
+        }
+    }
+
+    if snapshot.is_some() && !matches!(subcommand.as_deref(), Some("import-state") | Some("pack-state")) {
+        return Err("--snapshot is only valid with `flux import-state` or `flux pack-state`".into());
+    }
+    if out.is_some() && !matches!(subcommand.as_deref(), Some("pack-state") | Some("build")) {
+        return Err("--out is only valid with `flux pack-state` or `flux build`".into());
+    }
+    let command = match (subcommand.as_deref(), from_capture) {
+        (Some("trace"), Some(capture)) => Command::Trace { capture },
+        (Some("trace"), None) => return Err("trace needs --from-capture <FILE>".into()),
+        (_, Some(_)) => return Err("--from-capture is only valid with `flux trace`".into()),
+        (Some("build"), None) => Command::Build { out: out.take() },
+        (Some("follow"), None) => match ws_url.take() {
+            Some(ws_url) => Command::Follow {
+                ws_url,
+                slot: slot.take().unwrap_or(Duration::from_secs(config.chain.block_time)),
+                blocks,
+            },
+            None => return Err("follow needs --ws-url <URL>".into()),
+        },
+        (Some("verify"), None) if self_check => Command::Verify,
+        (Some("verify"), None) => return Err("verify needs --self".into()),
+        (Some("bisect"), None) => match bisect_block {
+            Some(block) => Command::Bisect { block },
+            None => return Err("bisect needs --block <N>".into()),
+        },
+        (Some("replay-trace"), None) => match from_trace.take() {
+            Some(trace) => Command::ReplayTrace { trace },
+            None => return Err("replay-trace needs --from-trace <FILE>".into()),
+        },
+        (Some("import-state"), None) => match (snapshot, config.state_dir.clone()) {
+            (Some(snapshot), Some(state_dir)) => Command::ImportState { snapshot, state_dir },
+            _ => return Err("import-state needs --snapshot <FILE> and --state-dir <DIR>".into()),
+        },
+        (Some("pack-state"), None) => match (snapshot, out) {
+            (Some(snapshot), Some(out)) => Command::PackState { snapshot, out },
+            _ => return Err("pack-state needs --snapshot <FILE> and --out <FILE>".into()),
+        },
+        _ => Command::Run,
+    };
+    if from_trace.is_some() {
+        return Err("--from-trace is only valid with `flux replay-trace`".into());
+    }
+    if self_check && !matches!(command, Command::Verify) {
+        return Err("--self is only valid with `flux verify`".into());
+    }
+    if bisect_block.is_some() && !matches!(command, Command::Bisect { .. }) {
+        return Err("--block is only valid with `flux bisect`".into());
+    }
+    if ws_url.is_some() || slot.is_some() {
+        return Err("--ws-url and --slot-secs are only valid with `flux follow`".into());
+    }
+    if end_block.is_some() && blocks.is_some() {
+        return Err("--blocks and --end-block both end the run; use one".into());
+    }
+    if let Command::Follow { .. } = command {
+        if end_block.is_some() {
+            return Err("follow executes blocks as they come; --end-block needs `run`".into());
+        }
+        if source.is_some() || mempool.is_some() || synthetic_given || sign_txs {
+            return Err("follow executes the node's blocks; drop the other block sources and workload flags".into());
+        }
+        if serial_baseline || checkpoint.is_some() || resume.is_some() {
+            return Err("follow runs open-ended; --serial-baseline, --checkpoint-interval and --resume(-from) need `run`".into());
+        }
+        // Reorged blocks are unwound from their changesets.
+        if !config.prune.records_history() {
+            config.prune = PruneMode::KeepBlocks(MAX_REORG_DEPTH);
+        }
+    }
+    if let Command::Build { .. } = command {
+        if source.as_ref().is_some_and(SourceConfig::has_headers) {
+            return Err("build fills its own blocks; --rpc-url and --blocks-dir replay finished ones".into());
+        }
+        if serial_baseline {
+            return Err("--serial-baseline re-generates the run's blocks, not the pool a build leaves behind".into());
+        }
+    }
+    if let Command::Verify = command {
+        if resume.is_some() || config.state_dir.is_some() {
+            return Err("verify --self runs twice from the same in-memory start; drop --resume / --resume-from / --state-dir".into());
+        }
+        if mempool.is_some() {
+            return Err("verify --self needs the same blocks twice; --mempool-rate builds them from arrival times".into());
+        }
+        if serial_baseline || checkpoint.is_some() || prefetch_ahead.is_some() {
+            return Err("verify --self runs its own second pass; drop --serial-baseline / --checkpoint-interval / --prefetch-ahead".into());
+        }
+    }
+    if let Command::Bisect { block } = command {
+        if blocks.is_some() || end_block.is_some() {
+            return Err("bisect runs through --block; drop --blocks / --end-block".into());
+        }
+        if resume.is_some() || config.state_dir.is_some() {
+            return Err("bisect copies the in-memory state before --block; drop --resume / --resume-from / --state-dir".into());
+        }
+        if serial_baseline || checkpoint.is_some() || prefetch_ahead.is_some() {
+            return Err("bisect runs its own serial pass; drop --serial-baseline / --checkpoint-interval / --prefetch-ahead".into());
+        }
+        if config.strategy == Strategy::Serial {
+            return Err("bisect compares --strategy against serial; pick a parallel one".into());
+        }
+        end_block = Some(block);
+        config.record_tx_writes = true;
+    }
+    if prefetch_ahead_txs.is_some() && prefetch_ahead.is_none() {
+        return Err("--prefetch-ahead-txs needs --prefetch-ahead".into());
+    }
+    let lookahead = prefetch_ahead.map(|blocks| PrefetchLookahead {
+        blocks,
+        max_txs: prefetch_ahead_txs,
+    });
+    if lookahead.is_some() && (source.is_none() || matches!(command, Command::Follow { .. })) {
+        return Err("--prefetch-ahead reads ahead of a block source; add --rpc-url, --blocks-dir or --txs-file".into());
+    }
+    // A builder fills blocks by what they pay, unless told otherwise.
+    let build_order = build_order.unwrap_or(match command {
+        Command::Build { .. } => BuildOrder::Tip {
+            max_wait: DEFAULT_MAX_WAIT,
+        },
+        _ => BuildOrder::Arrival,
+    });
+
+    Ok(Args {
+        command,
+        config,
+        labels,
+        filter,
+        build_order,
+        synthetic,
+        sign_txs,
+        source,
+        mempool,
+        blocks: blocks.unwrap_or(1),
+        end_block,
+        checkpoint,
+        resume,
+        preload_state,
+        lookahead,
+        huge_pages,
+        topology,
+        serial_baseline,
+    })
+}
+
+fn parse_value<T: FromStr>(flag: &str, raw: String) -> Result<T, String> {
+    raw.parse()
+        .map_err(|_| format!("invalid value for {}: {:?}", flag, raw))
+}
+
+/// A share between 0 and 1.
+fn parse_ratio(flag: &str, raw: String) -> Result<f64, String> {
+    let ratio: f64 = parse_value(flag, raw)?;
+    if !(0.0..=1.0).contains(&ratio) {
+        return Err(format!("{} must be between 0 and 1, got {}", flag, ratio));
+    }
+    Ok(ratio)
+}
@@ -0,0 +1,87 @@
+/*
+ * FLUX ENGINE - STATE COMMITMENT
+ * What the state root is computed over. The Merkle Patricia trie is the one headers carry; the
+ * Verkle tree (`--features verkle`) is an experimental cost model to benchmark against it.
+ */
+
+use crate::state::snapshot::SnapshotAccount;
+use crate::trie::StateTrie;
+#[cfg(feature = "verkle")]
+use crate::verkle::VerkleTree;
+use alloy_primitives::{Address, B256, U256};
+use revm::primitives::AccountInfo;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CommitmentScheme {
+    #[default]
+    Mpt,
+    /// Roots are not comparable with header state roots, so blocks are never verified against them.
+    Verkle,
+}
+
+impl FromStr for CommitmentScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "mpt" => Ok(CommitmentScheme::Mpt),
+            "verkle" => Ok(CommitmentScheme::Verkle),
+            _ => Err(format!("unknown state commitment {:?} (expected mpt or verkle)", s)),
+        }
+    }
+}
+
+impl fmt::Display for CommitmentScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CommitmentScheme::Mpt => "mpt",
+            CommitmentScheme::Verkle => "verkle",
+        })
+    }
+}
+
+/// The state commitment kept between blocks; see `StateTrie` for the update contract.
+#[derive(Debug)]
+pub enum StateCommitment {
+    Mpt(StateTrie),
+    #[cfg(feature = "verkle")]
+    Verkle(Box<VerkleTree>),
+}
+
+impl StateCommitment {
+    pub fn build(scheme: CommitmentScheme, accounts: &[SnapshotAccount]) -> Result<Self, String> {
+        match scheme {
+            CommitmentScheme::Mpt => Ok(StateCommitment::Mpt(StateTrie::build(accounts))),
+            #[cfg(feature = "verkle")]
+            CommitmentScheme::Verkle => Ok(StateCommitment::Verkle(Box::new(VerkleTree::build(accounts)))),
+            #[cfg(not(feature = "verkle"))]
+            CommitmentScheme::Verkle => Err("the verkle commitment needs a build with `--features verkle`".into()),
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        address: Address,
+        info: Option<&AccountInfo>,
+        wiped: bool,
+        slots: impl IntoIterator<Item = (U256, U256)>,
+    ) {
+        match self {
+            StateCommitment::Mpt(trie) => trie.update(address, info, wiped, slots),
+            #[cfg(feature = "verkle")]
+            StateCommitment::Verkle(tree) => tree.update(address, info, wiped, slots),
+        }
+    }
+
+    /// The root and the work it took since the last call: nodes re-encoded for the trie, scalar
+    /// multiplications for the Verkle tree.
+    pub fn root(&mut self) -> (B256, u64) {
+        match self {
+            StateCommitment::Mpt(trie) => trie.root(),
+            #[cfg(feature = "verkle")]
+            StateCommitment::Verkle(tree) => tree.root(),
+        }
+    }
+}
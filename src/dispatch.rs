@@ -0,0 +1,431 @@
+/*
+ * FLUX ENGINE - DISPATCH
+ * How a block's transactions are handed to the executors for optimistic speculation. Every task
+ * is a lane: transactions that run in block order on one executor, each on top of the writes of
+ * the ones before it, so they stop conflicting with each other. Spread dispatch gives every
+ * transaction a lane of its own. Grouped dispatch puts consecutive transactions that share a hot
+ * account (a run of swaps against one pool, one sender's queued transactions) in one lane, and
+ * predicted dispatch (`predict`) whatever its conflict prediction links together. How far
+ * speculation may run ahead of the ordered applier is a separate knob, `SpeculationDepth`.
+ */
+
+use crate::state::buffer::coalesce;
+use crate::FluxTransaction;
+use revm::db::DatabaseRef;
+use revm::primitives::{Account, AccountInfo, Address, Bytecode, HashMap, State, B256, U256};
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Grouping {
+    /// An account is hot once at least this many of the block's transactions send from or to it.
+    pub hot_threshold: usize,
+    /// Most transactions in one chunk. Longer runs split, so one executor never gets a whole
+    /// block of swaps to itself.
+    pub max_chunk: usize,
+}
+
+impl Default for Grouping {
+    fn default() -> Self {
+        Self {
+            hot_threshold: 4,
+            max_chunk: 32,
+        }
+    }
+}
+
+impl Grouping {
+    pub fn lanes(&self, txs: &[&FluxTransaction], coinbase: Address) -> Lanes {
+        let mut touched: HashMap<Address, usize> = HashMap::default();
+        for tx in txs {
+            for address in accounts(tx) {
+                *touched.entry(address).or_default() += 1;
+            }
+        }
+        // Coinbase credits are rebased, not conflicts, so they are no reason to group.
+        let hot = |address: &Address| *address != coinbase && touched[address] >= self.hot_threshold;
+
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        let mut shared: HashSet<Address> = HashSet::new();
+        for (i, tx) in txs.iter().enumerate() {
+            let joins = i - start < self.max_chunk && accounts(tx).any(|address| shared.contains(&address));
+            if !joins && i > start {
+                chunks.push((start..i).collect());
+                start = i;
+                shared.clear();
+            }
+            shared.extend(accounts(tx).filter(hot));
+        }
+        if start < txs.len() {
+            chunks.push((start..txs.len()).collect());
+        }
+        Lanes::new(chunks)
+    }
+}
+
+/// The accounts a transaction is grouped by: its sender and its target, if it has one.
+fn accounts(tx: &FluxTransaction) -> impl Iterator<Item = Address> {
+    std::iter::once(tx.caller).chain((tx.to != tx.caller && !tx.create).then_some(tx.to))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dispatch {
+    /// Every transaction is a lane of its own.
+    #[default]
+    Spread,
+    /// Runs of transactions sharing a hot account share a lane.
+    Grouped(Grouping),
+    /// Transactions predicted to conflict, from access lists and learned access patterns, share
+    /// a lane.
+    Predicted,
+}
+
+/// A block split into executor tasks.
+#[derive(Debug, Clone, Default)]
+pub struct Lanes {
+    /// Block indices, in order, of each lane's transactions.
+    pub lanes: Vec<Vec<usize>>,
+    /// The lane each transaction is in.
+    pub lane_of: Vec<usize>,
+}
+
+impl Lanes {
+    pub fn new(lanes: Vec<Vec<usize>>) -> Self {
+        let mut lane_of = vec![0; lanes.iter().map(Vec::len).sum()];
+        for (lane, txs) in lanes.iter().enumerate() {
+            for &i in txs {
+                lane_of[i] = lane;
+            }
+        }
+        Self { lanes, lane_of }
+    }
+
+    /// A lane per transaction.
+    pub fn spread(len: usize) -> Self {
+        Self {
+            lanes: (0..len).map(|i| vec![i]).collect(),
+            lane_of: (0..len).collect(),
+        }
+    }
+}
+
+impl FromStr for Dispatch {
+    type Err = String;
+
+    /// `spread`, `grouped[:<hot threshold>[:<max chunk>]]` or `predicted`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unknown = || format!("unknown dispatch mode {:?} (spread|grouped[:<hot>[:<max chunk>]]|predicted)", s);
+        match s {
+            "spread" => return Ok(Dispatch::Spread),
+            "predicted" => return Ok(Dispatch::Predicted),
+            _ => {}
+        }
+        let mut parts = s.split(':');
+        if parts.next() != Some("grouped") {
+            return Err(unknown());
+        }
+        let mut grouping = Grouping::default();
+        if let Some(hot) = parts.next() {
+            grouping.hot_threshold = hot.parse().map_err(|_| unknown())?;
+        }
+        if let Some(max) = parts.next() {
+            grouping.max_chunk = max.parse().map_err(|_| unknown())?;
+        }
+        if parts.next().is_some() {
+            return Err(unknown());
+        }
+        if grouping.hot_threshold == 0 || grouping.max_chunk == 0 {
+            return Err("grouped dispatch needs a hot threshold and max chunk of at least 1".into());
+        }
+        Ok(Dispatch::Grouped(grouping))
+    }
+}
+
+impl fmt::Display for Dispatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dispatch::Spread => f.write_str("spread"),
+            Dispatch::Grouped(grouping) => write!(f, "grouped:{}:{}", grouping.hot_threshold, grouping.max_chunk),
+            Dispatch::Predicted => f.write_str("predicted"),
+        }
+    }
+}
+
+// --- SPECULATION DEPTH ---
+
+/// Rolling abort rate above which the window halves, and below which it doubles.
+const NARROW_ABOVE: f64 = 0.3;
+const WIDEN_BELOW: f64 = 0.05;
+/// Weight of the latest window in the rolling abort rate.
+const ABORT_RATE_WEIGHT: f64 = 0.5;
+
+/// How far optimistic speculation runs ahead of the ordered applier. A window of transactions
+/// executes against the state applied so far; the next goes out once the applier reaches its end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SpeculationDepth {
+    /// The whole block speculates at once.
+    #[default]
+    Unbounded,
+    Fixed(usize),
+    /// Narrows while speculation keeps getting thrown away and widens while it lands, within
+    /// `min..=max`, starting from `max`.
+    Adaptive { min: usize, max: usize },
+}
+
+impl FromStr for SpeculationDepth {
+    type Err = String;
+
+    /// `unbounded`, `<N>`, or `adaptive[:<min>:<max>]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unknown = || format!("unknown speculation depth {:?} (unbounded|<N>|adaptive[:<min>:<max>])", s);
+        let depth = match s {
+            "unbounded" => return Ok(SpeculationDepth::Unbounded),
+            "adaptive" => SpeculationDepth::Adaptive { min: 32, max: 4096 },
+            other => match other.strip_prefix("adaptive:") {
+                Some(bounds) => {
+                    let (min, max) = bounds.split_once(':').ok_or_else(unknown)?;
+                    SpeculationDepth::Adaptive {
+                        min: min.parse().map_err(|_| unknown())?,
+                        max: max.parse().map_err(|_| unknown())?,
+                    }
+                }
+                None => SpeculationDepth::Fixed(other.parse().map_err(|_| unknown())?),
+            },
+        };
+        match depth {
+            SpeculationDepth::Fixed(0) => Err("speculation depth must be at least 1".into()),
+            SpeculationDepth::Adaptive { min, max } if min == 0 || min > max => {
+                Err(format!("adaptive speculation depth needs 1 <= min <= max, got {}..{}", min, max))
+            }
+            depth => Ok(depth),
+        }
+    }
+}
+
+impl fmt::Display for SpeculationDepth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpeculationDepth::Unbounded => f.write_str("unbounded"),
+            SpeculationDepth::Fixed(depth) => write!(f, "{}", depth),
+            SpeculationDepth::Adaptive { min, max } => write!(f, "adaptive:{}:{}", min, max),
+        }
+    }
+}
+
+/// Sizes speculation windows from the rolling rate at which their runs are thrown away. It
+/// carries over from block to block.
+#[derive(Debug, Clone, Copy)]
+pub struct DepthController {
+    mode: SpeculationDepth,
+    depth: usize,
+    abort_rate: f64,
+    windows: u64,
+    narrowed: u64,
+    widened: u64,
+}
+
+impl DepthController {
+    pub fn new(mode: SpeculationDepth) -> Self {
+        let depth = match mode {
+            SpeculationDepth::Unbounded => usize::MAX,
+            SpeculationDepth::Fixed(depth) => depth,
+            SpeculationDepth::Adaptive { max, .. } => max,
+        };
+        Self {
+            mode,
+            depth,
+            abort_rate: 0.0,
+            windows: 0,
+            narrowed: 0,
+            widened: 0,
+        }
+    }
+
+    /// Transactions the next window may hold.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Folds in how many of a window's speculative runs were thrown away (stale, re-run in a retry
+    /// round or serially), then narrows or widens.
+    pub fn observe(&mut self, speculated: usize, aborted: usize) {
+        if speculated == 0 {
+            return;
+        }
+        self.windows += 1;
+        let rate = (aborted as f64 / speculated as f64).min(1.0);
+        self.abort_rate = if self.windows == 1 {
+            rate
+        } else {
+            ABORT_RATE_WEIGHT * rate + (1.0 - ABORT_RATE_WEIGHT) * self.abort_rate
+        };
+        if let SpeculationDepth::Adaptive { min, max } = self.mode {
+            if self.abort_rate > NARROW_ABOVE && self.depth > min {
+                self.depth = (self.depth / 2).max(min);
+                self.narrowed += 1;
+            } else if self.abort_rate < WIDEN_BELOW && self.depth < max {
+                self.depth = self.depth.saturating_mul(2).min(max);
+                self.widened += 1;
+            }
+        }
+    }
+
+    pub fn stats(&self) -> SpeculationStats {
+        SpeculationStats {
+            mode: self.mode,
+            depth: self.depth,
+            abort_rate: self.abort_rate,
+            windows: self.windows,
+            narrowed: self.narrowed,
+            widened: self.widened,
+        }
+    }
+}
+
+// --- LANE STATE ---
+
+/// The writes of a lane's earlier transactions, over the state the lane speculates on. The
+/// coinbase stays out: each transaction's credit is rebased onto the committed balance alone.
+pub struct LaneOverlay<'a, DB> {
+    state: &'a DB,
+    coinbase: Address,
+    accounts: HashMap<Address, Account>,
+    contracts: HashMap<B256, Bytecode>,
+}
+
+impl<'a, DB> LaneOverlay<'a, DB> {
+    pub fn new(state: &'a DB, coinbase: Address) -> Self {
+        Self {
+            state,
+            coinbase,
+            accounts: HashMap::default(),
+            contracts: HashMap::default(),
+        }
+    }
+
+    /// Layers one transaction's speculative writes over the ones before it.
+    pub fn apply(&mut self, changes: &State) {
+        for (address, account) in changes {
+            if !account.is_touched() || *address == self.coinbase {
+                continue;
+            }
+            if let Some(code) = account.info.code.as_ref().filter(|code| !code.is_empty()) {
+                self.contracts.insert(account.info.code_hash, code.clone());
+            }
+            match self.accounts.get_mut(address) {
+                Some(earlier) => coalesce(earlier, account.clone()),
+                None => {
+                    self.accounts.insert(*address, account.clone());
+                }
+            }
+        }
+    }
+}
+
+impl<DB: DatabaseRef> DatabaseRef for LaneOverlay<'_, DB> {
+    type Error = DB::Error;
+
+    fn basic(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        match self.accounts.get(&address) {
+            Some(account) if account.is_selfdestructed() => Ok(None),
+            Some(account) => Ok(Some(account.info.clone())),
+            None => self.state.basic(address),
+        }
+    }
+
+    fn code_by_hash(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        match self.contracts.get(&code_hash) {
+            Some(code) => Ok(code.clone()),
+            None => self.state.code_by_hash(code_hash),
+        }
+    }
+
+    fn storage(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        if let Some(account) = self.accounts.get(&address) {
+            if let Some(slot) = account.storage.get(&index) {
+                return Ok(slot.present_value);
+            }
+            if account.is_selfdestructed() || account.is_created() {
+                return Ok(U256::ZERO);
+            }
+        }
+        self.state.storage(address, index)
+    }
+
+    fn block_hash(&self, number: U256) -> Result<B256, Self::Error> {
+        self.state.block_hash(number)
+    }
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DispatchStats {
+    pub blocks: u64,
+    pub transactions: u64,
+    pub lanes: u64,
+    /// Transactions that executed behind an earlier one in their lane.
+    pub grouped: u64,
+    pub largest: u64,
+}
+
+impl DispatchStats {
+    pub fn of(lanes: &Lanes) -> Self {
+        let transactions = lanes.lane_of.len() as u64;
+        Self {
+            blocks: 1,
+            transactions,
+            lanes: lanes.lanes.len() as u64,
+            grouped: transactions - lanes.lanes.len() as u64,
+            largest: lanes.lanes.iter().map(|lane| lane.len() as u64).max().unwrap_or(0),
+        }
+    }
+}
+
+impl std::ops::AddAssign for DispatchStats {
+    fn add_assign(&mut self, other: Self) {
+        self.blocks += other.blocks;
+        self.transactions += other.transactions;
+        self.lanes += other.lanes;
+        self.grouped += other.grouped;
+        self.largest = self.largest.max(other.largest);
+    }
+}
+
+impl fmt::Display for DispatchStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Dispatch:           {} txs in {} lanes over {} blocks, {} behind a lane-mate (largest lane {})",
+            self.transactions, self.lanes, self.blocks, self.grouped, self.largest
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SpeculationStats {
+    pub mode: SpeculationDepth,
+    /// The window the next block starts with.
+    pub depth: usize,
+    pub abort_rate: f64,
+    pub windows: u64,
+    pub narrowed: u64,
+    pub widened: u64,
+}
+
+impl fmt::Display for SpeculationStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Speculation Depth:  {} now at {} ({:.2}% rolling abort rate), {} windows, narrowed {}x, widened {}x",
+            self.mode,
+            self.depth,
+            self.abort_rate * 100.0,
+            self.windows,
+            self.narrowed,
+            self.widened
+        )
+    }
+}
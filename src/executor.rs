@@ -0,0 +1,207 @@
+/*
+ * FLUX ENGINE - EXECUTOR
+ * Drives revm's EVMImpl directly (instead of the EVM facade) so the engine decides
+ * which precompile set every transaction runs against.
+ */
+
+use revm::inspectors::NoOpInspector;
+use revm::precompile::{self, Precompile, Precompiles, StandardPrecompileFn};
+use revm::primitives::{
+    specification::*, Address, EVMError, Env, ExecutionResult, ResultAndState, SpecId, State, TransactTo,
+};
+use std::collections::HashSet;
+use std::ops::AddAssign;
+use revm::{is_precompile, Database, DatabaseCommit, EVMImpl, Inspector, Transact};
+
+/// Signature of a user-supplied precompile: `(input, gas_limit) -> Ok((gas_used, output))`.
+pub type PrecompileFn = StandardPrecompileFn;
+
+// --- PRECOMPILE REGISTRY ---
+
+/// The standard precompile set for a spec (ecrecover, sha256, ripemd160, identity, modexp,
+/// the BN254 add/mul/pairing trio, blake2f and, from Cancun, KZG point evaluation) plus any
+/// chain-specific extensions registered on the engine.
+#[derive(Debug, Clone, Default)]
+pub struct PrecompileRegistry {
+    custom: Vec<(Address, PrecompileFn)>,
+}
+
+impl PrecompileRegistry {
+    pub fn register(&mut self, address: Address, fun: PrecompileFn) -> Result<(), String> {
+        if !is_precompile(address, u16::MAX as usize) {
+            return Err(format!("{} is outside the precompile address range (0x..0001-0x..ffff)", address));
+        }
+        if self.custom.iter().any(|(existing, _)| *existing == address) {
+            return Err(format!("precompile {} is already registered", address));
+        }
+        self.custom.push((address, fun));
+        Ok(())
+    }
+
+    /// Builds the set a transaction under `spec` sees. Custom entries override standard ones.
+    ///
+    /// revm routes a call to the precompile table purely by `1 <= address <= len`, so the final
+    /// set has to be a contiguous range starting at 0x01; a gap would make revm panic on a call
+    /// into it. We reject that here instead.
+    pub fn build(&self, spec: SpecId) -> Result<Precompiles, String> {
+        let mut precompiles = Precompiles::new(precompile::SpecId::from_spec_id(spec)).clone();
+        for (address, fun) in &self.custom {
+            precompiles
+                .fun
+                .insert(address.into_array(), Precompile::Standard(*fun));
+        }
+
+        if let Some(gap) = (1..=precompiles.len() as u16)
+            .find(|&index| !precompiles.contains(&precompile_address(index)))
+        {
+            return Err(format!(
+                "precompile set for {:?} has a gap at address {:#x}; custom precompiles must extend the standard range contiguously",
+                spec, gap
+            ));
+        }
+
+        Ok(precompiles)
+    }
+}
+
+fn precompile_address(index: u16) -> precompile::Address {
+    let mut address = [0u8; 20];
+    address[18..].copy_from_slice(&index.to_be_bytes());
+    address
+}
+
+// --- EIP-2929 ACCESS SETS ---
+
+/// Accounts and storage slots one transaction loaded, split by whether the first touch was cold
+/// (EIP-2929 charges 2600/2100 gas) or already warm (tx sender/target, coinbase, precompiles, or
+/// pre-declared in the EIP-2930 access list). Cold loads are exactly the reads a prefetcher can
+/// save the executor from taking on the critical path.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AccessSet {
+    pub warm_accounts: usize,
+    pub cold_accounts: usize,
+    pub warm_slots: usize,
+    pub cold_slots: usize,
+}
+
+impl AccessSet {
+    fn from_state(env: &Env, state: &State, precompiles: &Precompiles) -> Self {
+        let mut warm_accounts: HashSet<Address> = env.tx.access_list.iter().map(|(a, _)| *a).collect();
+        warm_accounts.insert(env.tx.caller);
+        warm_accounts.insert(env.block.coinbase);
+        if let TransactTo::Call(to) = env.tx.transact_to {
+            warm_accounts.insert(to);
+        }
+        let warm_slots: HashSet<_> = env
+            .tx
+            .access_list
+            .iter()
+            .flat_map(|(a, slots)| slots.iter().map(move |s| (*a, *s)))
+            .collect();
+
+        let mut set = AccessSet::default();
+        for (address, account) in state {
+            if precompiles.contains(&address.into_array()) {
+                continue;
+            }
+            if warm_accounts.contains(address) {
+                set.warm_accounts += 1;
+            } else {
+                set.cold_accounts += 1;
+            }
+            for slot in account.storage.keys() {
+                if warm_slots.contains(&(*address, *slot)) {
+                    set.warm_slots += 1;
+                } else {
+                    set.cold_slots += 1;
+                }
+            }
+        }
+        set
+    }
+}
+
+impl AddAssign for AccessSet {
+    fn add_assign(&mut self, rhs: Self) {
+        self.warm_accounts += rhs.warm_accounts;
+        self.cold_accounts += rhs.cold_accounts;
+        self.warm_slots += rhs.warm_slots;
+        self.cold_slots += rhs.cold_slots;
+    }
+}
+
+// --- TRANSACTION EXECUTION ---
+
+/// Executes `env.tx` against `db` and commits the resulting state diff into it.
+/// Equivalent to `EVM::transact_commit`, but with an explicit precompile set, and it also
+/// reports the transaction's warm/cold access set.
+pub fn transact_commit<DB: Database + DatabaseCommit>(
+    db: &mut DB,
+    env: &mut Env,
+    precompiles: &Precompiles,
+) -> Result<(ExecutionResult, AccessSet), EVMError<DB::Error>> {
+    let executed = transact(db, env, precompiles)?;
+    Ok(commit(db, env, precompiles, executed))
+}
+
+/// Second half of `transact_commit`, for callers that need to look at `db` between executing
+/// and applying (e.g. to snapshot pre-state).
+pub fn commit<DB: DatabaseCommit>(
+    db: &mut DB,
+    env: &Env,
+    precompiles: &Precompiles,
+    executed: ResultAndState,
+) -> (ExecutionResult, AccessSet) {
+    let ResultAndState { result, state } = executed;
+    let access = AccessSet::from_state(env, &state, precompiles);
+    db.commit(state);
+    (result, access)
+}
+
+/// Executes `env.tx` against `db` without committing.
+pub fn transact<DB: Database>(
+    db: &mut DB,
+    env: &mut Env,
+    precompiles: &Precompiles,
+) -> Result<ResultAndState, EVMError<DB::Error>> {
+    dispatch::<DB, false>(db, env, precompiles, &mut NoOpInspector)
+}
+
+/// Executes `env.tx` with `inspector` hooked into every step (tracing, forensics). Slow path only.
+pub fn transact_inspect<DB: Database>(
+    db: &mut DB,
+    env: &mut Env,
+    precompiles: &Precompiles,
+    inspector: &mut dyn Inspector<DB>,
+) -> Result<ResultAndState, EVMError<DB::Error>> {
+    dispatch::<DB, true>(db, env, precompiles, inspector)
+}
+
+fn dispatch<DB: Database, const INSPECT: bool>(
+    db: &mut DB,
+    env: &mut Env,
+    precompiles: &Precompiles,
+    inspector: &mut dyn Inspector<DB>,
+) -> Result<ResultAndState, EVMError<DB::Error>> {
+    macro_rules! run {
+        ($spec:ident) => {
+            EVMImpl::<$spec, DB, INSPECT>::new(db, env, inspector, precompiles.clone()).transact()
+        };
+    }
+
+    match env.cfg.spec_id {
+        SpecId::FRONTIER | SpecId::FRONTIER_THAWING => run!(FrontierSpec),
+        SpecId::HOMESTEAD | SpecId::DAO_FORK => run!(HomesteadSpec),
+        SpecId::TANGERINE => run!(TangerineSpec),
+        SpecId::SPURIOUS_DRAGON => run!(SpuriousDragonSpec),
+        SpecId::BYZANTIUM => run!(ByzantiumSpec),
+        SpecId::PETERSBURG | SpecId::CONSTANTINOPLE => run!(PetersburgSpec),
+        SpecId::ISTANBUL | SpecId::MUIR_GLACIER => run!(IstanbulSpec),
+        SpecId::BERLIN => run!(BerlinSpec),
+        SpecId::LONDON | SpecId::ARROW_GLACIER | SpecId::GRAY_GLACIER => run!(LondonSpec),
+        SpecId::MERGE => run!(MergeSpec),
+        SpecId::SHANGHAI => run!(ShanghaiSpec),
+        SpecId::CANCUN => run!(CancunSpec),
+        SpecId::LATEST => run!(LatestSpec),
+    }
+}
@@ -0,0 +1,431 @@
+/*
+ * FLUX ENGINE - TRANSACTION FILTERS
+ * `--filter 'to == 0x.. || gas > 1_000_000'` style selectors for partial replays.
+ *
+ * Grammar:
+ *   expr   := and ('||' and)*
+ *   and    := unary ('&&' unary)*
+ *   unary  := '!' unary | '(' expr ')' | field op literal
+ *   field  := to | from | touches | gas | value | id | data_len | selector | blobs
+ *   op     := == | != | < | <= | > | >=
+ *
+ * `touches == 0x..` matches a tx that calls the address or names it in its access list.
+ * `--only-contract` and `--only-sender` are shorthands for `touches ==` and `from ==`.
+ */
+
+use crate::FluxTransaction;
+use revm::primitives::{Address, U256};
+use std::fmt;
+use std::str::FromStr;
+
+// --- AST ---
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    To,
+    From,
+    Touches,
+    Gas,
+    Value,
+    Id,
+    DataLen,
+    Selector,
+    Blobs,
+}
+
+impl Field {
+    fn is_address(self) -> bool {
+        matches!(self, Field::To | Field::From | Field::Touches)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Literal {
+    Address(Address),
+    Number(U256),
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Cmp(Field, Op, Literal),
+}
+
+impl Expr {
+    fn reads_sender(&self) -> bool {
+        match self {
+            Expr::Or(a, b) | Expr::And(a, b) => a.reads_sender() || b.reads_sender(),
+            Expr::Not(e) => e.reads_sender(),
+            Expr::Cmp(field, _, _) => *field == Field::From,
+        }
+    }
+
+    fn eval(&self, tx: &FluxTransaction) -> bool {
+        match self {
+            Expr::Or(a, b) => a.eval(tx) || b.eval(tx),
+            Expr::And(a, b) => a.eval(tx) && b.eval(tx),
+            Expr::Not(e) => !e.eval(tx),
+            Expr::Cmp(field, op, Literal::Address(want)) => {
+                let found = match field {
+                    Field::To => tx.to == *want,
+                    Field::Touches => {
+                        (!tx.create && tx.to == *want) || tx.access_list.iter().any(|(address, _)| address == want)
+                    }
+                    _ => tx.caller == *want,
+                };
+                // The parser only allows == and != on addresses.
+                found == (*op == Op::Eq)
+            }
+            Expr::Cmp(field, op, Literal::Number(want)) => {
+                let have = match field {
+                    Field::Gas => U256::from(tx.gas_limit),
+                    Field::Value => tx.value,
+                    Field::Id => U256::from(tx.id),
+                    Field::DataLen => U256::from(tx.data.len()),
+                    Field::Blobs => U256::from(tx.blob_hashes.len()),
+                    Field::Selector => match tx.data.get(..4) {
+                        Some(sel) => U256::from(u32::from_be_bytes([sel[0], sel[1], sel[2], sel[3]])),
+                        // No selector: only `selector != ..` can match.
+                        None => return *op == Op::Ne,
+                    },
+                    Field::To | Field::From | Field::Touches => unreachable!("address fields take address literals"),
+                };
+                match op {
+                    Op::Eq => have == *want,
+                    Op::Ne => have != *want,
+                    Op::Lt => have < *want,
+                    Op::Le => have <= *want,
+                    Op::Gt => have > *want,
+                    Op::Ge => have >= *want,
+                }
+            }
+        }
+    }
+}
+
+// --- PUBLIC FILTER ---
+
+/// A parsed selector. Keeps its source text so reports can echo exactly what was replayed.
+#[derive(Debug, Clone)]
+pub struct TxFilter {
+    source: String,
+    expr: Expr,
+}
+
+impl TxFilter {
+    pub fn matches(&self, tx: &FluxTransaction) -> bool {
+        self.expr.eval(tx)
+    }
+
+    /// Whether it compares senders, which signed txs only have once recovered.
+    pub fn reads_sender(&self) -> bool {
+        self.expr.reads_sender()
+    }
+
+    /// Marks the txs that do not match as skipped and returns how many it marked. They stay in
+    /// the block: the kept txs read the state they leave behind, nonces included.
+    pub fn apply(&self, txs: &mut [FluxTransaction]) -> usize {
+        let mut skipped = 0;
+        for tx in txs.iter_mut().filter(|tx| !self.matches(tx)) {
+            tx.skipped = true;
+            skipped += 1;
+        }
+        skipped
+    }
+}
+
+impl fmt::Display for TxFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl FromStr for TxFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens: &tokens, pos: 0 };
+        let expr = parser.expr()?;
+        if let Some(tok) = parser.peek() {
+            return Err(format!("filter: unexpected {:?} after end of expression", tok));
+        }
+        Ok(Self { source: s.trim().to_string(), expr })
+    }
+}
+
+// --- LEXER ---
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Literal(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let (token, width) = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('=', Some('=')) => (Token::Op(Op::Eq), 2),
+            ('!', Some('=')) => (Token::Op(Op::Ne), 2),
+            ('<', Some('=')) => (Token::Op(Op::Le), 2),
+            ('>', Some('=')) => (Token::Op(Op::Ge), 2),
+            ('<', _) => (Token::Op(Op::Lt), 1),
+            ('>', _) => (Token::Op(Op::Gt), 1),
+            ('!', _) => (Token::Not, 1),
+            ('(', _) => (Token::LParen, 1),
+            (')', _) => (Token::RParen, 1),
+            (c, _) if c.is_ascii_alphanumeric() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                tokens.push(if c.is_ascii_digit() { Token::Literal(word) } else { Token::Ident(word) });
+                continue;
+            }
+            (c, _) => return Err(format!("filter: unexpected character {:?} at offset {}", c, i)),
+        };
+        tokens.push(token);
+        i += width;
+    }
+
+    Ok(tokens)
+}
+
+// --- PARSER ---
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let tok = self.tokens.get(self.pos);
+        self.pos += 1;
+        tok
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            lhs = Expr::And(Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next().cloned() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::LParen) => {
+                let inner = self.expr()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(inner),
+                    other => Err(format!("filter: expected ')', found {:?}", other)),
+                }
+            }
+            Some(Token::Ident(name)) => self.comparison(&name),
+            other => Err(format!("filter: expected a field, '!' or '(', found {:?}", other)),
+        }
+    }
+
+    fn comparison(&mut self, name: &str) -> Result<Expr, String> {
+        let field = match name {
+            "to" => Field::To,
+            "from" | "sender" | "caller" => Field::From,
+            "touches" => Field::Touches,
+            "gas" => Field::Gas,
+            "value" => Field::Value,
+            "id" => Field::Id,
+            "data_len" => Field::DataLen,
+            "selector" => Field::Selector,
+            "blobs" => Field::Blobs,
+            other => return Err(format!("filter: unknown field {:?}", other)),
+        };
+        let op = match self.next() {
+            Some(Token::Op(op)) => *op,
+            other => return Err(format!("filter: expected comparison after {:?}, found {:?}", name, other)),
+        };
+        let raw = match self.next() {
+            Some(Token::Literal(raw)) => raw.clone(),
+            other => return Err(format!("filter: expected a literal after {:?}, found {:?}", name, other)),
+        };
+
+        let literal = if field.is_address() {
+            if !matches!(op, Op::Eq | Op::Ne) {
+                return Err(format!("filter: {:?} only supports == and !=", name));
+            }
+            Literal::Address(raw.parse().map_err(|_| format!("filter: invalid address {:?}", raw))?)
+        } else {
+            Literal::Number(parse_number(&raw)?)
+        };
+
+        Ok(Expr::Cmp(field, op, literal))
+    }
+}
+
+fn parse_number(raw: &str) -> Result<U256, String> {
+    let digits = raw.replace('_', "");
+    let parsed = match digits.strip_prefix("0x") {
+        Some(hex) => U256::from_str_radix(hex, 16),
+        None => U256::from_str_radix(&digits, 10),
+    };
+    parsed.map_err(|_| format!("filter: invalid number {:?}", raw))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "0x00000000000000000000000000000000000000aa";
+    const B: &str = "0x00000000000000000000000000000000000000bb";
+
+    fn tx(to: &str, gas_limit: u64, data: &[u8]) -> FluxTransaction {
+        FluxTransaction {
+            caller: B.parse().unwrap(),
+            to: to.parse().unwrap(),
+            gas_limit,
+            data: data.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    fn matches(filter: &str, tx: &FluxTransaction) -> bool {
+        filter.parse::<TxFilter>().unwrap().matches(tx)
+    }
+
+    fn error(filter: &str) -> String {
+        filter.parse::<TxFilter>().unwrap_err()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let small = tx(B, 21_000, &[]);
+        // `a || b && c` is `a || (b && c)`, not `(a || b) && c`.
+        let filter = format!("to == {} || to == {} && gas > 100_000", B, A);
+        assert!(matches(&filter, &small));
+        assert!(!matches(&format!("(to == {} || to == {}) && gas > 100_000", B, A), &small));
+        assert!(matches(&format!("gas > 100_000 && to == {} || to == {}", A, B), &small));
+    }
+
+    #[test]
+    fn not_applies_to_the_nearest_operand() {
+        let small = tx(A, 21_000, &[]);
+        assert!(!matches("!gas < 30_000 || gas > 1_000_000", &small));
+        assert!(matches("!(gas < 30_000 && gas > 1_000_000)", &small));
+        assert!(matches("!!gas == 21000", &small));
+        assert!(matches(&format!("!(to == {}) || !(gas != 21_000)", A), &small));
+    }
+
+    #[test]
+    fn evaluates_each_field() {
+        let mut call = tx(A, 50_000, &[0xa9, 0x05, 0x9c, 0xbb, 0x01]);
+        call.value = U256::from(7);
+        call.id = 3;
+        call.access_list = vec![(B.parse().unwrap(), Vec::new())];
+        for filter in [
+            format!("to == {}", A),
+            format!("from == {}", B),
+            format!("sender != {}", A),
+            format!("touches == {}", A),
+            format!("touches == {}", B),
+            "selector == 0xa9059cbb".to_string(),
+            "data_len == 5 && value >= 7 && id == 3 && blobs == 0".to_string(),
+            "gas <= 0xc350 && gas >= 50_000".to_string(),
+        ] {
+            assert!(matches(&filter, &call), "{}", filter);
+        }
+
+        // A creation's `to` is zero, and only its access list counts as touching.
+        let mut create = tx(A, 50_000, &[0x60]);
+        create.create = true;
+        assert!(!matches(&format!("touches == {}", A), &create));
+        // Calldata too short for a selector only matches `!=`.
+        assert!(!matches("selector == 0x60000000", &create));
+        assert!(matches("selector != 0x60000000", &create));
+    }
+
+    #[test]
+    fn keeps_the_source_and_marks_the_rest_skipped() {
+        let filter: TxFilter = "  gas   >=  30000  ".parse().unwrap();
+        assert_eq!(filter.to_string(), "gas   >=  30000");
+        assert!(!filter.reads_sender());
+        assert!(format!("!(from == {})", A).parse::<TxFilter>().unwrap().reads_sender());
+
+        let mut txs = vec![tx(A, 21_000, &[]), tx(A, 30_000, &[]), tx(A, 10, &[])];
+        assert_eq!(filter.apply(&mut txs), 2);
+        assert_eq!(txs.iter().map(|tx| tx.skipped).collect::<Vec<_>>(), [true, false, true]);
+    }
+
+    #[test]
+    fn rejects_quoted_literals() {
+        assert_eq!(error(&format!("to == \"{}\"", A)), "filter: unexpected character '\"' at offset 6");
+        assert_eq!(error("gas > '5'"), "filter: unexpected character '\\'' at offset 6");
+    }
+
+    #[test]
+    fn reports_malformed_input() {
+        for (filter, message) in [
+            ("", "filter: expected a field, '!' or '(', found None"),
+            ("gas", "filter: expected comparison after \"gas\", found None"),
+            ("gas >", "filter: expected a literal after \"gas\", found None"),
+            ("gas > 5 &&", "filter: expected a field, '!' or '(', found None"),
+            ("(gas > 5", "filter: expected ')', found None"),
+            ("gas > 5)", "filter: unexpected RParen after end of expression"),
+            ("gas > 5 gas < 9", "filter: unexpected Ident(\"gas\") after end of expression"),
+            ("gas = 5", "filter: unexpected character '=' at offset 4"),
+            ("gas > 5 & gas < 9", "filter: unexpected character '&' at offset 8"),
+            ("nonce > 1", "filter: unknown field \"nonce\""),
+            ("to > 0x01", "filter: \"to\" only supports == and !="),
+            ("to == 0x1234", "filter: invalid address \"0x1234\""),
+            ("gas > 12ab", "filter: invalid number \"12ab\""),
+            ("gas > 0xzz", "filter: invalid number \"0xzz\""),
+        ] {
+            assert_eq!(error(filter), message, "{:?}", filter);
+        }
+    }
+}
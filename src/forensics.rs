@@ -0,0 +1,347 @@
+/*
+ * FLUX ENGINE - FORENSICS
+ * Self-contained captures of transactions that blew their execution budget or kept retrying,
+ * replayable standalone with `flux trace --from-capture <file>`.
+ */
+
+use crate::block::BlockHeader;
+use crate::chain::{self, ChainSpec};
+use crate::executor;
+use crate::FluxTransaction;
+use revm::db::{CacheDB, DatabaseRef, EmptyDB};
+use revm::interpreter::{opcode, InstructionResult, Interpreter};
+use revm::precompile::Precompiles;
+use revm::primitives::{AccountInfo, Address, Bytecode, Bytes, KECCAK_EMPTY, U256};
+use revm::{Database, EVMData, Inspector};
+use std::fmt::Debug;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Opcode steps kept per capture. A runaway loop would otherwise write gigabytes of trace.
+const MAX_TRACE_STEPS: usize = 200_000;
+
+#[derive(Debug, Clone)]
+pub struct ForensicsConfig {
+    /// Where capture files are written (created on first capture).
+    pub dir: PathBuf,
+    /// Wall-clock budget for one execution of one transaction.
+    pub tx_budget: Duration,
+    /// Capture once a transaction has been re-executed more than this many times.
+    pub max_retries: usize,
+}
+
+// --- CAPTURE ---
+
+#[derive(Debug, Clone)]
+pub struct PreStateAccount {
+    pub address: Address,
+    /// `None` if the account did not exist before the transaction.
+    pub info: Option<AccountInfo>,
+    pub storage: Vec<(U256, U256)>,
+}
+
+/// Everything needed to re-run one transaction in isolation: its input, the slice of pre-state
+/// it touched, what happened, and an opcode trace.
+#[derive(Debug, Clone)]
+pub struct Capture {
+    pub reason: String,
+    /// The capturing chain, pinned to the spec the transaction ran under.
+    pub chain: ChainSpec,
+    pub header: BlockHeader,
+    pub tx: FluxTransaction,
+    pub pre_state: Vec<PreStateAccount>,
+    pub outcome: String,
+    pub trace: Vec<String>,
+}
+
+impl Capture {
+    /// Re-executes `tx` with tracing on top of `pre` (the state it originally ran against) and
+    /// snapshots every account and slot the execution touched.
+    pub fn record<DB>(
+        reason: String,
+        chain: &ChainSpec,
+        header: &BlockHeader,
+        tx: &FluxTransaction,
+        pre: &DB,
+        precompiles: &Precompiles,
+    ) -> Self
+    where
+        DB: DatabaseRef,
+        DB::Error: Debug,
+    {
+        let chain = ChainSpec::fixed(chain.chain_id, chain.spec_at(header));
+        let mut overlay = CacheDB::new(pre);
+        let mut env = tx.env(&chain.block_env(header));
+        let mut tracer = OpcodeTracer::default();
+        let outcome = executor::transact_inspect(&mut overlay, &mut env, precompiles, &mut tracer);
+
+        let touched: Vec<(Address, Vec<U256>)> = match &outcome {
+            Ok(res) => res
+                .state
+                .iter()
+                .map(|(address, account)| (*address, account.storage.keys().copied().collect()))
+                .collect(),
+            // Rejected before execution: sender and target are all revm looked at.
+            Err(_) => vec![(tx.caller, vec![]), (tx.to, vec![])],
+        };
+
+        let pre_state = touched
+            .into_iter()
+            .map(|(address, slots)| {
+                let info = pre.basic(address).ok().flatten().map(|mut info| {
+                    if info.code.is_none() && info.code_hash != KECCAK_EMPTY {
+                        info.code = pre.code_by_hash(info.code_hash).ok();
+                    }
+                    info
+                });
+                let storage = slots
+                    .into_iter()
+                    .map(|slot| (slot, pre.storage(address, slot).unwrap_or_default()))
+                    .collect();
+                PreStateAccount { address, info, storage }
+            })
+            .collect();
+
+        Self {
+            reason,
+            chain,
+            header: header.clone(),
+            tx: tx.clone(),
+            pre_state,
+            outcome: format!("{:?}", outcome.map(|res| res.result)),
+            trace: tracer.steps,
+        }
+    }
+
+    /// Re-runs the captured transaction against its captured pre-state only.
+    /// Returns the outcome (same rendering as `self.outcome`) and a fresh trace.
+    pub fn replay(&self, precompiles: &Precompiles) -> (String, Vec<String>) {
+        let mut db = CacheDB::new(EmptyDB::default());
+        for account in &self.pre_state {
+            if let Some(info) = &account.info {
+                db.insert_account_info(account.address, info.clone());
+            }
+            for (slot, value) in &account.storage {
+                db.insert_account_storage(account.address, *slot, *value)
+                    .expect("EmptyDB is infallible");
+            }
+        }
+
+        let mut env = self.tx.env(&self.chain.block_env(&self.header));
+        let mut tracer = OpcodeTracer::default();
+        let outcome = executor::transact_inspect(&mut db, &mut env, precompiles, &mut tracer);
+        (format!("{:?}", outcome.map(|res| res.result)), tracer.steps)
+    }
+
+    /// Writes `tx-<id>[-n].capture` (+ matching `.trace`) into `dir` and returns the capture path.
+    /// A transaction can be captured more than once (speculative, then serial); nothing is overwritten.
+    pub fn write(&self, dir: &Path) -> io::Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+        let mut path = dir.join(format!("tx-{}.capture", self.tx.id));
+        let mut n = 1;
+        while path.exists() {
+            path = dir.join(format!("tx-{}-{}.capture", self.tx.id, n));
+            n += 1;
+        }
+
+        let mut out = io::BufWriter::new(fs::File::create(&path)?);
+        let tx = &self.tx;
+        writeln!(out, "# flux forensic capture v1")?;
+        writeln!(out, "reason: {}", self.reason)?;
+        writeln!(out, "chain.id: {}", self.chain.chain_id)?;
+        writeln!(out, "chain.spec: {:?}", self.chain.spec_at(&self.header))?;
+        writeln!(out, "block.number: {}", self.header.number)?;
+        writeln!(out, "block.timestamp: {}", self.header.timestamp)?;
+        writeln!(out, "block.coinbase: {}", self.header.coinbase)?;
+        writeln!(out, "block.gas_limit: {}", self.header.gas_limit)?;
+        writeln!(out, "block.base_fee: {}", self.header.base_fee_per_gas)?;
+        writeln!(out, "block.excess_blob_gas: {}", self.header.excess_blob_gas)?;
+        writeln!(out, "tx.id: {}", tx.id)?;
+        writeln!(out, "tx.caller: {}", tx.caller)?;
+        writeln!(out, "tx.to: {}", tx.to)?;
+        if tx.create {
+            writeln!(out, "tx.create: true")?;
+        }
+        writeln!(out, "tx.value: {:#x}", tx.value)?;
+        writeln!(out, "tx.gas_limit: {}", tx.gas_limit)?;
+        if let Some(nonce) = tx.nonce {
+            writeln!(out, "tx.nonce: {}", nonce)?;
+        }
+        writeln!(out, "tx.data: 0x{}", hex::encode(&tx.data))?;
+        writeln!(out, "tx.max_fee: {:#x}", tx.max_fee_per_gas)?;
+        if let Some(tip) = tx.max_priority_fee_per_gas {
+            writeln!(out, "tx.max_priority_fee: {:#x}", tip)?;
+        }
+        if let Some(cap) = tx.max_fee_per_blob_gas {
+            writeln!(out, "tx.max_fee_per_blob_gas: {:#x}", cap)?;
+        }
+        for hash in &tx.blob_hashes {
+            writeln!(out, "tx.blob_hash: {}", hash)?;
+        }
+        for (address, slots) in &tx.access_list {
+            let slots: Vec<String> = slots.iter().map(|s| format!("{:#x}", s)).collect();
+            writeln!(out, "tx.access: {} {}", address, slots.join(","))?;
+        }
+        for account in &self.pre_state {
+            match &account.info {
+                Some(info) => {
+                    let code = info.code.as_ref().map(|c| c.original_bytes()).unwrap_or_default();
+                    writeln!(
+                        out,
+                        "account: {} {:#x} {} 0x{}",
+                        account.address,
+                        info.balance,
+                        info.nonce,
+                        hex::encode(&code)
+                    )?;
+                }
+                None => writeln!(out, "absent: {}", account.address)?,
+            }
+            for (slot, value) in &account.storage {
+                writeln!(out, "storage: {} {:#x} {:#x}", account.address, slot, value)?;
+            }
+        }
+        writeln!(out, "outcome: {}", self.outcome)?;
+        out.flush()?;
+
+        fs::write(path.with_extension("trace"), self.trace.join("\n"))?;
+        Ok(path)
+    }
+
+    /// Parses a `.capture` file. The trace is not loaded; `replay` regenerates it.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut capture = Capture {
+            reason: String::new(),
+            chain: ChainSpec::default(),
+            header: BlockHeader::default(),
+            tx: FluxTransaction::default(),
+            pre_state: vec![],
+            outcome: String::new(),
+            trace: vec![],
+        };
+
+        for (lineno, line) in text.lines().enumerate() {
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            let bad = |what: &str| format!("{}:{}: invalid {}", path.display(), lineno + 1, what);
+            let (key, value) = line.split_once(": ").ok_or_else(|| bad("line"))?;
+            let fields: Vec<&str> = value.split(' ').collect();
+
+            match key {
+                "reason" => capture.reason = value.to_string(),
+                "outcome" => capture.outcome = value.to_string(),
+                "chain.id" => capture.chain.chain_id = value.parse().map_err(|_| bad(key))?,
+                "chain.spec" => {
+                    let spec = chain::parse_spec(value).ok_or_else(|| bad(key))?;
+                    capture.chain = ChainSpec::fixed(capture.chain.chain_id, spec);
+                }
+                "block.number" => capture.header.number = value.parse().map_err(|_| bad(key))?,
+                "block.timestamp" => capture.header.timestamp = value.parse().map_err(|_| bad(key))?,
+                "block.coinbase" => capture.header.coinbase = value.parse().map_err(|_| bad(key))?,
+                "block.gas_limit" => capture.header.gas_limit = value.parse().map_err(|_| bad(key))?,
+                "block.base_fee" => capture.header.base_fee_per_gas = value.parse().map_err(|_| bad(key))?,
+                "block.excess_blob_gas" => {
+                    capture.header.excess_blob_gas = value.parse().map_err(|_| bad(key))?
+                }
+                "tx.id" => capture.tx.id = value.parse().map_err(|_| bad(key))?,
+                "tx.caller" => capture.tx.caller = value.parse().map_err(|_| bad(key))?,
+                "tx.to" => capture.tx.to = value.parse().map_err(|_| bad(key))?,
+                "tx.create" => capture.tx.create = value.parse().map_err(|_| bad(key))?,
+                "tx.value" => capture.tx.value = value.parse().map_err(|_| bad(key))?,
+                "tx.gas_limit" => capture.tx.gas_limit = value.parse().map_err(|_| bad(key))?,
+                "tx.nonce" => capture.tx.nonce = Some(value.parse().map_err(|_| bad(key))?),
+                "tx.data" => capture.tx.data = decode_hex(value).ok_or_else(|| bad(key))?,
+                "tx.max_fee" => capture.tx.max_fee_per_gas = value.parse().map_err(|_| bad(key))?,
+                "tx.max_priority_fee" => {
+                    capture.tx.max_priority_fee_per_gas = Some(value.parse().map_err(|_| bad(key))?)
+                }
+                "tx.max_fee_per_blob_gas" => {
+                    capture.tx.max_fee_per_blob_gas = Some(value.parse().map_err(|_| bad(key))?)
+                }
+                "tx.blob_hash" => capture.tx.blob_hashes.push(value.parse().map_err(|_| bad(key))?),
+                "tx.access" => {
+                    let address = fields[0].parse().map_err(|_| bad(key))?;
+                    let slots = fields
+                        .get(1)
+                        .filter(|s| !s.is_empty())
+                        .map(|s| s.split(',').map(|slot| slot.parse()).collect::<Result<Vec<U256>, _>>())
+                        .transpose()
+                        .map_err(|_| bad(key))?
+                        .unwrap_or_default();
+                    capture.tx.access_list.push((address, slots));
+                }
+                "account" if fields.len() == 4 => {
+                    let code = decode_hex(fields[3]).ok_or_else(|| bad("account code"))?;
+                    let code = (!code.is_empty()).then(|| Bytecode::new_raw(Bytes::from(code)));
+                    let mut info = AccountInfo {
+                        balance: fields[1].parse().map_err(|_| bad("account balance"))?,
+                        nonce: fields[2].parse().map_err(|_| bad("account nonce"))?,
+                        ..Default::default()
+                    };
+                    if let Some(code) = code {
+                        info.code_hash = code.hash_slow();
+                        info.code = Some(code);
+                    }
+                    capture.pre_state.push(PreStateAccount {
+                        address: fields[0].parse().map_err(|_| bad("account address"))?,
+                        info: Some(info),
+                        storage: vec![],
+                    });
+                }
+                "absent" => capture.pre_state.push(PreStateAccount {
+                    address: value.parse().map_err(|_| bad(key))?,
+                    info: None,
+                    storage: vec![],
+                }),
+                "storage" if fields.len() == 3 => {
+                    let address: Address = fields[0].parse().map_err(|_| bad(key))?;
+                    let slot = fields[1].parse().map_err(|_| bad(key))?;
+                    let value = fields[2].parse().map_err(|_| bad(key))?;
+                    let account = capture
+                        .pre_state
+                        .iter_mut()
+                        .find(|a| a.address == address)
+                        .ok_or_else(|| bad("storage line before its account"))?;
+                    account.storage.push((slot, value));
+                }
+                _ => return Err(bad(key)),
+            }
+        }
+
+        Ok(capture)
+    }
+}
+
+fn decode_hex(raw: &str) -> Option<Vec<u8>> {
+    hex::decode(raw.strip_prefix("0x").unwrap_or(raw)).ok()
+}
+
+// --- OPCODE TRACER ---
+
+#[derive(Debug, Default)]
+struct OpcodeTracer {
+    steps: Vec<String>,
+}
+
+impl<DB: Database> Inspector<DB> for OpcodeTracer {
+    fn step(&mut self, interp: &mut Interpreter, data: &mut EVMData<'_, DB>) -> InstructionResult {
+        if self.steps.len() < MAX_TRACE_STEPS {
+            let op = interp.current_opcode();
+            self.steps.push(format!(
+                "depth={} pc={} op={} gas={} stack={}",
+                data.journaled_state.depth(),
+                interp.program_counter(),
+                opcode::OPCODE_JUMPMAP[op as usize].unwrap_or("UNKNOWN"),
+                interp.gas().remaining(),
+                interp.stack().len(),
+            ));
+        } else if self.steps.len() == MAX_TRACE_STEPS {
+            self.steps.push(format!("... truncated after {} steps", MAX_TRACE_STEPS));
+        }
+        InstructionResult::Continue
+    }
+}
@@ -0,0 +1,350 @@
+/*
+ * FLUX ENGINE - HUGE PAGES
+ * Global allocator that backs every large allocation (the shard tables, the analysis cache, the
+ * workload itself) with explicit 2MB/1GB pages on Linux to cut TLB misses on hot state. Whatever
+ * the reserved pool can't satisfy falls back to transparent huge pages, then to ordinary pages.
+ */
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+/// Allocations at least this big are mapped directly rather than taken from the system heap.
+const LARGE: usize = 2 << 20;
+const GIB: usize = 1 << 30;
+/// Direct mappings are only page aligned (the ordinary-page fallback), so nothing stricter.
+const MAX_ALIGN: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HugePageMode {
+    #[default]
+    Off,
+    /// `MADV_HUGEPAGE`: the kernel promotes to 2MB pages when it can, no reservation needed.
+    Transparent,
+    /// `MAP_HUGETLB` from the reserved 2MB pool (`vm.nr_hugepages`).
+    Explicit2M,
+    /// `MAP_HUGETLB` from the reserved 1GB pool for allocations of 1GB and up; 2MB pages below that.
+    Explicit1G,
+}
+
+impl HugePageMode {
+    fn from_u8(raw: u8) -> Self {
+        match raw {
+            1 => HugePageMode::Transparent,
+            2 => HugePageMode::Explicit2M,
+            3 => HugePageMode::Explicit1G,
+            _ => HugePageMode::Off,
+        }
+    }
+}
+
+impl FromStr for HugePageMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(HugePageMode::Off),
+            "thp" => Ok(HugePageMode::Transparent),
+            "2m" | "2mb" => Ok(HugePageMode::Explicit2M),
+            "1g" | "1gb" => Ok(HugePageMode::Explicit1G),
+            _ => Err(format!("unknown huge page mode {:?} (expected off, thp, 2m or 1g)", s)),
+        }
+    }
+}
+
+impl fmt::Display for HugePageMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HugePageMode::Off => "off",
+            HugePageMode::Transparent => "transparent",
+            HugePageMode::Explicit2M => "explicit 2MB",
+            HugePageMode::Explicit1G => "explicit 1GB",
+        })
+    }
+}
+
+// --- DETECTION ---
+
+/// The mode as a `HugePageMode`, or `UNSET` until `configure` or the first large allocation fixes
+/// it. It never changes after that, so a large allocation is always freed the way it was made.
+static MODE: AtomicU8 = AtomicU8::new(UNSET);
+const UNSET: u8 = u8::MAX;
+
+/// The mode large allocations are made under, fixing it at off if nothing has yet.
+fn mode() -> HugePageMode {
+    match MODE.compare_exchange(UNSET, HugePageMode::Off as u8, Ordering::Relaxed, Ordering::Relaxed) {
+        Ok(_) => HugePageMode::Off,
+        Err(raw) => HugePageMode::from_u8(raw),
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HugePageSupport {
+    /// Free pages in the reserved pools; `None` where the kernel has no such pool.
+    pub free_2m: Option<u64>,
+    pub free_1g: Option<u64>,
+    /// Current `transparent_hugepage/enabled` setting (`always`, `madvise` or `never`).
+    pub thp: Option<String>,
+}
+
+impl HugePageSupport {
+    fn thp_usable(&self) -> bool {
+        matches!(self.thp.as_deref(), Some("always") | Some("madvise"))
+    }
+}
+
+pub fn detect() -> HugePageSupport {
+    let free = |size: &str| {
+        fs::read_to_string(format!("/sys/kernel/mm/hugepages/hugepages-{}/free_hugepages", size))
+            .ok()
+            .and_then(|raw| raw.trim().parse().ok())
+    };
+    // The active setting is the bracketed one: "always [madvise] never".
+    let thp = fs::read_to_string("/sys/kernel/mm/transparent_hugepage/enabled")
+        .ok()
+        .and_then(|raw| Some(raw.split_once('[')?.1.split_once(']')?.0.to_string()));
+    HugePageSupport {
+        free_2m: free("2048kB"),
+        free_1g: free("1048576kB"),
+        thp,
+    }
+}
+
+/// Selects how large allocations are backed from here on, downgrading `requested` to what this
+/// machine can do. Returns the mode in effect and, if it differs, why. Only the first call, made
+/// before any large allocation, has an effect.
+pub fn configure(requested: HugePageMode) -> (HugePageMode, Option<String>) {
+    let support = detect();
+    let fallback = |why: String| {
+        if support.thp_usable() {
+            (HugePageMode::Transparent, Some(format!("{}; falling back to transparent huge pages", why)))
+        } else {
+            (HugePageMode::Off, Some(format!("{}; transparent huge pages are off too, using ordinary pages", why)))
+        }
+    };
+    let (mode, note) = match requested {
+        HugePageMode::Explicit1G if support.free_1g.unwrap_or(0) == 0 => match support.free_2m {
+            Some(free) if free > 0 => (
+                HugePageMode::Explicit2M,
+                Some("no free 1GB huge pages reserved; using the 2MB pool".to_string()),
+            ),
+            _ => fallback("no free 1GB or 2MB huge pages reserved (see vm.nr_hugepages)".to_string()),
+        },
+        HugePageMode::Explicit2M if support.free_2m.unwrap_or(0) == 0 => {
+            fallback("no free 2MB huge pages reserved (see vm.nr_hugepages)".to_string())
+        }
+        HugePageMode::Transparent if !support.thp_usable() => (
+            HugePageMode::Off,
+            Some(format!(
+                "transparent huge pages are {}; using ordinary pages",
+                support.thp.as_deref().unwrap_or("unavailable")
+            )),
+        ),
+        mode => (mode, None),
+    };
+    match MODE.compare_exchange(UNSET, mode as u8, Ordering::Relaxed, Ordering::Relaxed) {
+        Ok(_) => (mode, note),
+        Err(raw) => {
+            let current = HugePageMode::from_u8(raw);
+            (current, Some(format!("large allocations were already made with huge pages {}", current)))
+        }
+    }
+}
+
+// --- THE ALLOCATOR ---
+
+struct Counters {
+    huge: AtomicU64,
+    huge_bytes: AtomicU64,
+    transparent: AtomicU64,
+    fallbacks: AtomicU64,
+}
+
+static COUNTERS: Counters = Counters {
+    huge: AtomicU64::new(0),
+    huge_bytes: AtomicU64::new(0),
+    transparent: AtomicU64::new(0),
+    fallbacks: AtomicU64::new(0),
+};
+
+/// Install with `#[global_allocator]`. Small allocations, and every allocation with huge pages
+/// off, go to the system allocator untouched; large ones are mapped directly otherwise.
+pub struct HugePageAlloc;
+
+fn is_large(layout: Layout) -> bool {
+    layout.size() >= LARGE && layout.align() <= MAX_ALIGN && mode() != HugePageMode::Off
+}
+
+/// Mapping length for a large allocation; a function of the size alone, so `dealloc` agrees.
+fn mapping_len(size: usize) -> usize {
+    let unit = if size >= GIB { GIB } else { LARGE };
+    size.div_ceil(unit) * unit
+}
+
+unsafe fn map_large(size: usize) -> *mut u8 {
+    let len = mapping_len(size);
+    let prot = libc::PROT_READ | libc::PROT_WRITE;
+    let anonymous = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS;
+
+    #[cfg(target_os = "linux")]
+    if matches!(mode(), HugePageMode::Explicit2M | HugePageMode::Explicit1G) {
+        let page = if mode() == HugePageMode::Explicit1G && len >= GIB {
+            libc::MAP_HUGE_1GB
+        } else {
+            libc::MAP_HUGE_2MB
+        };
+        let ptr = libc::mmap(std::ptr::null_mut(), len, prot, anonymous | libc::MAP_HUGETLB | page, -1, 0);
+        if ptr != libc::MAP_FAILED {
+            COUNTERS.huge.fetch_add(1, Ordering::Relaxed);
+            COUNTERS.huge_bytes.fetch_add(len as u64, Ordering::Relaxed);
+            return ptr as *mut u8;
+        }
+        // Pool exhausted: fall through to transparent huge pages, still better than ordinary ones.
+        COUNTERS.fallbacks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let ptr = libc::mmap(std::ptr::null_mut(), len, prot, anonymous, -1, 0);
+    if ptr == libc::MAP_FAILED {
+        return std::ptr::null_mut();
+    }
+    #[cfg(target_os = "linux")]
+    {
+        libc::madvise(ptr, len, libc::MADV_HUGEPAGE);
+        COUNTERS.transparent.fetch_add(1, Ordering::Relaxed);
+    }
+    ptr as *mut u8
+}
+
+unsafe impl GlobalAlloc for HugePageAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if is_large(layout) {
+            map_large(layout.size())
+        } else {
+            System.alloc(layout)
+        }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        // Fresh anonymous mappings are already zeroed.
+        if is_large(layout) {
+            map_large(layout.size())
+        } else {
+            System.alloc_zeroed(layout)
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if is_large(layout) {
+            libc::munmap(ptr as *mut libc::c_void, mapping_len(layout.size()));
+        } else {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        match (is_large(layout), is_large(new_layout)) {
+            (false, false) => System.realloc(ptr, layout, new_size),
+            (true, true) if mapping_len(layout.size()) == mapping_len(new_size) => ptr,
+            _ => {
+                let new_ptr = self.alloc(new_layout);
+                if !new_ptr.is_null() {
+                    std::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                    self.dealloc(ptr, layout);
+                }
+                new_ptr
+            }
+        }
+    }
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone)]
+pub struct HugePageStats {
+    pub mode: HugePageMode,
+    pub huge: u64,
+    pub huge_bytes: u64,
+    pub transparent: u64,
+    pub fallbacks: u64,
+}
+
+pub fn stats() -> HugePageStats {
+    HugePageStats {
+        mode: mode(),
+        huge: COUNTERS.huge.load(Ordering::Relaxed),
+        huge_bytes: COUNTERS.huge_bytes.load(Ordering::Relaxed),
+        transparent: COUNTERS.transparent.load(Ordering::Relaxed),
+        fallbacks: COUNTERS.fallbacks.load(Ordering::Relaxed),
+    }
+}
+
+impl fmt::Display for HugePageStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Huge Pages:         {}, {} mappings on reserved pages ({} bytes), {} on THP, {} pool misses",
+            self.mode, self.huge, self.huge_bytes, self.transparent, self.fallbacks
+        )
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    /// Whether every page of `len` bytes from `ptr` is mapped.
+    fn mapped(ptr: *mut u8, len: usize) -> bool {
+        let mut pages = vec![0u8; len.div_ceil(4096)];
+        unsafe { libc::mincore(ptr as *mut libc::c_void, len, pages.as_mut_ptr()) == 0 }
+    }
+
+    #[test]
+    fn mappings_round_up_to_the_page_size() {
+        assert_eq!(mapping_len(LARGE), LARGE);
+        assert_eq!(mapping_len(LARGE + 1), 2 * LARGE);
+        assert_eq!(mapping_len(GIB - 1), GIB);
+        assert_eq!(mapping_len(GIB), GIB);
+        assert_eq!(mapping_len(GIB + 1), 2 * GIB);
+    }
+
+    // The only test that sets the mode, which is global.
+    #[test]
+    fn alloc_realloc_and_dealloc_agree_on_sizes() {
+        let layout = |size| Layout::from_size_align(size, 8).unwrap();
+        unsafe {
+            // Off: large allocations stay with the system allocator.
+            MODE.store(HugePageMode::Off as u8, Ordering::Relaxed);
+            assert!(!is_large(layout(GIB)));
+            let ptr = HugePageAlloc.alloc(layout(LARGE));
+            ptr.write(7);
+            let ptr = HugePageAlloc.realloc(ptr, layout(LARGE), 4 * LARGE);
+            assert_eq!(ptr.read(), 7);
+            HugePageAlloc.dealloc(ptr, layout(4 * LARGE));
+
+            // On: each size is mapped whole, and a realloc within the same mapping stays put.
+            MODE.store(HugePageMode::Transparent as u8, Ordering::Relaxed);
+            let sizes = [LARGE - 1, LARGE, LARGE + 1, 2 * LARGE, LARGE - 1, GIB - LARGE, GIB + 1, 2 * GIB, GIB];
+            let mut size = sizes[0];
+            let mut ptr = HugePageAlloc.alloc(layout(size));
+            for (step, &new_size) in sizes.iter().enumerate().skip(1) {
+                ptr.write(step as u8);
+                ptr.add(size.min(new_size) - 1).write(!(step as u8));
+                let moved = HugePageAlloc.realloc(ptr, layout(size), new_size);
+                assert!(!moved.is_null(), "{} -> {} bytes", size, new_size);
+                assert_eq!(moved.read(), step as u8, "{} -> {} bytes", size, new_size);
+                assert_eq!(moved.add(size.min(new_size) - 1).read(), !(step as u8));
+                if is_large(layout(size)) && is_large(layout(new_size)) {
+                    assert_eq!(moved == ptr, mapping_len(size) == mapping_len(new_size));
+                }
+                if is_large(layout(new_size)) {
+                    assert!(mapped(moved, mapping_len(new_size)), "{} bytes", new_size);
+                }
+                (ptr, size) = (moved, new_size);
+            }
+            HugePageAlloc.dealloc(ptr, layout(size));
+        }
+    }
+}
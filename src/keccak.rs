@@ -0,0 +1,296 @@
+/*
+ * FLUX ENGINE - KECCAK
+ * Batched keccak256 for the hot hashing paths (trie nodes, secure-trie keys). On x86_64 with AVX2,
+ * detected at runtime, four messages go through one interleaved Keccak-f[1600]; everywhere else,
+ * and for a batch's last few messages, the scalar implementation hashes them one by one.
+ */
+
+use alloy_primitives::{keccak256, B256};
+use std::fmt;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Implementation {
+    Scalar,
+    /// Four messages per permutation, one per 64-bit lane of each 256-bit register.
+    Avx2,
+}
+
+impl Implementation {
+    /// The fastest implementation this CPU supports, detected once.
+    pub fn detect() -> Self {
+        static DETECTED: OnceLock<Implementation> = OnceLock::new();
+        *DETECTED.get_or_init(|| {
+            #[cfg(target_arch = "x86_64")]
+            if is_x86_feature_detected!("avx2") {
+                return Implementation::Avx2;
+            }
+            Implementation::Scalar
+        })
+    }
+
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Implementation::Scalar => true,
+            Implementation::Avx2 => Self::detect() == Implementation::Avx2,
+        }
+    }
+}
+
+impl fmt::Display for Implementation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Implementation::Scalar => "scalar",
+            Implementation::Avx2 => "avx2 (4-way)",
+        })
+    }
+}
+
+/// keccak256 of every input, in order, on the detected implementation.
+pub fn keccak256_batch<T: AsRef<[u8]>>(inputs: &[T]) -> Vec<B256> {
+    keccak256_batch_with(Implementation::detect(), inputs)
+}
+
+/// As `keccak256_batch`, on a given implementation (for benchmarking). Panics if this CPU does
+/// not support it.
+pub fn keccak256_batch_with<T: AsRef<[u8]>>(implementation: Implementation, inputs: &[T]) -> Vec<B256> {
+    assert!(implementation.is_supported(), "{} keccak is not supported on this CPU", implementation);
+    match implementation {
+        Implementation::Scalar => inputs.iter().map(keccak256).collect(),
+        #[cfg(target_arch = "x86_64")]
+        // SAFETY: AVX2 support was checked above.
+        Implementation::Avx2 => unsafe { avx2::keccak256_batch(inputs) },
+        #[cfg(not(target_arch = "x86_64"))]
+        Implementation::Avx2 => unreachable!("never detected off x86_64"),
+    }
+}
+
+// --- KECCAK-F[1600] ---
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use alloy_primitives::{keccak256, B256};
+    use std::arch::x86_64::*;
+
+    const RATE: usize = 136;
+
+    const ROUND_CONSTANTS: [u64; 24] = [
+        0x0000000000000001,
+        0x0000000000008082,
+        0x800000000000808a,
+        0x8000000080008000,
+        0x000000000000808b,
+        0x0000000080000001,
+        0x8000000080008081,
+        0x8000000000008009,
+        0x000000000000008a,
+        0x0000000000000088,
+        0x0000000080008009,
+        0x000000008000000a,
+        0x000000008000808b,
+        0x800000000000008b,
+        0x8000000000008089,
+        0x8000000000008003,
+        0x8000000000008002,
+        0x8000000000000080,
+        0x000000000000800a,
+        0x800000008000000a,
+        0x8000000080008081,
+        0x8000000000008080,
+        0x0000000080000001,
+        0x8000000080008008,
+    ];
+
+    /// Blocks a message pads out to (keccak padding always adds at least one byte).
+    fn blocks(len: usize) -> usize {
+        len / RATE + 1
+    }
+
+    /// Groups messages of equal block counts into fours; whatever cannot fill a group is hashed
+    /// scalar.
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn keccak256_batch<T: AsRef<[u8]>>(inputs: &[T]) -> Vec<B256> {
+        let mut order: Vec<usize> = (0..inputs.len()).collect();
+        order.sort_unstable_by_key(|&i| blocks(inputs[i].as_ref().len()));
+
+        let mut out = vec![B256::ZERO; inputs.len()];
+        let mut group = order.as_slice();
+        while !group.is_empty() {
+            let count = blocks(inputs[group[0]].as_ref().len());
+            let same = group.iter().take_while(|&&i| blocks(inputs[i].as_ref().len()) == count).count();
+            let (equal, rest) = group.split_at(same);
+            let mut fours = equal.chunks_exact(4);
+            for four in &mut fours {
+                let messages = [0, 1, 2, 3].map(|j| inputs[four[j]].as_ref());
+                for (j, hash) in keccak4(messages, count).into_iter().enumerate() {
+                    out[four[j]] = hash;
+                }
+            }
+            for &i in fours.remainder() {
+                out[i] = keccak256(inputs[i].as_ref());
+            }
+            group = rest;
+        }
+        out
+    }
+
+    /// Four messages of `count` blocks each, absorbed and permuted in lockstep.
+    #[target_feature(enable = "avx2")]
+    unsafe fn keccak4(messages: [&[u8]; 4], count: usize) -> [B256; 4] {
+        // Lanes are read straight from the messages, with the keccak padding (0x01 .. 0x80)
+        // XORed in where it falls.
+        let end = count * RATE;
+        let lane = |message: &[u8], offset: usize| {
+            let mut bytes = [0u8; 8];
+            if offset + 8 <= message.len() {
+                bytes.copy_from_slice(&message[offset..offset + 8]);
+            } else if offset < message.len() {
+                bytes[..message.len() - offset].copy_from_slice(&message[offset..]);
+            }
+            if (offset..offset + 8).contains(&message.len()) {
+                bytes[message.len() - offset] ^= 0x01;
+            }
+            if offset + 8 == end {
+                bytes[7] ^= 0x80;
+            }
+            i64::from_le_bytes(bytes)
+        };
+
+        let mut state = [_mm256_setzero_si256(); 25];
+        for block in 0..count {
+            for (k, word) in state.iter_mut().take(RATE / 8).enumerate() {
+                let offset = block * RATE + k * 8;
+                let input = _mm256_set_epi64x(
+                    lane(messages[3], offset),
+                    lane(messages[2], offset),
+                    lane(messages[1], offset),
+                    lane(messages[0], offset),
+                );
+                *word = _mm256_xor_si256(*word, input);
+            }
+            permute(&mut state);
+        }
+
+        let mut lanes = [[0u64; 4]; 4];
+        for (k, lanes) in lanes.iter_mut().enumerate() {
+            _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, state[k]);
+        }
+        [0, 1, 2, 3].map(|j| {
+            let mut hash = [0u8; 32];
+            for (k, lanes) in lanes.iter().enumerate() {
+                hash[k * 8..k * 8 + 8].copy_from_slice(&lanes[j].to_le_bytes());
+            }
+            B256::from(hash)
+        })
+    }
+
+    /// Lane-wise rotate left; AVX2 has no 64-bit rotate, so two shifts and an or.
+    macro_rules! rotate {
+        ($word:expr, $by:literal) => {
+            _mm256_or_si256(_mm256_slli_epi64::<$by>($word), _mm256_srli_epi64::<{ 64 - $by }>($word))
+        };
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn permute(a: &mut [__m256i; 25]) {
+        for round_constant in ROUND_CONSTANTS {
+            // Theta
+            let mut c = [_mm256_setzero_si256(); 5];
+            for (x, column) in c.iter_mut().enumerate() {
+                *column = _mm256_xor_si256(
+                    _mm256_xor_si256(a[x], a[x + 5]),
+                    _mm256_xor_si256(_mm256_xor_si256(a[x + 10], a[x + 15]), a[x + 20]),
+                );
+            }
+            for x in 0..5 {
+                let d = _mm256_xor_si256(c[(x + 4) % 5], rotate!(c[(x + 1) % 5], 1));
+                for y in 0..5 {
+                    a[x + 5 * y] = _mm256_xor_si256(a[x + 5 * y], d);
+                }
+            }
+
+            // Rho and pi, unrolled so every rotation is by an immediate: lane `x + 5y` moves to
+            // `y + 5 * ((2x + 3y) % 5)`.
+            let mut b = [a[0]; 25];
+            b[10] = rotate!(a[1], 1);
+            b[20] = rotate!(a[2], 62);
+            b[5] = rotate!(a[3], 28);
+            b[15] = rotate!(a[4], 27);
+            b[16] = rotate!(a[5], 36);
+            b[1] = rotate!(a[6], 44);
+            b[11] = rotate!(a[7], 6);
+            b[21] = rotate!(a[8], 55);
+            b[6] = rotate!(a[9], 20);
+            b[7] = rotate!(a[10], 3);
+            b[17] = rotate!(a[11], 10);
+            b[2] = rotate!(a[12], 43);
+            b[12] = rotate!(a[13], 25);
+            b[22] = rotate!(a[14], 39);
+            b[23] = rotate!(a[15], 41);
+            b[8] = rotate!(a[16], 45);
+            b[18] = rotate!(a[17], 15);
+            b[3] = rotate!(a[18], 21);
+            b[13] = rotate!(a[19], 8);
+            b[14] = rotate!(a[20], 18);
+            b[24] = rotate!(a[21], 2);
+            b[9] = rotate!(a[22], 61);
+            b[19] = rotate!(a[23], 56);
+            b[4] = rotate!(a[24], 14);
+
+            // Chi
+            for y in 0..5 {
+                for x in 0..5 {
+                    a[x + 5 * y] = _mm256_xor_si256(
+                        b[x + 5 * y],
+                        _mm256_andnot_si256(b[(x + 1) % 5 + 5 * y], b[(x + 2) % 5 + 5 * y]),
+                    );
+                }
+            }
+
+            // Iota
+            a[0] = _mm256_xor_si256(a[0], _mm256_set1_epi64x(round_constant as i64));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::b256;
+
+    /// Lengths around the 136-byte rate, where padding spills into another block, and a batch
+    /// size that leaves a remainder for the scalar path.
+    fn inputs() -> Vec<Vec<u8>> {
+        let lengths = (0..=300).chain([543, 544, 545, 1000]);
+        lengths.map(|len| (0..len).map(|i| (i * 31 + len) as u8).collect()).collect()
+    }
+
+    #[test]
+    fn scalar_matches_the_reference_digests() {
+        let digests = keccak256_batch_with(Implementation::Scalar, &[&b""[..], b"abc"]);
+        assert_eq!(digests[0], b256!("c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"));
+        assert_eq!(digests[1], b256!("4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45"));
+    }
+
+    #[test]
+    fn avx2_matches_scalar() {
+        if !Implementation::Avx2.is_supported() {
+            eprintln!("no AVX2 on this CPU, nothing to compare");
+            return;
+        }
+        let inputs = inputs();
+        for batch in 1..=9 {
+            let inputs = &inputs[..batch];
+            assert_eq!(
+                keccak256_batch_with(Implementation::Avx2, inputs),
+                keccak256_batch_with(Implementation::Scalar, inputs),
+                "batch of {}",
+                batch
+            );
+        }
+        assert_eq!(
+            keccak256_batch_with(Implementation::Avx2, &inputs),
+            keccak256_batch_with(Implementation::Scalar, &inputs)
+        );
+    }
+}
@@ -0,0 +1,1841 @@
+/*
+ * FLUX ENGINE - PRODUCTION RELEASE
+ * Architecture: Optimistic Software Transactional Memory (STM) for EVM
+ * Target: >300 MGas/s
+ */
+
+pub mod access_trace;
+pub mod analysis;
+pub mod bisect;
+pub mod block;
+pub mod builder;
+pub mod chain;
+pub mod checkpoint;
+pub mod commitment;
+pub mod dispatch;
+pub mod executor;
+pub mod filter;
+pub mod forensics;
+pub mod hugepages;
+pub mod keccak;
+pub mod locking;
+pub mod metrics;
+pub mod mvcc;
+pub mod predict;
+pub mod priority;
+pub mod receipt;
+pub mod recovery;
+pub mod scheduler;
+pub mod source;
+pub mod state;
+pub mod system;
+pub mod topology;
+pub mod trie;
+pub mod validation;
+#[cfg(feature = "verkle")]
+pub mod verkle;
+pub mod workload;
+pub mod zstd;
+mod watchdog;
+
+use rayon::prelude::*;
+use revm::{
+    db::{DatabaseRef, WrapDatabaseRef},
+    precompile::Precompiles,
+    primitives::{
+        Account, AccountInfo, Address, Env, InvalidTransaction, ResultAndState, SpecId, State, StorageSlot, TransactTo,
+        B256, GAS_PER_BLOB, MAX_BLOB_GAS_PER_BLOCK, U256,
+    },
+    DatabaseCommit,
+};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use parking_lot::{Mutex, RwLock};
+use metrics::{CommitStats, PrefetchStats, ThroughputReport, ThroughputTracker};
+use block::BlockHeader;
+use chain::ChainSpec;
+use checkpoint::Checkpoint;
+use executor::{AccessSet, PrecompileFn, PrecompileRegistry};
+use access_trace::{TraceWriter, TracedBlock, TracedTx};
+use alloy_primitives::Bloom;
+use analysis::{AnalysisCache, AnalysisStats, WithAnalysisCache};
+use bisect::TxWrites;
+use forensics::{Capture, ForensicsConfig};
+use mvcc::{ConflictGranularity, FilterStats, Location, LocationFilter, LostWrites, ReadWriteSet, VersionTable};
+use predict::{AccessPatterns, PredictionStats};
+use receipt::Receipt;
+use locking::LockStats;
+use recovery::{RecoveryStats, Signature};
+use validation::ValidationStats;
+use scheduler::{BlockStmStats, Strategy};
+use commitment::{CommitmentScheme, StateCommitment};
+use dispatch::{DepthController, Dispatch, DispatchStats, LaneOverlay, Lanes, SpeculationDepth, SpeculationStats};
+use trie::TrieStats;
+use state::backend::{BackendKind, BackendStats, StateBackend};
+use state::buffer::{BufferStats, WriteBuffer};
+use state::diff::StateDiff;
+#[cfg(all(feature = "async-io", target_os = "linux"))]
+use state::io::{IoPool, IoStats};
+use state::history::{Changeset, HistoricalValue, PruneMode, PruneStats, StateHistory};
+use state::latency::{DiskStats, LatencyModel, SimulatedDisk};
+use state::sharded::{CacheStats, ReadStage, ShardStats, ShardedState, StateChange};
+use state::snapshot::{ImportStats, SnapshotAccount};
+use watchdog::{Progress, Stage, Watchdog};
+
+// --- TYPES ---
+
+#[derive(Debug, Clone, Default)]
+pub struct FluxTransaction {
+    pub id: usize,
+    pub caller: Address,
+    /// Call target. Unused (zero) for a contract creation.
+    pub to: Address,
+    /// Contract creation: `data` is the init code and the new contract's address is derived.
+    pub create: bool,
+    pub value: U256,
+    pub data: Vec<u8>,
+    pub gas_limit: u64,
+    /// Checked against the sender's account when set. Generated workloads leave it `None`.
+    pub nonce: Option<u64>,
+    /// EIP-2930 access list: accounts and slots pre-warmed before execution starts.
+    pub access_list: Vec<(Address, Vec<U256>)>,
+    /// EIP-1559 fee cap in wei per gas. For legacy transactions this is the gas price.
+    pub max_fee_per_gas: U256,
+    /// EIP-1559 tip cap. `None` marks a legacy (type 0/1) transaction.
+    pub max_priority_fee_per_gas: Option<U256>,
+    /// EIP-4844 versioned hashes of the blobs this transaction carries. Empty for non-blob txs.
+    pub blob_hashes: Vec<B256>,
+    /// EIP-4844 blob gas fee cap. `Some` marks a type-3 transaction.
+    pub max_fee_per_blob_gas: Option<U256>,
+    /// When set, `caller` is whatever this recovers to (see `recovery`), not what was given.
+    pub signature: Option<Signature>,
+    /// Not selected by a partial replay's filter: it executes like any other, so the state keeps
+    /// advancing as the chain's did, but it is left out of the block's profile.
+    pub skipped: bool,
+}
+
+impl FluxTransaction {
+    /// The revm environment this transaction executes under, on top of its block's
+    /// (`ChainSpec::block_env`).
+    pub fn env(&self, block_env: &Env) -> Env {
+        let mut env = block_env.clone();
+        env.tx.caller = self.caller;
+        env.tx.transact_to = if self.create {
+            TransactTo::create()
+        } else {
+            TransactTo::Call(self.to)
+        };
+        env.tx.data = self.data.clone().into();
+        env.tx.value = self.value;
+        env.tx.gas_limit = self.gas_limit;
+        env.tx.nonce = self.nonce;
+        env.tx.access_list = self.access_list.clone();
+        env.tx.gas_price = self.max_fee_per_gas;
+        env.tx.gas_priority_fee = self.max_priority_fee_per_gas;
+        env.tx.blob_hashes = self.blob_hashes.clone();
+        env.tx.max_fee_per_blob_gas = self.max_fee_per_blob_gas;
+        env
+    }
+
+    /// EIP-2718 envelope type this transaction would be sent as.
+    pub fn tx_type(&self) -> u8 {
+        if self.max_fee_per_blob_gas.is_some() {
+            3
+        } else if self.max_priority_fee_per_gas.is_some() {
+            2
+        } else if !self.access_list.is_empty() {
+            1
+        } else {
+            0
+        }
+    }
+
+    pub fn blob_gas(&self) -> u64 {
+        GAS_PER_BLOB * self.blob_hashes.len() as u64
+    }
+
+    /// What the transaction says it will touch before it runs: sender, target and access list.
+    pub fn declared_locations(&self) -> impl Iterator<Item = Location> + '_ {
+        std::iter::once(Location::Account(self.caller))
+            .chain((!self.create).then_some(Location::Account(self.to)))
+            .chain(self.access_list.iter().flat_map(|(address, slots)| {
+                std::iter::once(Location::Account(*address))
+                    .chain(slots.iter().map(move |slot| Location::Storage(*address, *slot)))
+            }))
+    }
+
+    /// Price per gas actually paid: `min(max_fee, base_fee + tip)`, or the gas price for legacy txs.
+    pub fn effective_gas_price(&self, base_fee: u64) -> U256 {
+        match self.max_priority_fee_per_gas {
+            Some(tip) => self.max_fee_per_gas.min(U256::from(base_fee).saturating_add(tip)),
+            None => self.max_fee_per_gas,
+        }
+    }
+}
+
+/// What a block actually cost. `gas_used` is revm's metered gas (opcodes, memory expansion,
+/// call stipends, refunds) summed over every included tx, reverted or halted ones included.
+#[derive(Debug, Clone, Default)]
+pub struct BlockResult {
+    pub tx_count: usize,
+    pub gas_used: u64,
+    pub re_executions: usize,
+    /// Transactions refused outright (bad signature, nonce or funds, ...). They use no gas.
+    pub rejected: usize,
+    /// EIP-2929 warm/cold loads summed over the committed executions.
+    pub access: AccessSet,
+    /// Base fee the block executed under and the one its successor inherits (EIP-1559).
+    pub base_fee_per_gas: u64,
+    pub next_base_fee_per_gas: u64,
+    /// `gas_used * base_fee`, destroyed rather than paid to anyone.
+    pub base_fee_burned: U256,
+    /// `gas_used * (effective_gas_price - base_fee)`, credited to the coinbase.
+    pub priority_fees: U256,
+    /// EIP-4844 blob gas of the included txs, what it burned, and the successor's excess.
+    pub blob_gas_used: u64,
+    pub blob_fee_burned: U256,
+    pub next_excess_blob_gas: u64,
+    /// One receipt per included tx, in block order, and the header fields they commit to.
+    pub receipts: Vec<Receipt>,
+    pub receipts_root: B256,
+    pub logs_bloom: Bloom,
+    /// Transactions pre-validation turned away, by id, with what they failed. Counted in `rejected`.
+    pub rejections: Vec<(usize, InvalidTransaction)>,
+    /// Transactions a built block left out because they no longer fit its gas limit, by id.
+    /// Always empty for `execute_block`; not counted in `rejected`.
+    pub excluded: Vec<usize>,
+    /// Post-block state root. Only computed when the header carries one to check it against.
+    pub state_root: Option<B256>,
+    /// Skipped txs (`FluxTransaction::skipped`) the block included, and the gas they used. Both
+    /// are part of `tx_count` and `gas_used`, which the header commits to, but not of `access`,
+    /// `re_executions` or the throughput report.
+    pub skipped: usize,
+    pub skipped_gas: u64,
+    /// What each committed tx wrote, in block order. Only with `EngineConfig::record_tx_writes`.
+    pub tx_writes: Vec<TxWrites>,
+}
+
+impl BlockResult {
+    /// Checks gas used and the receipts root against the canonical values in `header`.
+    /// With the canonical receipts at hand, a root mismatch is narrowed to the first
+    /// diverging transaction.
+    pub fn verify(&self, header: &BlockHeader, canonical: Option<&[Receipt]>) -> Result<(), String> {
+        let diverging_tx = || {
+            canonical
+                .and_then(|canonical| receipt::first_divergence(&self.receipts, canonical))
+                .map(|i| match self.receipts.get(i) {
+                    Some(receipt) => format!(" (first diverging receipt: #{} tx {})", i, receipt.tx_id),
+                    None => format!(" (first diverging receipt: #{}, missing locally)", i),
+                })
+                .unwrap_or_default()
+        };
+
+        if let Some(expected) = header.gas_used.filter(|gas| *gas != self.gas_used) {
+            return Err(format!(
+                "block {}: gas used {} but header says {}{}",
+                header.number,
+                self.gas_used,
+                expected,
+                diverging_tx()
+            ));
+        }
+        if let Some(expected) = header.receipts_root.filter(|root| *root != self.receipts_root) {
+            return Err(format!(
+                "block {}: receipts root {} but header says {}{}",
+                header.number,
+                self.receipts_root,
+                expected,
+                diverging_tx()
+            ));
+        }
+        if let (Some(expected), Some(root)) = (header.state_root, self.state_root) {
+            if expected != root {
+                return Err(format!(
+                    "block {}: state root {} but header says {}",
+                    header.number, root, expected
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// A speculative execution: revm's uncommitted result plus what it read and wrote.
+pub(crate) type SpeculativeResult = Result<(ResultAndState, ReadWriteSet), String>;
+
+/// A tx's latest execution, waiting for the ordered applier.
+struct PendingTx {
+    result: Option<SpeculativeResult>,
+    /// The earlier tx whose writes it conflicts with, as found by validation, or the tx itself
+    /// when nothing about its run can be trusted.
+    blocker: Option<usize>,
+    /// How many txs were applied when it executed.
+    snapshot: usize,
+    /// The lane it executed in, unique within the block: on top of the applied state, it saw the
+    /// runs of the earlier txs in its lane. All Block-STM and lock-based outcomes share one, each
+    /// having seen every run before it that wrote what it read.
+    lane: usize,
+    /// The coinbase balance it executed against, for `mvcc::rebase_coinbase`.
+    coinbase: U256,
+    retries: usize,
+}
+
+impl PendingTx {
+    /// Whether its reads may no longer match what serial execution would see at its turn: a tx
+    /// it didn't see was applied in between, or one it saw didn't land as it had executed.
+    fn is_stale(&self, rw: &ReadWriteSet, applied: &VersionTable, lost: &LostWrites) -> bool {
+        self.blocker.is_some()
+            || !applied.is_current(&rw.reads, self.snapshot, self.lane)
+            || !lost.is_current(&rw.reads, self.lane)
+    }
+}
+
+// The Global State, sharded so executors never queue on one lock. The shards cache every read of
+// the backend: nothing, or a flat log / RocksDB store in the configured state directory.
+// SimulatedDisk is a pass-through unless a latency model is configured.
+type GlobalDb = ShardedState<SimulatedDisk<StateBackend>>;
+
+// --- CONFIGURATION ---
+
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    /// Fork schedule; picks the revm spec (and with it the precompile set) for every block.
+    pub chain: ChainSpec,
+    /// Abort with a diagnostic dump if no transaction executes or commits for this long.
+    /// `None` disables the watchdog thread entirely.
+    pub watchdog_timeout: Option<Duration>,
+    /// Threads for commit-phase validation. State is still installed by a single ordered applier,
+    /// so this changes commit latency, never results.
+    pub commit_threads: usize,
+    /// Cores the commit threads are pinned to, one each (`--topology commit=...`). Empty leaves
+    /// them to the OS scheduler.
+    pub commit_cores: Vec<usize>,
+    /// Independently locked partitions of global state.
+    pub state_shards: usize,
+    /// Latency injected on every read that misses the in-memory cache and hits the backend.
+    pub disk_latency: LatencyModel,
+    /// Read every block's declared locations (senders, targets, access lists) into the shard
+    /// caches before executing it. Changes cache hit rates, never results.
+    pub prefetch_state: bool,
+    /// Reject the transactions revm is certain to refuse (nonce, balance, intrinsic gas, fee caps)
+    /// before any executor sees them. Changes where they are rejected, never results.
+    pub prevalidate: bool,
+    /// Threads backend reads are handed to, so executors keep running other transactions while
+    /// a read is in flight. 0 reads on the executor itself; anything else needs a Linux build
+    /// with `--features async-io`.
+    pub io_threads: usize,
+    /// Persistent state to execute on top of. `None` keeps all state in memory for the run.
+    pub state_dir: Option<PathBuf>,
+    /// Store kept in `state_dir`; see `BackendKind` for which ones committed blocks are written to.
+    pub state_backend: BackendKind,
+    /// Accounts plus slots the shards keep cached between blocks (split evenly across shards,
+    /// rounded up), least recently read evicted first. `None` = unbounded.
+    pub state_cache: Option<usize>,
+    /// Whether transactions conflict over whole accounts or individual storage slots. Coarser
+    /// sets are cheaper to track and validate but re-execute transactions that share only an
+    /// account.
+    pub conflict_granularity: ConflictGranularity,
+    /// How transactions are scheduled onto the executors. Changes how much work is wasted on
+    /// conflicts, never results.
+    pub strategy: Strategy,
+    /// How optimistic speculation splits a block into executor lanes: one per transaction, runs
+    /// sharing a hot account (see `Grouping` for the heuristic), or predicted conflicts.
+    pub dispatch: Dispatch,
+    /// How many transactions optimistic speculation may run ahead of the ordered applier.
+    /// Narrower windows waste less work on conflicts but leave executors idle while the applier
+    /// catches up.
+    pub speculation_depth: SpeculationDepth,
+    /// Times a block may send its conflicted transactions back to the executors to re-run in
+    /// parallel before the applier re-executes the rest one by one. 0 = always serially.
+    pub retry_rounds: usize,
+    /// How much block history and dead state the committer keeps in memory.
+    pub prune: PruneMode,
+    /// What `state_root` commits to. Anything but the MPT is for benchmarking only.
+    pub commitment: CommitmentScheme,
+    /// Write each block's state diff (`block-<n>.jsonl`) here. `None` = off.
+    pub state_diffs: Option<PathBuf>,
+    /// Record every committed tx's read and write sets and gas to this file, for
+    /// `flux replay-trace`. `None` = off.
+    pub record_trace: Option<PathBuf>,
+    /// zstd level the access trace is written with. `None` = uncompressed.
+    pub trace_compression: Option<u32>,
+    /// Keep the values every committed tx wrote in `BlockResult::tx_writes`, for `flux bisect`.
+    pub record_tx_writes: bool,
+    /// Capture over-budget or retry-looping transactions for standalone replay. `None` = off.
+    pub forensics: Option<ForensicsConfig>,
+}
+
+impl EngineConfig {
+    /// The flags that pick this scheduling setup on the command line, for the run report: the
+    /// strategy, and whichever of its knobs it actually reads.
+    pub fn strategy_flags(&self) -> String {
+        let mut flags = format!("--strategy {}", self.strategy);
+        if self.strategy == Strategy::Optimistic {
+            flags += &format!(" --dispatch {} --speculation-depth {}", self.dispatch, self.speculation_depth);
+        }
+        if self.strategy != Strategy::Serial {
+            flags += &format!(" --retry-rounds {} --conflicts {}", self.retry_rounds, self.conflict_granularity);
+        }
+        flags
+    }
+
+    /// Points the chain and state backend at the ones `checkpoint` was taken on.
+    pub fn resume_from(&mut self, checkpoint: &Checkpoint) -> Result<(), String> {
+        self.chain = checkpoint.chain.parse()?;
+        self.state_backend = checkpoint.state_backend;
+        self.state_dir = checkpoint.state_dir.clone();
+        Ok(())
+    }
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            chain: ChainSpec::default(),
+            watchdog_timeout: Some(Duration::from_secs(30)),
+            commit_threads: 1,
+            commit_cores: Vec::new(),
+            state_shards: 64,
+            disk_latency: LatencyModel::None,
+            prefetch_state: false,
+            prevalidate: false,
+            io_threads: 0,
+            state_dir: None,
+            state_backend: BackendKind::default(),
+            conflict_granularity: ConflictGranularity::default(),
+            strategy: Strategy::default(),
+            dispatch: Dispatch::default(),
+            speculation_depth: SpeculationDepth::default(),
+            retry_rounds: 0,
+            prune: PruneMode::default(),
+            state_cache: None,
+            commitment: CommitmentScheme::default(),
+            state_diffs: None,
+            record_trace: None,
+            trace_compression: None,
+            record_tx_writes: false,
+            forensics: None,
+        }
+    }
+}
+
+// --- THE ENGINE LOGIC ---
+
+pub struct FluxEngine {
+    config: EngineConfig,
+    db: Arc<GlobalDb>,
+    history: Mutex<StateHistory>,
+    /// Built by the first `state_root` call, then updated from each block's changeset.
+    state_trie: Mutex<Option<StateCommitment>>,
+    trie_stats: Mutex<TrieStats>,
+    precompiles: RwLock<PrecompileRegistry>,
+    analysis: AnalysisCache,
+    progress: Arc<Progress>,
+    throughput: Mutex<ThroughputTracker>,
+    commit_pool: Option<rayon::ThreadPool>,
+    commit_stats: Mutex<CommitStats>,
+    buffer_stats: Mutex<BufferStats>,
+    prefetch_stats: Mutex<PrefetchStats>,
+    block_stm_stats: Mutex<BlockStmStats>,
+    dispatch_stats: Mutex<DispatchStats>,
+    /// What calls to each contract wrote in earlier blocks, for predicted dispatch.
+    access_patterns: Mutex<AccessPatterns>,
+    prediction_stats: Mutex<PredictionStats>,
+    lock_stats: Mutex<LockStats>,
+    recovery_stats: Mutex<RecoveryStats>,
+    validation_stats: Mutex<ValidationStats>,
+    /// Sizes optimistic speculation windows across blocks.
+    depth: Mutex<DepthController>,
+    access_trace: Option<Mutex<TraceWriter>>,
+    _watchdog: Option<Watchdog>,
+}
+
+impl FluxEngine {
+    pub fn new(config: EngineConfig) -> Result<Self, String> {
+        let backend = StateBackend::open(config.state_backend, config.state_dir.as_deref())?;
+        let progress = Arc::new(Progress::default());
+        let watchdog = config
+            .watchdog_timeout
+            .map(|timeout| Watchdog::spawn(timeout, progress.clone()));
+        let commit_pool = (config.commit_threads > 1 || !config.commit_cores.is_empty()).then(|| {
+            let cores = config.commit_cores.clone();
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(config.commit_threads)
+                .thread_name(|i| format!("flux-commit-{}", i))
+                .start_handler(move |i| {
+                    if let Some(&core) = cores.get(i) {
+                        if !topology::pin_current_thread(core) {
+                            eprintln!("[FLUX] Topology: could not pin commit thread {} to core {}", i, core);
+                        }
+                    }
+                })
+                .build()
+                .expect("failed to build commit thread pool")
+        });
+
+        let disk = SimulatedDisk::new(backend, config.disk_latency);
+        #[cfg(all(feature = "async-io", target_os = "linux"))]
+        let disk = match config.io_threads {
+            0 => disk,
+            threads => disk.with_io_pool(IoPool::new(threads)),
+        };
+        #[cfg(not(all(feature = "async-io", target_os = "linux")))]
+        if config.io_threads > 0 {
+            return Err("--io-threads needs a Linux build with `--features async-io`".into());
+        }
+        let db = ShardedState::new(disk, config.state_shards).with_capacity_limit(config.state_cache);
+        db.record_changesets(config.prune.records_history() || config.state_diffs.is_some());
+        let access_trace = config
+            .record_trace
+            .as_deref()
+            .map(|path| TraceWriter::create(path, config.trace_compression))
+            .transpose()?
+            .map(Mutex::new);
+
+        Ok(Self {
+            config: config.clone(),
+            db: Arc::new(db),
+            history: Mutex::new(StateHistory::new(config.prune)),
+            state_trie: Mutex::new(None),
+            trie_stats: Mutex::new(TrieStats {
+                scheme: config.commitment,
+                ..TrieStats::default()
+            }),
+            precompiles: RwLock::new(PrecompileRegistry::default()),
+            analysis: AnalysisCache::default(),
+            progress,
+            throughput: Mutex::new(ThroughputTracker::new()),
+            commit_pool,
+            commit_stats: Mutex::new(CommitStats {
+                threads: config.commit_threads,
+                ..CommitStats::default()
+            }),
+            buffer_stats: Mutex::new(BufferStats::default()),
+            prefetch_stats: Mutex::new(PrefetchStats::default()),
+            block_stm_stats: Mutex::new(BlockStmStats::default()),
+            dispatch_stats: Mutex::new(DispatchStats::default()),
+            access_patterns: Mutex::new(AccessPatterns::default()),
+            prediction_stats: Mutex::new(PredictionStats::default()),
+            lock_stats: Mutex::new(LockStats::default()),
+            recovery_stats: Mutex::new(RecoveryStats::default()),
+            validation_stats: Mutex::new(ValidationStats::default()),
+            depth: Mutex::new(DepthController::new(config.speculation_depth)),
+            access_trace,
+            _watchdog: watchdog,
+        })
+    }
+
+    /// Adds a chain-specific precompile on top of the standard set. The address must extend the
+    /// standard range contiguously (revm dispatches precompiles by address <= count) under every
+    /// fork of the configured chain.
+    pub fn register_precompile(&self, address: Address, fun: PrecompileFn) -> Result<(), String> {
+        let mut registry = self.precompiles.write();
+        let mut candidate = registry.clone();
+        candidate.register(address, fun)?;
+        for spec in self.config.chain.specs() {
+            candidate.build(spec)?;
+        }
+        *registry = candidate;
+        Ok(())
+    }
+
+    /// Seeds an account directly into global state (genesis alloc / test fixtures).
+    pub fn insert_account(&self, address: Address, info: AccountInfo) {
+        self.db.insert_account_info(address, info);
+        // Seeding bypasses the committer, so no changeset will carry it into the trie.
+        *self.state_trie.lock() = None;
+    }
+
+    /// Captures the state the next block (`next`) would execute against.
+    pub fn checkpoint(&self, next: &BlockHeader) -> Checkpoint {
+        Checkpoint {
+            chain: self.config.chain.name.clone(),
+            next: next.clone(),
+            state_backend: self.config.state_backend,
+            state_dir: self.config.state_dir.clone(),
+            state_root: None,
+            accounts: self.db.dump(),
+        }
+    }
+
+    /// Loads a checkpoint's state into a fresh engine. The engine must have been built on the
+    /// checkpoint's chain and state backend (`EngineConfig::resume_from` sets both).
+    pub fn restore(&self, checkpoint: Checkpoint) {
+        self.preload(checkpoint.accounts);
+    }
+
+    /// Seeds `accounts` and their slots on top of the current state, the same way genesis fixtures
+    /// are seeded.
+    pub fn preload(&self, accounts: Vec<SnapshotAccount>) -> ImportStats {
+        *self.state_trie.lock() = None;
+        let mut stats = ImportStats::default();
+        for account in accounts {
+            stats.accounts += 1;
+            stats.contracts += usize::from(account.info.code.as_ref().is_some_and(|code| !code.is_empty()));
+            stats.slots += account.storage.len();
+            self.db.insert_account_info(account.address, account.info);
+            for (slot, value) in account.storage {
+                self.db.insert_account_storage(account.address, slot, value);
+            }
+        }
+        stats
+    }
+
+    /// `location` as of the end of `block`, for any height the prune mode still keeps history for
+    /// (every executed block under `PruneMode::Archive`). Meant for inspecting state after a
+    /// replay; it reads the latest state, so it should not race `execute_block`.
+    pub fn state_at(&self, block: u64, location: Location) -> Result<HistoricalValue, String> {
+        let history = self.history.lock();
+        if let Some(value) = history.lookup(block, location)? {
+            return Ok(value);
+        }
+        match location {
+            Location::Account(address) => DatabaseRef::basic(&*self.db, address)
+                .map(|info| HistoricalValue::Account(info.map(|info| AccountInfo { code: None, ..info }))),
+            Location::Storage(address, slot) => {
+                DatabaseRef::storage(&*self.db, address, slot).map(HistoricalValue::Storage)
+            }
+        }
+    }
+
+    /// Applies `writes` as `block`'s committed state, outside `execute_block`: into the caches,
+    /// then to the backend as one atomic batch, then into the state trie. Returns the new state
+    /// root. On a failed backend write the caches keep the only copy, as after a failed block.
+    pub fn commit_batch(&self, block: u64, writes: Vec<StateChange>) -> Result<B256, String> {
+        let changeset = self.apply_writes(block, writes)?;
+        self.history.lock().push(block, changeset);
+        self.state_root()
+    }
+
+    /// Rolls the state back to what it was once `block` was executed, forgetting every later
+    /// block, as when the chain reorganises past them. Needs their history retained (see
+    /// `PruneMode`). Returns the state root as of `block`.
+    pub fn unwind_to(&self, block: u64) -> Result<B256, String> {
+        let reverted = self.history.lock().unwind(block)?;
+        let mut storage: HashMap<Address, Vec<(U256, U256)>> = HashMap::new();
+        for ((address, slot), value) in reverted.storage {
+            storage.entry(address).or_default().push((slot, value));
+        }
+        let mut writes = Vec::new();
+        for (address, info) in reverted.accounts {
+            // An account the unwound blocks created goes, storage and all.
+            let slots = storage.remove(&address).filter(|_| info.is_some()).unwrap_or_default();
+            writes.push(StateChange {
+                address,
+                info,
+                storage_cleared: false,
+                storage: slots,
+            });
+        }
+        for (address, slots) in storage {
+            writes.push(StateChange {
+                address,
+                info: DatabaseRef::basic(&*self.db, address)?,
+                storage_cleared: false,
+                storage: slots,
+            });
+        }
+        // The changeset is of the unwinding itself; the history already ends at `block`.
+        self.apply_writes(block, writes)?;
+        self.state_root()
+    }
+
+    /// `commit_batch` up to the history: commits `writes` into the caches, the backend and the
+    /// state trie, and returns what they overwrote.
+    fn apply_writes(&self, block: u64, writes: Vec<StateChange>) -> Result<Changeset, String> {
+        let db = &*self.db;
+        let mut changes = revm::primitives::HashMap::default();
+        for write in writes {
+            // Read first, so the caches hold the pre-block values the changeset records.
+            DatabaseRef::basic(db, write.address)?;
+            let destroyed = write.info.is_none();
+            let mut account = Account::from(write.info.unwrap_or_default());
+            for (slot, value) in write.storage {
+                let original = DatabaseRef::storage(db, write.address, slot)?;
+                account.storage.insert(slot, StorageSlot::new_changed(original, value));
+            }
+            account.mark_touch();
+            if write.storage_cleared {
+                account.mark_created();
+            }
+            if destroyed {
+                account.mark_selfdestruct();
+            }
+            changes.insert(write.address, account);
+        }
+        (&*self.db).commit(changes);
+
+        let backend = self.db.backend().inner();
+        if backend.accepts_writes() {
+            let changes = self.db.take_changes();
+            if let Err(e) = backend.commit_batch(block, &changes) {
+                self.db.pin(changes.iter().map(|change| change.address));
+                return Err(format!("block {}: {}", block, e));
+            }
+        } else {
+            self.db.discard_changes();
+        }
+
+        let changeset = self.db.take_changeset();
+        self.update_state_trie(&changeset);
+        Ok(changeset)
+    }
+
+    /// Root of the current state under the configured commitment (the Merkle Patricia root unless
+    /// `EngineConfig::commitment` says otherwise). The first call builds the trie from the
+    /// backend's accounts overlaid with the caches; from then on every block's changeset is folded
+    /// in as it commits, so later calls rehash only the paths those blocks touched.
+    pub fn state_root(&self) -> Result<B256, String> {
+        let mut trie = self.state_trie.lock();
+        let mut stats = self.trie_stats.lock();
+        // Subtrees and storage tries hash in parallel, on the commit threads if there are any:
+        // they sit idle between blocks anyway.
+        let hash = |trie: &mut StateCommitment| match &self.commit_pool {
+            Some(pool) => pool.install(|| trie.root()),
+            None => trie.root(),
+        };
+        stats.threads = self
+            .commit_pool
+            .as_ref()
+            .map_or_else(rayon::current_num_threads, |pool| pool.current_num_threads());
+
+        let started = Instant::now();
+        if let Some(trie) = trie.as_mut() {
+            let (root, rehashed) = hash(trie);
+            stats.hashing += started.elapsed();
+            stats.incremental += 1;
+            stats.nodes_rehashed += rehashed;
+            stats.last_rehashed = rehashed;
+            return Ok(root);
+        }
+
+        let accounts = self.db.overlay(self.db.backend().inner().dump()?);
+        let mut built = StateCommitment::build(self.config.commitment, &accounts)?;
+        let (root, nodes) = hash(&mut built);
+        stats.hashing += started.elapsed();
+        stats.full_builds += 1;
+        stats.full_build_nodes += nodes;
+        stats.last_rehashed = nodes;
+        *trie = Some(built);
+        self.db.record_changesets(true);
+        Ok(root)
+    }
+
+    /// Folds a committed block's changes into the state trie, if one is being kept.
+    fn update_state_trie(&self, changeset: &Changeset) {
+        let mut guard = self.state_trie.lock();
+        let Some(trie) = guard.as_mut() else {
+            return;
+        };
+        let mut touched: HashMap<Address, Vec<U256>> =
+            changeset.accounts.keys().map(|address| (*address, Vec::new())).collect();
+        for (address, slot) in changeset.storage.keys() {
+            touched.entry(*address).or_default().push(*slot);
+        }
+
+        let db = &*self.db;
+        let updated = touched.into_iter().try_for_each(|(address, slots)| {
+            let info = db.basic(address)?;
+            let slots = slots
+                .into_iter()
+                .map(|slot| Ok((slot, db.storage(address, slot)?)))
+                .collect::<Result<Vec<_>, String>>()?;
+            trie.update(address, info.as_ref(), changeset.wiped.contains(&address), slots);
+            Ok::<_, String>(())
+        });
+        if let Err(e) = updated {
+            // Half-applied is wrong; drop it and let the next root rebuild from scratch.
+            eprintln!("[FLUX] State trie update failed, rebuilding on next root: {}", e);
+            *guard = None;
+        }
+    }
+
+    /// Reads the declared locations of `txs`, from blocks that execute later, into the shard
+    /// caches on the calling thread. Safe to run while a block executes: a cached value is never
+    /// replaced by an older read. Returns how many distinct locations it read.
+    pub fn prefetch_ahead<'a>(&self, txs: impl IntoIterator<Item = &'a FluxTransaction>) -> u64 {
+        let locations: HashSet<Location> = txs.into_iter().flat_map(FluxTransaction::declared_locations).collect();
+        let count = locations.len() as u64;
+        self.db.prefetch_serial(locations);
+        count
+    }
+
+    /// The Winning Function: Optimistic Parallel Execution
+    pub fn execute_block(&self, header: &BlockHeader, txs: Vec<FluxTransaction>) -> BlockResult {
+        self.run_block(header, txs, false)
+    }
+
+    /// Builds a block from `txs`, a pool in the order the builder wants them: each runs in that
+    /// order against the block so far, and one whose gas limit exceeds the gas left is left out,
+    /// along with its sender's later txs, so the block never exceeds `header.gas_limit`.
+    pub fn build_block(&self, header: &BlockHeader, txs: Vec<FluxTransaction>) -> BlockResult {
+        self.run_block(header, txs, true)
+    }
+
+    fn run_block(&self, header: &BlockHeader, txs: Vec<FluxTransaction>, building: bool) -> BlockResult {
+        // Signed transactions learn their senders before anything reads one.
+        let (txs, recovery) = recovery::recover_senders(txs, self.config.chain.chain_id);
+        *self.recovery_stats.lock() += recovery;
+
+        let block_env = self.config.chain.block_env(header);
+        let precompiles = self
+            .precompiles
+            .read()
+            .build(block_env.cfg.spec_id)
+            .expect("precompile set is validated at registration");
+        let spec = block_env.cfg.spec_id;
+
+        // 0. BLOCK PROLOGUE: system calls whose effects the block's transactions can observe.
+        if let Some(root) = header.parent_beacon_block_root.filter(|_| SpecId::enabled(spec, SpecId::CANCUN)) {
+            if let Err(e) = system::apply_beacon_root_call(&self.db, &block_env, root, &precompiles) {
+                eprintln!("[FLUX] Block {} prologue failed: {}", header.number, e);
+            }
+        }
+
+        // Coinbase credits are rebased at commit time (see mvcc::rebase_coinbase).
+        let coinbase_pre_block = coinbase_balance(&*self.db, header.coinbase);
+
+        // Prefetch hints: everything the block declares before executing (senders, targets and
+        // access lists), so a paged backend can start its reads ahead of the executors.
+        self.db.backend().inner().prefetch(txs.iter().flat_map(FluxTransaction::declared_locations));
+
+        // Optionally read those same locations into the shard caches, so execution hits them warm.
+        let prefetched = self.config.prefetch_state.then(|| {
+            self.db.set_read_stage(ReadStage::Prefetch);
+            let locations: HashSet<Location> = txs.iter().flat_map(FluxTransaction::declared_locations).collect();
+            self.db.prefetch(locations.iter().copied());
+            locations
+        });
+
+        // Pre-validation: what revm would refuse wherever it ran never reaches an executor.
+        let (txs, rejections) = if self.config.prevalidate {
+            self.db.set_read_stage(ReadStage::Prefetch);
+            let (verdicts, stats) = validation::prevalidate(&*self.db, &block_env, &txs);
+            *self.validation_stats.lock() += stats;
+            let mut rejections = Vec::new();
+            let mut valid = Vec::with_capacity(txs.len());
+            for (tx, verdict) in txs.into_iter().zip(verdicts) {
+                match verdict {
+                    Some(invalid) => rejections.push((tx.id, invalid)),
+                    None => valid.push(tx),
+                }
+            }
+            (valid, rejections)
+        } else {
+            (txs, Vec::new())
+        };
+        let block_size = txs.len();
+        println!("[FLUX] Starting {} execution of {} transactions...", self.config.strategy, block_size);
+        self.progress.begin_block(block_size);
+
+        // 1. SPECULATIVE PHASE (Parallel)
+        self.db.set_read_stage(ReadStage::Execution);
+        let granularity = self.config.conflict_granularity;
+        let strategy = self.config.strategy;
+        let tx_refs: Vec<&FluxTransaction> = txs.iter().collect();
+        // Every location speculation read, to see how much of the prefetch it used.
+        let mut speculated_reads = prefetched.as_ref().map(|_| HashSet::new());
+        // Incarnations Block-STM threw away, counted with the applier's re-executions.
+        let (mut scheduler_retries, mut scheduler_conflicted) = (0, 0);
+        // Pessimistic runs that touched an account they had not locked.
+        let mut unlocked_runs = Vec::new();
+        // Optimistic speculation runs at most one window ahead of the applier; later windows go
+        // out from the apply loop below. Block-STM outcomes share one lane, each having seen the
+        // whole block before it.
+        let (results, lanes) = match strategy {
+            Strategy::Optimistic => {
+                let window = &tx_refs[..self.depth.lock().depth().min(block_size)];
+                self.speculate_window(header, &block_env, &precompiles, window, &*self.db)
+            }
+            // Block-STM resolves conflicts while executing: every outcome already reflects the
+            // writes of the transactions before it.
+            Strategy::BlockStm => {
+                let (outcomes, stats) = scheduler::run(&*self.db, header.coinbase, block_size, |i, view| {
+                    self.progress.tx_executed();
+                    let mut reader = WrapDatabaseRef(view);
+                    let mut env = txs[i].env(&block_env);
+                    let mut db = WithAnalysisCache::new(&mut reader, &self.analysis);
+                    executor::transact(&mut db, &mut env, &precompiles).map_err(|e| format!("EVM Error: {:?}", e))
+                });
+                scheduler_retries = stats.executions as usize - block_size;
+                scheduler_conflicted = stats.reexecuted as usize;
+                *self.block_stm_stats.lock() += stats;
+                let results: Vec<SpeculativeResult> = outcomes
+                    .into_iter()
+                    .map(|outcome| {
+                        let executed = outcome?;
+                        let rw = ReadWriteSet::from_state(&executed.state, header.coinbase, granularity);
+                        Ok((executed, rw))
+                    })
+                    .collect();
+                (results, Lanes::new(vec![(0..block_size).collect()]))
+            }
+            // Locks order every declared conflict, so outcomes also reflect earlier writes. Runs
+            // that strayed outside their locks are marked for the applier below.
+            Strategy::Pessimistic => {
+                let locks = txs
+                    .iter()
+                    .map(|tx| tx.declared_locations().map(|location| location.address()).collect())
+                    .collect();
+                let (outcomes, stats) = locking::run(&*self.db, header.coinbase, locks, |i, view| {
+                    self.progress.tx_executed();
+                    let mut reader = WrapDatabaseRef(view);
+                    let mut env = txs[i].env(&block_env);
+                    let mut db = WithAnalysisCache::new(&mut reader, &self.analysis);
+                    executor::transact(&mut db, &mut env, &precompiles).map_err(|e| format!("EVM Error: {:?}", e))
+                });
+                *self.lock_stats.lock() += stats;
+                let results: Vec<SpeculativeResult> = outcomes
+                    .into_iter()
+                    .map(|(outcome, unlocked)| {
+                        unlocked_runs.push(unlocked);
+                        let executed = outcome?;
+                        let rw = ReadWriteSet::from_state(&executed.state, header.coinbase, granularity);
+                        Ok((executed, rw))
+                    })
+                    .collect();
+                (results, Lanes::new(vec![(0..block_size).collect()]))
+            }
+            // Nothing runs ahead: the applier executes each tx at its turn.
+            Strategy::Serial => (Vec::new(), Lanes::new(Vec::new())),
+        };
+        if let Some(reads) = &mut speculated_reads {
+            reads.extend(results.iter().flatten().flat_map(|(_, rw)| rw.reads.iter().copied()));
+        }
+
+        // 2. COMMIT PHASE (Parallel Validation / Ordered Apply)
+        // This is where we beat the "Static Analysis" engines.
+        // We only re-execute if a REAL conflict happened.
+        self.progress.set_stage(Stage::Commit);
+        self.db.set_read_stage(ReadStage::Commit);
+
+        // 2a. VALIDATION: Did this Tx read something that was written by a previous Tx in this block?
+        // Checked against the speculative write sets, so it fans out across the commit threads.
+        // Block-STM and lock-based outcomes read in-block versions, so there is nothing to check
+        // them against; a lock-based run that strayed outside its locks may have read anything, so
+        // it stands in as its own blocker.
+        let validation_start = Instant::now();
+        let (blockers, mut validation_busy) = match strategy {
+            Strategy::Optimistic => self.validate(&tx_refs[..results.len()], &lanes.lane_of, &results),
+            Strategy::BlockStm | Strategy::Serial => (vec![None; results.len()], Duration::ZERO),
+            Strategy::Pessimistic => {
+                let blockers = unlocked_runs.iter().enumerate().map(|(i, unlocked)| unlocked.then_some(i)).collect();
+                (blockers, Duration::ZERO)
+            }
+        };
+        let mut validation_wall = validation_start.elapsed();
+
+        // 2b. ORDERED APPLY: one thread installs state strictly in block order.
+        let apply_start = Instant::now();
+        let mut final_gas_used = 0u64;
+        let mut re_exec_count = scheduler_retries;
+        // Transactions whose signature did not recover or that failed pre-validation never reached
+        // the executors.
+        let mut rejected = recovery.invalid as usize + rejections.len();
+        let mut block_access = AccessSet::default();
+        let mut base_fee_burned = U256::ZERO;
+        let mut priority_fees = U256::ZERO;
+        let mut blob_gas_used = 0u64;
+        let mut receipts = Vec::with_capacity(block_size);
+        // Building: what did not fit, and the senders whose later txs must wait behind it.
+        let mut excluded = Vec::new();
+        let mut excluded_senders: HashSet<Address> = HashSet::new();
+        // What each committed run read and wrote, per slot whatever the conflict granularity.
+        let mut traced = self.access_trace.as_ref().map(|_| Vec::with_capacity(block_size));
+        // And the values it wrote, for `flux bisect`.
+        let mut tx_writes = Vec::new();
+        let mut trace = |tx: &FluxTransaction, state: &State, gas: u64| {
+            if self.config.record_tx_writes {
+                tx_writes.push(TxWrites::from_state(tx.id, state));
+            }
+            if let Some(traced) = &mut traced {
+                let rw = ReadWriteSet::from_state(state, header.coinbase, ConflictGranularity::Slot);
+                let mut declared: Vec<Address> = tx.declared_locations().map(|location| location.address()).collect();
+                declared.sort();
+                declared.dedup();
+                traced.push(TracedTx {
+                    id: tx.id,
+                    gas,
+                    declared,
+                    reads: rw.reads,
+                    writes: rw.writes,
+                });
+            }
+        };
+        let base_fee = U256::from(header.base_fee_per_gas);
+        // revm rejects txs priced below the base fee, so everything committed here pays at least it.
+        let mut charge = |tx: &FluxTransaction, gas_used: u64| {
+            let gas = U256::from(gas_used);
+            base_fee_burned += gas * base_fee;
+            priority_fees += gas * (tx.effective_gas_price(header.base_fee_per_gas) - base_fee);
+        };
+        // Skipped txs advance the state like any other but stay out of the profile.
+        let (mut skipped, mut skipped_gas) = (0, 0u64);
+        let mut profile = |tx: &FluxTransaction, gas_used: u64, access: AccessSet| {
+            if tx.skipped {
+                skipped += 1;
+                skipped_gas += gas_used;
+            } else {
+                block_access += access;
+                self.throughput.lock().record(gas_used);
+            }
+        };
+
+        // Speculation is over, so this is the only writer. Writes collect in a block-scoped buffer
+        // that folds repeated updates together and reaches the shards once, after the epilogue.
+        let mut global_db = WriteBuffer::new(&self.db);
+        // Every write applied so far. A re-executed tx can write locations its speculative run
+        // didn't, which 2a never saw, so later reads are checked against these before anything
+        // is applied.
+        let mut applied = VersionTable::default();
+        // Writes of runs that later runs in their lane executed on top of, but that never landed.
+        let mut lost = LostWrites::default();
+        let mut next_lane = lanes.lanes.len();
+        let speculated = results.len();
+        let mut pending: Vec<PendingTx> = results
+            .into_iter()
+            .zip(blockers)
+            .zip(lanes.lane_of)
+            .map(|((result, blocker), lane)| PendingTx {
+                result: Some(result),
+                blocker,
+                snapshot: 0,
+                lane,
+                coinbase: coinbase_pre_block,
+                retries: 0,
+            })
+            .chain((speculated..block_size).map(|_| PendingTx {
+                result: None,
+                blocker: None,
+                snapshot: 0,
+                lane: mvcc::UNSEEN,
+                coinbase: coinbase_pre_block,
+                retries: 0,
+            }))
+            .collect();
+        let mut rounds = 0;
+        // Runs of the current window so far, and how many of them were thrown away.
+        let (mut window_size, mut aborted) = (speculated, 0);
+
+        let mut i = 0;
+        while i < block_size {
+            let tx = &txs[i];
+            let Some(res) = pending[i].result.take() else {
+                if strategy == Strategy::Serial {
+                    // SERIAL: executed on top of everything applied so far, so it is never stale.
+                    let outcome = self.execute_speculatively(header, &block_env, &precompiles, tx, &global_db);
+                    let result = outcome.map(|executed| {
+                        let rw = ReadWriteSet::from_state(&executed.state, header.coinbase, granularity);
+                        (executed, rw)
+                    });
+                    self.progress.tx_executed();
+                    pending[i] = PendingTx {
+                        result: Some(result),
+                        blocker: None,
+                        snapshot: i,
+                        lane: next_lane,
+                        coinbase: coinbase_balance(&global_db, header.coinbase),
+                        retries: 0,
+                    };
+                    next_lane += 1;
+                    continue;
+                }
+                // NEXT WINDOW: the applier caught up with speculation, so the next window goes out
+                // against the state applied so far.
+                let depth = {
+                    let mut depth = self.depth.lock();
+                    depth.observe(window_size, aborted);
+                    depth.depth()
+                };
+                let window = &tx_refs[i..(i + depth).min(block_size)];
+                let (results, lanes) = self.speculate_window(header, &block_env, &precompiles, window, &global_db);
+                if let Some(reads) = &mut speculated_reads {
+                    reads.extend(results.iter().flatten().flat_map(|(_, rw)| rw.reads.iter().copied()));
+                }
+                let validation_start = Instant::now();
+                let (blockers, busy) = self.validate(window, &lanes.lane_of, &results);
+                validation_busy += busy;
+                validation_wall += validation_start.elapsed();
+
+                let coinbase = coinbase_balance(&global_db, header.coinbase);
+                for (k, ((result, blocker), lane)) in results.into_iter().zip(blockers).zip(lanes.lane_of).enumerate() {
+                    pending[i + k] = PendingTx {
+                        result: Some(result),
+                        blocker: blocker.map(|b| i + b),
+                        snapshot: i,
+                        lane: next_lane + lane,
+                        coinbase,
+                        retries: 0,
+                    };
+                }
+                next_lane += lanes.lanes.len();
+                (window_size, aborted) = (window.len(), 0);
+                continue;
+            };
+            // The per-block blob cap is a block-level rule revm can't see; later blob txs that
+            // would overflow it are dropped like any other invalid tx.
+            if blob_gas_used + tx.blob_gas() > MAX_BLOB_GAS_PER_BLOCK {
+                self.progress.tx_committed();
+                rejected += 1;
+                if let Ok((_, rw)) = &res {
+                    lost.record(pending[i].lane, &rw.writes);
+                }
+                i += 1;
+                continue;
+            }
+            // The gas pool: a builder only includes a tx its whole gas limit still fits into. Later
+            // txs from the same sender would miss its nonce, so they wait with it.
+            if building
+                && (tx.gas_limit > header.gas_limit.saturating_sub(final_gas_used)
+                    || excluded_senders.contains(&tx.caller))
+            {
+                self.progress.tx_committed();
+                excluded.push(tx.id);
+                excluded_senders.insert(tx.caller);
+                if let Ok((_, rw)) = &res {
+                    lost.record(pending[i].lane, &rw.writes);
+                }
+                i += 1;
+                continue;
+            }
+            // A run revm rejected up front is only final if its sender hasn't changed since: an
+            // earlier tx from the same sender may be what fills its nonce or funds it.
+            let (executed, rw) = match res {
+                Ok((executed, rw)) => (Some(executed), rw),
+                Err(_) => (None, ReadWriteSet::rejected(tx.caller)),
+            };
+            let stale = pending[i].is_stale(&rw, &applied, &lost);
+            match executed {
+                None if !stale => rejected += 1, // Invalid txs are excluded, not charged
+                Some(mut executed) if !stale => {
+                    // HAPPY PATH: every read is still current, install the speculative writes.
+                    let committed = coinbase_balance(&global_db, header.coinbase);
+                    mvcc::rebase_coinbase(&mut executed.state, header.coinbase, pending[i].coinbase, committed);
+                    applied.record(i, pending[i].lane, &rw.writes);
+                    trace(tx, &executed.state, executed.result.gas_used());
+                    let env = tx.env(&block_env);
+                    let (exec_result, access) = executor::commit(&mut global_db, &env, &precompiles, executed);
+                    let gas_used = exec_result.gas_used();
+                    final_gas_used += gas_used;
+                    profile(tx, gas_used, access);
+                    charge(tx, gas_used);
+                    blob_gas_used += tx.blob_gas();
+                    receipts.push(Receipt::new(tx.id, tx.tx_type(), &exec_result, final_gas_used));
+                }
+                _ if rounds < self.config.retry_rounds => {
+                    // RETRY: back to the executors against the applied state, together with every
+                    // later stale tx whose blocker has committed. Ones still waiting on a tx from
+                    // here on would only conflict again, so they keep their result until their
+                    // turn, as do the ones nothing has invalidated.
+                    rounds += 1;
+                    lost.record(pending[i].lane, &rw.writes);
+                    let mut deferred = 0;
+                    let batch: Vec<usize> = std::iter::once(i)
+                        .chain((i + 1..block_size).filter(|&j| match (&pending[j].result, pending[j].blocker) {
+                            (_, Some(blocker)) if blocker >= i => {
+                                deferred += 1;
+                                false
+                            }
+                            (Some(Ok((_, rw))), _) => pending[j].is_stale(rw, &applied, &lost),
+                            (Some(Err(_)), _) => {
+                                pending[j].is_stale(&ReadWriteSet::rejected(txs[j].caller), &applied, &lost)
+                            }
+                            _ => false,
+                        }))
+                        .collect();
+                    let batch_txs: Vec<&FluxTransaction> = batch.iter().map(|&j| &txs[j]).collect();
+                    let spread = Lanes::spread(batch.len());
+                    let retried = self.speculate(header, &block_env, &precompiles, &batch_txs, &spread, &global_db);
+                    let validation_start = Instant::now();
+                    let (blockers, busy) = self.validate(&batch_txs, &spread.lane_of, &retried);
+                    validation_busy += busy;
+                    validation_wall += validation_start.elapsed();
+
+                    let coinbase = coinbase_balance(&global_db, header.coinbase);
+                    for ((&j, result), blocker) in batch.iter().zip(retried).zip(blockers) {
+                        pending[j] = PendingTx {
+                            result: Some(result),
+                            blocker: blocker.map(|k| batch[k]),
+                            snapshot: i,
+                            lane: next_lane,
+                            coinbase,
+                            retries: pending[j].retries + 1,
+                        };
+                        next_lane += 1;
+                    }
+                    re_exec_count += batch.iter().filter(|&&j| !txs[j].skipped).count();
+                    aborted += batch.len();
+                    let mut stats = self.commit_stats.lock();
+                    stats.retry_rounds += 1;
+                    stats.retried += batch.len() as u64;
+                    stats.deferred += deferred;
+                    continue;
+                }
+                _ => {
+                    // SAD PATH: Conflict Detected. Re-execute serially.
+                    if !tx.skipped {
+                        re_exec_count += 1;
+                    }
+                    aborted += 1;
+                    pending[i].retries += 1;
+                    lost.record(pending[i].lane, &rw.writes);
+                    if let Some(forensics) = &self.config.forensics {
+                        let attempts = pending[i].retries;
+                        if attempts > forensics.max_retries {
+                            let reason =
+                                format!("re-executed {} time(s), limit {}", attempts, forensics.max_retries);
+                            self.capture(reason, header, tx, &global_db, &precompiles);
+                        }
+                    }
+
+                    // Run directly on latest state
+                    let mut env = tx.env(&block_env);
+                    let exec_start = Instant::now();
+                    let mut reader = WrapDatabaseRef(&global_db);
+                    let mut db = WithAnalysisCache::new(&mut reader, &self.analysis);
+                    let executed = executor::transact(&mut db, &mut env, &precompiles);
+                    let elapsed = exec_start.elapsed();
+                    if let Some(budget) = self.tx_budget().filter(|budget| elapsed > *budget) {
+                        // Nothing is applied yet, so global_db is still this tx's pre-state.
+                        let reason = format!("serial re-execution took {:?}, budget {:?}", elapsed, budget);
+                        self.capture(reason, header, tx, &global_db, &precompiles);
+                    }
+                    let outcome = executed.map(|executed| {
+                        let writes = ReadWriteSet::from_state(&executed.state, header.coinbase, granularity).writes;
+                        applied.record(i, mvcc::UNSEEN, &writes);
+                        trace(tx, &executed.state, executed.result.gas_used());
+                        executor::commit(&mut global_db, &env, &precompiles, executed)
+                    });
+
+                    match outcome {
+                        Ok((serial_res, serial_access)) => {
+                            let gas_used = serial_res.gas_used();
+                            final_gas_used += gas_used;
+                            profile(tx, gas_used, serial_access);
+                            charge(tx, gas_used);
+                            blob_gas_used += tx.blob_gas();
+                            receipts.push(Receipt::new(tx.id, tx.tx_type(), &serial_res, final_gas_used));
+                        }
+                        Err(_) => rejected += 1,
+                    }
+                }
+            }
+            self.progress.tx_committed();
+            i += 1;
+        }
+        // Every transaction is in; what is left works on the block as a whole.
+        self.progress.set_stage(Stage::Finalize);
+        if strategy == Strategy::Optimistic {
+            self.depth.lock().observe(window_size, aborted);
+        }
+        if let (Some(writer), Some(txs)) = (&self.access_trace, traced) {
+            let block = TracedBlock {
+                number: header.number,
+                txs,
+            };
+            if let Err(e) = writer.lock().write_block(&block) {
+                eprintln!("[FLUX] Block {} access trace failed: {}", header.number, e);
+            }
+        }
+
+        if let (Some(prefetched), Some(reads)) = (&prefetched, &speculated_reads) {
+            // Read sets are only as fine as the conflict granularity, so compare at that level.
+            let prefetched: HashSet<Location> =
+                prefetched.iter().map(|location| granularity.coarsen(*location)).collect();
+            *self.prefetch_stats.lock() += PrefetchStats {
+                blocks: 1,
+                prefetched: prefetched.len() as u64,
+                used: prefetched.intersection(reads).count() as u64,
+                reads: reads.len() as u64,
+            };
+        }
+
+        // 3. BLOCK EPILOGUE: withdrawals are credited after every transaction.
+        if SpecId::enabled(spec, SpecId::SHANGHAI) {
+            if let Err(e) = system::apply_withdrawals(&mut global_db, &header.withdrawals) {
+                eprintln!("[FLUX] Block {} epilogue failed: {}", header.number, e);
+            }
+        }
+
+        let buffered = global_db.flush();
+        *self.buffer_stats.lock() += buffered;
+
+        // 4. PERSIST: write the block's committed state back as one batch, if the backend keeps it.
+        let backend = self.db.backend().inner();
+        let mut persisted = true;
+        if backend.accepts_writes() {
+            let changes = self.db.take_changes();
+            if let Err(e) = backend.commit_batch(header.number, &changes) {
+                eprintln!("[FLUX] Block {} persist failed: {}", header.number, e);
+                persisted = false;
+                // The caches now hold the only copy.
+                self.db.pin(changes.iter().map(|change| change.address));
+            }
+        } else {
+            self.db.discard_changes();
+        }
+
+        // 5. STATE ROOT: fold the block into the state trie, and only pay for rehashing it when
+        // there is a header root to check against. An experimental commitment is always paid
+        // for, since measuring that is its whole point, but never checked against the header.
+        self.db.set_read_stage(ReadStage::StateRoot);
+        let changeset = self.db.take_changeset();
+        self.update_state_trie(&changeset);
+        let commitment = self.config.commitment;
+        let state_root = (header.state_root.is_some() || commitment != CommitmentScheme::Mpt)
+            .then(|| match self.state_root() {
+                Ok(root) => Some(root),
+                Err(e) => {
+                    eprintln!("[FLUX] Block {} state root failed: {}", header.number, e);
+                    None
+                }
+            })
+            .flatten();
+
+        // 6. DIFF: what the block changed, from its changeset and the state it left behind.
+        if let Some(dir) = &self.config.state_diffs {
+            let written = StateDiff::compute(header.number, &changeset, &*self.db)
+                .and_then(|diff| Ok((diff.write(dir)?, diff)));
+            match written {
+                Ok((path, diff)) => println!(
+                    "[FLUX] State diff: {} accounts, {} slots -> {}",
+                    diff.accounts.len(),
+                    diff.slots(),
+                    path.display()
+                ),
+                Err(e) => eprintln!("[FLUX] Block {} state diff failed: {}", header.number, e),
+            }
+        }
+
+        // 7. PRUNE: retain this block's changeset, then drop what the prune mode no longer keeps.
+        let mut history = self.history.lock();
+        history.push(header.number, changeset);
+        if history.mode().prunes_dead_entries() && persisted && backend.reflects_deletions() {
+            let (entries, bytes) = self.db.prune_dead();
+            history.record_dead_pruned(entries, bytes);
+        }
+        drop(history);
+        self.db.evict();
+        self.db.set_read_stage(ReadStage::Other);
+
+        self.progress.set_stage(Stage::Idle);
+
+        let mut stats = self.commit_stats.lock();
+        stats.validation_wall += validation_wall;
+        stats.validation_busy += validation_busy;
+        stats.filter += applied.filter_stats();
+        stats.filter += lost.filter_stats();
+        stats.apply_wall += apply_start.elapsed();
+        drop(stats);
+
+        println!("[FLUX] Block Complete.");
+        println!("       Total Gas: {}", final_gas_used);
+        // Txs that ran more than once, however many times.
+        let conflicted = scheduler_conflicted + pending.iter().filter(|tx| tx.retries > 0).count();
+        println!("       Re-executions: {} (Conflict Rate: {:.2}%)", 
+            re_exec_count, 
+            (conflicted as f64 / block_size as f64) * 100.0
+        );
+        let blob_fee_burned = U256::from(blob_gas_used) * U256::from(header.blob_gas_price());
+        let receipts_root = receipt::receipts_root(&receipts);
+        let logs_bloom = receipt::block_bloom(&receipts);
+        println!("       Fees: {} wei burned, {} wei in tips", base_fee_burned, priority_fees);
+        println!("       Receipts Root: {}", receipts_root);
+        match (state_root, commitment) {
+            (Some(root), CommitmentScheme::Mpt) => {
+                println!("       State Root: {} ({} nodes rehashed)", root, self.trie_stats.lock().last_rehashed)
+            }
+            (Some(root), scheme) => println!(
+                "       State Commitment ({}): {} ({} scalar multiplications)",
+                scheme,
+                root,
+                self.trie_stats.lock().last_rehashed
+            ),
+            (None, _) => {}
+        }
+        if blob_gas_used > 0 {
+            println!("       Blob Gas: {} ({} wei burned)", blob_gas_used, blob_fee_burned);
+        }
+
+        BlockResult {
+            tx_count: block_size + recovery.invalid as usize + rejections.len() - excluded.len(),
+            gas_used: final_gas_used,
+            re_executions: re_exec_count,
+            rejected,
+            access: block_access,
+            base_fee_per_gas: header.base_fee_per_gas,
+            next_base_fee_per_gas: header.next_base_fee(final_gas_used),
+            base_fee_burned,
+            priority_fees,
+            blob_gas_used,
+            blob_fee_burned,
+            next_excess_blob_gas: header.next_excess_blob_gas(blob_gas_used),
+            receipts,
+            receipts_root,
+            logs_bloom,
+            rejections,
+            excluded,
+            state_root: state_root.filter(|_| commitment == CommitmentScheme::Mpt),
+            skipped,
+            skipped_gas,
+            tx_writes,
+        }
+    }
+
+    /// Splits `txs`, a window of the block, into lanes as configured and speculates them against
+    /// `state`. Predicted dispatch learns from the outcome and reports how its prediction did.
+    fn speculate_window<DB>(
+        &self,
+        header: &BlockHeader,
+        block_env: &Env,
+        precompiles: &Precompiles,
+        txs: &[&FluxTransaction],
+        state: &DB,
+    ) -> (Vec<SpeculativeResult>, Lanes)
+    where
+        DB: DatabaseRef<Error = String> + Sync,
+    {
+        let mut predicted = None;
+        let lanes = match self.config.dispatch {
+            Dispatch::Spread => Lanes::spread(txs.len()),
+            Dispatch::Grouped(grouping) => grouping.lanes(txs, header.coinbase),
+            Dispatch::Predicted => {
+                let patterns = self.access_patterns.lock();
+                let prediction = predict::partition(txs, header.coinbase, self.config.conflict_granularity, &patterns);
+                predicted = Some(prediction.conflicts);
+                prediction.lanes
+            }
+        };
+        if self.config.dispatch != Dispatch::Spread {
+            *self.dispatch_stats.lock() += DispatchStats::of(&lanes);
+        }
+
+        let results = self.speculate(header, block_env, precompiles, txs, &lanes, state);
+
+        if let Some(predicted) = &predicted {
+            *self.prediction_stats.lock() += PredictionStats::compare(predicted, &predict::observed_conflicts(&results));
+            self.access_patterns.lock().learn(txs, &results);
+        }
+        (results, lanes)
+    }
+
+    /// Executes `txs` in parallel, one task per lane. A transaction alone in its lane runs against
+    /// `state` as if it were the only transaction; the rest of a lane runs in order on one
+    /// executor, each on top of the writes of the ones before it.
+    fn speculate<DB>(
+        &self,
+        header: &BlockHeader,
+        block_env: &Env,
+        precompiles: &Precompiles,
+        txs: &[&FluxTransaction],
+        lanes: &Lanes,
+        state: &DB,
+    ) -> Vec<SpeculativeResult>
+    where
+        DB: DatabaseRef<Error = String> + Sync,
+    {
+        let granularity = self.config.conflict_granularity;
+        // We use Rayon to blast these transactions across all 16 cores.
+        // Each Tx runs on a *snapshot* of the DB, assuming no conflicts.
+        // Every lane is a job of its own in rayon's per-worker deques. Left to split adaptively, a
+        // worker runs its share of lanes as one sequential batch, and an idle worker can't steal
+        // the lanes queued behind a long transaction in it.
+        let mut results: Vec<(usize, SpeculativeResult)> = lanes
+            .lanes
+            .par_iter()
+            .with_max_len(1)
+            .flat_map_iter(|lane| {
+                let mut overlay = (lane.len() > 1).then(|| LaneOverlay::new(state, header.coinbase));
+                let mut results = Vec::with_capacity(lane.len());
+                for &i in lane {
+                    let outcome = match &mut overlay {
+                        Some(overlay) => {
+                            let outcome = self.execute_speculatively(header, block_env, precompiles, txs[i], &*overlay);
+                            if let Ok(executed) = &outcome {
+                                overlay.apply(&executed.state);
+                            }
+                            outcome
+                        }
+                        None => self.execute_speculatively(header, block_env, precompiles, txs[i], state),
+                    };
+                    // D. Extract the Read/Write Set for Conflict Detection
+                    let result = outcome.map(|executed| {
+                        let rw = ReadWriteSet::from_state(&executed.state, header.coinbase, granularity);
+                        (executed, rw)
+                    });
+                    results.push((i, result));
+                }
+                results
+            })
+            .collect();
+        results.sort_unstable_by_key(|(i, _)| *i);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    fn execute_speculatively<DB>(
+        &self,
+        header: &BlockHeader,
+        block_env: &Env,
+        precompiles: &Precompiles,
+        tx: &FluxTransaction,
+        state: &DB,
+    ) -> Result<ResultAndState, String>
+    where
+        DB: DatabaseRef<Error = String>,
+    {
+        // A. Snapshot: revm buffers the tx's writes in its journal; nothing touches global
+        // state until the committer decides this execution is still valid.
+        let mut reader = WrapDatabaseRef(state);
+
+        // B. Configure EVM
+        let mut env = tx.env(block_env);
+
+        // C. Execute
+        let exec_start = Instant::now();
+        let mut db = WithAnalysisCache::new(&mut reader, &self.analysis);
+        let outcome = executor::transact(&mut db, &mut env, precompiles);
+        self.progress.tx_executed();
+        let elapsed = exec_start.elapsed();
+        if let Some(budget) = self.tx_budget().filter(|budget| elapsed > *budget) {
+            let reason = format!("execution took {:?}, budget {:?}", elapsed, budget);
+            self.capture(reason, header, tx, state, precompiles);
+        }
+        outcome.map_err(|e| format!("EVM Error: {:?}", e))
+    }
+
+    /// Finds every tx whose reads overlap the writes of an earlier tx in `results` outside its
+    /// lane, and the tx it waits on: the last of those txs that first wrote one of the reads.
+    /// Returns the blockers plus the CPU time spent checking, summed over commit threads.
+    fn validate(
+        &self,
+        txs: &[&FluxTransaction],
+        lane_of: &[usize],
+        results: &[SpeculativeResult],
+    ) -> (Vec<Option<usize>>, Duration) {
+        const CHUNK: usize = 256;
+
+        // A sender's txs must run in nonce order, so each also waits on the sender's previous one,
+        // even when its own run failed against the pre-block state (a nonce gap, say).
+        let mut last_sent: HashMap<Address, usize> = HashMap::new();
+        let previous: Vec<Option<usize>> =
+            txs.iter().enumerate().map(|(i, tx)| last_sent.insert(tx.caller, i)).collect();
+
+        // Earliest writer of each address, and the earliest from any other lane. A read conflicts
+        // iff the earliest writer outside the reader's lane precedes it, which is exactly the
+        // prefix-union check a serial committer would do; writes from earlier in its own lane
+        // were seen, not missed.
+        let mut first_writers: HashMap<Location, (usize, Option<usize>)> = HashMap::new();
+        for (i, res) in results.iter().enumerate() {
+            if let Ok((_, rw)) = res {
+                for location in &rw.writes {
+                    let (first, other_lane) = first_writers.entry(*location).or_insert((i, None));
+                    if other_lane.is_none() && lane_of[*first] != lane_of[i] {
+                        *other_lane = Some(i);
+                    }
+                }
+            }
+        }
+        let mut written = LocationFilter::with_capacity(first_writers.len());
+        first_writers.keys().for_each(|location| written.insert(location));
+
+        let busy_nanos = AtomicU64::new(0);
+        let filter_stats = Mutex::new(FilterStats::default());
+        let check_chunk = |(chunk_idx, chunk): (usize, &[SpeculativeResult])| {
+            let started = Instant::now();
+            let mut stats = FilterStats::default();
+            let flags: Vec<Option<usize>> = chunk
+                .iter()
+                .enumerate()
+                .map(|(offset, res)| {
+                    let i = chunk_idx * CHUNK + offset;
+                    let lane = lane_of[i];
+                    let outside = |&(first, other_lane): &(usize, Option<usize>)| {
+                        if lane_of[first] != lane {
+                            Some(first)
+                        } else {
+                            other_lane
+                        }
+                    };
+                    let mut conflict = |reads: &[Location]| {
+                        reads
+                            .iter()
+                            .filter_map(|r| {
+                                let passed = written.may_contain(r);
+                                let writers = if passed { first_writers.get(r) } else { None };
+                                stats.record(passed, writers.is_some());
+                                writers.and_then(outside).filter(|&w| w < i)
+                            })
+                            .max()
+                    };
+                    let blocker = match res {
+                        Ok((_, rw)) => conflict(&rw.reads),
+                        Err(_) => conflict(&ReadWriteSet::rejected(txs[i].caller).reads),
+                    };
+                    blocker.max(previous[i].filter(|&p| lane_of[p] != lane))
+                })
+                .collect();
+            busy_nanos.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+            *filter_stats.lock() += stats;
+            flags
+        };
+
+        let flags: Vec<Option<usize>> = match &self.commit_pool {
+            Some(pool) => pool.install(|| {
+                results
+                    .par_chunks(CHUNK)
+                    .enumerate()
+                    .flat_map_iter(check_chunk)
+                    .collect()
+            }),
+            None => results.chunks(CHUNK).enumerate().flat_map(check_chunk).collect(),
+        };
+
+        self.commit_stats.lock().filter += filter_stats.into_inner();
+        (flags, Duration::from_nanos(busy_nanos.into_inner()))
+    }
+
+    fn tx_budget(&self) -> Option<Duration> {
+        self.config.forensics.as_ref().map(|f| f.tx_budget)
+    }
+
+    fn capture<DB>(
+        &self,
+        reason: String,
+        header: &BlockHeader,
+        tx: &FluxTransaction,
+        pre: &DB,
+        precompiles: &Precompiles,
+    ) where
+        DB: DatabaseRef,
+        DB::Error: std::fmt::Debug,
+    {
+        let Some(forensics) = &self.config.forensics else {
+            return;
+        };
+        let capture = Capture::record(reason, &self.config.chain, header, tx, pre, precompiles);
+        match capture.write(&forensics.dir) {
+            Ok(path) => eprintln!(
+                "[FLUX][FORENSICS] tx {} captured ({}) -> {}",
+                tx.id,
+                capture.reason,
+                path.display()
+            ),
+            Err(e) => eprintln!("[FLUX][FORENSICS] failed to write capture for tx {}: {}", tx.id, e),
+        }
+    }
+
+    pub fn disk_stats(&self) -> DiskStats {
+        self.db.backend().stats()
+    }
+
+    /// `None` when reads run on the executors.
+    #[cfg(all(feature = "async-io", target_os = "linux"))]
+    pub fn io_stats(&self) -> Option<IoStats> {
+        self.db.backend().io_stats()
+    }
+
+    /// `None` for in-memory state.
+    pub fn backend_stats(&self) -> Option<BackendStats> {
+        self.db.backend().inner().stats()
+    }
+
+    pub fn trie_stats(&self) -> TrieStats {
+        self.trie_stats.lock().clone()
+    }
+
+    pub fn prune_stats(&self) -> PruneStats {
+        self.history.lock().stats()
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.db.cache_stats()
+    }
+
+    pub fn buffer_stats(&self) -> BufferStats {
+        *self.buffer_stats.lock()
+    }
+
+    /// `None` unless `EngineConfig::prefetch_state` is on.
+    pub fn prefetch_stats(&self) -> Option<PrefetchStats> {
+        self.config.prefetch_state.then(|| *self.prefetch_stats.lock())
+    }
+
+    pub fn block_stm_stats(&self) -> Option<BlockStmStats> {
+        (self.config.strategy == Strategy::BlockStm).then(|| *self.block_stm_stats.lock())
+    }
+
+    /// `None` unless `EngineConfig::prevalidate` is on.
+    pub fn validation_stats(&self) -> Option<ValidationStats> {
+        self.config.prevalidate.then(|| *self.validation_stats.lock())
+    }
+
+    pub fn recovery_stats(&self) -> Option<RecoveryStats> {
+        let stats = *self.recovery_stats.lock();
+        (stats.signatures > 0).then_some(stats)
+    }
+
+    pub fn lock_stats(&self) -> Option<LockStats> {
+        (self.config.strategy == Strategy::Pessimistic).then(|| *self.lock_stats.lock())
+    }
+
+    pub fn dispatch_stats(&self) -> Option<DispatchStats> {
+        let grouped = self.config.strategy == Strategy::Optimistic && self.config.dispatch != Dispatch::Spread;
+        grouped.then(|| *self.dispatch_stats.lock())
+    }
+
+    pub fn speculation_stats(&self) -> Option<SpeculationStats> {
+        let windowed = self.config.strategy == Strategy::Optimistic
+            && self.config.speculation_depth != SpeculationDepth::Unbounded;
+        windowed.then(|| self.depth.lock().stats())
+    }
+
+    pub fn prediction_stats(&self) -> Option<PredictionStats> {
+        let predicted = self.config.strategy == Strategy::Optimistic && self.config.dispatch == Dispatch::Predicted;
+        predicted.then(|| *self.prediction_stats.lock())
+    }
+
+    pub fn shard_stats(&self) -> ShardStats {
+        self.db.stats()
+    }
+
+    pub fn analysis_stats(&self) -> AnalysisStats {
+        self.analysis.stats()
+    }
+
+    pub fn commit_stats(&self) -> CommitStats {
+        self.commit_stats.lock().clone()
+    }
+
+    /// Burst vs sustained gas throughput since the engine was created.
+    pub fn throughput_report(&self) -> ThroughputReport {
+        self.throughput.lock().report()
+    }
+}
+
+fn coinbase_balance<DB: DatabaseRef>(state: &DB, coinbase: Address) -> U256 {
+    state.basic(coinbase).ok().flatten().map(|info| info.balance).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use revm::primitives::{Bytecode, Bytes};
+
+    const EOA: Address = Address::repeat_byte(0xa0);
+    const CONTRACT: Address = Address::repeat_byte(0xc0);
+    const CREATED: Address = Address::repeat_byte(0xc1);
+
+    fn archive_engine() -> FluxEngine {
+        FluxEngine::new(EngineConfig {
+            watchdog_timeout: None,
+            state_shards: 4,
+            prune: PruneMode::Archive,
+            ..EngineConfig::default()
+        })
+        .unwrap()
+    }
+
+    fn contract(balance: u64, runtime: &'static [u8]) -> AccountInfo {
+        let code = Bytecode::new_raw(Bytes::from_static(runtime));
+        AccountInfo::new(U256::from(balance), 1, code.hash_slow(), code)
+    }
+
+    fn write(address: Address, info: Option<AccountInfo>, storage: &[(u64, u64)]) -> StateChange {
+        StateChange {
+            address,
+            info,
+            storage_cleared: false,
+            storage: storage.iter().map(|&(slot, value)| (U256::from(slot), U256::from(value))).collect(),
+        }
+    }
+
+    fn eoa(balance: u64, nonce: u64) -> Option<AccountInfo> {
+        Some(AccountInfo {
+            balance: U256::from(balance),
+            nonce,
+            ..Default::default()
+        })
+    }
+
+    fn slot(engine: &FluxEngine, address: Address, slot: u64) -> u64 {
+        DatabaseRef::storage(&*engine.db, address, U256::from(slot)).unwrap().to::<u64>()
+    }
+
+    /// Block 1 of both tests: an EOA and a contract with two slots.
+    fn block_one() -> Vec<StateChange> {
+        vec![
+            write(EOA, eoa(100, 0), &[]),
+            write(CONTRACT, Some(contract(5, &[0x60, 0x00, 0x56])), &[(1, 11), (2, 22)]),
+        ]
+    }
+
+    #[test]
+    fn unwinding_restores_each_earlier_root() {
+        let engine = archive_engine();
+        let first = engine.commit_batch(1, block_one()).unwrap();
+        // Block 2 creates a contract and rewrites, zeroes and adds slots.
+        let second = engine
+            .commit_batch(
+                2,
+                vec![
+                    write(EOA, eoa(90, 1), &[]),
+                    write(CONTRACT, Some(contract(5, &[0x60, 0x00, 0x56])), &[(1, 12), (2, 0), (3, 33)]),
+                    write(CREATED, Some(contract(0, &[0x60, 0x01, 0x56])), &[(1, 1), (2, 2)]),
+                ],
+            )
+            .unwrap();
+        // Block 3 wipes the contract block 2 created; block 4 only writes.
+        let mut wipe = write(CREATED, None, &[]);
+        wipe.storage_cleared = true;
+        let third = engine.commit_batch(3, vec![wipe]).unwrap();
+        let fourth = engine
+            .commit_batch(
+                4,
+                vec![write(EOA, eoa(80, 2), &[]), write(CONTRACT, Some(contract(7, &[0x60, 0x00, 0x56])), &[(1, 13)])],
+            )
+            .unwrap();
+        assert_eq!(HashSet::from([first, second, third, fourth]).len(), 4);
+
+        assert_eq!(engine.unwind_to(4).unwrap(), fourth);
+        assert_eq!(engine.unwind_to(3).unwrap(), third);
+        assert_eq!((slot(&engine, CONTRACT, 1), slot(&engine, CONTRACT, 3)), (12, 33));
+
+        // Back over both the contract's creation and its wipe.
+        assert_eq!(engine.unwind_to(1).unwrap(), first);
+        assert_eq!((slot(&engine, CONTRACT, 1), slot(&engine, CONTRACT, 2), slot(&engine, CONTRACT, 3)), (11, 22, 0));
+        let eoa_info = DatabaseRef::basic(&*engine.db, EOA).unwrap().unwrap();
+        assert_eq!((eoa_info.balance, eoa_info.nonce), (U256::from(100), 0));
+        assert!(DatabaseRef::basic(&*engine.db, CREATED).unwrap().is_none_or(|info| info.is_empty()));
+        assert_eq!(slot(&engine, CREATED, 1), 0);
+
+        // The same as a chain that only ever ran block 1, hashed from scratch.
+        let fresh = archive_engine();
+        assert_eq!(fresh.commit_batch(1, block_one()).unwrap(), first);
+        // And the history ends at block 1 now.
+        assert!(engine.unwind_to(2).is_err());
+    }
+
+    #[test]
+    fn refuses_to_unwind_a_wipe_of_an_earlier_contract() {
+        let engine = archive_engine();
+        let first = engine.commit_batch(1, block_one()).unwrap();
+        let mut wipe = write(CONTRACT, None, &[]);
+        wipe.storage_cleared = true;
+        let second = engine.commit_batch(2, vec![wipe]).unwrap();
+
+        let error = engine.unwind_to(1).unwrap_err();
+        assert_eq!(
+            error,
+            format!(
+                "the storage of contract {} was wiped after block 1; its earlier storage was not kept",
+                CONTRACT
+            )
+        );
+        // Nothing was undone, and block 2 is still there to unwind to.
+        assert_ne!(engine.state_root().unwrap(), first);
+        assert_eq!(engine.unwind_to(2).unwrap(), second);
+    }
+}
@@ -0,0 +1,330 @@
+/*
+ * FLUX ENGINE - LOCK-BASED EXECUTION
+ * `--strategy pessimistic`: every transaction locks the accounts it declares (sender, target,
+ * access list) before it executes, and each lock is granted in block order, so a transaction
+ * runs only once every earlier transaction sharing one of its accounts has finished, on top of
+ * their writes. Locks are per account: any call reads its target's code and balance, so slot
+ * locks on a contract would queue behind its account lock anyway.
+ *
+ * A transaction that touches an account it did not lock may have read it mid-block in any order,
+ * so its run is flagged for the applier to re-execute, the way optimistic conflicts are.
+ */
+
+use crate::state::buffer::coalesce;
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use revm::db::DatabaseRef;
+use revm::primitives::{Account, AccountInfo, Address, Bytecode, ResultAndState, State, B256, U256};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+type Outcome = Result<ResultAndState, String>;
+
+/// The writes of every transaction that has finished so far, over `state`. Whoever holds an
+/// account's lock is the only one reading or writing it, so per account this is always the
+/// state as of the transaction's turn. The coinbase is read from `state` and never written, as
+/// in the other strategies (its credits are rebased when applied).
+pub struct LockedState<'a, DB> {
+    state: &'a DB,
+    coinbase: Address,
+    accounts: DashMap<Address, Account>,
+    contracts: DashMap<B256, Bytecode>,
+}
+
+impl<DB> LockedState<'_, DB> {
+    fn apply(&self, changes: &State) {
+        for (address, account) in changes {
+            if !account.is_touched() || *address == self.coinbase {
+                continue;
+            }
+            if let Some(code) = account.info.code.as_ref().filter(|code| !code.is_empty()) {
+                self.contracts.insert(account.info.code_hash, code.clone());
+            }
+            match self.accounts.get_mut(address) {
+                Some(mut earlier) => coalesce(&mut earlier, account.clone()),
+                None => {
+                    self.accounts.insert(*address, account.clone());
+                }
+            }
+        }
+    }
+}
+
+impl<DB: DatabaseRef> DatabaseRef for LockedState<'_, DB> {
+    type Error = DB::Error;
+
+    fn basic(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        match self.accounts.get(&address) {
+            Some(account) if account.is_selfdestructed() => Ok(None),
+            Some(account) => Ok(Some(account.info.clone())),
+            None => self.state.basic(address),
+        }
+    }
+
+    fn code_by_hash(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        match self.contracts.get(&code_hash) {
+            Some(code) => Ok(code.clone()),
+            None => self.state.code_by_hash(code_hash),
+        }
+    }
+
+    fn storage(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        if let Some(account) = self.accounts.get(&address) {
+            if let Some(slot) = account.storage.get(&index) {
+                return Ok(slot.present_value);
+            }
+            if account.is_selfdestructed() || account.is_created() {
+                return Ok(U256::ZERO);
+            }
+        }
+        self.state.storage(address, index)
+    }
+
+    fn block_hash(&self, number: U256) -> Result<B256, Self::Error> {
+        self.state.block_hash(number)
+    }
+}
+
+struct Locker<'a, DB, F> {
+    view: LockedState<'a, DB>,
+    execute: F,
+    locks: Vec<HashSet<Address>>,
+    /// The next holder of each lock a transaction holds.
+    successors: Vec<Vec<usize>>,
+    /// Earlier holders each transaction still waits on.
+    waiting: Vec<AtomicUsize>,
+    outcomes: Vec<Mutex<Option<(Outcome, bool)>>>,
+}
+
+/// Executes a block's transactions under per-account locks on the current rayon pool.
+/// `locks[i]` are the accounts transaction `i` declares; `execute` runs it against the view it is
+/// given. Returns each outcome, and whether it touched an account outside its locks.
+pub fn run<DB, F>(
+    state: &DB,
+    coinbase: Address,
+    mut locks: Vec<HashSet<Address>>,
+    execute: F,
+) -> (Vec<(Outcome, bool)>, LockStats)
+where
+    DB: DatabaseRef<Error = String> + Sync,
+    F: Fn(usize, &LockedState<'_, DB>) -> Outcome + Sync,
+{
+    let block_size = locks.len();
+    for accounts in &mut locks {
+        accounts.remove(&coinbase);
+    }
+
+    // Each lock's queue, in block order, as links from every holder to the next.
+    let mut last_holder: HashMap<Address, usize> = HashMap::new();
+    let mut successors: Vec<Vec<usize>> = vec![Vec::new(); block_size];
+    let mut waiting: Vec<usize> = vec![0; block_size];
+    for (i, accounts) in locks.iter().enumerate() {
+        for account in accounts {
+            if let Some(previous) = last_holder.insert(*account, i) {
+                successors[previous].push(i);
+                waiting[i] += 1;
+            }
+        }
+    }
+    let mut stats = LockStats {
+        blocks: 1,
+        transactions: block_size as u64,
+        locks: locks.iter().map(|accounts| accounts.len() as u64).sum(),
+        queued: waiting.iter().filter(|waits| **waits > 0).count() as u64,
+        unlocked: 0,
+    };
+
+    let ready: Vec<usize> = (0..block_size).filter(|&i| waiting[i] == 0).collect();
+    let locker = Locker {
+        view: LockedState {
+            state,
+            coinbase,
+            accounts: DashMap::new(),
+            contracts: DashMap::new(),
+        },
+        execute,
+        locks,
+        successors,
+        waiting: waiting.into_iter().map(AtomicUsize::new).collect(),
+        outcomes: (0..block_size).map(|_| Mutex::new(None)).collect(),
+    };
+    rayon::scope(|scope| {
+        for i in ready {
+            let locker = &locker;
+            scope.spawn(move |scope| locker.execute(scope, i));
+        }
+    });
+
+    let outcomes: Vec<(Outcome, bool)> = locker
+        .outcomes
+        .into_iter()
+        .map(|outcome| outcome.into_inner().expect("every transaction gets its locks"))
+        .collect();
+    stats.unlocked = outcomes.iter().filter(|(_, unlocked)| *unlocked).count() as u64;
+    (outcomes, stats)
+}
+
+impl<'a, DB, F> Locker<'a, DB, F>
+where
+    DB: DatabaseRef<Error = String> + Sync,
+    F: Fn(usize, &LockedState<'_, DB>) -> Outcome + Sync,
+{
+    /// Runs transaction `i`, which holds all its locks, then hands each lock to its next holder.
+    fn execute<'s>(&'s self, scope: &rayon::Scope<'s>, i: usize) {
+        let outcome = (self.execute)(i, &self.view);
+        let coinbase = self.view.coinbase;
+        let unlocked = outcome.as_ref().is_ok_and(|executed| {
+            executed.state.keys().any(|address| *address != coinbase && !self.locks[i].contains(address))
+        });
+        if let Ok(executed) = &outcome {
+            self.view.apply(&executed.state);
+        }
+        *self.outcomes[i].lock() = Some((outcome, unlocked));
+
+        for &next in &self.successors[i] {
+            if self.waiting[next].fetch_sub(1, Ordering::AcqRel) == 1 {
+                scope.spawn(move |scope| self.execute(scope, next));
+            }
+        }
+    }
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LockStats {
+    pub blocks: u64,
+    pub transactions: u64,
+    /// Account locks taken, summed over transactions.
+    pub locks: u64,
+    /// Transactions that had to wait for an earlier holder of one of their locks.
+    pub queued: u64,
+    /// Transactions that touched an account they had not locked, re-executed by the applier.
+    pub unlocked: u64,
+}
+
+impl std::ops::AddAssign for LockStats {
+    fn add_assign(&mut self, other: Self) {
+        self.blocks += other.blocks;
+        self.transactions += other.transactions;
+        self.locks += other.locks;
+        self.queued += other.queued;
+        self.unlocked += other.unlocked;
+    }
+}
+
+impl fmt::Display for LockStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Locking:            {} txs, {:.2} account locks per tx, {} queued behind a holder, {} strayed outside them",
+            self.transactions,
+            self.locks as f64 / self.transactions.max(1) as f64,
+            self.queued,
+            self.unlocked
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use revm::primitives::{AccountStatus, Bytes, Eval, ExecutionResult, Output, StorageSlot};
+    use std::time::Duration;
+
+    const COINBASE: Address = Address::repeat_byte(0xcb);
+
+    /// Every slot of every account starts at zero.
+    struct Empty;
+
+    impl DatabaseRef for Empty {
+        type Error = String;
+
+        fn basic(&self, _: Address) -> Result<Option<AccountInfo>, String> {
+            Ok(Some(AccountInfo::default()))
+        }
+
+        fn code_by_hash(&self, _: B256) -> Result<Bytecode, String> {
+            Ok(Bytecode::new())
+        }
+
+        fn storage(&self, _: Address, _: U256) -> Result<U256, String> {
+            Ok(U256::ZERO)
+        }
+
+        fn block_hash(&self, _: U256) -> Result<B256, String> {
+            Ok(B256::ZERO)
+        }
+    }
+
+    /// Adds one to slot 0 of each of `accounts`, and credits the coinbase.
+    fn increment(view: &LockedState<'_, Empty>, accounts: &[Address]) -> Outcome {
+        let mut state = State::default();
+        for &address in accounts.iter().chain([&COINBASE]) {
+            let value = view.storage(address, U256::ZERO)? + U256::from(1);
+            let account = Account {
+                info: view.basic(address)?.unwrap_or_default(),
+                storage: [(U256::ZERO, StorageSlot::new_changed(value - U256::from(1), value))].into(),
+                status: AccountStatus::Touched,
+            };
+            state.insert(address, account);
+        }
+        let result = ExecutionResult::Success {
+            reason: Eval::Stop,
+            gas_used: 21_000,
+            gas_refunded: 0,
+            logs: Vec::new(),
+            output: Output::Call(Bytes::new()),
+        };
+        Ok(ResultAndState { result, state })
+    }
+
+    fn slot_after(outcome: &(Outcome, bool), address: Address) -> U256 {
+        outcome.0.as_ref().unwrap().state[&address].storage[&U256::ZERO].present_value
+    }
+
+    #[test]
+    fn grants_each_lock_in_block_order() {
+        let (a, b, c) = (Address::repeat_byte(1), Address::repeat_byte(2), Address::repeat_byte(3));
+        // Tx 0 is slow, so everything queued behind it would run first if the queue allowed.
+        let txs = [vec![a], vec![b], vec![a, b], vec![c], vec![b], vec![a, c]];
+        let order = Mutex::new(Vec::new());
+        let locks = txs.iter().map(|accounts| accounts.iter().copied().collect()).collect();
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        let (outcomes, stats) = pool.install(|| {
+            run(&Empty, COINBASE, locks, |i, view| {
+                if i == 0 {
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+                order.lock().push(i);
+                increment(view, &txs[i])
+            })
+        });
+
+        let order = order.into_inner();
+        let position = |i| order.iter().position(|&executed| executed == i).unwrap();
+        for account in [a, b, c] {
+            let holders: Vec<usize> = (0..txs.len()).filter(|&i| txs[i].contains(&account)).collect();
+            assert!(holders.windows(2).all(|pair| position(pair[0]) < position(pair[1])), "{:?}", order);
+            // Each holder saw every earlier one's write.
+            for (n, &i) in holders.iter().enumerate() {
+                assert_eq!(slot_after(&outcomes[i], account), U256::from(n + 1), "tx {} on {}", i, account);
+            }
+        }
+        assert!(outcomes.iter().all(|(_, unlocked)| !unlocked), "the coinbase needs no lock");
+        assert_eq!((stats.locks, stats.queued, stats.unlocked), (8, 3, 0));
+    }
+
+    #[test]
+    fn flags_runs_that_touch_an_undeclared_account() {
+        let (a, b) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let touched = [vec![a], vec![a, b], vec![b]];
+        // Tx 1 declares only a, but writes b as well.
+        let locks = vec![[a].into(), [a].into(), [b].into()];
+        let (outcomes, stats) = run(&Empty, COINBASE, locks, |i, view| increment(view, &touched[i]));
+        let unlocked: Vec<bool> = outcomes.iter().map(|(_, unlocked)| *unlocked).collect();
+        assert_eq!(unlocked, [false, true, false]);
+        assert_eq!(stats.unlocked, 1);
+    }
+}
@@ -1,195 +1,1205 @@
 /*
- * FLUX ENGINE - PRODUCTION RELEASE
- * Architecture: Optimistic Software Transactional Memory (STM) for EVM
- * Target: >300 MGas/s
+ * FLUX ENGINE - BENCHMARK ENTRY POINT
  */
 
+mod cli;
+
+use cli::{CheckpointSchedule, Command, PrefetchLookahead};
+use flux_engine::access_trace::{self, ReplayStats};
+use flux_engine::bisect;
+use flux_engine::block::BlockHeader;
+use flux_engine::builder::BlockBuilder;
+use flux_engine::chain::ChainSpec;
+use flux_engine::checkpoint::{Checkpoint, CheckpointWriter};
+use flux_engine::commitment::CommitmentScheme;
+use flux_engine::executor::AccessSet;
+use flux_engine::executor::PrecompileRegistry;
+use flux_engine::metrics::LookaheadStats;
+use flux_engine::filter::TxFilter;
+use flux_engine::forensics::Capture;
+use flux_engine::priority::BuildOrder;
+use flux_engine::receipt;
+use flux_engine::recovery::{self, Signer};
+use flux_engine::hugepages::{self, HugePageAlloc, HugePageMode};
+use flux_engine::state::backend::{BackendKind, StateBackend};
+use flux_engine::state::preload::{read_preload, write_preload};
+use flux_engine::state::snapshot::{self, read_dump};
+use flux_engine::scheduler::Strategy;
+use flux_engine::source::mempool::MempoolSource;
+use flux_engine::source::ws::WsBlockSource;
+use flux_engine::source::{BlockSource, SourceBlock, SourceConfig};
+use flux_engine::topology;
+use flux_engine::workload::SyntheticWorkload;
+use flux_engine::{BlockResult, EngineConfig, FluxEngine, FluxTransaction};
 use rayon::prelude::*;
-use revm::{
-    db::{CacheDB, DatabaseRef, EmptyDB},
-    primitives::{AccountInfo, Address, Bytecode, Env, ExecutionResult, TransactTo, U256},
-    EVM,
-};
-use std::collections::{HashMap, HashSet};
-use std::sync::{Arc, Mutex};
-use parking_lot::RwLock;
-
-// --- TYPES ---
-
-// A "Dirty" State records what a transaction Read and what it Wrote.
-#[derive(Debug, Clone)]
-struct AccessList {
-    reads: HashSet<Address>,
-    writes: HashSet<Address>,
-}
-
-#[derive(Debug, Clone)]
-struct FluxTransaction {
-    id: usize,
-    caller: Address,
-    to: Address,
-    value: U256,
-    data: Vec<u8>,
-    gas_limit: u64,
-}
-
-// The Global State (In-Memory Flat Log for Speed)
-// In a real node, EmptyDB would be replaced by 'reth_db::Database'
-type GlobalDb = CacheDB<EmptyDB>;
-
-// --- THE ENGINE LOGIC ---
-
-struct FluxEngine {
-    db: Arc<RwLock<GlobalDb>>,
-}
-
-impl FluxEngine {
-    pub fn new() -> Self {
-        Self {
-            db: Arc::new(RwLock::new(CacheDB::new(EmptyDB::default()))),
-        }
-    }
-
-    /// The Winning Function: Optimistic Parallel Execution
-    pub fn execute_block(&self, txs: Vec<FluxTransaction>) {
-        let block_size = txs.len();
-        println!("[FLUX] Starting Optimistic Execution of {} transactions...", block_size);
-
-        // 1. SPECULATIVE PHASE (Parallel)
-        // We use Rayon to blast these transactions across all 16 cores.
-        // Each Tx runs on a *snapshot* of the DB, assuming no conflicts.
-        let results: Vec<Result<(ExecutionResult, AccessList), String>> = txs
-            .par_iter()
-            .map(|tx| {
-                // A. COW (Copy on Write) Snapshot
-                // We clone the DB ref. This is fast because CacheDB uses Arc internal structures.
-                // Note: In strict Rust, deep cloning the DB is heavy, so we use a Ref wrapper in prod.
-                // For this challenge code, we treat the standard CacheDB as our snapshot source.
-                let mut local_db = self.db.read().clone(); 
-                
-                // B. Configure EVM
-                let mut evm = EVM::new();
-                evm.database(local_db);
-                
-                let mut env = Env::default();
-                env.tx.caller = tx.caller;
-                env.tx.transact_to = TransactTo::Call(tx.to);
-                env.tx.data = tx.data.clone().into();
-                env.tx.value = tx.value;
-                evm.env = env;
-
-                // C. Execute
-                match evm.transact_commit() {
-                    Ok(result) => {
-                        // D. Extract Access List (Read/Write Set) for Conflict Detection
-                        // In reality, we hook the Inspector to capture this. 
-                        // Here we infer based on 'to' and 'caller' for the algorithm demonstration.
-                        let mut reads = HashSet::new();
-                        let mut writes = HashSet::new();
-                        
-                        reads.insert(tx.caller);
-                        writes.insert(tx.to); // Simplification: Target is written to
-                        
-                        Ok((result, AccessList { reads, writes }))
+use revm::primitives::{keccak256, AccountInfo, Address, SpecId, B256, U256};
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::time::Duration;
+
+#[global_allocator]
+static ALLOC: HugePageAlloc = HugePageAlloc;
+
+// --- ENTRY POINT ---
+
+fn main() {
+    // 1. Setup the Engine
+    let args = match cli::parse() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("[FLUX] {}", e);
+            eprintln!("{}", cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    match &args.command {
+        Command::Trace { capture } => std::process::exit(trace_capture(capture)),
+        Command::ReplayTrace { trace } => std::process::exit(replay_trace(trace, &args.config)),
+        Command::ImportState { snapshot, state_dir } => {
+            std::process::exit(import_state(snapshot, args.config.state_backend, state_dir))
+        }
+        Command::PackState { snapshot, out } => std::process::exit(pack_state(snapshot, out)),
+        Command::Run | Command::Build { .. } | Command::Follow { .. } | Command::Verify | Command::Bisect { .. } => {}
+    }
+
+    // Before the engine exists, so the state store's tables are allocated under the chosen mode.
+    if args.huge_pages != HugePageMode::Off {
+        let (mode, note) = hugepages::configure(args.huge_pages);
+        if let Some(note) = note {
+            println!("[FLUX] Huge pages: {}", note);
+        }
+        println!("[FLUX] Huge pages: {}", mode);
+    }
+
+    // Also before the engine: nothing may have started the global rayon pool yet.
+    if let Some(layout) = &args.topology {
+        if let Err(e) = layout.validate().and_then(|_| layout.install_executor_pool()) {
+            eprintln!("[FLUX] {}", e);
+            std::process::exit(1);
+        }
+        println!("[FLUX] Topology: {} ({} cores available)", layout, topology::available_cores().len());
+    }
+
+    // Resuming switches the chain and state backend to the checkpoint's before the engine opens them.
+    let mut config = args.config;
+    let resumed = match &args.resume {
+        Some(path) => match Checkpoint::load(path).and_then(|cp| config.resume_from(&cp).map(|_| cp)) {
+            Ok(checkpoint) => Some(checkpoint),
+            Err(e) => {
+                eprintln!("[FLUX] {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    let chain = config.chain.clone();
+    let strategy = config.strategy;
+    let strategy_flags = config.strategy_flags();
+    // The baseline replays the same blocks from the same genesis, in memory.
+    let baseline_config = args.serial_baseline.then(|| EngineConfig {
+        strategy: Strategy::Serial,
+        state_diffs: None,
+        record_trace: None,
+        forensics: None,
+        ..config.clone()
+    });
+    // `flux bisect` re-runs its block serially from a copy of the state before it.
+    let bisect_config = matches!(args.command, Command::Bisect { .. }).then(|| EngineConfig {
+        strategy: Strategy::Serial,
+        state_diffs: None,
+        record_trace: None,
+        forensics: None,
+        ..config.clone()
+    });
+    // The second pass of `verify --self` commits on another number of threads and writes nothing.
+    // Kept with the first pass's commit threads, for the report.
+    let second_config = matches!(args.command, Command::Verify).then(|| {
+        let second = EngineConfig {
+            commit_threads: if config.commit_threads > 1 { 1 } else { 2 },
+            commit_cores: vec![],
+            state_diffs: None,
+            record_trace: None,
+            forensics: None,
+            ..config.clone()
+        };
+        (config.commit_threads, second)
+    });
+    if let Some(dir) = &config.state_dir {
+        println!("[FLUX] State: {}", dir.display());
+    }
+    let engine = match FluxEngine::new(config) {
+        Ok(engine) => engine,
+        Err(e) => {
+            eprintln!("[FLUX] {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Command::Follow { ws_url, slot, blocks } = &args.command {
+        if let Some(path) = &args.preload_state {
+            preload_state(&engine, path);
+        }
+        if strategy == Strategy::Serial {
+            pin_serial_thread();
+        }
+        std::process::exit(follow(&engine, ws_url, *slot, *blocks, &strategy_flags));
+    }
+    let range = match block_range(args.source.as_ref(), args.blocks, args.end_block, resumed.as_ref()) {
+        Ok(range) => range,
+        Err(e) => {
+            eprintln!("[FLUX] {}", e);
+            std::process::exit(1);
+        }
+    };
+    let blocks = range.end() - range.start() + 1;
+    let labels = args.labels;
+    let mut source = match args.source.as_ref().map(|source| source.open(blocks)).transpose() {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("[FLUX] {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(config) = args.mempool {
+        // Arrivals are generated up front of any header, so they take the genesis fork's fee shape.
+        let london = SpecId::enabled(chain.spec_at(&genesis_header(&chain)), SpecId::LONDON);
+        let senders = args.synthetic.sender_addresses(Address::ZERO);
+        source = Some(Box::new(MempoolSource::new(config, args.synthetic.clone(), senders, london)));
+        println!("[FLUX] Mempool: {}", config);
+    }
+    if let Some(config) = &args.source {
+        println!("[FLUX] Source: {}", config);
+    }
+    if args.source.is_none() && args.synthetic != SyntheticWorkload::default() {
+        println!("[FLUX] Workload: {}", args.synthetic);
+    }
+    let mut workload = Workload {
+        synthetic: &args.synthetic,
+        filter: args.filter.as_ref(),
+        build_order: args.build_order,
+        signer: args
+            .sign_txs
+            .then(|| Signer::new(B256::repeat_byte(0x46)).expect("the workload key is a valid secret")),
+        source,
+        builder: None,
+    };
+    if let Command::Build { out } = &args.command {
+        match BlockBuilder::new(out.as_deref()) {
+            Ok(builder) => workload.builder = Some(builder),
+            Err(e) => {
+                eprintln!("[FLUX] {}", e);
+                std::process::exit(1);
+            }
+        }
+        println!("[FLUX] Building blocks in {} order", workload.build_order);
+    }
+
+    let header = match resumed {
+        Some(checkpoint) => {
+            let next = checkpoint.next.clone();
+            let expected = checkpoint.state_root;
+            println!(
+                "[FLUX] Resuming at block {} ({} accounts restored)",
+                next.number,
+                checkpoint.accounts.len()
+            );
+            engine.restore(checkpoint);
+            // A checkpoint that does not hash to its block's root would only fail on the next one.
+            if let Some(expected) = expected {
+                match engine.state_root() {
+                    Ok(root) if root == expected => {
+                        println!("[FLUX] Checkpoint state root {} matches block {}", root, next.number - 1)
                     }
-                    Err(e) => Err(format!("EVM Error: {:?}", e)),
-                }
-            })
-            .collect();
-
-        // 2. COMMIT PHASE (Serial / Conflict Resolution)
-        // This is where we beat the "Static Analysis" engines.
-        // We only re-execute if a REAL conflict happened.
-        
-        let mut committed_writes: HashSet<Address> = HashSet::new();
-        let mut final_gas_used = 0u64;
-        let mut re_exec_count = 0;
-
-        // We acquire the WRITE lock on the global DB only once here.
-        let mut global_db = self.db.write();
-
-        for (i, res) in results.into_iter().enumerate() {
-            match res {
-                Ok((exec_result, access_list)) => {
-                    // Check Conflict: Did this Tx read something that was written by a previous Tx in this block?
-                    let has_conflict = access_list.reads.iter().any(|r| committed_writes.contains(r));
-
-                    if !has_conflict {
-                        // HAPPY PATH: Commit immediately.
-                        // (In a real engine, we merge the 'local_db' changes into 'global_db')
-                        committed_writes.extend(access_list.writes);
-                        
-                        if let ExecutionResult::Success { gas_used, .. } = exec_result {
-                            final_gas_used += gas_used;
-                        }
-                    } else {
-                        // SAD PATH: Conflict Detected. Re-execute serially.
-                        re_exec_count += 1;
-                        
-                        let tx = &txs[i];
-                        let mut evm = EVM::new();
-                        evm.database(&mut *global_db); // Run directly on latest state
-                        
-                        let mut env = Env::default();
-                        env.tx.caller = tx.caller;
-                        env.tx.transact_to = TransactTo::Call(tx.to);
-                        evm.env = env;
-
-                        if let Ok(serial_res) = evm.transact_commit() {
-                            if let ExecutionResult::Success { gas_used, .. } = serial_res {
-                                final_gas_used += gas_used;
-                            }
-                            // Update the committed writes with the new touches
-                            committed_writes.insert(tx.to);
-                        }
+                    Ok(root) => {
+                        eprintln!(
+                            "[FLUX] Checkpoint state root {} but block {}'s header says {}",
+                            root,
+                            next.number - 1,
+                            expected
+                        );
+                        std::process::exit(1);
+                    }
+                    Err(e) => {
+                        eprintln!("[FLUX] Checkpoint state root: {}", e);
+                        std::process::exit(1);
                     }
                 }
-                Err(_) => continue, // Skip failed txs
             }
+            next
+        }
+        // Replayed blocks bring their own headers, and their pre-state comes from --state-dir.
+        None if args.source.as_ref().is_some_and(SourceConfig::has_headers) => BlockHeader::default(),
+        None => genesis(&engine, &chain, &workload),
+    };
+
+    // Warm-up state goes in before the first block, so the clock only ever sees execution.
+    if let Some(path) = &args.preload_state {
+        preload_state(&engine, path);
+    }
+
+    if let Some((commit_threads, config)) = second_config {
+        let second_commit_threads = config.commit_threads;
+        let second = match FluxEngine::new(config) {
+            Ok(engine) => engine,
+            Err(e) => {
+                eprintln!("[FLUX] Verify: {}", e);
+                std::process::exit(1);
+            }
+        };
+        if !args.source.as_ref().is_some_and(SourceConfig::has_headers) {
+            genesis(&second, &chain, &workload);
+        }
+        if let Some(path) = &args.preload_state {
+            preload_state(&second, path);
         }
+        // Half the executors, or one more when there are too few to halve.
+        let executors = rayon::current_num_threads();
+        let others = if executors > 2 { executors / 2 } else { executors + 1 };
+        let pool = match rayon::ThreadPoolBuilder::new()
+            .num_threads(others)
+            .thread_name(|i| format!("flux-verify-{}", i))
+            .build()
+        {
+            Ok(pool) => pool,
+            Err(e) => {
+                eprintln!("[FLUX] Verify: executor pool: {}", e);
+                std::process::exit(1);
+            }
+        };
+        println!(
+            "[FLUX] Verify: pass 1 on {} executors and {} commit threads, pass 2 on {} and {}",
+            executors, commit_threads, others, second_commit_threads
+        );
+        let summary = match self_verify([&engine, &second], &pool, &chain, header, blocks, &mut workload, args.source.as_ref())
+        {
+            Ok(summary) => summary,
+            Err(e) => {
+                eprintln!("[FLUX] SELF VERIFICATION FAILED: {}", e);
+                std::process::exit(1);
+            }
+        };
+        println!("--------------------------------------------------");
+        println!("Blocks: {} (#{} - #{})", blocks, range.start(), range.end());
+        println!("Strategy: {}", strategy_flags);
+        println!("Self Verification:  {}", summary);
+        println!("--------------------------------------------------");
+        return;
+    }
+
+    if let Some(config) = bisect_config {
+        std::process::exit(bisect(&engine, config, &chain, header, blocks, &mut workload, &strategy_flags));
+    }
+
+    if strategy == Strategy::Serial {
+        pin_serial_thread();
+    }
+    let RunTotals {
+        duration,
+        gas_used,
+        tx_count,
+        rejected,
+        skipped,
+        access,
+        lookahead,
+        last: result,
+        ..
+    } = run_blocks(
+        &engine,
+        &chain,
+        header.clone(),
+        blocks,
+        &mut workload,
+        args.checkpoint.as_ref(),
+        args.lookahead.as_ref(),
+    );
+
+    println!("--------------------------------------------------");
+    if !labels.is_empty() {
+        let rendered: Vec<String> = labels.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        println!("Run Labels: {}", rendered.join(" "));
+    }
+    println!("REAL TIME RESULT: {:?}", duration);
+    println!("Blocks: {} (#{} - #{})", blocks, range.start(), range.end());
+    println!("Strategy: {}", strategy_flags);
+    match skipped {
+        0 => println!("Gas Used: {} ({} txs, {} rejected)", gas_used, tx_count, rejected),
+        _ => println!(
+            "Gas Used: {} ({} txs, {} rejected; {} more skipped by the filter, executed but not counted)",
+            gas_used, tx_count, rejected, skipped
+        ),
+    }
+    println!(
+        "Access Sets: {} accounts ({} cold), {} slots ({} cold)",
+        access.warm_accounts + access.cold_accounts,
+        access.cold_accounts,
+        access.warm_slots + access.cold_slots,
+        access.cold_slots
+    );
+    println!(
+        "Fees: base fee {} wei -> {} wei next block, {} wei burned, {} wei in tips",
+        result.base_fee_per_gas, result.next_base_fee_per_gas, result.base_fee_burned, result.priority_fees
+    );
+    println!(
+        "Blob Gas: {} ({} wei burned), next excess {}",
+        result.blob_gas_used, result.blob_fee_burned, result.next_excess_blob_gas
+    );
+    match engine.state_root() {
+        Ok(root) => match engine.trie_stats().scheme {
+            CommitmentScheme::Mpt => println!("State Root: {}", root),
+            scheme => println!("State Root: {} ({}, not comparable with header roots)", root, scheme),
+        },
+        Err(e) => println!("State Root: unavailable ({})", e),
+    }
+    println!("Approx Throughput: {:.2} TPS", tx_count as f64 / duration.as_secs_f64());
+    println!("Approx Throughput: {:.2} MGas/s", gas_used as f64 / 1_000_000.0 / duration.as_secs_f64());
+    print!("{}", engine.throughput_report());
+    if let Some(report) = workload.source.as_ref().and_then(|source| source.report()) {
+        print!("{}", report);
+    }
+    if let Some(builder) = &workload.builder {
+        print!("{}", builder.stats());
+    }
+    if args.lookahead.is_some() {
+        print!("{}", lookahead);
+    }
+    print!("{}", engine.commit_stats());
+    print!("{}", engine.buffer_stats());
+    print!("{}", engine.shard_stats());
+    print!("{}", engine.cache_stats());
+    if let Some(prefetch) = engine.prefetch_stats() {
+        print!("{}", prefetch);
+    }
+    if let Some(validation) = engine.validation_stats() {
+        print!("{}", validation);
+    }
+    if let Some(recovery) = engine.recovery_stats() {
+        print!("{}", recovery);
+    }
+    if let Some(block_stm) = engine.block_stm_stats() {
+        print!("{}", block_stm);
+    }
+    if let Some(locking) = engine.lock_stats() {
+        print!("{}", locking);
+    }
+    if let Some(dispatch) = engine.dispatch_stats() {
+        print!("{}", dispatch);
+    }
+    if let Some(prediction) = engine.prediction_stats() {
+        print!("{}", prediction);
+    }
+    if let Some(speculation) = engine.speculation_stats() {
+        print!("{}", speculation);
+    }
+    print!("{}", engine.prune_stats());
+    print!("{}", engine.trie_stats());
+    if args.huge_pages != HugePageMode::Off {
+        print!("{}", hugepages::stats());
+    }
+    if let Some(backend) = engine.backend_stats() {
+        print!("{}", backend);
+    }
+    #[cfg(all(feature = "async-io", target_os = "linux"))]
+    if let Some(io) = engine.io_stats() {
+        print!("{}", io);
+    }
+    print!("{}", engine.analysis_stats());
+    let disk = engine.disk_stats();
+    if disk.model.is_enabled() {
+        print!("{}", disk);
+    }
+    if let Some(config) = baseline_config {
+        let root = engine.state_root();
+        drop(engine);
+        let preload = args.preload_state.as_deref();
+        let baseline = serial_baseline(config, &chain, header, blocks, &mut workload, preload);
+        let speedup = baseline.duration.as_secs_f64() / duration.as_secs_f64();
+        let matches = match (&root, &baseline.root) {
+            (Ok(root), Ok(serial)) if root == serial => "state root matches".to_string(),
+            (Ok(_), Ok(serial)) => format!("STATE ROOT DIFFERS (serial {})", serial),
+            (Err(e), _) | (_, Err(e)) => format!("state roots not compared ({})", e),
+        };
+        println!(
+            "Serial Baseline:    {:?} for the same blocks, {:.2}x speedup, {}",
+            baseline.duration, speedup, matches
+        );
+    }
+    println!("--------------------------------------------------");
+}
 
-        println!("[FLUX] Block Complete.");
-        println!("       Total Gas: {}", final_gas_used);
-        println!("       Re-executions: {} (Conflict Rate: {:.2}%)", 
-            re_exec_count, 
-            (re_exec_count as f64 / block_size as f64) * 100.0
+/// A fresh chain: the workload's senders funded, its contracts deployed, and the first header.
+fn genesis(engine: &FluxEngine, chain: &ChainSpec, workload: &Workload) -> BlockHeader {
+    // Fund the senders so transfers actually execute (and burn gas) instead of being rejected.
+    for sender in workload.senders() {
+        engine.insert_account(
+            sender,
+            AccountInfo {
+                balance: U256::from(10u128.pow(30)),
+                ..Default::default()
+            },
         );
     }
+    let accounts = match &workload.source {
+        Some(source) => source.genesis_accounts(),
+        None => workload.synthetic.genesis_accounts(),
+    };
+    for (address, info) in accounts {
+        engine.insert_account(address, info);
+    }
+    let mut header = genesis_header(chain);
+    if !SpecId::enabled(chain.spec_at(&header), SpecId::LONDON) {
+        header.base_fee_per_gas = 0;
+    }
+    header
 }
 
-// --- ENTRY POINT ---
+/// The first and last block to execute: from the block source's first block, the checkpoint's
+/// next one or the generated chain's first, through `--end-block` or for `--blocks`. A source
+/// that knows what it holds has the range checked against it before anything is fetched.
+fn block_range(
+    source: Option<&SourceConfig>,
+    blocks: u64,
+    end_block: Option<u64>,
+    resumed: Option<&Checkpoint>,
+) -> Result<RangeInclusive<u64>, String> {
+    let available = source.map(SourceConfig::available).transpose()?.flatten();
+    let start = match (source.and_then(SourceConfig::first_block), available, resumed) {
+        (Some(first), _, _) | (None, Some((first, _)), _) => first,
+        (None, None, Some(checkpoint)) => checkpoint.next.number,
+        (None, None, None) => 1,
+    };
+    let end = match end_block {
+        Some(end) if end < start => return Err(format!("last block {} is before the first block, {}", end, start)),
+        Some(end) => end,
+        None => start + blocks - 1,
+    };
+    if let Some((first, last)) = available {
+        if start < first || end > last {
+            return Err(format!(
+                "blocks {} - {} are not all available from the source, which has {} - {}",
+                start, end, first, last
+            ));
+        }
+    }
+    Ok(start..=end)
+}
 
-fn main() {
-    // 1. Setup the Engine
-    let engine = FluxEngine::new();
-
-    // 2. Generate Real Workload (Mocking 10k transactions)
-    // We create realistic distinct addresses to prove the parallelism works.
-    let mut txs = Vec::new();
-    for i in 0..10_000 {
-        // Creates a mix of independent and conflicting transactions
-        // i % 100 ensures some overlap (conflicts) to test the re-execution logic
-        let target_addr = Address::from_low_u64_be((i % 100) as u64); 
-        
-        txs.push(FluxTransaction {
-            id: i,
-            caller: Address::ZERO,
-            to: target_addr,
-            value: U256::from(100),
-            data: vec![], // Empty for simple transfer benchmark
-            gas_limit: 21000,
-        });
+/// The first block of a fresh chain, with a 10 gwei base fee (dropped by `genesis` before London).
+fn genesis_header(chain: &ChainSpec) -> BlockHeader {
+    BlockHeader {
+        number: 1,
+        coinbase: Address::repeat_byte(0xc0),
+        gas_limit: chain.gas_limit,
+        base_fee_per_gas: 10_000_000_000,
+        ..BlockHeader::default()
     }
+}
 
-    // 3. Run Benchmark
+fn preload_state(engine: &FluxEngine, path: &Path) {
     let start = std::time::Instant::now();
-    
-    // This calls the PARALLEL engine
-    engine.execute_block(txs);
+    match read_preload(path) {
+        Ok(accounts) => {
+            let stats = engine.preload(accounts);
+            println!(
+                "[FLUX] Preloaded {} accounts ({} contracts, {} storage slots) from {} in {:?}",
+                stats.accounts,
+                stats.contracts,
+                stats.slots,
+                path.display(),
+                start.elapsed()
+            );
+        }
+        Err(e) => {
+            eprintln!("[FLUX] {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+struct RunTotals {
+    /// Time spent inside `execute_block`, nothing else.
+    duration: Duration,
+    /// Gas and txs of the selected txs only; `skipped` is how many others a filter left out.
+    gas_used: u64,
+    tx_count: usize,
+    rejected: usize,
+    skipped: usize,
+    access: AccessSet,
+    lookahead: LookaheadStats,
+    last: BlockResult,
+    /// Header context of the block after the last one run.
+    next: BlockHeader,
+}
+
+/// How each block's transactions are generated and handed to the engine.
+struct Workload<'a> {
+    synthetic: &'a SyntheticWorkload,
+    filter: Option<&'a TxFilter>,
+    build_order: BuildOrder,
+    /// Signs every generated tx, so the engine recovers its sender instead of being told it.
+    signer: Option<Signer>,
+    /// Replays these blocks instead of generating any; filter and build order still apply.
+    source: Option<Box<dyn BlockSource>>,
+    /// Builds each block from a pool instead of executing all of it; see `flux build`.
+    builder: Option<BlockBuilder>,
+}
+
+impl Workload<'_> {
+    /// The account every generated tx is sent from.
+    fn sender(&self) -> Address {
+        self.signer.as_ref().map_or(Address::ZERO, Signer::address)
+    }
+
+    /// Every account the workload sends from, for genesis to fund.
+    fn senders(&self) -> Vec<Address> {
+        match &self.source {
+            Some(source) => source.senders(),
+            None => self.synthetic.sender_addresses(self.sender()),
+        }
+    }
+}
+
+/// Generates (or takes from the workload's source) and executes `blocks` consecutive blocks
+/// starting at `header`.
+fn run_blocks(
+    engine: &FluxEngine,
+    chain: &ChainSpec,
+    mut header: BlockHeader,
+    blocks: u64,
+    workload: &mut Workload,
+    checkpoint: Option<&CheckpointSchedule>,
+    lookahead: Option<&PrefetchLookahead>,
+) -> RunTotals {
+    let mut duration = Duration::ZERO;
+    let mut gas_used = 0;
+    let mut tx_count = 0;
+    let mut rejected = 0;
+    let mut skipped = 0;
+    let mut access = AccessSet::default();
+    let mut last = None;
+    let mut checkpoints = checkpoint.map(|schedule| {
+        (schedule.interval, CheckpointWriter::new(schedule.dir.clone(), schedule.compression))
+    });
+    // Blocks taken from the source, the executing one first, and how many of the rest are warm.
+    // A source error behind queued blocks waits until they have run.
+    let mut queued: VecDeque<SourceBlock> = VecDeque::new();
+    let mut warmed: usize = 0;
+    let mut source_error = None;
+    let mut lookahead_stats = LookaheadStats::default();
+    for executed in 0..blocks {
+        if let Some(source) = workload.source.as_mut() {
+            let (ahead, max_txs) = lookahead.map_or((0, None), |lookahead| (lookahead.blocks, lookahead.max_txs));
+            let depth = (ahead + 1).min((blocks - executed) as usize);
+            while source_error.is_none() && queued.len() < depth {
+                let queued_txs: usize = queued.iter().skip(1).map(|block| block.txs.len()).sum();
+                if queued.len() > 1 && max_txs.is_some_and(|max| queued_txs >= max) {
+                    break;
+                }
+                match source.next_block() {
+                    Ok(Some(mut block)) => {
+                        if let Some(filter) = workload.filter {
+                            select(filter, chain.chain_id, &mut block.txs);
+                        }
+                        queued.push_back(block)
+                    }
+                    Ok(None) => break,
+                    Err(e) => source_error = Some(e),
+                }
+            }
+        }
+        let replayed = match (workload.source.is_some(), queued.pop_front()) {
+            (true, Some(block)) => {
+                warmed = warmed.saturating_sub(1);
+                if let Some(canonical) = block.header {
+                    header = canonical;
+                }
+                Some(block.txs)
+            }
+            (true, None) => {
+                match source_error.take() {
+                    Some(e) => eprintln!("[FLUX] {}", e),
+                    None => eprintln!("[FLUX] Block source ran out before block {}", header.number + 1),
+                }
+                std::process::exit(1);
+            }
+            (false, _) => None,
+        };
+        let spec = chain.spec_at(&header);
+        println!("[FLUX] Chain: {}, block {} executes under {:?}", chain, header.number, spec);
+
+        let mut txs = match replayed {
+            Some(txs) => txs,
+            None => {
+                let mut txs = generate_block(chain, &header, workload);
+                if let Some(filter) = workload.filter {
+                    select(filter, chain.chain_id, &mut txs);
+                }
+                txs
+            }
+        };
+
+        // A builder's pool: what earlier blocks had no room for goes first.
+        if let Some(builder) = &mut workload.builder {
+            txs = builder.candidates(txs);
+        }
+
+        // Block building: the builder, not arrival, decides the order the executors see.
+        if workload.build_order != BuildOrder::Arrival {
+            let (ordered, stats) = workload.build_order.apply(txs, header.base_fee_per_gas);
+            txs = ordered;
+            println!("[FLUX] Build order {}: {}", workload.build_order, stats);
+        }
+
+        // Settling the pool needs a built block's candidates back, so they are kept off the clock.
+        let candidates = workload.builder.as_ref().map(|_| txs.clone());
+
+        // 3. Run Benchmark
+        // The lookahead warms the queued blocks not warmed yet on a thread of its own, beside
+        // this block's execution; only the execution is timed.
+        let (result, elapsed, warm) = std::thread::scope(|scope| {
+            let warm = lookahead.map(|_| {
+                let ahead = queued.range(warmed..);
+                scope.spawn(move || {
+                    let start = std::time::Instant::now();
+                    let locations = engine.prefetch_ahead(ahead.flat_map(|block| &block.txs));
+                    (locations, start.elapsed())
+                })
+            });
+            let start = std::time::Instant::now();
+
+            // This calls the PARALLEL engine
+            let result = match &candidates {
+                Some(_) => engine.build_block(&header, txs),
+                None => engine.execute_block(&header, txs),
+            };
+            let elapsed = start.elapsed();
+            (result, elapsed, warm.map(|warm| warm.join().expect("lookahead prefetch panicked")))
+        });
+        if let Some((locations, warm_time)) = warm {
+            lookahead_stats += LookaheadStats {
+                blocks: 1,
+                queued_blocks: queued.len() as u64,
+                queued_txs: queued.iter().map(|block| block.txs.len() as u64).sum(),
+                warmed: locations,
+                duration: warm_time,
+            };
+            warmed = queued.len();
+        }
+
+        duration += elapsed;
+        if let (Some(builder), Some(candidates)) = (&mut workload.builder, candidates) {
+            if let Err(e) = builder.settle(header.number, header.gas_limit, candidates, &result) {
+                eprintln!("[FLUX] {}", e);
+                std::process::exit(1);
+            }
+        }
+        if let Err(e) = result.verify(&header, None) {
+            eprintln!("[FLUX] VERIFICATION FAILED: {}", e);
+            std::process::exit(1);
+        }
+        gas_used += result.gas_used - result.skipped_gas;
+        tx_count += result.tx_count - result.skipped;
+        rejected += result.rejected;
+        skipped += result.skipped;
+        access += result.access;
+
+        // The child inherits the fee market this block left behind.
+        let next = BlockHeader {
+            number: header.number + 1,
+            timestamp: header.timestamp + chain.block_time,
+            base_fee_per_gas: result.next_base_fee_per_gas,
+            excess_blob_gas: result.next_excess_blob_gas,
+            ..header.clone()
+        };
+        if let Some((interval, writer)) = &mut checkpoints {
+            if header.number.is_multiple_of(*interval) {
+                let checkpoint = Checkpoint {
+                    state_root: header.state_root,
+                    ..engine.checkpoint(&next)
+                };
+                match writer.write(checkpoint) {
+                    Ok(path) => println!("[FLUX] Checkpoint -> {}", path.display()),
+                    Err(e) => eprintln!("[FLUX] Checkpoint at block {} failed: {}", header.number, e),
+                }
+            }
+        }
+        header = next;
+        last = Some(result);
+    }
+    RunTotals {
+        duration,
+        gas_used,
+        tx_count,
+        rejected,
+        skipped,
+        access,
+        lookahead: lookahead_stats,
+        last: last.expect("--blocks is at least 1"),
+        next: header,
+    }
+}
+
+/// Partial replay: marks the txs `filter` does not select as skipped, as a block is queued or
+/// generated. They still execute, so the block stays the chain's and its header stays checked, but
+/// the run's numbers only count the selected ones.
+fn select(filter: &TxFilter, chain_id: u64, txs: &mut Vec<FluxTransaction>) {
+    // Signed txs only name their sender once recovered. The engine recovers them again, on the
+    // clock, as it would without a filter.
+    if filter.reads_sender() && txs.iter().any(|tx| tx.signature.is_some()) {
+        *txs = recovery::recover_senders(std::mem::take(txs), chain_id).0;
+    }
+    let total = txs.len();
+    let skipped = filter.apply(txs);
+    println!("[FLUX] Filter `{}` selected {} of {} transactions", filter, total - skipped, total);
+}
+
+/// A generated block, signed if the workload signs.
+fn generate_block(chain: &ChainSpec, header: &BlockHeader, workload: &Workload) -> Vec<FluxTransaction> {
+    let london = SpecId::enabled(chain.spec_at(header), SpecId::LONDON);
+    let mut txs = workload.synthetic.generate(header.number, &workload.senders(), london);
+    if let Some(signer) = &workload.signer {
+        txs.par_iter_mut().for_each(|tx| signer.sign(tx, chain.chain_id));
+    }
+    txs
+}
+
+struct Baseline {
+    duration: Duration,
+    root: Result<B256, String>,
+}
+
+/// Re-runs the same blocks from the same starting state under `--strategy serial`, on one core.
+fn serial_baseline(
+    config: EngineConfig,
+    chain: &ChainSpec,
+    first: BlockHeader,
+    blocks: u64,
+    workload: &mut Workload,
+    preload: Option<&Path>,
+) -> Baseline {
+    println!("[FLUX] Serial baseline: re-running {} block(s) on one core", blocks);
+    let engine = match FluxEngine::new(config) {
+        Ok(engine) => engine,
+        Err(e) => {
+            eprintln!("[FLUX] Serial baseline: {}", e);
+            std::process::exit(1);
+        }
+    };
+    genesis(&engine, chain, workload);
+    if let Some(path) = preload {
+        preload_state(&engine, path);
+    }
+    pin_serial_thread();
+    let run = run_blocks(&engine, chain, first, blocks, workload, None, None);
+    Baseline {
+        duration: run.duration,
+        root: engine.state_root(),
+    }
+}
+
+/// One pass of `flux verify --self`.
+struct VerifyPass {
+    /// Block number, state root and receipts root of every block, in order.
+    roots: Vec<(u64, B256, B256)>,
+    /// The state after the last block, one dump line per account, by address.
+    state: Vec<(Address, String)>,
+    duration: Duration,
+}
+
+/// `flux verify --self`: executes the blocks on `engines[0]` as configured, then again on
+/// `engines[1]` inside `pool`, so the transactions interleave differently across executor and
+/// commit threads. Neither may change a result: both passes have to agree on every block's roots
+/// and on the final state, byte for byte. Returns a summary, or the first difference.
+fn self_verify(
+    engines: [&FluxEngine; 2],
+    pool: &rayon::ThreadPool,
+    chain: &ChainSpec,
+    first: BlockHeader,
+    blocks: u64,
+    workload: &mut Workload,
+    source: Option<&SourceConfig>,
+) -> Result<String, String> {
+    let one = verify_pass(engines[0], chain, first.clone(), blocks, workload)?;
+    // The second pass takes the same blocks from the start.
+    if let Some(source) = source {
+        workload.source = Some(source.open(blocks)?);
+    }
+    let two = pool.install(|| verify_pass(engines[1], chain, first, blocks, workload))?;
+
+    for ((number, state, receipts), (_, other_state, other_receipts)) in one.roots.iter().zip(&two.roots) {
+        if receipts != other_receipts {
+            return Err(format!(
+                "block {}: receipts root {} in pass 1 but {} in pass 2",
+                number, receipts, other_receipts
+            ));
+        }
+        if state != other_state {
+            return Err(format!("block {}: state root {} in pass 1 but {} in pass 2", number, state, other_state));
+        }
+    }
+    match one.state.iter().zip(&two.state).find(|(a, b)| a != b) {
+        Some(((address, _), (other, _))) if address != other => {
+            let pass = if address < other { 1 } else { 2 };
+            return Err(format!("final state: account {} is only in pass {}", address.min(other), pass));
+        }
+        Some(((address, _), _)) => return Err(format!("final state: account {} differs", address)),
+        None if one.state.len() != two.state.len() => {
+            return Err(format!("final state: {} accounts in pass 1 but {} in pass 2", one.state.len(), two.state.len()))
+        }
+        None => {}
+    }
+
+    let dump: Vec<&str> = one.state.iter().map(|(_, line)| line.as_str()).collect();
+    Ok(format!(
+        "2 passes agree on {} blocks' roots and the final state ({} accounts, dump keccak {}); executed in {:?} and {:?}",
+        one.roots.len(),
+        one.state.len(),
+        keccak256(dump.join("\n")),
+        one.duration,
+        two.duration
+    ))
+}
+
+/// Runs the blocks one at a time, taking the state root after each.
+fn verify_pass(
+    engine: &FluxEngine,
+    chain: &ChainSpec,
+    mut header: BlockHeader,
+    blocks: u64,
+    workload: &mut Workload,
+) -> Result<VerifyPass, String> {
+    let mut roots = Vec::with_capacity(blocks as usize);
+    let mut duration = Duration::ZERO;
+    for _ in 0..blocks {
+        let run = run_blocks(engine, chain, header, 1, workload, None, None);
+        duration += run.duration;
+        roots.push((run.next.number - 1, engine.state_root()?, run.last.receipts_root));
+        header = run.next;
+    }
+    let mut state: Vec<(Address, String)> = engine
+        .checkpoint(&header)
+        .accounts
+        .iter()
+        .map(|account| (account.address, snapshot::dump_line(account)))
+        .collect();
+    state.sort_unstable();
+    Ok(VerifyPass { roots, state, duration })
+}
+
+/// `flux bisect --block <n>`: runs the blocks before `n` on `engine`, then executes `n` on it and,
+/// from a copy of the state it starts from, on a serial engine built from `serial`. The first tx
+/// whose writes differ between the two runs, and the first key it wrote differently, is where
+/// they part ways. Returns the exit code: 1 when they do.
+fn bisect(
+    engine: &FluxEngine,
+    serial: EngineConfig,
+    chain: &ChainSpec,
+    mut header: BlockHeader,
+    blocks: u64,
+    workload: &mut Workload,
+    strategy_flags: &str,
+) -> i32 {
+    if blocks > 1 {
+        header = run_blocks(engine, chain, header, blocks - 1, workload, None, None).next;
+    }
+    // The block itself, taken or generated and then ordered as `run_blocks` would.
+    let mut txs = match workload.source.as_mut().map(|source| source.next_block()) {
+        Some(Ok(Some(block))) => {
+            if let Some(canonical) = block.header {
+                header = canonical;
+            }
+            block.txs
+        }
+        Some(Ok(None)) => {
+            eprintln!("[FLUX] Block source ran out before block {}", header.number);
+            return 1;
+        }
+        Some(Err(e)) => {
+            eprintln!("[FLUX] {}", e);
+            return 1;
+        }
+        None => generate_block(chain, &header, workload),
+    };
+    if let Some(filter) = workload.filter {
+        select(filter, chain.chain_id, &mut txs);
+    }
+    if workload.build_order != BuildOrder::Arrival {
+        let (ordered, stats) = workload.build_order.apply(txs, header.base_fee_per_gas);
+        txs = ordered;
+        println!("[FLUX] Build order {}: {}", workload.build_order, stats);
+    }
+
+    let reference = match FluxEngine::new(serial) {
+        Ok(engine) => engine,
+        Err(e) => {
+            eprintln!("[FLUX] Bisect: {}", e);
+            return 1;
+        }
+    };
+    reference.restore(engine.checkpoint(&header));
+    println!(
+        "[FLUX] Bisect: block {} ({} txs) as configured, then serially from the same state",
+        header.number,
+        txs.len()
+    );
+    let parallel = engine.execute_block(&header, txs.clone());
+    let serial = reference.execute_block(&header, txs);
+
+    let names = ["parallel", "serial"];
+    let roots = (engine.state_root(), reference.state_root());
+    let divergence = match bisect::first_divergence(&parallel.tx_writes, &serial.tx_writes) {
+        Some(divergence) => Some(divergence.describe(names)),
+        None => match receipt::first_divergence(&parallel.receipts, &serial.receipts) {
+            Some(i) => Some(format!("every tx wrote the same values, but receipt #{} differs", i)),
+            None => match &roots {
+                (Ok(one), Ok(two)) if one != two => Some(format!(
+                    "every tx wrote the same values, but the block leaves state root {} in the parallel run, {} in the \
+                     serial run",
+                    one, two
+                )),
+                _ => None,
+            },
+        },
+    };
+    let header_check = |result: &BlockResult| match result.verify(&header, None) {
+        Ok(()) if header.receipts_root.is_some() || header.state_root.is_some() => "matches the header".to_string(),
+        Ok(()) => "no header roots to check".to_string(),
+        Err(e) => e,
+    };
 
-    let duration = start.elapsed();
     println!("--------------------------------------------------");
-    println!("REAL TIME RESULT: {:?}", duration);
-    println!("Approx Throughput: {:.2} TPS", 10_000.0 / duration.as_secs_f64());
+    println!("Block: {} ({} txs committed)", header.number, parallel.tx_writes.len());
+    println!("Strategy: {} vs --strategy serial", strategy_flags);
+    println!("Parallel Run:       {}", header_check(&parallel));
+    println!("Serial Run:         {}", header_check(&serial));
+    let code = match (divergence, roots) {
+        (Some(divergence), _) => {
+            println!("Bisect:             {}", divergence);
+            1
+        }
+        (None, (Ok(root), _)) => {
+            println!("Bisect:             both runs agree after every tx (state root {})", root);
+            0
+        }
+        (None, (Err(e), _)) => {
+            println!("Bisect:             both runs agree after every tx (state root failed: {})", e);
+            0
+        }
+    };
+    println!("--------------------------------------------------");
+    code
+}
+
+/// Keeps the calling thread, which runs the serial strategy's whole pipeline, on the core it is
+/// on now, so the baseline is not also measuring migrations.
+fn pin_serial_thread() {
+    #[cfg(target_os = "linux")]
+    // SAFETY: a plain syscall with no arguments.
+    let pinned = match unsafe { libc::sched_getcpu() } {
+        cpu if cpu >= 0 => topology::pin_current_thread(cpu as usize).then_some(cpu),
+        _ => None,
+    };
+    #[cfg(not(target_os = "linux"))]
+    let pinned: Option<i32> = None;
+    match pinned {
+        Some(cpu) => println!("[FLUX] Serial: pinned to core {}", cpu),
+        None => println!("[FLUX] Serial: could not pin to a core, the scheduler may migrate it"),
+    }
+}
+
+/// `flux trace --from-capture <file>`: replay a forensic capture against its own pre-state.
+fn trace_capture(path: &Path) -> i32 {
+    let capture = match Capture::load(path) {
+        Ok(capture) => capture,
+        Err(e) => {
+            eprintln!("[FLUX] {}", e);
+            return 1;
+        }
+    };
+    let precompiles = PrecompileRegistry::default()
+        .build(capture.chain.spec_at(&capture.header))
+        .expect("standard precompile set is contiguous");
+
+    let (outcome, trace) = capture.replay(&precompiles);
+    for step in &trace {
+        println!("{}", step);
+    }
+    println!("--------------------------------------------------");
+    println!("Capture Reason:   {}", capture.reason);
+    println!("Recorded Outcome: {}", capture.outcome);
+    println!("Replayed Outcome: {}", outcome);
+
+    if outcome == capture.outcome {
+        println!("[FLUX] Replay matches the recorded outcome.");
+        0
+    } else {
+        println!("[FLUX] Replay DIVERGES from the recorded outcome.");
+        1
+    }
+}
+
+/// `flux follow --ws-url <url>`: executes blocks as the node produces them, timed against the
+/// slot they have to fit in.
+fn follow(engine: &FluxEngine, url: &str, slot: Duration, blocks: Option<u64>, strategy_flags: &str) -> i32 {
+    let mut source = match WsBlockSource::connect(url) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("[FLUX] {}", e);
+            return 1;
+        }
+    };
+    println!("[FLUX] Following {} ({:?} slots)", url, slot);
+
+    let mut latencies = Vec::new();
+    let (mut gas_used, mut tx_count, mut late) = (0, 0, 0);
+    let mut status = 0;
+    while blocks.is_none_or(|limit| (latencies.len() as u64) < limit) {
+        let block = match source.next_block() {
+            Ok(Some(block)) => block,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("[FLUX] {}", e);
+                status = 1;
+                break;
+            }
+        };
+        let header = block.header.expect("followed blocks carry their headers");
+        let arrival = source.last_arrival().expect("set with every block returned");
+        if let Some(reorg) = source.last_reorg() {
+            match engine.unwind_to(reorg.fork) {
+                Ok(root) => println!(
+                    "[FLUX] Follow: unwound {} block(s) to block {} (state root {}), executing the new branch",
+                    reorg.depth, reorg.fork, root
+                ),
+                Err(e) => {
+                    eprintln!("[FLUX] Follow: cannot unwind to block {}: {}", reorg.fork, e);
+                    status = 1;
+                    break;
+                }
+            }
+        }
+
+        let start = std::time::Instant::now();
+        let result = engine.execute_block(&header, block.txs);
+        let execution = start.elapsed();
+        if let Err(e) = result.verify(&header, None) {
+            eprintln!("[FLUX] VERIFICATION FAILED: {}", e);
+            status = 1;
+            break;
+        }
+        // Fetching included: how long after the node announced it the block was done.
+        let since_head = arrival.elapsed();
+        if since_head > slot {
+            late += 1;
+        }
+        println!(
+            "[FLUX] Block {}: executed in {:?} ({:.1}% of the slot), done {:?} after its head arrived",
+            header.number,
+            execution,
+            execution.as_secs_f64() / slot.as_secs_f64() * 100.0,
+            since_head
+        );
+        latencies.push(execution);
+        gas_used += result.gas_used;
+        tx_count += result.tx_count;
+    }
+
+    latencies.sort();
+    let percentile = |p: usize| latencies.get((latencies.len() * p / 100).min(latencies.len().saturating_sub(1)));
+    println!("--------------------------------------------------");
+    println!("Followed: {}", url);
+    println!("Blocks: {} ({} txs, {} gas)", latencies.len(), tx_count, gas_used);
+    println!("Strategy: {}", strategy_flags);
+    if let (Some(p50), Some(p95), Some(max)) = (percentile(50), percentile(95), latencies.last()) {
+        println!(
+            "Execution Latency: p50 {:?}, p95 {:?}, max {:?} against {:?} slots; {} block(s) done over a slot after \
+             their head",
+            p50, p95, max, slot, late
+        );
+    }
+    if let Some(report) = source.report() {
+        print!("{}", report);
+    }
+    print!("{}", engine.commit_stats());
+    println!("--------------------------------------------------");
+    status
+}
+
+/// `flux replay-trace --from-trace <file>`: schedule a recorded run's blocks without the EVM.
+fn replay_trace(path: &Path, config: &EngineConfig) -> i32 {
+    let blocks = match access_trace::read_trace(path) {
+        Ok(blocks) => blocks,
+        Err(e) => {
+            eprintln!("[FLUX] {}", e);
+            return 1;
+        }
+    };
+    let mut total = ReplayStats::default();
+    for block in &blocks {
+        let stats = access_trace::replay_block(block, config.strategy, config.conflict_granularity);
+        println!(
+            "[FLUX] Block {}: {} txs, {} executions, {} re-executed ({:.2}%)",
+            block.number,
+            stats.transactions,
+            stats.executions,
+            stats.reexecuted,
+            stats.reexecuted as f64 / stats.transactions.max(1) as f64 * 100.0
+        );
+        total += stats;
+    }
+    println!("--------------------------------------------------");
+    println!("Strategy: --strategy {} --conflicts {}", config.strategy, config.conflict_granularity);
+    println!("Gas Recorded: {}", total.gas);
+    print!("{}", total);
     println!("--------------------------------------------------");
+    0
+}
+
+/// `flux pack-state --snapshot <file> --out <file>`: pack a state dump into a warm-up set for
+/// `--preload-state`.
+fn pack_state(snapshot: &Path, out: &Path) -> i32 {
+    let start = std::time::Instant::now();
+    let packed = read_dump(snapshot)
+        .and_then(|accounts| accounts.collect::<Result<Vec<_>, _>>())
+        .and_then(|accounts| write_preload(out, &accounts));
+    match packed {
+        Ok(stats) => {
+            println!(
+                "[FLUX] Packed {} accounts ({} contracts, {} storage slots) into {} in {:?}",
+                stats.accounts,
+                stats.contracts,
+                stats.slots,
+                out.display(),
+                start.elapsed()
+            );
+            0
+        }
+        Err(e) => {
+            eprintln!("[FLUX] {}", e);
+            1
+        }
+    }
+}
+
+/// `flux import-state --snapshot <file> --state-dir <dir>`: load a state dump for later runs.
+fn import_state(snapshot: &Path, kind: BackendKind, state_dir: &Path) -> i32 {
+    let start = std::time::Instant::now();
+    let imported = read_dump(snapshot).and_then(|accounts| StateBackend::import(kind, state_dir, accounts));
+    match imported {
+        Ok(stats) => {
+            println!(
+                "[FLUX] Imported {} accounts ({} contracts, {} storage slots) into {} in {:?}",
+                stats.accounts,
+                stats.contracts,
+                stats.slots,
+                state_dir.display(),
+                start.elapsed()
+            );
+            0
+        }
+        Err(e) => {
+            eprintln!("[FLUX] {}", e);
+            1
+        }
+    }
 }
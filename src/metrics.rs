@@ -0,0 +1,245 @@
+/*
+ * FLUX ENGINE - METRICS
+ * Run-level counters surfaced in the final benchmark report.
+ */
+
+use crate::mvcc::FilterStats;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// --- THROUGHPUT (BURST vs SUSTAINED) ---
+
+/// Rolling windows reported next to the peak 1s figure. A healthy engine has all of these close
+/// to the aggregate; a big gap means periodic stalls (block boundaries, checkpoints, GC of state).
+const SUSTAINED_WINDOWS_SECS: [usize; 2] = [10, 60];
+
+/// Buckets committed gas into 1-second bins measured from the start of the run.
+#[derive(Debug)]
+pub struct ThroughputTracker {
+    started: Instant,
+    buckets: Vec<u64>,
+}
+
+impl ThroughputTracker {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            buckets: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, gas: u64) {
+        let second = self.started.elapsed().as_secs() as usize;
+        if self.buckets.len() <= second {
+            self.buckets.resize(second + 1, 0);
+        }
+        self.buckets[second] += gas;
+    }
+
+    pub fn report(&self) -> ThroughputReport {
+        let elapsed = self.started.elapsed();
+        let total_gas: u64 = self.buckets.iter().sum();
+
+        // The bucket we're currently in is partial, so only whole seconds feed the windows.
+        let complete = &self.buckets[..(elapsed.as_secs() as usize).min(self.buckets.len())];
+
+        let peak_1s = complete.iter().copied().max().map(mgas);
+        let sustained = SUSTAINED_WINDOWS_SECS
+            .iter()
+            .map(|&window| {
+                let worst = complete
+                    .windows(window)
+                    .map(|w| w.iter().sum::<u64>() as f64 / window as f64)
+                    .min_by(|a, b| a.total_cmp(b));
+                (window, worst.map(|gas_per_sec| gas_per_sec / 1_000_000.0))
+            })
+            .collect();
+
+        ThroughputReport {
+            elapsed,
+            total_gas,
+            peak_1s,
+            sustained,
+        }
+    }
+}
+
+impl Default for ThroughputTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn mgas(gas: u64) -> f64 {
+    gas as f64 / 1_000_000.0
+}
+
+#[derive(Debug, Clone)]
+pub struct ThroughputReport {
+    pub elapsed: Duration,
+    pub total_gas: u64,
+    /// Best single second, `None` if the run was shorter than one second.
+    pub peak_1s: Option<f64>,
+    /// (window seconds, worst rolling-window MGas/s), `None` if the run was shorter than the window.
+    pub sustained: Vec<(usize, Option<f64>)>,
+}
+
+impl ThroughputReport {
+    pub fn aggregate_mgas(&self) -> f64 {
+        mgas(self.total_gas) / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl fmt::Display for ThroughputReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Aggregate:          {:.2} MGas/s", self.aggregate_mgas())?;
+        match self.peak_1s {
+            Some(peak) => writeln!(f, "Peak (1s):          {:.2} MGas/s", peak)?,
+            None => writeln!(f, "Peak (1s):          n/a (run < 1s)")?,
+        }
+        for (window, worst) in &self.sustained {
+            let label = format!("Sustained ({}s):", window);
+            match worst {
+                Some(v) => writeln!(f, "{:<20}{:.2} MGas/s (worst rolling window)", label, v)?,
+                None => writeln!(f, "{:<20}n/a (run < {}s)", label, window)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// --- COMMIT PHASE ---
+
+/// Where commit-phase time goes. `validation_busy` is summed across commit threads, so
+/// busy / wall is the parallelism the extra commit threads actually bought.
+#[derive(Debug, Clone, Default)]
+pub struct CommitStats {
+    pub threads: usize,
+    pub validation_wall: Duration,
+    pub validation_busy: Duration,
+    pub apply_wall: Duration,
+    /// Times the applier sent conflicted txs back to the executors.
+    pub retry_rounds: u64,
+    pub retried: u64,
+    /// Conflicted txs left alone because the tx they wait on had not committed yet, summed over
+    /// rounds.
+    pub deferred: u64,
+    /// Bloom filters in front of the validator's and applier's conflict lookups.
+    pub filter: FilterStats,
+}
+
+impl CommitStats {
+    pub fn validation_speedup(&self) -> f64 {
+        self.validation_busy.as_secs_f64() / self.validation_wall.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl fmt::Display for CommitStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Commit Threads:     {}", self.threads)?;
+        writeln!(
+            f,
+            "Validation:         {:?} wall / {:?} busy ({:.2}x from parallelism)",
+            self.validation_wall,
+            self.validation_busy,
+            self.validation_speedup()
+        )?;
+        writeln!(f, "Ordered Apply:      {:?}", self.apply_wall)?;
+        if self.retry_rounds > 0 {
+            writeln!(
+                f,
+                "Retry Rounds:       {} ({} txs re-run in parallel, {} deferred to their blocker)",
+                self.retry_rounds, self.retried, self.deferred
+            )?;
+        }
+        if self.filter.probes > 0 {
+            write!(f, "{}", self.filter)?;
+        }
+        Ok(())
+    }
+}
+
+// --- STATE PREFETCH ---
+
+/// How well each block's declared locations (senders, targets, access lists) predicted what its
+/// transactions actually read, over the blocks prefetched so far.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PrefetchStats {
+    pub blocks: u64,
+    /// Distinct locations read ahead of execution.
+    pub prefetched: u64,
+    /// Of those, the ones some transaction in the block went on to read.
+    pub used: u64,
+    /// Distinct locations the block's speculative executions read.
+    pub reads: u64,
+}
+
+impl PrefetchStats {
+    /// Share of prefetched locations that were read.
+    pub fn accuracy(&self) -> f64 {
+        self.used as f64 / self.prefetched.max(1) as f64 * 100.0
+    }
+
+    /// Share of executed reads that were prefetched.
+    pub fn coverage(&self) -> f64 {
+        self.used as f64 / self.reads.max(1) as f64 * 100.0
+    }
+}
+
+impl std::ops::AddAssign for PrefetchStats {
+    fn add_assign(&mut self, other: Self) {
+        self.blocks += other.blocks;
+        self.prefetched += other.prefetched;
+        self.used += other.used;
+        self.reads += other.reads;
+    }
+}
+
+impl fmt::Display for PrefetchStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "State Prefetch:     {} locations over {} blocks, {:.2}% accuracy, {:.2}% of execution reads covered",
+            self.prefetched,
+            self.blocks,
+            self.accuracy(),
+            self.coverage()
+        )
+    }
+}
+
+/// `--prefetch-ahead`: blocks queued behind the executing one, and what warming them cost.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LookaheadStats {
+    /// Blocks executed, and the blocks and txs queued behind each, summed over them.
+    pub blocks: u64,
+    pub queued_blocks: u64,
+    pub queued_txs: u64,
+    /// Distinct locations warmed, block by block, and the time it took beside execution.
+    pub warmed: u64,
+    pub duration: Duration,
+}
+
+impl std::ops::AddAssign for LookaheadStats {
+    fn add_assign(&mut self, other: Self) {
+        self.blocks += other.blocks;
+        self.queued_blocks += other.queued_blocks;
+        self.queued_txs += other.queued_txs;
+        self.warmed += other.warmed;
+        self.duration += other.duration;
+    }
+}
+
+impl fmt::Display for LookaheadStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let blocks = self.blocks.max(1) as f64;
+        writeln!(
+            f,
+            "Prefetch Lookahead: {:.1} blocks ({:.0} txs) queued on average, {} locations warmed in {:?} beside execution",
+            self.queued_blocks as f64 / blocks,
+            self.queued_txs as f64 / blocks,
+            self.warmed,
+            self.duration
+        )
+    }
+}
@@ -0,0 +1,468 @@
+/*
+ * FLUX ENGINE - MULTI-VERSION CONCURRENCY CONTROL
+ * Read/write sets of speculative executions, taken straight from the state revm loaded and
+ * changed, and the version table the ordered committer validates reads against. Written
+ * locations are also summarized in bloom filters, so most reads are cleared without an exact
+ * lookup.
+ */
+
+use revm::primitives::{Address, HashMap, State, U256};
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Location {
+    /// Balance, nonce and code of an account.
+    Account(Address),
+    Storage(Address, U256),
+}
+
+impl Location {
+    pub fn address(&self) -> Address {
+        match self {
+            Location::Account(address) | Location::Storage(address, _) => *address,
+        }
+    }
+}
+
+/// What two transactions must both touch to conflict. Whole accounts track far fewer locations
+/// but also flag transactions that only share an account, not a slot (a hot token contract).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictGranularity {
+    /// Any slot of an account stands for the whole account.
+    Account,
+    #[default]
+    Slot,
+}
+
+impl ConflictGranularity {
+    /// The location tracked for `location` at this granularity.
+    pub fn coarsen(&self, location: Location) -> Location {
+        match (self, location) {
+            (ConflictGranularity::Account, Location::Storage(address, _)) => Location::Account(address),
+            _ => location,
+        }
+    }
+}
+
+impl FromStr for ConflictGranularity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "account" => Ok(ConflictGranularity::Account),
+            "slot" => Ok(ConflictGranularity::Slot),
+            _ => Err(format!("unknown conflict granularity {:?} (expected account or slot)", s)),
+        }
+    }
+}
+
+impl fmt::Display for ConflictGranularity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConflictGranularity::Account => "account",
+            ConflictGranularity::Slot => "slot",
+        })
+    }
+}
+
+/// Every speculative execution runs against the pre-block state, so every read it records saw
+/// the same version: the one before any transaction in the block wrote it.
+#[derive(Debug, Clone, Default)]
+pub struct ReadWriteSet {
+    pub reads: Vec<Location>,
+    pub writes: Vec<Location>,
+}
+
+impl ReadWriteSet {
+    /// `coinbase` is left out. Every transaction credits it, and those credits commute, so the
+    /// committer rebases them (`rebase_coinbase`) instead of treating them as conflicts.
+    pub fn from_state(state: &State, coinbase: Address, granularity: ConflictGranularity) -> Self {
+        let mut set = Self::default();
+        for (address, account) in state.iter().filter(|(address, _)| **address != coinbase) {
+            set.reads.push(Location::Account(*address));
+            match granularity {
+                ConflictGranularity::Account => {
+                    if account.is_touched() || account.storage.values().any(|value| value.is_changed()) {
+                        set.writes.push(Location::Account(*address));
+                    }
+                }
+                ConflictGranularity::Slot => {
+                    if account.is_touched() {
+                        set.writes.push(Location::Account(*address));
+                    }
+                    for (slot, value) in &account.storage {
+                        set.reads.push(Location::Storage(*address, *slot));
+                        if value.is_changed() {
+                            set.writes.push(Location::Storage(*address, *slot));
+                        }
+                    }
+                }
+            }
+        }
+        set
+    }
+
+    /// A run revm rejected before executing (nonce, balance, fee caps) wrote nothing and depended
+    /// only on its sender's account.
+    pub fn rejected(caller: Address) -> Self {
+        Self {
+            reads: vec![Location::Account(caller)],
+            writes: Vec::new(),
+        }
+    }
+}
+
+// --- LOCATION FILTER ---
+
+/// Filter bits per location it is sized for, and bits set per location: ~0.5% false positives
+/// at capacity.
+const FILTER_BITS_PER_LOCATION: usize = 16;
+const FILTER_PROBES: u64 = 3;
+const FILTER_MIN_CAPACITY: usize = 1024;
+
+/// Bloom summary of a set of locations. A location it rules out is certainly not in the set;
+/// one it may contain still needs the exact check.
+#[derive(Debug, Clone)]
+pub struct LocationFilter {
+    bits: Vec<u64>,
+    capacity: usize,
+    len: usize,
+}
+
+impl LocationFilter {
+    pub fn with_capacity(capacity: usize) -> Self {
+        let capacity = capacity.max(FILTER_MIN_CAPACITY);
+        let words = (capacity * FILTER_BITS_PER_LOCATION).next_power_of_two() / 64;
+        Self {
+            bits: vec![0; words],
+            capacity,
+            len: 0,
+        }
+    }
+
+    pub fn insert(&mut self, location: &Location) {
+        self.insert_hash(location_hash(location));
+    }
+
+    pub fn may_contain(&self, location: &Location) -> bool {
+        self.may_contain_hash(location_hash(location))
+    }
+
+    /// `location` as written by a run in `lane`, for sets keyed by both.
+    pub fn insert_in_lane(&mut self, location: &Location, lane: usize) {
+        self.insert_hash(lane_hash(location, lane));
+    }
+
+    pub fn may_contain_in_lane(&self, location: &Location, lane: usize) -> bool {
+        self.may_contain_hash(lane_hash(location, lane))
+    }
+
+    /// Whether enough locations went in that it should be rebuilt larger.
+    pub fn is_full(&self) -> bool {
+        self.len > self.capacity
+    }
+
+    fn insert_hash(&mut self, hash: u64) {
+        for bit in self.bit_indices(hash) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+        self.len += 1;
+    }
+
+    fn may_contain_hash(&self, hash: u64) -> bool {
+        self.bit_indices(hash).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Double hashing over one 64-bit hash.
+    fn bit_indices(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let (h1, h2) = (hash, (hash >> 32) | 1);
+        let mask = (self.bits.len() * 64 - 1) as u64;
+        (0..FILTER_PROBES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) & mask) as usize)
+    }
+}
+
+/// Addresses and mapping slots are keccak outputs already; the mix only has to spread the rest
+/// (small slot indices) across the word.
+fn location_hash(location: &Location) -> u64 {
+    let word = |bytes: &[u8]| u64::from_le_bytes(bytes.try_into().expect("8 bytes"));
+    let (address, slot) = match location {
+        Location::Account(address) => (address, None),
+        Location::Storage(address, slot) => (address, Some(slot)),
+    };
+    let mut hash = word(&address[..8]) ^ word(&address[8..16]).rotate_left(21) ^ word(&address[12..20]).rotate_left(42);
+    if let Some(slot) = slot {
+        for limb in slot.as_limbs() {
+            hash = (hash ^ limb).wrapping_mul(0x9e37_79b9_7f4a_7c15).rotate_left(31);
+        }
+        hash ^= 0xff51_afd7_ed55_8ccd;
+    }
+    mix(hash)
+}
+
+fn lane_hash(location: &Location, lane: usize) -> u64 {
+    mix(location_hash(location) ^ (lane as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+}
+
+/// splitmix64 finalizer.
+fn mix(mut hash: u64) -> u64 {
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+/// How the filters in front of conflict lookups did.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FilterStats {
+    /// Reads checked against a filter.
+    pub probes: u64,
+    /// Reads the filter could not rule out, so were looked up exactly.
+    pub passed: u64,
+    /// Of those, the ones the exact lookup did not find.
+    pub false_positives: u64,
+}
+
+impl FilterStats {
+    pub fn record(&mut self, passed: bool, found: bool) {
+        self.probes += 1;
+        self.passed += passed as u64;
+        self.false_positives += (passed && !found) as u64;
+    }
+
+    /// Share of absent locations the filter failed to rule out.
+    pub fn false_positive_rate(&self) -> f64 {
+        let absent = self.probes - (self.passed - self.false_positives);
+        self.false_positives as f64 / absent.max(1) as f64 * 100.0
+    }
+}
+
+impl std::ops::AddAssign for FilterStats {
+    fn add_assign(&mut self, other: Self) {
+        self.probes += other.probes;
+        self.passed += other.passed;
+        self.false_positives += other.false_positives;
+    }
+}
+
+impl fmt::Display for FilterStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Conflict Filter:    {} reads probed, {:.2}% needed an exact lookup, {:.3}% false positive rate",
+            self.probes,
+            self.passed as f64 / self.probes.max(1) as f64 * 100.0,
+            self.false_positive_rate()
+        )
+    }
+}
+
+// --- VERSIONS ---
+
+/// Lane of a run no other transaction saw, such as a serial re-execution.
+pub const UNSEEN: usize = usize::MAX;
+
+/// Latest committed writer (block index) of each location written during the block, with the
+/// lane its installed run executed in. A location with no entry is still at its pre-block version.
+#[derive(Debug)]
+pub struct VersionTable {
+    latest: HashMap<Location, (usize, usize)>,
+    filter: LocationFilter,
+    stats: Cell<FilterStats>,
+}
+
+impl Default for VersionTable {
+    fn default() -> Self {
+        Self {
+            latest: HashMap::default(),
+            filter: LocationFilter::with_capacity(0),
+            stats: Cell::default(),
+        }
+    }
+}
+
+impl VersionTable {
+    pub fn record(&mut self, tx_index: usize, lane: usize, writes: &[Location]) {
+        for location in writes {
+            if self.latest.insert(*location, (tx_index, lane)).is_none() {
+                self.filter.insert(location);
+            }
+        }
+        if self.filter.is_full() {
+            self.filter = LocationFilter::with_capacity(self.latest.len() * 2);
+            self.latest.keys().for_each(|location| self.filter.insert(location));
+        }
+    }
+
+    /// Whether reads taken by a run in `lane`, once the block's first `snapshot` txs were applied,
+    /// still hold: nothing applied since wrote them except runs the reader saw, from its lane.
+    pub fn is_current(&self, reads: &[Location], snapshot: usize, lane: usize) -> bool {
+        let mut stats = self.stats.get();
+        let current = reads.iter().all(|location| {
+            let passed = self.filter.may_contain(location);
+            let latest = if passed { self.latest.get(location) } else { None };
+            stats.record(passed, latest.is_some());
+            latest.is_none_or(|&(writer, writer_lane)| writer < snapshot || writer_lane == lane)
+        });
+        self.stats.set(stats);
+        current
+    }
+
+    pub fn filter_stats(&self) -> FilterStats {
+        self.stats.get()
+    }
+}
+
+/// Writes of runs that never landed as executed (dropped, or re-executed), by the lane they ran
+/// in. Every later run in that lane executed on top of them.
+#[derive(Debug)]
+pub struct LostWrites {
+    writes: HashSet<(Location, usize)>,
+    filter: LocationFilter,
+    stats: Cell<FilterStats>,
+}
+
+impl Default for LostWrites {
+    fn default() -> Self {
+        Self {
+            writes: HashSet::new(),
+            filter: LocationFilter::with_capacity(0),
+            stats: Cell::default(),
+        }
+    }
+}
+
+impl LostWrites {
+    pub fn record(&mut self, lane: usize, writes: &[Location]) {
+        for location in writes {
+            if self.writes.insert((*location, lane)) {
+                self.filter.insert_in_lane(location, lane);
+            }
+        }
+        if self.filter.is_full() {
+            self.filter = LocationFilter::with_capacity(self.writes.len() * 2);
+            self.writes.iter().for_each(|(location, lane)| self.filter.insert_in_lane(location, *lane));
+        }
+    }
+
+    /// Whether a run in `lane` read none of them.
+    pub fn is_current(&self, reads: &[Location], lane: usize) -> bool {
+        let mut stats = self.stats.get();
+        let current = reads.iter().all(|location| {
+            let passed = self.filter.may_contain_in_lane(location, lane);
+            let lost = passed && self.writes.contains(&(*location, lane));
+            stats.record(passed, lost);
+            !lost
+        });
+        self.stats.set(stats);
+        current
+    }
+
+    pub fn filter_stats(&self) -> FilterStats {
+        self.stats.get()
+    }
+}
+
+/// Replaces the coinbase balance a speculative execution computed from the pre-block balance
+/// with the same credit applied on top of the balance as committed so far.
+pub fn rebase_coinbase(state: &mut State, coinbase: Address, pre_block: U256, committed: U256) {
+    if let Some(account) = state.get_mut(&coinbase) {
+        account.info.balance = account.info.balance.saturating_add(committed).saturating_sub(pre_block);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slot(byte: u8, index: u64) -> Location {
+        Location::Storage(Address::repeat_byte(byte), U256::from(index))
+    }
+
+    #[test]
+    fn version_table_clears_reads_by_snapshot_and_lane() {
+        let (a, b, c) = (Location::Account(Address::repeat_byte(1)), slot(2, 0), slot(3, 0));
+        let mut versions = VersionTable::default();
+        versions.record(2, 0, &[a]);
+        versions.record(5, 1, &[b]);
+
+        // Tx 2 was applied before a snapshot of 3 or more, tx 5 only from 6 on.
+        assert!(versions.is_current(&[a], 3, 7));
+        assert!(!versions.is_current(&[a], 2, 7));
+        assert!(!versions.is_current(&[b], 5, 0));
+        assert!(versions.is_current(&[b], 6, 0));
+        // A run in the writer's lane executed on top of its write.
+        assert!(versions.is_current(&[b], 5, 1));
+        assert!(versions.is_current(&[a], 0, 0));
+        // Unwritten locations are at their pre-block version.
+        assert!(versions.is_current(&[c], 0, 3));
+        assert!(!versions.is_current(&[c, a, b], 4, 3));
+
+        // A later writer replaces the earlier one.
+        versions.record(8, UNSEEN, &[a]);
+        assert!(!versions.is_current(&[a], 3, 0));
+        assert!(versions.is_current(&[a], 9, 0));
+        assert!(versions.filter_stats().probes > 0);
+    }
+
+    #[test]
+    fn lost_writes_are_stale_only_in_their_lane() {
+        let (a, b) = (slot(1, 1), slot(1, 2));
+        let mut lost = LostWrites::default();
+        assert!(lost.is_current(&[a, b], 0));
+
+        lost.record(0, &[a]);
+        lost.record(2, &[b]);
+        assert!(!lost.is_current(&[a], 0));
+        assert!(lost.is_current(&[a], 1));
+        assert!(lost.is_current(&[a], 2));
+        assert!(lost.is_current(&[b], 0));
+        assert!(!lost.is_current(&[b], 2));
+        assert!(!lost.is_current(&[b, a], 0));
+        assert!(lost.is_current(&[a, b], UNSEEN));
+    }
+
+    #[test]
+    fn filters_keep_every_location_across_rebuilds() {
+        let locations: Vec<Location> = (0..5 * FILTER_MIN_CAPACITY as u64).map(|i| slot((i % 7) as u8, i)).collect();
+        let mut versions = VersionTable::default();
+        let mut lost = LostWrites::default();
+        for (i, chunk) in locations.chunks(100).enumerate() {
+            versions.record(i, i % 3, chunk);
+            lost.record(i % 3, chunk);
+        }
+        // Both outgrew the starting filter and were rebuilt to hold everything.
+        for filter in [&versions.filter, &lost.filter] {
+            assert!(filter.capacity > FILTER_MIN_CAPACITY && filter.capacity >= locations.len());
+            assert!(!filter.is_full());
+        }
+
+        for (i, chunk) in locations.chunks(100).enumerate() {
+            for location in chunk {
+                assert!(versions.filter.may_contain(location), "{:?}", location);
+                assert!(lost.filter.may_contain_in_lane(location, i % 3), "{:?}", location);
+                assert!(!versions.is_current(&[*location], i, UNSEEN));
+                assert!(!lost.is_current(&[*location], i % 3));
+            }
+        }
+
+        // The rebuilt filter still rules most absent locations out.
+        let absent: Vec<Location> = (0..10_000).map(|i| slot(0xee, i)).collect();
+        assert!(versions.is_current(&absent, 0, 0));
+        let stats = versions.filter_stats();
+        assert!(stats.false_positive_rate() < 2.0, "{:.2}%", stats.false_positive_rate());
+    }
+
+    #[test]
+    fn a_plain_filter_has_no_false_negatives_past_capacity() {
+        let mut filter = LocationFilter::with_capacity(0);
+        let locations: Vec<Location> = (0..=FILTER_MIN_CAPACITY as u64).map(|i| slot(9, i)).collect();
+        for (i, location) in locations.iter().enumerate() {
+            assert!(!filter.is_full(), "full after {}", i);
+            filter.insert(location);
+        }
+        assert!(filter.is_full());
+        assert!(locations.iter().all(|location| filter.may_contain(location)));
+    }
+}
@@ -0,0 +1,211 @@
+/*
+ * FLUX ENGINE - CONFLICT PREDICTION
+ * Guesses, before anything executes, which of a block's transactions will read what an earlier
+ * one writes, and links those into the same dispatch lane (`--dispatch predicted`). A transaction
+ * is expected to write its sender, its target when it carries value, the slots of its EIP-2930
+ * access list, and whatever earlier calls to the same target kept writing; it reads all of that
+ * plus its target and the accounts in its access list.
+ */
+
+use crate::dispatch::Lanes;
+use crate::mvcc::{ConflictGranularity, Location};
+use crate::{FluxTransaction, SpeculativeResult};
+use revm::primitives::{Address, HashMap};
+use std::collections::HashSet;
+use std::fmt;
+
+/// Locations tracked per contract; past this, new ones are no longer learned.
+const MAX_LOCATIONS_PER_CONTRACT: usize = 256;
+/// Contracts tracked at all.
+const MAX_CONTRACTS: usize = 16_384;
+/// Calls that must have written a location before later calls are expected to.
+const MIN_HITS: u32 = 2;
+
+/// What calls to each contract wrote in earlier blocks.
+#[derive(Debug, Default)]
+pub struct AccessPatterns {
+    writes: HashMap<Address, HashMap<Location, u32>>,
+}
+
+impl AccessPatterns {
+    /// Records what this block's speculative runs wrote, by the contract each one called.
+    pub fn learn(&mut self, txs: &[&FluxTransaction], results: &[SpeculativeResult]) {
+        for (tx, result) in txs.iter().zip(results) {
+            let Ok((_, rw)) = result else {
+                continue;
+            };
+            if self.writes.len() >= MAX_CONTRACTS && !self.writes.contains_key(&tx.to) {
+                continue;
+            }
+            let learned = self.writes.entry(tx.to).or_default();
+            // The sender differs from call to call; it is predicted anyway.
+            for location in rw.writes.iter().filter(|location| **location != Location::Account(tx.caller)) {
+                if let Some(hits) = learned.get_mut(location) {
+                    *hits += 1;
+                } else if learned.len() < MAX_LOCATIONS_PER_CONTRACT {
+                    learned.insert(*location, 1);
+                }
+            }
+        }
+    }
+
+    /// Locations a call to `contract` is expected to write.
+    fn expected_writes(&self, contract: Address) -> impl Iterator<Item = Location> + '_ {
+        self.writes
+            .get(&contract)
+            .into_iter()
+            .flat_map(|learned| learned.iter().filter(|(_, hits)| **hits >= MIN_HITS).map(|(location, _)| *location))
+    }
+
+    pub fn contracts(&self) -> usize {
+        self.writes.len()
+    }
+}
+
+/// A block's predicted lanes, and which transactions were predicted to conflict.
+pub struct Prediction {
+    pub lanes: Lanes,
+    pub conflicts: Vec<bool>,
+}
+
+/// Links every transaction to the earlier ones it is expected to read a write of, and makes a
+/// lane of each linked group.
+pub fn partition(
+    txs: &[&FluxTransaction],
+    coinbase: Address,
+    granularity: ConflictGranularity,
+    patterns: &AccessPatterns,
+) -> Prediction {
+    let mut parent: Vec<usize> = (0..txs.len()).collect();
+    let mut conflicts = vec![false; txs.len()];
+    let mut first_writer: HashMap<Location, usize> = HashMap::default();
+
+    for (i, tx) in txs.iter().enumerate() {
+        let mut writes: HashSet<Location> = std::iter::once(Location::Account(tx.caller))
+            .chain((!tx.value.is_zero()).then_some(Location::Account(tx.to)))
+            .chain(tx.access_list.iter().flat_map(|(address, slots)| {
+                slots.iter().map(move |slot| Location::Storage(*address, *slot))
+            }))
+            .chain(patterns.expected_writes(tx.to))
+            .map(|location| granularity.coarsen(location))
+            .collect();
+        writes.retain(|location| !is_coinbase(location, coinbase));
+        let reads = tx
+            .declared_locations()
+            .map(|location| granularity.coarsen(location))
+            .filter(|location| !is_coinbase(location, coinbase) && !writes.contains(location))
+            .chain(writes.iter().copied());
+
+        for location in reads {
+            if let Some(&writer) = first_writer.get(&location) {
+                conflicts[i] = true;
+                union(&mut parent, i, writer);
+            }
+        }
+        for location in writes {
+            first_writer.entry(location).or_insert(i);
+        }
+    }
+
+    let mut lane_of_root: HashMap<usize, usize> = HashMap::default();
+    let mut lanes: Vec<Vec<usize>> = Vec::new();
+    for i in 0..txs.len() {
+        let root = find(&mut parent, i);
+        let lane = *lane_of_root.entry(root).or_insert_with(|| {
+            lanes.push(Vec::new());
+            lanes.len() - 1
+        });
+        lanes[lane].push(i);
+    }
+    Prediction {
+        lanes: Lanes::new(lanes),
+        conflicts,
+    }
+}
+
+fn is_coinbase(location: &Location, coinbase: Address) -> bool {
+    matches!(location, Location::Account(address) | Location::Storage(address, _) if *address == coinbase)
+}
+
+fn find(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+fn union(parent: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(parent, a), find(parent, b));
+    parent[a.max(b)] = a.min(b);
+}
+
+/// Which transactions actually read a location an earlier one's speculative run wrote.
+pub fn observed_conflicts(results: &[SpeculativeResult]) -> Vec<bool> {
+    let mut first_writer: HashMap<Location, usize> = HashMap::default();
+    for (i, result) in results.iter().enumerate() {
+        if let Ok((_, rw)) = result {
+            for location in &rw.writes {
+                first_writer.entry(*location).or_insert(i);
+            }
+        }
+    }
+    results
+        .iter()
+        .enumerate()
+        .map(|(i, result)| match result {
+            Ok((_, rw)) => rw.reads.iter().any(|location| first_writer.get(location).is_some_and(|&w| w < i)),
+            Err(_) => false,
+        })
+        .collect()
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PredictionStats {
+    pub blocks: u64,
+    pub transactions: u64,
+    pub predicted: u64,
+    pub actual: u64,
+    /// Predicted conflicts that did happen.
+    pub hits: u64,
+}
+
+impl PredictionStats {
+    pub fn compare(predicted: &[bool], actual: &[bool]) -> Self {
+        let count = |flags: &[bool]| flags.iter().filter(|flag| **flag).count() as u64;
+        Self {
+            blocks: 1,
+            transactions: predicted.len() as u64,
+            predicted: count(predicted),
+            actual: count(actual),
+            hits: predicted.iter().zip(actual).filter(|(p, a)| **p && **a).count() as u64,
+        }
+    }
+}
+
+impl std::ops::AddAssign for PredictionStats {
+    fn add_assign(&mut self, other: Self) {
+        self.blocks += other.blocks;
+        self.transactions += other.transactions;
+        self.predicted += other.predicted;
+        self.actual += other.actual;
+        self.hits += other.hits;
+    }
+}
+
+impl fmt::Display for PredictionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rate = |n: u64| n as f64 / self.transactions.max(1) as f64 * 100.0;
+        writeln!(
+            f,
+            "Prediction:         {:.2}% of txs predicted to conflict, {:.2}% did ({} caught, {} missed, {} false alarms)",
+            rate(self.predicted),
+            rate(self.actual),
+            self.hits,
+            self.actual - self.hits,
+            self.predicted - self.hits
+        )
+    }
+}
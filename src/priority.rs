@@ -0,0 +1,177 @@
+/*
+ * FLUX ENGINE - BLOCK BUILDING ORDER
+ * `--build-order tip` hands transactions to the executors by effective tip instead of arrival
+ * order, the way a block builder fills a block. A sender's transactions stay in arrival order
+ * (their nonces depend on it), so only the next one of each sender competes. A transaction
+ * passed over `max_wait` times goes next regardless, so a low bid is delayed, never starved.
+ * Other orderings plug in as a `Priority`.
+ */
+
+use crate::FluxTransaction;
+use revm::primitives::{Address, HashMap, U256};
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+
+/// Times a transaction may be passed over before it goes next, unless configured.
+pub const DEFAULT_MAX_WAIT: usize = 1024;
+
+/// How much a builder wants a transaction included early; the higher, the sooner.
+pub trait Priority {
+    fn priority(&self, tx: &FluxTransaction, base_fee: u64) -> U256;
+}
+
+/// What the builder earns per gas: the effective price above the base fee.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EffectiveTip;
+
+impl Priority for EffectiveTip {
+    fn priority(&self, tx: &FluxTransaction, base_fee: u64) -> U256 {
+        tx.effective_gas_price(base_fee).saturating_sub(U256::from(base_fee))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BuildOrder {
+    /// As the transactions came in.
+    #[default]
+    Arrival,
+    /// Highest effective tip first; a transaction passed over `max_wait` times goes next.
+    Tip { max_wait: usize },
+}
+
+impl BuildOrder {
+    /// Orders a block's transactions for execution. Arrival order leaves them untouched.
+    pub fn apply(&self, txs: Vec<FluxTransaction>, base_fee: u64) -> (Vec<FluxTransaction>, OrderStats) {
+        match *self {
+            BuildOrder::Arrival => {
+                let stats = OrderStats {
+                    transactions: txs.len() as u64,
+                    ..OrderStats::default()
+                };
+                (txs, stats)
+            }
+            BuildOrder::Tip { max_wait } => order(txs, base_fee, &EffectiveTip, max_wait),
+        }
+    }
+}
+
+impl FromStr for BuildOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        let mut parts = lower.split(':');
+        let order = match (parts.next(), parts.next()) {
+            (Some("arrival"), None) => BuildOrder::Arrival,
+            (Some("tip"), None) => BuildOrder::Tip {
+                max_wait: DEFAULT_MAX_WAIT,
+            },
+            (Some("tip"), Some(max_wait)) => BuildOrder::Tip {
+                max_wait: max_wait
+                    .parse()
+                    .map_err(|_| format!("invalid max wait in build order {:?}", s))?,
+            },
+            _ => return Err(format!("unknown build order {:?} (expected arrival or tip[:<max wait>])", s)),
+        };
+        match parts.next() {
+            Some(_) => Err(format!("unknown build order {:?} (expected arrival or tip[:<max wait>])", s)),
+            None => Ok(order),
+        }
+    }
+}
+
+impl fmt::Display for BuildOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildOrder::Arrival => f.write_str("arrival"),
+            BuildOrder::Tip { max_wait } => write!(f, "tip:{}", max_wait),
+        }
+    }
+}
+
+/// Drains `txs` through a priority queue holding each sender's next transaction.
+pub fn order<P: Priority>(
+    txs: Vec<FluxTransaction>,
+    base_fee: u64,
+    priority: &P,
+    max_wait: usize,
+) -> (Vec<FluxTransaction>, OrderStats) {
+    let mut stats = OrderStats {
+        transactions: txs.len() as u64,
+        ..OrderStats::default()
+    };
+    let mut by_sender: HashMap<Address, VecDeque<usize>> = HashMap::default();
+    for (i, tx) in txs.iter().enumerate() {
+        by_sender.entry(tx.caller).or_default().push_back(i);
+    }
+
+    // Ties go to the earlier arrival. `waiting` holds the same transactions by when they became
+    // eligible, so the one passed over longest is always at hand.
+    let mut queue: BinaryHeap<(U256, Reverse<usize>)> = BinaryHeap::new();
+    let mut waiting: BTreeSet<(usize, usize)> = BTreeSet::new();
+    for senders_txs in by_sender.values() {
+        let first = senders_txs[0];
+        queue.push((priority.priority(&txs[first], base_fee), Reverse(first)));
+        waiting.insert((0, first));
+    }
+
+    let mut eligible_since: Vec<usize> = vec![0; txs.len()];
+    let mut sequence = Vec::with_capacity(txs.len());
+    while let Some(&(since, oldest)) = waiting.first() {
+        let now = sequence.len();
+        // Entries of promoted transactions are left in the heap; drop them as they surface.
+        while let Some(&(_, Reverse(i))) = queue.peek() {
+            if waiting.contains(&(eligible_since[i], i)) {
+                break;
+            }
+            queue.pop();
+        }
+        let (_, Reverse(best)) = *queue.peek().expect("every waiting tx is queued");
+        let next = if now - since >= max_wait && best != oldest {
+            stats.promoted += 1;
+            oldest
+        } else {
+            queue.pop();
+            best
+        };
+        waiting.remove(&(eligible_since[next], next));
+        sequence.push(next);
+
+        let sender = by_sender.get_mut(&txs[next].caller).expect("every tx has a sender queue");
+        sender.pop_front();
+        if let Some(&following) = sender.front() {
+            queue.push((priority.priority(&txs[following], base_fee), Reverse(following)));
+            waiting.insert((now + 1, following));
+            eligible_since[following] = now + 1;
+        }
+    }
+
+    stats.moved = sequence.iter().enumerate().filter(|(position, i)| position != *i).count() as u64;
+    let mut slots: Vec<Option<FluxTransaction>> = txs.into_iter().map(Some).collect();
+    let ordered = sequence
+        .into_iter()
+        .map(|i| slots[i].take().expect("each tx is emitted once"))
+        .collect();
+    (ordered, stats)
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OrderStats {
+    pub transactions: u64,
+    /// Transactions executed at a different position than they arrived at.
+    pub moved: u64,
+    /// Transactions sent ahead by the starvation guard rather than by their priority.
+    pub promoted: u64,
+}
+
+impl fmt::Display for OrderStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} txs moved, {} promoted by the starvation guard",
+            self.moved, self.transactions, self.promoted
+        )
+    }
+}
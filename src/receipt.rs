@@ -0,0 +1,83 @@
+/*
+ * FLUX ENGINE - RECEIPTS
+ * Consensus receipts (EIP-2718 envelopes) and the per-block receipts root, so a replay can be
+ * cross-checked against canonical block data.
+ */
+
+use crate::trie;
+use alloy_primitives::{Bloom, B256};
+use alloy_rlp::{Encodable, Header};
+use revm::primitives::{ExecutionResult, Log};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Receipt {
+    pub tx_id: usize,
+    /// EIP-2718 type: 0 legacy, 1 access list, 2 dynamic fee, 3 blob.
+    pub tx_type: u8,
+    pub success: bool,
+    /// Gas used by this transaction and every one before it in the block.
+    pub cumulative_gas_used: u64,
+    pub logs: Vec<Log>,
+    pub logs_bloom: Bloom,
+}
+
+impl Receipt {
+    pub fn new(tx_id: usize, tx_type: u8, result: &ExecutionResult, cumulative_gas_used: u64) -> Self {
+        let logs = result.logs();
+        let logs_bloom = Bloom::logs_bloom(
+            logs.iter()
+                .map(|log| (log.address.into_array(), log.topics.iter().map(|topic| topic.0))),
+        );
+        Self {
+            tx_id,
+            tx_type,
+            success: result.is_success(),
+            cumulative_gas_used,
+            logs,
+            logs_bloom,
+        }
+    }
+
+    /// Consensus encoding: `rlp([status, cumulative_gas, bloom, logs])`, prefixed with the
+    /// type byte for anything but legacy transactions. This is the receipts-trie leaf.
+    pub fn encoded(&self) -> Vec<u8> {
+        let payload_length = self.success.length()
+            + self.cumulative_gas_used.length()
+            + self.logs_bloom.length()
+            + self.logs.length();
+        let mut out = Vec::with_capacity(payload_length + 4);
+        if self.tx_type != 0 {
+            out.push(self.tx_type);
+        }
+        Header { list: true, payload_length }.encode(&mut out);
+        self.success.encode(&mut out);
+        self.cumulative_gas_used.encode(&mut out);
+        self.logs_bloom.encode(&mut out);
+        self.logs.encode(&mut out);
+        out
+    }
+}
+
+pub fn receipts_root(receipts: &[Receipt]) -> B256 {
+    let encoded: Vec<Vec<u8>> = receipts.iter().map(Receipt::encoded).collect();
+    trie::ordered_trie_root(&encoded)
+}
+
+/// Index of the first receipt that differs from the canonical one (or the first one missing
+/// on either side). Only the consensus fields are compared.
+pub fn first_divergence(computed: &[Receipt], canonical: &[Receipt]) -> Option<usize> {
+    computed
+        .iter()
+        .zip(canonical)
+        .position(|(a, b)| a.encoded() != b.encoded())
+        .or_else(|| (computed.len() != canonical.len()).then(|| computed.len().min(canonical.len())))
+}
+
+/// The header's `logsBloom`: the union of every receipt's bloom.
+pub fn block_bloom(receipts: &[Receipt]) -> Bloom {
+    let mut bloom = Bloom::ZERO;
+    for receipt in receipts {
+        bloom.accrue_bloom(&receipt.logs_bloom);
+    }
+    bloom
+}
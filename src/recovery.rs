@@ -0,0 +1,250 @@
+/*
+ * FLUX ENGINE - SENDER RECOVERY
+ * A signed transaction's sender is not given, it is recovered from the ECDSA signature over the
+ * transaction's signing hash. Nothing can execute before that (the sender decides nonce, balance
+ * and half of every conflict), so each block's senders are recovered up front, as one parallel
+ * batch on the executor pool, and timed apart from execution.
+ */
+
+use crate::FluxTransaction;
+use alloy_primitives::keccak256;
+use alloy_rlp::{Encodable, Header, EMPTY_STRING_CODE};
+use rayon::prelude::*;
+use revm::primitives::{Address, B256, U256};
+use secp256k1::ecdsa::{RecoverableSignature, RecoveryId};
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey, SignOnly, Verification};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Half the secp256k1 group order. EIP-2 rejects any `s` above it, so every signature has
+/// exactly one valid form.
+const SECP256K1N_HALF: U256 =
+    U256::from_limbs([0xdfe92f46681b20a0, 0x5d576e7357a4501d, 0xffffffffffffffff, 0x7fffffffffffffff]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signature {
+    /// Parity of the signature point's y coordinate. Legacy `v` is 27/28 or, under EIP-155,
+    /// `35 + 2 * chain_id` plus this.
+    pub y_parity: bool,
+    pub r: U256,
+    pub s: U256,
+}
+
+impl Signature {
+    /// The address whose key signed `hash`.
+    pub fn recover<C: Verification>(&self, secp: &Secp256k1<C>, hash: B256) -> Result<Address, String> {
+        if self.s > SECP256K1N_HALF {
+            return Err("signature s is above half the curve order (EIP-2)".into());
+        }
+        let mut compact = [0u8; 64];
+        compact[..32].copy_from_slice(&self.r.to_be_bytes::<32>());
+        compact[32..].copy_from_slice(&self.s.to_be_bytes::<32>());
+        let id = RecoveryId::from_i32(self.y_parity as i32).map_err(|e| e.to_string())?;
+        let signature =
+            RecoverableSignature::from_compact(&compact, id).map_err(|e| format!("malformed signature: {}", e))?;
+        let message = Message::from_slice(hash.as_slice()).map_err(|e| e.to_string())?;
+        let public = secp
+            .recover_ecdsa(&message, &signature)
+            .map_err(|e| format!("unrecoverable signature: {}", e))?;
+        Ok(address_of(&public))
+    }
+}
+
+fn address_of(public: &PublicKey) -> Address {
+    Address::from_slice(&keccak256(&public.serialize_uncompressed()[1..])[12..])
+}
+
+/// The hash a sender signs: `keccak(rlp([nonce, gas_price, gas, to, value, data, chain_id, 0, 0]))`
+/// for legacy transactions (EIP-155), `keccak(type || rlp(fields))` for typed ones. A transaction
+/// without a nonce signs as nonce 0.
+pub fn signing_hash(tx: &FluxTransaction, chain_id: u64) -> B256 {
+    signing_hash_as(tx, tx.tx_type(), Some(chain_id))
+}
+
+/// `signing_hash` for an envelope type given rather than read off the fields (a type-1
+/// transaction with an empty access list looks legacy). A legacy transaction without a chain id
+/// signs the six fields alone, as before EIP-155.
+pub fn signing_hash_as(tx: &FluxTransaction, tx_type: u8, chain_id: Option<u64>) -> B256 {
+    let nonce = tx.nonce.unwrap_or_default();
+    let mut fields = Vec::with_capacity(tx.data.len() + 128);
+    if tx_type == 0 {
+        nonce.encode(&mut fields);
+        tx.max_fee_per_gas.encode(&mut fields);
+        tx.gas_limit.encode(&mut fields);
+        encode_to(tx, &mut fields);
+        tx.value.encode(&mut fields);
+        tx.data.as_slice().encode(&mut fields);
+        if let Some(chain_id) = chain_id {
+            chain_id.encode(&mut fields);
+            0u8.encode(&mut fields);
+            0u8.encode(&mut fields);
+        }
+    } else {
+        let chain_id = chain_id.unwrap_or_default();
+        chain_id.encode(&mut fields);
+        nonce.encode(&mut fields);
+        if let Some(tip) = tx.max_priority_fee_per_gas {
+            tip.encode(&mut fields);
+        }
+        tx.max_fee_per_gas.encode(&mut fields);
+        tx.gas_limit.encode(&mut fields);
+        encode_to(tx, &mut fields);
+        tx.value.encode(&mut fields);
+        tx.data.as_slice().encode(&mut fields);
+        encode_access_list(&tx.access_list, &mut fields);
+        if let Some(blob_fee) = tx.max_fee_per_blob_gas {
+            blob_fee.encode(&mut fields);
+            tx.blob_hashes.encode(&mut fields);
+        }
+    }
+
+    let mut out = Vec::with_capacity(fields.len() + 8);
+    if tx_type != 0 {
+        out.push(tx_type);
+    }
+    Header {
+        list: true,
+        payload_length: fields.len(),
+    }
+    .encode(&mut out);
+    out.extend_from_slice(&fields);
+    keccak256(&out)
+}
+
+/// The target, or the empty string for a contract creation.
+fn encode_to(tx: &FluxTransaction, out: &mut Vec<u8>) {
+    if tx.create {
+        out.push(EMPTY_STRING_CODE);
+    } else {
+        tx.to.encode(out);
+    }
+}
+
+/// `rlp([[address, [key, ...]], ...])`, storage keys as 32-byte strings.
+fn encode_access_list(access_list: &[(Address, Vec<U256>)], out: &mut Vec<u8>) {
+    let mut items = Vec::new();
+    for (address, slots) in access_list {
+        let keys: Vec<B256> = slots.iter().map(|slot| B256::from(slot.to_be_bytes::<32>())).collect();
+        Header {
+            list: true,
+            payload_length: address.length() + keys.length(),
+        }
+        .encode(&mut items);
+        address.encode(&mut items);
+        keys.encode(&mut items);
+    }
+    Header {
+        list: true,
+        payload_length: items.len(),
+    }
+    .encode(out);
+    out.extend_from_slice(&items);
+}
+
+/// Signs transactions with a single key, for workloads that should pay for recovery.
+pub struct Signer {
+    secp: Secp256k1<SignOnly>,
+    key: SecretKey,
+    address: Address,
+}
+
+impl Signer {
+    pub fn new(secret: B256) -> Result<Self, String> {
+        let secp = Secp256k1::signing_only();
+        let key = SecretKey::from_slice(secret.as_slice()).map_err(|e| format!("invalid signing key: {}", e))?;
+        let address = address_of(&PublicKey::from_secret_key(&secp, &key));
+        Ok(Self { secp, key, address })
+    }
+
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    /// Signs `tx` for `chain_id`, making this key its sender.
+    pub fn sign(&self, tx: &mut FluxTransaction, chain_id: u64) {
+        tx.caller = self.address;
+        let hash = signing_hash(tx, chain_id);
+        let message = Message::from_slice(hash.as_slice()).expect("hashes are 32 bytes");
+        let (id, compact) = self.secp.sign_ecdsa_recoverable(&message, &self.key).serialize_compact();
+        tx.signature = Some(Signature {
+            y_parity: id.to_i32() == 1,
+            r: U256::from_be_slice(&compact[..32]),
+            s: U256::from_be_slice(&compact[32..]),
+        });
+    }
+}
+
+/// Recovers the sender of every signed transaction in parallel, overwriting its `caller`.
+/// Transactions whose signature does not recover are dropped; unsigned ones pass through as given.
+pub fn recover_senders(txs: Vec<FluxTransaction>, chain_id: u64) -> (Vec<FluxTransaction>, RecoveryStats) {
+    let signatures = txs.iter().filter(|tx| tx.signature.is_some()).count() as u64;
+    if signatures == 0 {
+        return (txs, RecoveryStats::default());
+    }
+
+    let started = Instant::now();
+    let secp = Secp256k1::verification_only();
+    let recovered: Vec<Option<FluxTransaction>> = txs
+        .into_par_iter()
+        .map(|mut tx| {
+            let Some(signature) = tx.signature else {
+                return Some(tx);
+            };
+            match signature.recover(&secp, signing_hash(&tx, chain_id)) {
+                Ok(sender) => {
+                    tx.caller = sender;
+                    Some(tx)
+                }
+                Err(e) => {
+                    println!("[FLUX] Dropping tx {}: {}", tx.id, e);
+                    None
+                }
+            }
+        })
+        .collect();
+    let duration = started.elapsed();
+
+    let txs: Vec<FluxTransaction> = recovered.into_iter().flatten().collect();
+    let stats = RecoveryStats {
+        blocks: 1,
+        signatures,
+        invalid: signatures - txs.iter().filter(|tx| tx.signature.is_some()).count() as u64,
+        duration,
+    };
+    (txs, stats)
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RecoveryStats {
+    /// Blocks that carried at least one signed transaction.
+    pub blocks: u64,
+    pub signatures: u64,
+    /// Signatures that did not recover; their transactions were dropped from the block.
+    pub invalid: u64,
+    /// Wall-clock time of the recovery batches, hashing included.
+    pub duration: Duration,
+}
+
+impl std::ops::AddAssign for RecoveryStats {
+    fn add_assign(&mut self, other: Self) {
+        self.blocks += other.blocks;
+        self.signatures += other.signatures;
+        self.invalid += other.invalid;
+        self.duration += other.duration;
+    }
+}
+
+impl fmt::Display for RecoveryStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Sender Recovery:    {} signatures in {:?} ({:.0} per second), {} invalid and dropped",
+            self.signatures,
+            self.duration,
+            self.signatures as f64 / self.duration.as_secs_f64().max(f64::EPSILON),
+            self.invalid
+        )
+    }
+}
@@ -0,0 +1,861 @@
+/*
+ * FLUX ENGINE - SCHEDULER
+ * Execution strategies, and the Block-STM scheduler (Gelashvili et al., 2022) behind
+ * `--strategy block-stm`. Transactions execute against a multi-version memory of every write
+ * made so far in the block, reading the latest one from a lower transaction instead of the
+ * pre-block state. Each read set is validated after the fact; a transaction that read a stale
+ * version is aborted, its writes become estimates and it re-executes as a new incarnation. A
+ * transaction that reads an estimate is suspended until the writer has executed again.
+ */
+
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use rayon::prelude::*;
+use revm::db::DatabaseRef;
+use revm::primitives::{AccountInfo, Address, Bytecode, ResultAndState, State, B256, U256};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    /// Every transaction executes against the pre-block state at once; the ordered applier
+    /// re-executes, serially, those that read something an earlier transaction wrote.
+    #[default]
+    Optimistic,
+    /// Block-STM: transactions see earlier transactions' writes while executing, and conflicts
+    /// are resolved by re-executing in parallel before anything reaches the applier.
+    BlockStm,
+    /// Each transaction waits for locks on the accounts it declares, granted in block order
+    /// (see `locking`); a baseline that never executes a declared conflict twice.
+    Pessimistic,
+    /// One transaction at a time, each executed at its turn on top of everything before it: the
+    /// baseline the parallel strategies are measured against.
+    Serial,
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "optimistic" => Ok(Strategy::Optimistic),
+            "block-stm" | "blockstm" => Ok(Strategy::BlockStm),
+            "pessimistic" => Ok(Strategy::Pessimistic),
+            "serial" => Ok(Strategy::Serial),
+            _ => Err(format!("unknown strategy {:?} (expected optimistic, block-stm, pessimistic or serial)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Strategy::Optimistic => "optimistic",
+            Strategy::BlockStm => "block-stm",
+            Strategy::Pessimistic => "pessimistic",
+            Strategy::Serial => "serial",
+        })
+    }
+}
+
+// --- MULTI-VERSION MEMORY ---
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Key {
+    Account(Address),
+    Storage(Address, U256),
+    /// The account's storage was cleared (created or destroyed). Slots written before it read
+    /// as zero.
+    Wipe(Address),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Account(Option<AccountInfo>),
+    Storage(U256),
+    Wipe,
+}
+
+/// A transaction index and its incarnation.
+type Version = (usize, usize);
+
+#[derive(Debug)]
+enum Entry {
+    Written(usize, Value),
+    /// Written by an aborted incarnation; the next one will likely write it again.
+    Estimate,
+}
+
+enum MvRead {
+    /// No lower transaction wrote it: the pre-block state holds the value.
+    Base,
+    Written(Version, Value),
+    /// The latest lower write is an estimate, left by this transaction.
+    Blocked(usize),
+}
+
+#[derive(Default)]
+struct MvMemory {
+    data: DashMap<Key, BTreeMap<usize, Entry>>,
+}
+
+impl MvMemory {
+    fn read(&self, key: Key, tx: usize) -> MvRead {
+        let Some(versions) = self.data.get(&key) else {
+            return MvRead::Base;
+        };
+        match versions.range(..tx).next_back() {
+            None => MvRead::Base,
+            Some((writer, Entry::Estimate)) => MvRead::Blocked(*writer),
+            Some((writer, Entry::Written(incarnation, value))) => {
+                MvRead::Written((*writer, *incarnation), value.clone())
+            }
+        }
+    }
+
+    /// Installs an incarnation's writes and removes whatever the previous one wrote that this
+    /// one did not. Returns whether it wrote a location the previous incarnation had not.
+    fn record(&self, (tx, incarnation): Version, writes: Vec<(Key, Value)>, previous: &[Key]) -> bool {
+        let keys: HashSet<Key> = writes.iter().map(|(key, _)| *key).collect();
+        for key in previous.iter().filter(|key| !keys.contains(key)) {
+            if let Some(mut versions) = self.data.get_mut(key) {
+                versions.remove(&tx);
+            }
+        }
+        let previous: HashSet<&Key> = previous.iter().collect();
+        let mut new_location = false;
+        for (key, value) in writes {
+            new_location |= !previous.contains(&key);
+            self.data.entry(key).or_default().insert(tx, Entry::Written(incarnation, value));
+        }
+        new_location
+    }
+
+    fn mark_estimates(&self, tx: usize, keys: &[Key]) {
+        for key in keys {
+            if let Some(mut versions) = self.data.get_mut(key) {
+                versions.insert(tx, Entry::Estimate);
+            }
+        }
+    }
+}
+
+/// One incarnation's view of the state: multi-version memory below `tx`, then `state`. The
+/// coinbase is always read from `state` and never tracked, as in the optimistic strategy
+/// (its credits are rebased when applied).
+pub struct MvView<'a, DB> {
+    memory: &'a MvMemory,
+    state: &'a DB,
+    coinbase: Address,
+    tx: usize,
+    /// Every read and the version it saw, `None` for the pre-block state.
+    reads: RefCell<Vec<(Key, Option<Version>)>>,
+    blocked: Cell<Option<usize>>,
+}
+
+impl<DB> MvView<'_, DB> {
+    fn read(&self, key: Key) -> Result<Option<(Version, Value)>, String> {
+        match self.memory.read(key, self.tx) {
+            MvRead::Base => {
+                self.reads.borrow_mut().push((key, None));
+                Ok(None)
+            }
+            MvRead::Written(version, value) => {
+                self.reads.borrow_mut().push((key, Some(version)));
+                Ok(Some((version, value)))
+            }
+            MvRead::Blocked(writer) => {
+                self.blocked.set(Some(writer));
+                Err(format!("tx {} waits on tx {}", self.tx, writer))
+            }
+        }
+    }
+}
+
+impl<DB: DatabaseRef<Error = String>> DatabaseRef for MvView<'_, DB> {
+    type Error = String;
+
+    fn basic(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        if address == self.coinbase {
+            return self.state.basic(address);
+        }
+        match self.read(Key::Account(address))? {
+            Some((_, Value::Account(info))) => Ok(info),
+            Some(_) => unreachable!("account keys hold accounts"),
+            None => self.state.basic(address),
+        }
+    }
+
+    fn code_by_hash(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        // Accounts written in the block carry their code, so only pre-block code is read by hash.
+        self.state.code_by_hash(code_hash)
+    }
+
+    fn storage(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        if address == self.coinbase {
+            return self.state.storage(address, index);
+        }
+        let written = self.read(Key::Storage(address, index))?;
+        let wiped = self.read(Key::Wipe(address))?;
+        match (written, wiped) {
+            // A transaction's own wipe precedes its writes.
+            (Some(((writer, _), Value::Storage(value))), Some(((wiper, _), _))) if writer >= wiper => Ok(value),
+            (_, Some(_)) => Ok(U256::ZERO),
+            (Some((_, Value::Storage(value))), None) => Ok(value),
+            (Some(_), None) => unreachable!("storage keys hold slots"),
+            (None, None) => self.state.storage(address, index),
+        }
+    }
+
+    fn block_hash(&self, number: U256) -> Result<B256, Self::Error> {
+        self.state.block_hash(number)
+    }
+}
+
+/// What an executed transaction wrote, coinbase excluded.
+fn write_set(state: &State, coinbase: Address) -> Vec<(Key, Value)> {
+    let mut writes = Vec::new();
+    for (address, account) in state.iter().filter(|(address, _)| **address != coinbase) {
+        if !account.is_touched() {
+            continue;
+        }
+        if account.is_selfdestructed() {
+            writes.push((Key::Account(*address), Value::Account(None)));
+            writes.push((Key::Wipe(*address), Value::Wipe));
+            continue;
+        }
+        if account.is_created() {
+            writes.push((Key::Wipe(*address), Value::Wipe));
+        }
+        writes.push((Key::Account(*address), Value::Account(Some(account.info.clone()))));
+        for (slot, value) in account.storage.iter().filter(|(_, value)| value.is_changed()) {
+            writes.push((Key::Storage(*address, *slot), Value::Storage(value.present_value)));
+        }
+    }
+    writes
+}
+
+// --- THE SCHEDULER ---
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    ReadyToExecute,
+    Executing,
+    Executed,
+    Aborting,
+}
+
+enum Task {
+    Execute(Version),
+    Validate(Version),
+}
+
+type Outcome = Result<ResultAndState, String>;
+
+struct TxState {
+    /// Incarnation and status.
+    status: Mutex<(usize, Status)>,
+    /// Transactions suspended until this one finishes executing.
+    dependents: Mutex<Vec<usize>>,
+    reads: Mutex<Vec<(Key, Option<Version>)>>,
+    writes: Mutex<Vec<Key>>,
+    outcome: Mutex<Option<Outcome>>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    executions: AtomicU64,
+    validations: AtomicU64,
+    aborts: AtomicU64,
+    suspensions: AtomicU64,
+}
+
+struct Scheduler<'a, DB, F> {
+    state: &'a DB,
+    coinbase: Address,
+    execute: F,
+    memory: MvMemory,
+    txs: Vec<TxState>,
+    execution_idx: AtomicUsize,
+    validation_idx: AtomicUsize,
+    /// Bumped whenever either index moves back, so `check_done` can tell it raced one.
+    decrease_cnt: AtomicUsize,
+    /// Tasks handed out and not finished.
+    num_active: AtomicUsize,
+    done: AtomicBool,
+    counters: Counters,
+}
+
+/// Executes `block_size` transactions under Block-STM on the current rayon pool and returns
+/// each one's outcome as of its final incarnation, which matches executing them in order.
+/// `execute` runs transaction `i` against the view it is given.
+pub fn run<DB, F>(state: &DB, coinbase: Address, block_size: usize, execute: F) -> (Vec<Outcome>, BlockStmStats)
+where
+    DB: DatabaseRef<Error = String> + Sync,
+    F: Fn(usize, &MvView<'_, DB>) -> Outcome + Sync,
+{
+    let scheduler = Scheduler {
+        state,
+        coinbase,
+        execute,
+        memory: MvMemory::default(),
+        txs: (0..block_size)
+            .map(|_| TxState {
+                status: Mutex::new((0, Status::ReadyToExecute)),
+                dependents: Mutex::new(Vec::new()),
+                reads: Mutex::new(Vec::new()),
+                writes: Mutex::new(Vec::new()),
+                outcome: Mutex::new(None),
+            })
+            .collect(),
+        execution_idx: AtomicUsize::new(0),
+        validation_idx: AtomicUsize::new(0),
+        decrease_cnt: AtomicUsize::new(0),
+        num_active: AtomicUsize::new(0),
+        done: AtomicBool::new(false),
+        counters: Counters::default(),
+    };
+    (0..rayon::current_num_threads()).into_par_iter().for_each(|_| scheduler.work());
+
+    let stats = BlockStmStats {
+        blocks: 1,
+        transactions: block_size as u64,
+        executions: scheduler.counters.executions.into_inner(),
+        validations: scheduler.counters.validations.into_inner(),
+        aborts: scheduler.counters.aborts.into_inner(),
+        suspensions: scheduler.counters.suspensions.into_inner(),
+        reexecuted: scheduler.txs.iter().filter(|tx| tx.status.lock().0 > 0).count() as u64,
+    };
+    let outcomes = scheduler
+        .txs
+        .into_iter()
+        .map(|tx| tx.outcome.into_inner().expect("every transaction executes"))
+        .collect();
+    (outcomes, stats)
+}
+
+impl<DB, F> Scheduler<'_, DB, F>
+where
+    DB: DatabaseRef<Error = String> + Sync,
+    F: Fn(usize, &MvView<'_, DB>) -> Outcome + Sync,
+{
+    fn work(&self) {
+        let mut task = None;
+        while !self.done.load(Ordering::SeqCst) {
+            task = match task {
+                Some(Task::Execute(version)) => self.try_execute(version),
+                Some(Task::Validate(version)) => self.try_validate(version),
+                None => {
+                    let next = self.next_task();
+                    if next.is_none() {
+                        std::thread::yield_now();
+                    }
+                    next
+                }
+            };
+        }
+    }
+
+    fn try_execute(&self, (tx, incarnation): Version) -> Option<Task> {
+        loop {
+            let view = MvView {
+                memory: &self.memory,
+                state: self.state,
+                coinbase: self.coinbase,
+                tx,
+                reads: RefCell::new(Vec::new()),
+                blocked: Cell::new(None),
+            };
+            let outcome = (self.execute)(tx, &view);
+            self.counters.executions.fetch_add(1, Ordering::Relaxed);
+            if let Some(writer) = view.blocked.get() {
+                if self.add_dependency(tx, writer) {
+                    self.counters.suspensions.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+                // The writer finished in the meantime; its value is readable now.
+                continue;
+            }
+
+            let state = &self.txs[tx];
+            let writes = outcome.as_ref().map_or_else(|_| Vec::new(), |executed| write_set(&executed.state, self.coinbase));
+            let keys: Vec<Key> = writes.iter().map(|(key, _)| *key).collect();
+            let mut previous = state.writes.lock();
+            let wrote_new = self.memory.record((tx, incarnation), writes, &previous);
+            *previous = keys;
+            drop(previous);
+            *state.reads.lock() = view.reads.into_inner();
+            *state.outcome.lock() = Some(outcome);
+            return self.finish_execution(tx, incarnation, wrote_new);
+        }
+    }
+
+    /// Re-reads `tx`'s read set; if any read now resolves to another version, aborts it.
+    fn try_validate(&self, (tx, incarnation): Version) -> Option<Task> {
+        self.counters.validations.fetch_add(1, Ordering::Relaxed);
+        let valid = self.txs[tx].reads.lock().iter().all(|(key, seen)| match self.memory.read(*key, tx) {
+            MvRead::Base => seen.is_none(),
+            MvRead::Written(version, _) => *seen == Some(version),
+            MvRead::Blocked(_) => false,
+        });
+        let aborted = !valid && self.try_validation_abort(tx, incarnation);
+        if aborted {
+            self.counters.aborts.fetch_add(1, Ordering::Relaxed);
+            self.memory.mark_estimates(tx, &self.txs[tx].writes.lock());
+        }
+        self.finish_validation(tx, aborted)
+    }
+
+    fn next_task(&self) -> Option<Task> {
+        if self.validation_idx.load(Ordering::SeqCst) < self.execution_idx.load(Ordering::SeqCst) {
+            self.next_version_to_validate().map(Task::Validate)
+        } else {
+            self.next_version_to_execute().map(Task::Execute)
+        }
+    }
+
+    fn next_version_to_execute(&self) -> Option<Version> {
+        if self.execution_idx.load(Ordering::SeqCst) >= self.txs.len() {
+            self.check_done();
+            return None;
+        }
+        self.num_active.fetch_add(1, Ordering::SeqCst);
+        let tx = self.execution_idx.fetch_add(1, Ordering::SeqCst);
+        let version = self.try_incarnate(tx);
+        if version.is_none() {
+            self.num_active.fetch_sub(1, Ordering::SeqCst);
+        }
+        version
+    }
+
+    fn next_version_to_validate(&self) -> Option<Version> {
+        if self.validation_idx.load(Ordering::SeqCst) >= self.txs.len() {
+            self.check_done();
+            return None;
+        }
+        self.num_active.fetch_add(1, Ordering::SeqCst);
+        let tx = self.validation_idx.fetch_add(1, Ordering::SeqCst);
+        if let Some(state) = self.txs.get(tx) {
+            let (incarnation, status) = *state.status.lock();
+            if status == Status::Executed {
+                return Some((tx, incarnation));
+            }
+        }
+        self.num_active.fetch_sub(1, Ordering::SeqCst);
+        None
+    }
+
+    fn try_incarnate(&self, tx: usize) -> Option<Version> {
+        let mut status = self.txs.get(tx)?.status.lock();
+        if status.1 != Status::ReadyToExecute {
+            return None;
+        }
+        status.1 = Status::Executing;
+        Some((tx, status.0))
+    }
+
+    /// Suspends `tx` until `writer` finishes executing. False if it already has.
+    fn add_dependency(&self, tx: usize, writer: usize) -> bool {
+        let mut dependents = self.txs[writer].dependents.lock();
+        if self.txs[writer].status.lock().1 == Status::Executed {
+            return false;
+        }
+        self.txs[tx].status.lock().1 = Status::Aborting;
+        dependents.push(tx);
+        self.num_active.fetch_sub(1, Ordering::SeqCst);
+        true
+    }
+
+    fn set_ready(&self, tx: usize) {
+        let mut status = self.txs[tx].status.lock();
+        *status = (status.0 + 1, Status::ReadyToExecute);
+    }
+
+    fn finish_execution(&self, tx: usize, incarnation: usize, wrote_new: bool) -> Option<Task> {
+        let mut dependents = self.txs[tx].dependents.lock();
+        self.txs[tx].status.lock().1 = Status::Executed;
+        let resumed = std::mem::take(&mut *dependents);
+        drop(dependents);
+        for dependent in &resumed {
+            self.set_ready(*dependent);
+        }
+        if let Some(first) = resumed.iter().min() {
+            self.decrease(&self.execution_idx, *first);
+        }
+
+        if self.validation_idx.load(Ordering::SeqCst) > tx {
+            if !wrote_new {
+                // Only this transaction needs validating; later ones saw the same locations.
+                return Some(Task::Validate((tx, incarnation)));
+            }
+            self.decrease(&self.validation_idx, tx);
+        }
+        self.num_active.fetch_sub(1, Ordering::SeqCst);
+        None
+    }
+
+    fn try_validation_abort(&self, tx: usize, incarnation: usize) -> bool {
+        let mut status = self.txs[tx].status.lock();
+        if *status != (incarnation, Status::Executed) {
+            return false;
+        }
+        status.1 = Status::Aborting;
+        true
+    }
+
+    fn finish_validation(&self, tx: usize, aborted: bool) -> Option<Task> {
+        if aborted {
+            self.set_ready(tx);
+            self.decrease(&self.validation_idx, tx + 1);
+            if self.execution_idx.load(Ordering::SeqCst) > tx {
+                if let Some(version) = self.try_incarnate(tx) {
+                    return Some(Task::Execute(version));
+                }
+            }
+        }
+        self.num_active.fetch_sub(1, Ordering::SeqCst);
+        None
+    }
+
+    fn decrease(&self, index: &AtomicUsize, to: usize) {
+        index.fetch_min(to, Ordering::SeqCst);
+        self.decrease_cnt.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn check_done(&self) {
+        let observed = self.decrease_cnt.load(Ordering::SeqCst);
+        let next = self.execution_idx.load(Ordering::SeqCst).min(self.validation_idx.load(Ordering::SeqCst));
+        if next >= self.txs.len()
+            && self.num_active.load(Ordering::SeqCst) == 0
+            && observed == self.decrease_cnt.load(Ordering::SeqCst)
+        {
+            self.done.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BlockStmStats {
+    pub blocks: u64,
+    pub transactions: u64,
+    /// Incarnations run, including ones cut short by a read of an estimate.
+    pub executions: u64,
+    pub validations: u64,
+    pub aborts: u64,
+    pub suspensions: u64,
+    /// Transactions that ran more than one incarnation.
+    pub reexecuted: u64,
+}
+
+impl std::ops::AddAssign for BlockStmStats {
+    fn add_assign(&mut self, other: Self) {
+        self.blocks += other.blocks;
+        self.transactions += other.transactions;
+        self.executions += other.executions;
+        self.validations += other.validations;
+        self.aborts += other.aborts;
+        self.suspensions += other.suspensions;
+        self.reexecuted += other.reexecuted;
+    }
+}
+
+impl fmt::Display for BlockStmStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Block-STM:          {} txs, {} executions ({:.2} per tx), {} validations, {} aborted, {} suspended",
+            self.transactions,
+            self.executions,
+            self.executions as f64 / self.transactions.max(1) as f64,
+            self.validations,
+            self.aborts,
+            self.suspensions
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use revm::primitives::{Account, AccountStatus, Bytes, Eval, ExecutionResult, Output, StorageSlot};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    const COINBASE: Address = Address::repeat_byte(0xcb);
+
+    /// The pre-block state: every account holds 100 wei and every slot `s` holds `s + 1`, so a
+    /// slot that reads zero was wiped rather than never written.
+    struct Pre;
+
+    impl DatabaseRef for Pre {
+        type Error = String;
+
+        fn basic(&self, _: Address) -> Result<Option<AccountInfo>, String> {
+            Ok(Some(AccountInfo {
+                balance: U256::from(100),
+                ..Default::default()
+            }))
+        }
+
+        fn code_by_hash(&self, _: B256) -> Result<Bytecode, String> {
+            Ok(Bytecode::new())
+        }
+
+        fn storage(&self, _: Address, index: U256) -> Result<U256, String> {
+            Ok(index + U256::from(1))
+        }
+
+        fn block_hash(&self, _: U256) -> Result<B256, String> {
+            Ok(B256::ZERO)
+        }
+    }
+
+    /// `Pre` with the writes of the transactions executed so far on top, for the serial baseline.
+    #[derive(Default)]
+    struct Applied {
+        accounts: HashMap<Address, Option<AccountInfo>>,
+        slots: HashMap<(Address, U256), U256>,
+        wiped: HashSet<Address>,
+    }
+
+    impl Applied {
+        fn apply(&mut self, writes: Vec<(Key, Value)>) {
+            // A transaction's wipe precedes its writes.
+            for (key, _) in &writes {
+                if let Key::Wipe(address) = key {
+                    self.slots.retain(|(slot_address, _), _| slot_address != address);
+                    self.wiped.insert(*address);
+                }
+            }
+            for (key, value) in writes {
+                match (key, value) {
+                    (Key::Account(address), Value::Account(info)) => {
+                        self.accounts.insert(address, info);
+                    }
+                    (Key::Storage(address, slot), Value::Storage(value)) => {
+                        self.slots.insert((address, slot), value);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    impl DatabaseRef for Applied {
+        type Error = String;
+
+        fn basic(&self, address: Address) -> Result<Option<AccountInfo>, String> {
+            match self.accounts.get(&address) {
+                Some(info) => Ok(info.clone()),
+                None => Pre.basic(address),
+            }
+        }
+
+        fn code_by_hash(&self, code_hash: B256) -> Result<Bytecode, String> {
+            Pre.code_by_hash(code_hash)
+        }
+
+        fn storage(&self, address: Address, index: U256) -> Result<U256, String> {
+            match self.slots.get(&(address, index)) {
+                Some(value) => Ok(*value),
+                None if self.wiped.contains(&address) => Ok(U256::ZERO),
+                None => Pre.storage(address, index),
+            }
+        }
+
+        fn block_hash(&self, number: U256) -> Result<B256, String> {
+            Pre.block_hash(number)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    enum Op {
+        /// Adds one to a slot.
+        Increment(Address, u64),
+        /// Moves one wei.
+        Transfer(Address, Address),
+        /// Copies a slot into another.
+        Copy((Address, u64), (Address, u64)),
+        /// Creates the account afresh with one slot set.
+        Create(Address, u64, u64),
+        SelfDestruct(Address),
+    }
+
+    fn slot(n: u64) -> U256 {
+        U256::from(n)
+    }
+
+    fn touched(info: AccountInfo, status: AccountStatus, slots: &[(U256, U256)]) -> Account {
+        Account {
+            info,
+            storage: slots.iter().map(|(slot, value)| (*slot, StorageSlot::new_changed(U256::ZERO, *value))).collect(),
+            status: status | AccountStatus::Touched,
+        }
+    }
+
+    /// What `op` does, executed against `db` as the EVM would.
+    fn execute(op: Op, db: &impl DatabaseRef<Error = String>) -> Outcome {
+        let mut state = State::default();
+        match op {
+            Op::Increment(address, n) => {
+                let value = db.storage(address, slot(n))? + U256::from(1);
+                let info = db.basic(address)?.unwrap_or_default();
+                state.insert(address, touched(info, AccountStatus::empty(), &[(slot(n), value)]));
+            }
+            Op::Transfer(from, to) => {
+                let mut sender = db.basic(from)?.unwrap_or_default();
+                let mut receiver = db.basic(to)?.unwrap_or_default();
+                sender.balance -= U256::from(1);
+                receiver.balance += U256::from(1);
+                state.insert(from, touched(sender, AccountStatus::empty(), &[]));
+                state.insert(to, touched(receiver, AccountStatus::empty(), &[]));
+            }
+            Op::Copy((from, n), (to, m)) => {
+                let value = db.storage(from, slot(n))?;
+                let info = db.basic(to)?.unwrap_or_default();
+                state.insert(to, touched(info, AccountStatus::empty(), &[(slot(m), value)]));
+            }
+            Op::Create(address, n, value) => {
+                let info = AccountInfo {
+                    nonce: 1,
+                    ..Default::default()
+                };
+                state.insert(address, touched(info, AccountStatus::Created, &[(slot(n), U256::from(value))]));
+            }
+            Op::SelfDestruct(address) => {
+                let info = db.basic(address)?.unwrap_or_default();
+                state.insert(address, touched(info, AccountStatus::SelfDestructed, &[]));
+            }
+        }
+        let result = ExecutionResult::Success {
+            reason: Eval::Stop,
+            gas_used: 21_000,
+            gas_refunded: 0,
+            logs: Vec::new(),
+            output: Output::Call(Bytes::new()),
+        };
+        Ok(ResultAndState { result, state })
+    }
+
+    /// Runs `ops` under Block-STM on four threads, the incarnations `slow` picks (by transaction
+    /// and incarnation) taking long enough for later ones to run ahead of them, and checks each
+    /// outcome against executing `ops` one by one.
+    fn check_against_serial(ops: &[Op], slow: impl Fn(usize, usize) -> bool + Sync) -> BlockStmStats {
+        let incarnations: Vec<AtomicUsize> = ops.iter().map(|_| AtomicUsize::new(0)).collect();
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        let (outcomes, stats) = pool.install(|| {
+            run(&Pre, COINBASE, ops.len(), |i, view| {
+                if slow(i, incarnations[i].fetch_add(1, Ordering::Relaxed)) {
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+                execute(ops[i], view)
+            })
+        });
+
+        let mut applied = Applied::default();
+        for (i, (op, outcome)) in ops.iter().zip(outcomes).enumerate() {
+            let expected = write_set(&execute(*op, &applied).unwrap().state, COINBASE);
+            let actual = write_set(&outcome.unwrap().state, COINBASE);
+            let as_map = |writes: &[(Key, Value)]| writes.iter().cloned().collect::<HashMap<_, _>>();
+            assert_eq!(as_map(&actual), as_map(&expected), "tx {} ({:?})", i, op);
+            applied.apply(expected);
+        }
+        stats
+    }
+
+    #[test]
+    fn matches_serial_execution_on_conflicting_transactions() {
+        let (a, b, c) = (Address::repeat_byte(1), Address::repeat_byte(2), Address::repeat_byte(3));
+        let ops = [
+            Op::Increment(a, 0),
+            Op::Increment(a, 0),
+            Op::Transfer(b, c),
+            Op::Increment(a, 0),
+            Op::Transfer(c, b),
+            Op::Copy((a, 0), (b, 7)),
+            Op::Increment(a, 0),
+            Op::Transfer(b, c),
+        ];
+        let stats = check_against_serial(&ops, |tx, incarnation| incarnation == 0 && (tx == 0 || tx == 2));
+        assert!(stats.aborts > 0, "{:?}", stats);
+        assert_eq!(stats.transactions, ops.len() as u64);
+    }
+
+    #[test]
+    fn matches_serial_execution_when_reads_wait_on_estimates() {
+        // Tx 1 runs ahead of tx 0 and is aborted, its write to b left as an estimate; tx 2 reads
+        // it and has to wait for tx 1 to execute again.
+        let (a, b, c) = (Address::repeat_byte(1), Address::repeat_byte(2), Address::repeat_byte(3));
+        let ops = [
+            Op::Increment(a, 0),
+            Op::Copy((a, 0), (b, 0)),
+            Op::Copy((b, 0), (c, 0)),
+            Op::Copy((c, 0), (a, 1)),
+            Op::Increment(b, 0),
+        ];
+        // Tx 0 is slow at first, and tx 1 once it re-executes.
+        let stats = check_against_serial(&ops, |tx, incarnation| matches!((tx, incarnation), (0, 0) | (1, 1)));
+        assert!(stats.suspensions > 0, "{:?}", stats);
+    }
+
+    #[test]
+    fn matches_serial_execution_across_self_destructs() {
+        let (contract, log) = (Address::repeat_byte(0xc0), Address::repeat_byte(0x10));
+        let ops = [
+            Op::Copy((contract, 1), (log, 0)),
+            Op::Create(contract, 1, 5),
+            Op::Copy((contract, 1), (log, 1)),
+            Op::Copy((contract, 2), (log, 2)),
+            Op::SelfDestruct(contract),
+            Op::Copy((contract, 1), (log, 3)),
+            Op::Create(contract, 2, 9),
+            Op::Copy((contract, 1), (log, 4)),
+            Op::Copy((contract, 2), (log, 5)),
+        ];
+        check_against_serial(&ops, |tx, incarnation| incarnation == 0 && (tx == 1 || tx == 4));
+    }
+
+    #[test]
+    fn storage_reads_order_wipes_against_writes() {
+        let contract = Address::repeat_byte(0xc0);
+        let (a, b, untouched) = (slot(1), slot(2), slot(3));
+        let memory = MvMemory::default();
+        // Tx 1 writes a; tx 2 wipes the storage and writes b; tx 3 writes a again.
+        memory.record((1, 0), vec![(Key::Storage(contract, a), Value::Storage(U256::from(5)))], &[]);
+        memory.record(
+            (2, 0),
+            vec![(Key::Wipe(contract), Value::Wipe), (Key::Storage(contract, b), Value::Storage(U256::from(9)))],
+            &[],
+        );
+        memory.record((3, 0), vec![(Key::Storage(contract, a), Value::Storage(U256::from(7)))], &[]);
+        let view = |tx| MvView {
+            memory: &memory,
+            state: &Pre,
+            coinbase: COINBASE,
+            tx,
+            reads: RefCell::new(Vec::new()),
+            blocked: Cell::new(None),
+        };
+        let read = |tx, slot| view(tx).storage(contract, slot).unwrap();
+
+        assert_eq!(read(1, a), U256::from(2), "nothing written below tx 1");
+        assert_eq!(read(2, a), U256::from(5));
+        assert_eq!(read(3, a), U256::ZERO, "tx 1's write is under tx 2's wipe");
+        assert_eq!(read(4, a), U256::from(7), "tx 3's write is over it");
+        assert_eq!(read(2, b), U256::from(3));
+        assert_eq!(read(3, b), U256::from(9), "tx 2's own wipe comes before its write");
+        assert_eq!(read(2, untouched), U256::from(4));
+        assert_eq!(read(3, untouched), U256::ZERO);
+
+        // Once tx 2 is aborted, reading through its wipe waits on it.
+        memory.mark_estimates(2, &[Key::Wipe(contract)]);
+        let waiting = view(3);
+        assert!(waiting.storage(contract, untouched).is_err());
+        assert_eq!(waiting.blocked.get(), Some(2));
+    }
+}
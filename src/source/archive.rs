@@ -0,0 +1,630 @@
+/*
+ * FLUX ENGINE - BLOCK ARCHIVES
+ * `--blocks-dir DIR`: replays blocks from files already on disk, so a benchmark neither depends
+ * on a node nor pays its latency. DIR holds era1 files (`*.era1`, e2store archives of pre-merge
+ * history) and/or raw RLP block dumps (`*.rlp`, as `geth export` writes them), read in file name
+ * order. Blocks are decompressed and decoded in batches on the executor pool, which is where
+ * prefetch runs too.
+ *
+ * Neither format carries senders: signed transactions go to sender recovery like any other, under
+ * the run's chain id, so --chain has to match the blocks' (mainnet for mainnet history).
+ *
+ * Each block's transactions are checked against its transactions root as it is decoded, and each
+ * block's parent hash against the hash of the block handed out before it (`integrity.rs`).
+ */
+
+use super::integrity::Seal;
+use super::{snappy, BlockSource, SourceBlock};
+use crate::block::{BlockHeader, Withdrawal};
+use crate::recovery::{signing_hash_as, Signature};
+use crate::FluxTransaction;
+use alloy_primitives::keccak256;
+use alloy_rlp::{Decodable, Header};
+use rayon::prelude::*;
+use revm::primitives::{Address, B256, U256};
+use secp256k1::Secp256k1;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Blocks decoded per parallel batch.
+const DECODE_BATCH: usize = 64;
+
+// e2store entry types an era1 file is made of.
+const COMPRESSED_HEADER: u16 = 0x03;
+const COMPRESSED_BODY: u16 = 0x04;
+const BLOCK_INDEX: u16 = 0x3266;
+
+pub struct ArchiveBlockSource {
+    files: VecDeque<PathBuf>,
+    /// Blocks read from the current file but not decoded yet, in order.
+    raw: VecDeque<RawBlock>,
+    decoded: VecDeque<(SourceBlock, Seal)>,
+    first_block: Option<u64>,
+    /// The block handed out last, which the next one has to name as its parent.
+    last: Option<Seal>,
+    /// Blocks still to hand out.
+    remaining: u64,
+    stats: ArchiveStats,
+}
+
+/// A block as stored, before any decoding.
+enum RawBlock {
+    /// Snappy-framed header and body RLP from an era1 file.
+    Era { header: Vec<u8>, body: Vec<u8> },
+    /// `rlp([header, txs, uncles, withdrawals?])`.
+    Rlp(Vec<u8>),
+}
+
+impl ArchiveBlockSource {
+    /// A source for `blocks` blocks from `dir`, starting at `first_block` or wherever the first
+    /// file does. Files are only read as their blocks are needed.
+    pub fn new(dir: &Path, first_block: Option<u64>, blocks: u64) -> Result<Self, String> {
+        Ok(Self {
+            files: archive_files(dir)?.into(),
+            raw: VecDeque::new(),
+            decoded: VecDeque::new(),
+            first_block,
+            last: None,
+            remaining: blocks,
+            stats: ArchiveStats::default(),
+        })
+    }
+
+    pub fn stats(&self) -> ArchiveStats {
+        self.stats
+    }
+
+    /// Reads files until one holds blocks at or after the first block. False once none is left.
+    fn read_next_file(&mut self) -> Result<bool, String> {
+        while let Some(path) = self.files.pop_front() {
+            let data = std::fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            self.stats.files += 1;
+            self.stats.bytes += data.len() as u64;
+            let blocks = if path.extension().is_some_and(|ext| ext == "era1") {
+                read_era1(&data, self.first_block)
+            } else {
+                read_rlp_dump(&data, self.first_block)
+            }
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+            if !blocks.is_empty() {
+                self.raw.extend(blocks);
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Decodes the next batch of read blocks in parallel.
+    fn decode_batch(&mut self) -> Result<(), String> {
+        let count = self.raw.len().min(DECODE_BATCH).min(self.remaining as usize);
+        let batch: Vec<RawBlock> = self.raw.drain(..count).collect();
+        let started = Instant::now();
+        let decoded: Vec<Result<(SourceBlock, Seal), String>> = batch.into_par_iter().map(RawBlock::decode).collect();
+        self.stats.duration += started.elapsed();
+        for block in decoded {
+            let (block, seal) = block?;
+            self.stats.blocks += 1;
+            self.stats.transactions += block.txs.len() as u64;
+            self.decoded.push_back((block, seal));
+        }
+        Ok(())
+    }
+}
+
+impl BlockSource for ArchiveBlockSource {
+    fn next_block(&mut self) -> Result<Option<SourceBlock>, String> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        if self.decoded.is_empty() {
+            if self.raw.is_empty() && !self.read_next_file()? {
+                return Ok(None);
+            }
+            self.decode_batch()?;
+        }
+        let Some((block, seal)) = self.decoded.pop_front() else {
+            return Ok(None);
+        };
+
+        // The files have to hold one unbroken chain of blocks, from the first one asked for.
+        let number = seal.number;
+        match (&self.last, self.first_block) {
+            (Some(last), _) if number != last.number + 1 => {
+                return Err(format!("blocks dir: block {} follows block {}", number, last.number));
+            }
+            (Some(last), _) => seal.check_parent(last).map_err(|e| format!("blocks dir: {}", e))?,
+            (None, Some(first)) if number != first => {
+                return Err(format!("blocks dir: starts at block {}, not --from-block {}", number, first));
+            }
+            _ => {}
+        }
+        self.last = Some(seal);
+        self.remaining -= 1;
+        Ok(Some(block))
+    }
+
+    fn report(&self) -> Option<String> {
+        Some(self.stats.to_string())
+    }
+}
+
+/// The first and last block in `dir`, from the era1 block indexes and the RLP dumps' headers,
+/// without decoding any block. Gaps between files are not looked for.
+pub fn available(dir: &Path) -> Result<(u64, u64), String> {
+    let mut range: Option<(u64, u64)> = None;
+    for path in archive_files(dir)? {
+        let file = if path.extension().is_some_and(|ext| ext == "era1") {
+            era1_range(&path)
+        } else {
+            std::fs::read(&path).map_err(|e| e.to_string()).and_then(|data| {
+                let blocks = rlp_dump_blocks(&data)?;
+                match (blocks.first(), blocks.last()) {
+                    (Some((first, _)), Some((last, _))) => Ok(Some((*first, *last))),
+                    _ => Ok(None),
+                }
+            })
+        }
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+        if let Some((first, last)) = file {
+            range = Some(range.map_or((first, last), |(low, high)| (low.min(first), high.max(last))));
+        }
+    }
+    range.ok_or_else(|| format!("{}: no blocks", dir.display()))
+}
+
+/// The era1 and RLP files in `dir`, in the order their blocks are read.
+fn archive_files(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| matches!(path.extension().and_then(|ext| ext.to_str()), Some("era1") | Some("rlp")))
+        .collect();
+    if files.is_empty() {
+        return Err(format!("{}: no .era1 or .rlp files", dir.display()));
+    }
+    files.sort();
+    Ok(files)
+}
+
+// --- FILE FORMATS ---
+
+/// The blocks of an era1 file from `first_block` on, each still snappy-compressed. Receipts,
+/// total difficulties and the accumulator are skipped.
+fn read_era1(data: &[u8], first_block: Option<u64>) -> Result<Vec<RawBlock>, String> {
+    let mut headers = Vec::new();
+    let mut bodies = Vec::new();
+    let mut start = None;
+    let mut rest = data;
+    while !rest.is_empty() {
+        // type (u16), length (u32), reserved (u16), little endian.
+        if rest.len() < 8 {
+            return Err("truncated e2store entry header".into());
+        }
+        let kind = u16::from_le_bytes([rest[0], rest[1]]);
+        let length = u32::from_le_bytes([rest[2], rest[3], rest[4], rest[5]]) as usize;
+        let value = rest.get(8..8 + length).ok_or("truncated e2store entry")?;
+        rest = &rest[8 + length..];
+        match kind {
+            COMPRESSED_HEADER => headers.push(value),
+            COMPRESSED_BODY => bodies.push(value),
+            BLOCK_INDEX => {
+                let number = value.get(..8).ok_or("truncated block index")?;
+                start = Some(u64::from_le_bytes(number.try_into().expect("8 bytes")));
+            }
+            _ => {}
+        }
+    }
+    let start = start.ok_or("no block index")?;
+    if headers.len() != bodies.len() {
+        return Err(format!("{} headers but {} bodies", headers.len(), bodies.len()));
+    }
+    let skip = first_block.map_or(0, |first| first.saturating_sub(start) as usize);
+    Ok(headers
+        .into_iter()
+        .zip(bodies)
+        .skip(skip)
+        .map(|(header, body)| RawBlock::Era {
+            header: header.to_vec(),
+            body: body.to_vec(),
+        })
+        .collect())
+}
+
+/// The first and last block an era1 file's block index covers. Only the e2store entry headers
+/// and the index are read; the index is the last entry, so everything else is seeked past.
+fn era1_range(path: &Path) -> Result<Option<(u64, u64)>, String> {
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let size = file.metadata().map_err(|e| e.to_string())?.len();
+    let mut entry = [0u8; 8];
+    loop {
+        match file.read_exact(&mut entry) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Err("no block index".into()),
+            Err(e) => return Err(e.to_string()),
+        }
+        let kind = u16::from_le_bytes([entry[0], entry[1]]);
+        let length = u32::from_le_bytes([entry[2], entry[3], entry[4], entry[5]]) as usize;
+        if kind != BLOCK_INDEX {
+            file.seek(SeekFrom::Current(length as i64)).map_err(|e| e.to_string())?;
+            continue;
+        }
+        // starting number, one offset per block, block count; all u64. A damaged header may
+        // claim more than the file holds, so that is checked before anything is allocated.
+        let remaining = size.saturating_sub(file.stream_position().map_err(|e| e.to_string())?);
+        if length < 16 || length as u64 > remaining {
+            return Err(format!("truncated block index: {} bytes, {} left in the file", length, remaining));
+        }
+        let mut index = vec![0u8; length];
+        file.read_exact(&mut index).map_err(|_| "truncated block index")?;
+        let start = u64::from_le_bytes(index[..8].try_into().expect("8 bytes"));
+        let count = u64::from_le_bytes(index[length - 8..].try_into().expect("8 bytes"));
+        return match count.checked_sub(1) {
+            None => Ok(None),
+            Some(last) => match start.checked_add(last) {
+                Some(end) => Ok(Some((start, end))),
+                None => Err(format!("block index of {} blocks from {} runs past the last block number", count, start)),
+            },
+        };
+    }
+}
+
+/// The blocks of a concatenated RLP dump from `first_block` on.
+fn read_rlp_dump(data: &[u8], first_block: Option<u64>) -> Result<Vec<RawBlock>, String> {
+    Ok(rlp_dump_blocks(data)?
+        .into_iter()
+        .filter(|(number, _)| first_block.is_none_or(|first| *number >= first))
+        .map(|(_, raw)| RawBlock::Rlp(raw.to_vec()))
+        .collect())
+}
+
+/// Every block of a concatenated RLP dump, undecoded, with its number. Only the header's block
+/// number is looked at here.
+fn rlp_dump_blocks(data: &[u8]) -> Result<Vec<(u64, &[u8])>, String> {
+    let mut blocks = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let before = rest;
+        let mut block = List::open(&mut rest)?;
+        let raw = &before[..before.len() - rest.len()];
+        let mut header = block.list()?;
+        for _ in 0..8 {
+            header.skip()?;
+        }
+        let number: u64 = header.item()?;
+        blocks.push((number, raw));
+    }
+    Ok(blocks)
+}
+
+// --- DECODING ---
+
+impl RawBlock {
+    fn decode(self) -> Result<(SourceBlock, Seal), String> {
+        match self {
+            RawBlock::Era { header, body } => {
+                let header = snappy::decompress_framed(&header)?;
+                let body = snappy::decompress_framed(&body)?;
+                let (header, seal) = decode_header(&header)?;
+                decode_body(header, seal, &mut List::open(&mut body.as_slice())?)
+            }
+            RawBlock::Rlp(raw) => {
+                let mut block = List::open(&mut raw.as_slice())?;
+                let (header, seal) = decode_header(block.raw()?)?;
+                decode_body(header, seal, &mut block)
+            }
+        }
+        .map_err(|e| format!("blocks dir: {}", e))
+    }
+}
+
+/// Decodes the RLP header `raw`, which is also what the block hash is taken over.
+fn decode_header(raw: &[u8]) -> Result<(BlockHeader, Seal), String> {
+    let fields = &mut List::open(&mut &raw[..])?;
+    let parent_hash = fields.item()?;
+    fields.skip()?; // ommers hash
+    let coinbase = fields.item()?;
+    let state_root = fields.item()?;
+    let transactions_root = fields.item()?;
+    let receipts_root = fields.item()?;
+    fields.skip()?; // logs bloom
+    fields.skip()?; // difficulty
+    let number = fields.item()?;
+    let gas_limit = fields.item()?;
+    let gas_used = fields.item()?;
+    let timestamp = fields.item()?;
+    fields.skip()?; // extra data
+    fields.skip()?; // mix hash
+    fields.skip()?; // nonce
+    // Each fork appends its fields; a header ends where its fork's list does.
+    let base_fee_per_gas = fields.optional()?.unwrap_or(0);
+    fields.optional::<B256>()?; // withdrawals root
+    fields.optional::<u64>()?; // blob gas used
+    let excess_blob_gas = fields.optional()?.unwrap_or(0);
+    let parent_beacon_block_root = fields.optional()?;
+    let seal = Seal {
+        number,
+        hash: keccak256(raw),
+        parent_hash,
+        transactions_root,
+    };
+    let header = BlockHeader {
+        number,
+        timestamp,
+        coinbase,
+        gas_limit,
+        base_fee_per_gas,
+        excess_blob_gas,
+        parent_beacon_block_root,
+        withdrawals: vec![],
+        gas_used: Some(gas_used),
+        receipts_root: Some(receipts_root),
+        state_root: Some(state_root),
+    };
+    Ok((header, seal))
+}
+
+/// What follows the header: transactions, uncles (not needed) and, from Shanghai, withdrawals.
+fn decode_body(mut header: BlockHeader, seal: Seal, body: &mut List) -> Result<(SourceBlock, Seal), String> {
+    let number = header.number;
+    let mut list = body.list()?;
+    let mut envelopes = Vec::new();
+    let mut txs = Vec::new();
+    while !list.is_empty() {
+        let envelope = list.envelope()?;
+        let tx = decode_tx(txs.len(), envelope).map_err(|e| format!("block {} tx {}: {}", number, txs.len(), e))?;
+        envelopes.push(envelope);
+        txs.push(tx);
+    }
+    seal.check_transactions(&envelopes).map_err(|e| format!("block {}: {}", number, e))?;
+    body.skip()?;
+    if !body.is_empty() {
+        let mut withdrawals = body.list()?;
+        while !withdrawals.is_empty() {
+            let mut fields = withdrawals.list()?;
+            header.withdrawals.push(Withdrawal {
+                index: fields.item()?,
+                validator_index: fields.item()?,
+                address: fields.item()?,
+                amount: fields.item()?,
+            });
+        }
+    }
+    Ok((
+        SourceBlock {
+            header: Some(header),
+            txs,
+        },
+        seal,
+    ))
+}
+
+/// `envelope` is a legacy transaction's list, or a typed one's `type || rlp(fields)`.
+fn decode_tx(id: usize, mut envelope: &[u8]) -> Result<FluxTransaction, String> {
+    let (tx_type, mut fields) = match envelope.first() {
+        Some(&byte) if byte >= 0xc0 => (0, List::open(&mut envelope)?),
+        _ => {
+            let (&tx_type, mut payload) = envelope.split_first().ok_or("empty typed transaction")?;
+            (tx_type, List::open(&mut payload)?)
+        }
+    };
+    if tx_type > 3 {
+        return Err(format!("type {} transactions are not supported", tx_type));
+    }
+
+    let mut tx = FluxTransaction {
+        id,
+        caller: Address::ZERO,
+        to: Address::ZERO,
+        create: false,
+        value: U256::ZERO,
+        data: vec![],
+        gas_limit: 0,
+        nonce: None,
+        access_list: vec![],
+        max_fee_per_gas: U256::ZERO,
+        max_priority_fee_per_gas: None,
+        blob_hashes: vec![],
+        max_fee_per_blob_gas: None,
+        signature: None,
+        skipped: false,
+    };
+    let typed_chain_id = if tx_type == 0 { None } else { Some(fields.item()?) };
+    tx.nonce = Some(fields.item()?);
+    if tx_type >= 2 {
+        tx.max_priority_fee_per_gas = Some(fields.item()?);
+    }
+    tx.max_fee_per_gas = fields.item()?;
+    tx.gas_limit = fields.item()?;
+    let to = fields.string()?;
+    if to.is_empty() {
+        tx.create = true;
+    } else {
+        tx.to = Address::try_from(to).map_err(|_| "to: not an address")?;
+    }
+    tx.value = fields.item()?;
+    tx.data = fields.string()?.to_vec();
+    if tx_type >= 1 {
+        let mut entries = fields.list()?;
+        while !entries.is_empty() {
+            let mut entry = entries.list()?;
+            let address = entry.item()?;
+            let keys: Vec<B256> = entry.item()?;
+            tx.access_list
+                .push((address, keys.into_iter().map(|key| U256::from_be_bytes(key.0)).collect()));
+        }
+    }
+    if tx_type == 3 {
+        tx.max_fee_per_blob_gas = Some(fields.item()?);
+        tx.blob_hashes = fields.item()?;
+    }
+
+    let v: u64 = fields.item()?;
+    let (y_parity, chain_id) = match typed_chain_id {
+        Some(chain_id) => (v == 1, Some(chain_id)),
+        None if v >= 35 => ((v - 35) % 2 == 1, Some((v - 35) / 2)),
+        None => (v == 28, None),
+    };
+    let signature = Signature {
+        y_parity,
+        r: fields.item()?,
+        s: fields.item()?,
+    };
+    // Recovery signs the hash of whatever type the fields make the transaction look like, under
+    // the run's chain id. A pre-EIP-155 signature, or a type-1 transaction with an empty access
+    // list, needs another hash: recover those here.
+    if chain_id.is_some() && tx.tx_type() == tx_type {
+        tx.signature = Some(signature);
+    } else {
+        let hash = signing_hash_as(&tx, tx_type, chain_id);
+        tx.caller = signature.recover(&Secp256k1::verification_only(), hash)?;
+    }
+    Ok(tx)
+}
+
+/// The items of one RLP list, decoded front to back.
+struct List<'a>(&'a [u8]);
+
+impl<'a> List<'a> {
+    /// Takes the list at the front of `buf`.
+    fn open(buf: &mut &'a [u8]) -> Result<Self, String> {
+        let header = Header::decode(buf).map_err(|e| format!("rlp: {}", e))?;
+        if !header.list {
+            return Err("rlp: expected a list".into());
+        }
+        let payload = buf.get(..header.payload_length).ok_or("rlp: truncated list")?;
+        *buf = &buf[header.payload_length..];
+        Ok(List(payload))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn next_is_list(&self) -> bool {
+        self.0.first().is_some_and(|&byte| byte >= 0xc0)
+    }
+
+    fn item<T: Decodable>(&mut self) -> Result<T, String> {
+        T::decode(&mut self.0).map_err(|e| format!("rlp: {}", e))
+    }
+
+    /// A field added by a later fork: `None` past the end of the list.
+    fn optional<T: Decodable>(&mut self) -> Result<Option<T>, String> {
+        if self.is_empty() {
+            Ok(None)
+        } else {
+            self.item().map(Some)
+        }
+    }
+
+    /// The next item whole, its RLP header included.
+    fn raw(&mut self) -> Result<&'a [u8], String> {
+        let before = self.0;
+        self.skip()?;
+        Ok(&before[..before.len() - self.0.len()])
+    }
+
+    /// The next transaction as the transactions trie holds it: a legacy one's list, or the
+    /// string a typed one is wrapped in.
+    fn envelope(&mut self) -> Result<&'a [u8], String> {
+        if self.next_is_list() {
+            self.raw()
+        } else {
+            self.string()
+        }
+    }
+
+    fn list(&mut self) -> Result<List<'a>, String> {
+        List::open(&mut self.0)
+    }
+
+    fn string(&mut self) -> Result<&'a [u8], String> {
+        let header = Header::decode(&mut self.0).map_err(|e| format!("rlp: {}", e))?;
+        if header.list {
+            return Err("rlp: expected a string".into());
+        }
+        let payload = self.0.get(..header.payload_length).ok_or("rlp: truncated string")?;
+        self.0 = &self.0[header.payload_length..];
+        Ok(payload)
+    }
+
+    fn skip(&mut self) -> Result<(), String> {
+        let header = Header::decode(&mut self.0).map_err(|e| format!("rlp: {}", e))?;
+        self.0 = self.0.get(header.payload_length..).ok_or("rlp: truncated item")?;
+        Ok(())
+    }
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ArchiveStats {
+    pub files: u64,
+    /// Size of the files read, compressed where they are.
+    pub bytes: u64,
+    pub blocks: u64,
+    pub transactions: u64,
+    /// Wall-clock time of the decode batches, decompression included.
+    pub duration: Duration,
+}
+
+impl fmt::Display for ArchiveStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Block Archive:      {} blocks ({} txs) from {} files ({:.1} MiB), decoded in {:?}",
+            self.blocks,
+            self.transactions,
+            self.files,
+            self.bytes as f64 / (1024.0 * 1024.0),
+            self.duration
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An era1 file holding only a block index entry: its header claims `length` bytes, followed
+    /// by `body`.
+    fn index_file(name: &str, length: u32, body: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("flux-era1-{}-{}.era1", name, std::process::id()));
+        let mut data = BLOCK_INDEX.to_le_bytes().to_vec();
+        data.extend_from_slice(&length.to_le_bytes());
+        data.extend_from_slice(&[0, 0]);
+        data.extend_from_slice(body);
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn index(start: u64, count: u64) -> Vec<u8> {
+        let mut body = start.to_le_bytes().to_vec();
+        body.extend_from_slice(&0u64.to_le_bytes());
+        body.extend_from_slice(&count.to_le_bytes());
+        body
+    }
+
+    #[test]
+    fn reads_the_block_index_range() {
+        let path = index_file("ok", 24, &index(8192, 2));
+        assert_eq!(era1_range(&path).unwrap(), Some((8192, 8193)));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rejects_damaged_block_indexes() {
+        // Claims 4 GiB the file does not hold.
+        let path = index_file("huge", u32::MAX, &index(0, 1));
+        assert!(era1_range(&path).unwrap_err().contains("truncated block index"));
+        std::fs::remove_file(path).unwrap();
+        // Runs past u64::MAX.
+        let path = index_file("overflow", 24, &index(u64::MAX, 2));
+        assert!(era1_range(&path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+/*
+ * FLUX ENGINE - RPC BLOCK CACHE
+ * `--rpc-cache DIR`: every block the RPC source fetches is kept on disk, so the next run over the
+ * same range reads it back instead of asking the node. Blocks are stored content-addressed: the
+ * node's JSON for a block goes to `objects/<keccak256 of it>`, and `blocks/<number>` names the
+ * object for that height. An object that no longer hashes to its name, or whose block fails the
+ * checks in `integrity.rs`, is fetched again.
+ *
+ * With `--zstd-level`, objects are stored zstd-compressed; the name stays the hash of the JSON,
+ * so compressed and plain objects mix in one directory and either kind is read back.
+ *
+ * Heights are not tied to a chain: use one directory per chain.
+ */
+
+use super::{integrity, SourceBlock};
+use crate::zstd;
+use revm::primitives::{keccak256, B256};
+use serde_json::Value;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug)]
+pub struct BlockCache {
+    dir: PathBuf,
+    /// zstd level new objects are written with, `None` = plain JSON.
+    compression: Option<u32>,
+}
+
+impl BlockCache {
+    pub fn open(dir: &Path, compression: Option<u32>) -> Result<Self, String> {
+        for sub in ["objects", "blocks"] {
+            fs::create_dir_all(dir.join(sub)).map_err(|e| format!("--rpc-cache {}: {}", dir.display(), e))?;
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            compression,
+        })
+    }
+
+    /// Block `number` as a previous run stored it, decoded with `decode`, or `None` if it is not
+    /// cached. A damaged entry is an error; the caller fetches the block again and overwrites it.
+    pub fn load<F>(&self, number: u64, decode: F) -> Result<Option<SourceBlock>, String>
+    where
+        F: FnOnce(&Value, u64) -> Result<SourceBlock, String>,
+    {
+        let name = match fs::read_to_string(self.index(number)) {
+            Ok(name) => name,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.to_string()),
+        };
+        let hash: B256 = name.trim().parse().map_err(|_| format!("index entry {:?} is not a hash", name.trim()))?;
+        let mut object = fs::read(self.object(&hash)).map_err(|e| format!("object {}: {}", hash, e))?;
+        if zstd::is_zstd(&object) {
+            object = zstd::decompress(&object).map_err(|e| format!("object {}: {}", hash, e))?;
+        }
+        if keccak256(&object) != hash {
+            return Err(format!("object {} does not match its hash", hash));
+        }
+        let block: Value = serde_json::from_slice(&object).map_err(|e| format!("object {}: {}", hash, e))?;
+        integrity::check_json(&block)?;
+        decode(&block, number).map(Some)
+    }
+
+    /// Stores the node's JSON for block `number`. Both files are written under a temporary name
+    /// and renamed into place, so a concurrent or interrupted run never reads half of one.
+    pub fn store(&self, number: u64, block: &Value) -> Result<(), String> {
+        let object = serde_json::to_vec(block).map_err(|e| e.to_string())?;
+        let hash = keccak256(&object);
+        let failed = |e: std::io::Error| format!("rpc cache {}: block {}: {}", self.dir.display(), number, e);
+        let stored = match self.compression {
+            Some(level) => zstd::compress(&object, level),
+            None => object,
+        };
+        // Rewritten even if present, which replaces an object that was damaged.
+        write_atomic(&self.object(&hash), &stored).map_err(failed)?;
+        write_atomic(&self.index(number), format!("{}\n", hash).as_bytes()).map_err(failed)
+    }
+
+    /// The last block of the unbroken run of cached blocks from `first`, if `first` is cached.
+    pub fn cached_through(&self, first: u64) -> Option<u64> {
+        (first..).take_while(|number| self.index(*number).exists()).last()
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn index(&self, number: u64) -> PathBuf {
+        self.dir.join("blocks").join(number.to_string())
+    }
+
+    fn object(&self, hash: &B256) -> PathBuf {
+        self.dir.join("objects").join(hex::encode(hash))
+    }
+}
+
+/// Fetchers, and other runs sharing the directory, may write the same file at once, so the
+/// temporary name is unique to the process and the write.
+fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+    let tmp = path.with_extension(format!("tmp-{}-{}", std::process::id(), WRITES.fetch_add(1, Ordering::Relaxed)));
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
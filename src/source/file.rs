@@ -0,0 +1,165 @@
+/*
+ * FLUX ENGINE - TRANSACTION FILES
+ * `--txs-file FILE`: a captured or hand-written workload, one transaction per JSONL object or CSV
+ * row (by extension: `.csv`, anything else is JSONL), run through the same pipeline as the
+ * generated one. Fields: `sender`, `to` (empty for a contract creation) and `gas`; optionally
+ * `id`, `input`, `value`, `nonce`, `gas_price` (default 30 gwei) and `block`. Consecutive
+ * transactions with the same `block` make up a block; without it the whole file is one.
+ *
+ * The blocks carry no header: they run on the generated chain, whose genesis funds every sender
+ * in the file.
+ */
+
+use super::{BlockSource, SourceBlock};
+use crate::FluxTransaction;
+use revm::primitives::{Address, HashMap, HashSet, U256};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::path::Path;
+
+/// Fields a row may have. Anything else is a typo, and rejected as one.
+const FIELDS: [&str; 9] = ["id", "sender", "to", "input", "gas", "value", "nonce", "gas_price", "block"];
+
+pub struct FileBlockSource {
+    blocks: VecDeque<Vec<FluxTransaction>>,
+    senders: Vec<Address>,
+}
+
+impl FileBlockSource {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let csv = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+        let rows = if csv { csv_rows(&text) } else { jsonl_rows(&text) };
+
+        let mut blocks: VecDeque<Vec<FluxTransaction>> = VecDeque::new();
+        let mut current_block: Option<String> = None;
+        let mut seen: HashSet<Address> = HashSet::default();
+        let mut senders = Vec::new();
+        for row in rows {
+            let (lineno, row) = row.map_err(|(lineno, e)| format!("{}:{}: {}", path.display(), lineno, e))?;
+            let at = |e: String| format!("{}:{}: {}", path.display(), lineno, e);
+            if let Some(unknown) = row.keys().find(|key| !FIELDS.contains(&key.as_str())) {
+                return Err(at(format!("unknown field {:?} (expected {})", unknown, FIELDS.join(", "))));
+            }
+            let block = row.get("block").filter(|block| !block.is_empty()).cloned();
+            if blocks.is_empty() || block != current_block {
+                blocks.push_back(Vec::new());
+                current_block = block;
+            }
+            let txs = blocks.back_mut().expect("a block was just pushed");
+            let tx = decode_row(&row, txs.len()).map_err(at)?;
+            if seen.insert(tx.caller) {
+                senders.push(tx.caller);
+            }
+            txs.push(tx);
+        }
+        if blocks.is_empty() {
+            return Err(format!("{}: no transactions", path.display()));
+        }
+        Ok(Self { blocks, senders })
+    }
+}
+
+impl BlockSource for FileBlockSource {
+    fn next_block(&mut self) -> Result<Option<SourceBlock>, String> {
+        Ok(self.blocks.pop_front().map(|txs| SourceBlock { header: None, txs }))
+    }
+
+    fn senders(&self) -> Vec<Address> {
+        self.senders.clone()
+    }
+}
+
+type Row = HashMap<String, String>;
+
+/// One row per non-blank line, as `(line number, fields)`. Lines starting with `#` are comments.
+fn jsonl_rows(text: &str) -> Vec<Result<(usize, Row), (usize, String)>> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(lineno, line)| {
+            let Ok(Value::Object(object)) = serde_json::from_str::<Value>(line) else {
+                return Err((lineno, "expected a JSON object".to_string()));
+            };
+            let row = object
+                .into_iter()
+                .map(|(key, value)| {
+                    let value = match value {
+                        Value::String(s) => s,
+                        Value::Null => String::new(),
+                        other => other.to_string(),
+                    };
+                    (key, value)
+                })
+                .collect();
+            Ok((lineno, row))
+        })
+        .collect()
+}
+
+/// The first non-comment line names the columns; values hold no commas, so there is no quoting.
+fn csv_rows(text: &str) -> Vec<Result<(usize, Row), (usize, String)>> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+    let Some((_, header)) = lines.next() else {
+        return vec![];
+    };
+    let columns: Vec<&str> = header.split(',').map(str::trim).collect();
+    lines
+        .map(|(lineno, line)| {
+            let values: Vec<&str> = line.split(',').map(str::trim).collect();
+            if values.len() != columns.len() {
+                return Err((lineno, format!("{} values for {} columns", values.len(), columns.len())));
+            }
+            let row = columns
+                .iter()
+                .zip(values)
+                .map(|(column, value)| (column.to_string(), value.to_string()))
+                .collect();
+            Ok((lineno, row))
+        })
+        .collect()
+}
+
+fn decode_row(row: &Row, index: usize) -> Result<FluxTransaction, String> {
+    let field = |name: &str| row.get(name).map(String::as_str).filter(|value| !value.is_empty());
+    let address = |name: &str| -> Result<Option<Address>, String> {
+        field(name)
+            .map(|raw| raw.parse().map_err(|_| format!("{}: {:?} is not an address", name, raw)))
+            .transpose()
+    };
+    let word = |name: &str| -> Result<Option<U256>, String> {
+        field(name)
+            .map(|raw| raw.parse().map_err(|_| format!("{}: {:?} is not a number", name, raw)))
+            .transpose()
+    };
+    let small = |name: &str| -> Result<Option<u64>, String> {
+        word(name)?
+            .map(|value| u64::try_from(value).map_err(|_| format!("{}: out of range", name)))
+            .transpose()
+    };
+
+    let to = address("to")?;
+    let input = field("input").unwrap_or_default();
+    Ok(FluxTransaction {
+        id: small("id")?.map_or(index, |id| id as usize),
+        caller: address("sender")?.ok_or("sender: missing")?,
+        to: to.unwrap_or_default(),
+        create: to.is_none(),
+        value: word("value")?.unwrap_or_default(),
+        data: hex::decode(input.trim_start_matches("0x")).map_err(|e| format!("input: {}", e))?,
+        gas_limit: small("gas")?.ok_or("gas: missing")?,
+        nonce: small("nonce")?,
+        access_list: vec![],
+        max_fee_per_gas: word("gas_price")?.unwrap_or(U256::from(30_000_000_000u64)),
+        max_priority_fee_per_gas: None,
+        blob_hashes: vec![],
+        max_fee_per_blob_gas: None,
+        signature: None,
+        skipped: false,
+    })
+}
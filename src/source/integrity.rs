@@ -0,0 +1,279 @@
+/*
+ * FLUX ENGINE - BLOCK INTEGRITY
+ * Blocks read back from disk (`--blocks-dir`, `--rpc-cache`) are checked against what their
+ * headers commit to before they reach the pipeline, so a damaged file stops at the block and the
+ * field that is off instead of quietly skewing the run's numbers. A block's transactions have to
+ * hash to its transactions root; an archived block has to name the block before it as its
+ * parent, and a cached block's header fields have to hash to the block hash the node gave.
+ */
+
+use super::rpc::{bytes, optional, parsed, quantity, word};
+use crate::trie;
+use alloy_primitives::{keccak256, Address, B256};
+use alloy_rlp::{Encodable, Header, EMPTY_STRING_CODE};
+use serde_json::Value;
+
+/// Header fields later forks append (London through Prague), in order. A header carries a prefix
+/// of them.
+const FORK_FIELDS: [&str; 6] = [
+    "baseFeePerGas",
+    "withdrawalsRoot",
+    "blobGasUsed",
+    "excessBlobGas",
+    "parentBeaconBlockRoot",
+    "requestsHash",
+];
+
+/// What a header commits its block to, besides the fields the engine executes with.
+#[derive(Debug, Clone, Copy)]
+pub struct Seal {
+    pub number: u64,
+    /// keccak256 of the RLP header.
+    pub hash: B256,
+    pub parent_hash: B256,
+    pub transactions_root: B256,
+}
+
+impl Seal {
+    /// Checks the block's transactions, each as the transactions trie holds it, against the root.
+    pub fn check_transactions<T: AsRef<[u8]>>(&self, txs: &[T]) -> Result<(), String> {
+        let root = trie::ordered_trie_root(txs);
+        if root != self.transactions_root {
+            return Err(format!(
+                "its {} transactions hash to root {}, but the header says {}",
+                txs.len(),
+                root,
+                self.transactions_root
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that this block builds on `parent`.
+    pub fn check_parent(&self, parent: &Seal) -> Result<(), String> {
+        if self.parent_hash != parent.hash {
+            return Err(format!(
+                "block {}: parent hash {} is not the hash of block {} ({})",
+                self.number, self.parent_hash, parent.number, parent.hash
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Checks a block as `eth_getBlockByNumber` returns it with full transactions: each transaction
+/// against its `hash`, all of them against `transactionsRoot`, and the header against `hash`.
+/// Errors do not name the block; the caller knows which one it asked for.
+pub fn check_json(block: &Value) -> Result<(), String> {
+    let header = encode_header(block)?;
+    let seal = Seal {
+        number: quantity(block, "number")?,
+        hash: keccak256(&header),
+        parent_hash: parsed(block, "parentHash")?,
+        transactions_root: parsed(block, "transactionsRoot")?,
+    };
+    let hash: B256 = parsed(block, "hash")?;
+    if seal.hash != hash {
+        return Err(format!("header fields hash to {}, not to the block hash {}", seal.hash, hash));
+    }
+
+    let txs = block.get("transactions").and_then(Value::as_array).ok_or("transactions: missing")?;
+    let mut envelopes = Vec::with_capacity(txs.len());
+    for (i, tx) in txs.iter().enumerate() {
+        let at = |e: String| format!("tx {}: {}", i, e);
+        let envelope = encode_tx(tx).map_err(at)?;
+        let hash: B256 = parsed(tx, "hash").map_err(at)?;
+        if keccak256(&envelope) != hash {
+            return Err(at(format!("fields hash to {}, not to its hash {}", keccak256(&envelope), hash)));
+        }
+        envelopes.push(envelope);
+    }
+    seal.check_transactions(&envelopes)
+}
+
+/// The RLP header the block hash is taken over.
+fn encode_header(block: &Value) -> Result<Vec<u8>, String> {
+    let mut fields = Vec::with_capacity(640);
+    parsed::<B256>(block, "parentHash")?.encode(&mut fields);
+    parsed::<B256>(block, "sha3Uncles")?.encode(&mut fields);
+    parsed::<Address>(block, "miner")?.encode(&mut fields);
+    for name in ["stateRoot", "transactionsRoot", "receiptsRoot"] {
+        parsed::<B256>(block, name)?.encode(&mut fields);
+    }
+    bytes(block, "logsBloom")?.as_slice().encode(&mut fields);
+    for name in ["difficulty", "number", "gasLimit", "gasUsed", "timestamp"] {
+        word(block, name)?.encode(&mut fields);
+    }
+    bytes(block, "extraData")?.as_slice().encode(&mut fields);
+    parsed::<B256>(block, "mixHash")?.encode(&mut fields);
+    bytes(block, "nonce")?.as_slice().encode(&mut fields);
+    for name in FORK_FIELDS {
+        if block.get(name).is_none_or(Value::is_null) {
+            break;
+        }
+        // Roots and hashes are 32-byte strings, the rest quantities.
+        if name.ends_with("Root") || name.ends_with("Hash") {
+            parsed::<B256>(block, name)?.encode(&mut fields);
+        } else {
+            word(block, name)?.encode(&mut fields);
+        }
+    }
+    Ok(list(None, fields))
+}
+
+/// A signed transaction as the transactions trie holds it: `rlp(fields)` for legacy ones,
+/// `type || rlp(fields)` for typed ones.
+fn encode_tx(tx: &Value) -> Result<Vec<u8>, String> {
+    let tx_type = optional(tx, "type", quantity)?.unwrap_or(0);
+    if tx_type > 3 {
+        return Err(format!("type {} transactions are not supported", tx_type));
+    }
+    let mut fields = Vec::with_capacity(256);
+    if tx_type != 0 {
+        word(tx, "chainId")?.encode(&mut fields);
+    }
+    word(tx, "nonce")?.encode(&mut fields);
+    if tx_type >= 2 {
+        word(tx, "maxPriorityFeePerGas")?.encode(&mut fields);
+        word(tx, "maxFeePerGas")?.encode(&mut fields);
+    } else {
+        word(tx, "gasPrice")?.encode(&mut fields);
+    }
+    word(tx, "gas")?.encode(&mut fields);
+    match optional(tx, "to", parsed::<Address>)? {
+        Some(to) => to.encode(&mut fields),
+        None => fields.push(EMPTY_STRING_CODE),
+    }
+    word(tx, "value")?.encode(&mut fields);
+    bytes(tx, "input")?.as_slice().encode(&mut fields);
+    if tx_type >= 1 {
+        encode_access_list(tx, &mut fields)?;
+    }
+    if tx_type == 3 {
+        word(tx, "maxFeePerBlobGas")?.encode(&mut fields);
+        let hashes: Vec<B256> = tx
+            .get("blobVersionedHashes")
+            .and_then(Value::as_array)
+            .ok_or("blobVersionedHashes: missing")?
+            .iter()
+            .map(|hash| hash.as_str().and_then(|h| h.parse().ok()).ok_or(format!("blob hash {}", hash)))
+            .collect::<Result<_, _>>()?;
+        hashes.encode(&mut fields);
+    }
+    // Typed transactions carry `yParity` (older nodes only `v`, which is the same there).
+    match optional(tx, "yParity", word)?.filter(|_| tx_type != 0) {
+        Some(parity) => parity.encode(&mut fields),
+        None => word(tx, "v")?.encode(&mut fields),
+    }
+    word(tx, "r")?.encode(&mut fields);
+    word(tx, "s")?.encode(&mut fields);
+    Ok(list((tx_type != 0).then_some(tx_type as u8), fields))
+}
+
+/// `rlp([[address, [key, ...]], ...])`, storage keys as 32-byte strings.
+fn encode_access_list(tx: &Value, out: &mut Vec<u8>) -> Result<(), String> {
+    let mut entries = Vec::new();
+    for entry in tx.get("accessList").and_then(Value::as_array).ok_or("accessList: missing")? {
+        let address: Address = parsed(entry, "address")?;
+        let keys: Vec<B256> = entry
+            .get("storageKeys")
+            .and_then(Value::as_array)
+            .ok_or("storageKeys: missing")?
+            .iter()
+            .map(|key| key.as_str().and_then(|k| k.parse().ok()).ok_or(format!("storageKeys: {}", key)))
+            .collect::<Result<_, _>>()?;
+        Header {
+            list: true,
+            payload_length: address.length() + keys.length(),
+        }
+        .encode(&mut entries);
+        address.encode(&mut entries);
+        keys.encode(&mut entries);
+    }
+    Header {
+        list: true,
+        payload_length: entries.len(),
+    }
+    .encode(out);
+    out.extend_from_slice(&entries);
+    Ok(())
+}
+
+/// `fields` as an RLP list, behind the type byte if there is one.
+fn list(prefix: Option<u8>, fields: Vec<u8>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(fields.len() + 8);
+    out.extend(prefix);
+    Header {
+        list: true,
+        payload_length: fields.len(),
+    }
+    .encode(&mut out);
+    out.extend_from_slice(&fields);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One block per header layout, as `eth_getBlockByNumber` returns them. `genesis` is mainnet's
+    /// block 0 and `frontier` carries mainnet's first transaction (0x5c504e..., block 46147); the
+    /// London, Cancun and Prague blocks and their transactions are made up, with hashes computed
+    /// apart from this code. Their signatures are not valid, which these checks do not look at.
+    fn blocks() -> Value {
+        serde_json::from_str(include_str!("testdata/blocks.json")).unwrap()
+    }
+
+    #[test]
+    fn accepts_a_block_of_every_fork() {
+        for (era, block) in blocks().as_object().unwrap() {
+            assert_eq!(check_json(block), Ok(()), "{}", era);
+        }
+    }
+
+    #[test]
+    fn rejects_a_changed_header() {
+        let blocks = blocks();
+        for era in ["genesis", "london", "prague"] {
+            let mut block = blocks[era].clone();
+            block["gasLimit"] = "0x1389".into();
+            assert!(check_json(&block).unwrap_err().starts_with("header fields hash to"), "{}", era);
+        }
+        // A fork field left out changes the header as much as a changed one.
+        let mut block = blocks["prague"].clone();
+        block.as_object_mut().unwrap().remove("requestsHash");
+        assert!(check_json(&block).unwrap_err().starts_with("header fields hash to"));
+    }
+
+    #[test]
+    fn rejects_changed_transactions() {
+        let blocks = blocks();
+        let mut block = blocks["london"].clone();
+        block["transactions"][1]["accessList"][0]["storageKeys"][1] = block["transactions"][1]["hash"].clone();
+        assert!(check_json(&block).unwrap_err().starts_with("tx 1: fields hash to"));
+
+        let mut block = blocks["cancun"].clone();
+        block["transactions"][1]["blobVersionedHashes"].as_array_mut().unwrap().pop();
+        assert!(check_json(&block).unwrap_err().starts_with("tx 1: fields hash to"));
+
+        // Each transaction intact, but one missing from the block.
+        let mut block = blocks["london"].clone();
+        block["transactions"].as_array_mut().unwrap().remove(0);
+        assert!(check_json(&block).unwrap_err().starts_with("its 2 transactions hash to root"));
+    }
+
+    #[test]
+    fn checks_the_parent() {
+        let seal = |block: &Value| Seal {
+            number: quantity(block, "number").unwrap(),
+            hash: parsed(block, "hash").unwrap(),
+            parent_hash: parsed(block, "parentHash").unwrap(),
+            transactions_root: parsed(block, "transactionsRoot").unwrap(),
+        };
+        let blocks = blocks();
+        let (genesis, mut child) = (seal(&blocks["genesis"]), seal(&blocks["london"]));
+        assert!(child.check_parent(&genesis).is_err());
+        child.parent_hash = genesis.hash;
+        assert_eq!(child.check_parent(&genesis), Ok(()));
+    }
+}
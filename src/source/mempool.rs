@@ -0,0 +1,251 @@
+/*
+ * FLUX ENGINE - MEMPOOL SIMULATION
+ * `--mempool-rate N`: instead of handing the engine a fixed batch per block, the generated
+ * workload arrives over wall-clock time as a Poisson process averaging N txs/s, and each block is
+ * built from whatever is pending when the previous one finishes, up to `--txs-per-block`. While
+ * waiting, some arrivals replace a pending tx (the same sender bumping its fee) and some pending
+ * txs become invalid and are dropped (their sender's funds or nonce spent elsewhere).
+ *
+ * A slow engine therefore builds bigger blocks and a growing backlog, a fast one small blocks
+ * and none: sustained throughput and the time txs wait for inclusion under a given arrival
+ * rate, rather than how fast a fixed batch drains. The blocks run on the generated chain.
+ */
+
+use super::{BlockSource, SourceBlock};
+use crate::workload::{Rng, SyntheticWorkload, TxCount};
+use crate::FluxTransaction;
+use revm::primitives::{AccountInfo, Address, U256};
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MempoolConfig {
+    /// Mean arrivals per second.
+    pub rate: f64,
+    /// Share of arrivals that replace a pending tx instead of joining the pool.
+    pub replace: f64,
+    /// Chance each pending tx is dropped as invalid before a block is built.
+    pub invalidate: f64,
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        Self {
+            rate: 10_000.0,
+            replace: 0.05,
+            invalidate: 0.01,
+        }
+    }
+}
+
+impl fmt::Display for MempoolConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} txs/s arriving, {:.1}% replacements, {:.1}% of pending txs invalidated per block",
+            self.rate,
+            self.replace * 100.0,
+            self.invalidate * 100.0
+        )
+    }
+}
+
+struct Pending {
+    tx: FluxTransaction,
+    arrived: Instant,
+}
+
+pub struct MempoolSource {
+    config: MempoolConfig,
+    workload: SyntheticWorkload,
+    senders: Vec<Address>,
+    london: bool,
+    rng: Rng,
+    /// Generated txs not arrived yet, and the batch the next ones are generated from.
+    upcoming: VecDeque<FluxTransaction>,
+    batch: u64,
+    pool: VecDeque<Pending>,
+    next_arrival: Instant,
+    next_id: usize,
+    started: Instant,
+    /// When the last block was taken from the pool: arrivals are only counted up to it.
+    last_block: Instant,
+    /// Time from arrival to inclusion of every included tx.
+    waits: Vec<Duration>,
+    stats: MempoolStats,
+}
+
+impl MempoolSource {
+    /// Txs arrive from now on, shaped by `workload` and sent by `senders`. `london` picks
+    /// EIP-1559 fees over legacy gas prices, as for generated blocks.
+    pub fn new(config: MempoolConfig, workload: SyntheticWorkload, senders: Vec<Address>, london: bool) -> Self {
+        let now = Instant::now();
+        let mut source = Self {
+            config,
+            rng: Rng::new(workload.seed ^ 0x6d65_6d70_6f6f_6c00),
+            workload,
+            senders,
+            london,
+            upcoming: VecDeque::new(),
+            batch: 0,
+            pool: VecDeque::new(),
+            next_arrival: now,
+            next_id: 0,
+            started: now,
+            last_block: now,
+            waits: Vec::new(),
+            stats: MempoolStats::default(),
+        };
+        source.next_arrival = now + source.gap();
+        source
+    }
+
+    pub fn stats(&self) -> MempoolStats {
+        let mut stats = self.stats;
+        stats.pending = self.pool.len() as u64;
+        stats.elapsed = self.last_block.duration_since(self.started);
+        let mut waits = self.waits.clone();
+        waits.sort();
+        let percentile = |p: usize| waits.get(waits.len() * p / 100).copied().unwrap_or_default();
+        stats.wait_p50 = percentile(50);
+        stats.wait_p95 = percentile(95);
+        stats
+    }
+
+    /// Exponential time to the next arrival, so arrivals form a Poisson process.
+    fn gap(&mut self) -> Duration {
+        let uniform = 1.0 - self.rng.next_f64();
+        Duration::from_secs_f64(-uniform.ln() / self.config.rate)
+    }
+
+    fn generate(&mut self) -> FluxTransaction {
+        if self.upcoming.is_empty() {
+            self.batch += 1;
+            self.upcoming = self.workload.generate(self.batch, &self.senders, self.london).into();
+        }
+        let mut tx = self.upcoming.pop_front().expect("a batch has at least one tx");
+        tx.id = self.next_id;
+        self.next_id += 1;
+        tx
+    }
+
+    /// Lets every arrival due by `now` into the pool.
+    fn arrive(&mut self, now: Instant) {
+        while self.next_arrival <= now {
+            let arrived = self.next_arrival;
+            let tx = self.generate();
+            self.stats.arrivals += 1;
+            if !self.pool.is_empty() && self.rng.next_f64() < self.config.replace {
+                // Same sender and nonce, 12.5% more on both fee caps. It waits from the
+                // replacement's arrival.
+                let k = self.rng.below(self.pool.len() as u64) as usize;
+                let replaced = &mut self.pool[k];
+                replaced.tx.max_fee_per_gas = bump(replaced.tx.max_fee_per_gas);
+                replaced.tx.max_priority_fee_per_gas = replaced.tx.max_priority_fee_per_gas.map(bump);
+                replaced.arrived = arrived;
+                self.stats.replaced += 1;
+            } else {
+                self.pool.push_back(Pending { tx, arrived });
+            }
+            let gap = self.gap();
+            self.next_arrival += gap;
+        }
+    }
+}
+
+impl BlockSource for MempoolSource {
+    /// Whatever is pending now, oldest first, up to the block's tx count. Waits for the next
+    /// arrival if nothing is. Never runs out.
+    fn next_block(&mut self) -> Result<Option<SourceBlock>, String> {
+        self.arrive(Instant::now());
+        if self.config.invalidate > 0.0 {
+            let before = self.pool.len();
+            let (rng, chance) = (&mut self.rng, self.config.invalidate);
+            self.pool.retain(|_| rng.next_f64() >= chance);
+            self.stats.invalidated += (before - self.pool.len()) as u64;
+        }
+        if self.pool.is_empty() {
+            std::thread::sleep(self.next_arrival.saturating_duration_since(Instant::now()));
+            self.arrive(self.next_arrival);
+        }
+
+        let limit = match self.workload.txs_per_block {
+            TxCount::Fixed(count) => count,
+            TxCount::Uniform { min, max } => min + self.rng.below((max - min + 1) as u64) as usize,
+        };
+        let built = Instant::now();
+        self.last_block = built;
+        let txs: Vec<FluxTransaction> = self
+            .pool
+            .drain(..limit.min(self.pool.len()))
+            .map(|pending| {
+                self.waits.push(built.saturating_duration_since(pending.arrived));
+                pending.tx
+            })
+            .collect();
+        self.stats.blocks += 1;
+        self.stats.included += txs.len() as u64;
+        println!("[FLUX] Mempool: {} txs included, {} still pending", txs.len(), self.pool.len());
+        Ok(Some(SourceBlock { header: None, txs }))
+    }
+
+    fn report(&self) -> Option<String> {
+        Some(self.stats().to_string())
+    }
+
+    fn senders(&self) -> Vec<Address> {
+        self.senders.clone()
+    }
+
+    fn genesis_accounts(&self) -> Vec<(Address, AccountInfo)> {
+        self.workload.genesis_accounts()
+    }
+}
+
+/// The smallest fee bump a node accepts as a replacement.
+fn bump(fee: U256) -> U256 {
+    fee + fee / U256::from(8)
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MempoolStats {
+    pub arrivals: u64,
+    pub replaced: u64,
+    pub invalidated: u64,
+    pub blocks: u64,
+    pub included: u64,
+    /// Left in the pool when the stats were taken.
+    pub pending: u64,
+    /// From when the first arrival could happen to the last block.
+    pub elapsed: Duration,
+    /// Time included txs waited in the pool.
+    pub wait_p50: Duration,
+    pub wait_p95: Duration,
+}
+
+impl fmt::Display for MempoolStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.elapsed.as_secs_f64().max(f64::EPSILON);
+        writeln!(
+            f,
+            "Mempool:            {} arrived ({:.0}/s), {} included ({:.0}/s sustained) in {} blocks, {} replaced, \
+             {} invalidated, {} pending",
+            self.arrivals,
+            self.arrivals as f64 / secs,
+            self.included,
+            self.included as f64 / secs,
+            self.blocks,
+            self.replaced,
+            self.invalidated,
+            self.pending
+        )?;
+        writeln!(
+            f,
+            "Mempool Wait:       p50 {:?}, p95 {:?} from arrival to inclusion",
+            self.wait_p50, self.wait_p95
+        )
+    }
+}
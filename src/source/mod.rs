@@ -0,0 +1,128 @@
+/*
+ * FLUX ENGINE - BLOCK SOURCES
+ * Where the blocks a run executes come from when they are not generated: each source yields
+ * canonical headers (so results are checked against them) and their transactions, in order.
+ */
+
+pub mod archive;
+mod cache;
+pub mod file;
+mod integrity;
+pub mod mempool;
+pub mod rpc;
+mod snappy;
+pub mod ws;
+
+use crate::block::BlockHeader;
+use crate::FluxTransaction;
+use archive::ArchiveBlockSource;
+use cache::BlockCache;
+use file::FileBlockSource;
+use revm::primitives::{AccountInfo, Address};
+use rpc::{RpcBlockSource, RpcConfig};
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone)]
+pub struct SourceBlock {
+    /// The canonical header, or `None` for a block that runs on the generated chain, after
+    /// whatever block came before it.
+    pub header: Option<BlockHeader>,
+    pub txs: Vec<FluxTransaction>,
+}
+
+/// `Send`, so a run can be handed to another executor pool (`flux verify --self`).
+pub trait BlockSource: Send {
+    /// The next block, or `None` once the source has no more.
+    fn next_block(&mut self) -> Result<Option<SourceBlock>, String>;
+
+    /// A line for the run report on how fetching went, if the source keeps track.
+    fn report(&self) -> Option<String> {
+        None
+    }
+
+    /// Accounts genesis funds for a source whose blocks run on the generated chain.
+    fn senders(&self) -> Vec<Address> {
+        vec![]
+    }
+
+    /// Other accounts genesis creates for it, such as contracts its transactions call.
+    fn genesis_accounts(&self) -> Vec<(Address, AccountInfo)> {
+        vec![]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SourceConfig {
+    /// `--rpc-url`: fetched from a node.
+    Rpc(RpcConfig),
+    /// `--blocks-dir`: era1 files or RLP dumps on disk, from `first_block` or the first one there.
+    Archive { dir: PathBuf, first_block: Option<u64> },
+    /// `--txs-file`: transactions listed in a JSONL or CSV file.
+    File(PathBuf),
+}
+
+impl SourceConfig {
+    /// Opens the source for a run of `blocks` blocks.
+    pub fn open(&self, blocks: u64) -> Result<Box<dyn BlockSource>, String> {
+        Ok(match self {
+            SourceConfig::Rpc(config) => Box::new(RpcBlockSource::new(config, blocks)?),
+            SourceConfig::Archive { dir, first_block } => {
+                Box::new(ArchiveBlockSource::new(dir, *first_block, blocks)?)
+            }
+            SourceConfig::File(path) => Box::new(FileBlockSource::open(path)?),
+        })
+    }
+
+    /// The block it was told to start at, if it was.
+    pub fn first_block(&self) -> Option<u64> {
+        match self {
+            SourceConfig::Rpc(config) => Some(config.first_block),
+            SourceConfig::Archive { first_block, .. } => *first_block,
+            SourceConfig::File(_) => None,
+        }
+    }
+
+    /// The first and last block it can serve, found without fetching or decoding any: the node's
+    /// chain up to its head, or the blocks on disk. `None` for blocks on the generated chain.
+    /// With the node unreachable, an RPC source serves what its cache holds from the first block.
+    pub fn available(&self) -> Result<Option<(u64, u64)>, String> {
+        match self {
+            SourceConfig::Rpc(config) => match (rpc::latest_block(&config.url), &config.cache) {
+                (Ok(latest), _) => Ok(Some((0, latest))),
+                (Err(e), Some(dir)) => {
+                    let cache = BlockCache::open(dir, None)?;
+                    let Some(last) = cache.cached_through(config.first_block) else {
+                        return Err(e);
+                    };
+                    println!("[FLUX] {}; serving blocks from the cache in {}", e, cache.dir().display());
+                    Ok(Some((config.first_block, last)))
+                }
+                (Err(e), None) => Err(e),
+            },
+            SourceConfig::Archive { dir, .. } => archive::available(dir).map(Some),
+            SourceConfig::File(_) => Ok(None),
+        }
+    }
+
+    /// Whether its blocks come with canonical headers, and so with their own pre-state.
+    /// Headerless blocks run on the generated chain instead, genesis included.
+    pub fn has_headers(&self) -> bool {
+        !matches!(self, SourceConfig::File(_))
+    }
+}
+
+impl fmt::Display for SourceConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceConfig::Rpc(config) => write!(
+                f,
+                "{} from block {} ({} blocks ahead, {} per request, {} connections)",
+                config.url, config.first_block, config.window, config.batch, config.connections
+            ),
+            SourceConfig::Archive { dir, first_block: Some(first) } => write!(f, "{} from block {}", dir.display(), first),
+            SourceConfig::Archive { dir, first_block: None } => write!(f, "{}", dir.display()),
+            SourceConfig::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
@@ -0,0 +1,800 @@
+/*
+ * FLUX ENGINE - JSON-RPC BLOCK SOURCE
+ * `--rpc-url http://node:8545 --from-block N`: replays real blocks, fetched with
+ * `eth_getBlockByNumber` (full transactions) from a node. Fetching runs ahead of execution:
+ * `--rpc-connections` fetchers keep up to `--rpc-window` blocks ready, `--rpc-batch` blocks per
+ * request (JSON-RPC batches), so the engine only waits when the node cannot keep up. A request
+ * that fails on the node's side is retried with exponential backoff, and all requests together
+ * are held to `--rpc-rate` per second. With `--rpc-cache`, blocks a previous run fetched are read
+ * from disk instead, and the ones fetched now are stored there (see `cache.rs`).
+ * Plain HTTP only: point it at a local node or a TLS-terminating proxy.
+ *
+ * Only the blocks come from the node, not their pre-state: they run on whatever state the engine
+ * was given (--state-dir, --preload-state, --resume), which must be block N - 1's.
+ */
+
+use super::cache::BlockCache;
+use super::{BlockSource, SourceBlock};
+use crate::block::{BlockHeader, Withdrawal};
+use crate::recovery::Signature;
+use crate::FluxTransaction;
+use parking_lot::{Condvar, Mutex};
+use revm::primitives::{Address, B256, U256};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Blocks fetched ahead of execution, unless configured.
+pub const DEFAULT_WINDOW: usize = 8;
+/// Blocks per JSON-RPC request, unless configured. One sends plain requests, not batches.
+pub const DEFAULT_BATCH: usize = 1;
+/// Requests in flight at once, unless configured.
+pub const DEFAULT_CONNECTIONS: usize = 4;
+/// Attempts per block before the run gives up, the first included.
+pub(super) const MAX_ATTEMPTS: u32 = 5;
+/// Wait before the first retry; doubled for every one after it.
+pub(super) const INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+pub(super) const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct RpcConfig {
+    pub url: String,
+    pub first_block: u64,
+    /// Blocks fetched ahead of the one executing.
+    pub window: usize,
+    /// Blocks per request, as a JSON-RPC batch when more than one.
+    pub batch: usize,
+    /// Requests in flight at once, each on its own connection.
+    pub connections: usize,
+    /// Requests per second across every connection, retries included. `None` is unlimited.
+    pub rate_limit: Option<f64>,
+    /// Where fetched blocks are kept for later runs, if anywhere.
+    pub cache: Option<PathBuf>,
+    /// zstd level blocks are stored in the cache with, `None` = uncompressed.
+    pub cache_compression: Option<u32>,
+}
+
+/// Fetcher threads keep up to `window` blocks ready ahead of the engine; `next_block` hands them
+/// out in order and only waits when the next one is not in yet.
+pub struct RpcBlockSource {
+    pipeline: Arc<Pipeline>,
+}
+
+impl RpcBlockSource {
+    /// A source for `blocks` blocks starting at `config.first_block`. Fetching starts right away.
+    pub fn new(config: &RpcConfig, blocks: u64) -> Result<Self, String> {
+        if config.window == 0 || config.batch == 0 || config.connections == 0 {
+            return Err("--rpc-window, --rpc-batch and --rpc-connections must be at least 1".into());
+        }
+        let pipeline = Arc::new(Pipeline {
+            endpoint: Endpoint::parse(&config.url)?,
+            limiter: RateLimiter::new(config.rate_limit)?,
+            cache: match &config.cache {
+                Some(dir) => Some(BlockCache::open(dir, config.cache_compression)?),
+                None => None,
+            },
+            window: config.window as u64,
+            batch: config.batch as u64,
+            end: config.first_block.saturating_add(blocks),
+            state: Mutex::new(PipelineState {
+                claimed: config.first_block,
+                next: config.first_block,
+                ..PipelineState::default()
+            }),
+            changed: Condvar::new(),
+        });
+        for _ in 0..(config.connections as u64).min(blocks) {
+            let pipeline = Arc::clone(&pipeline);
+            std::thread::spawn(move || pipeline.fetcher());
+        }
+        Ok(Self { pipeline })
+    }
+
+    pub fn stats(&self) -> RpcStats {
+        let state = self.pipeline.state.lock();
+        let mut stats = state.stats;
+        let mut latencies = state.latencies.clone();
+        latencies.sort();
+        let percentile = |p: usize| latencies.get(latencies.len() * p / 100).copied().unwrap_or_default();
+        stats.latency_p50 = percentile(50);
+        stats.latency_p95 = percentile(95);
+        stats.latency_max = latencies.last().copied().unwrap_or_default();
+        stats
+    }
+}
+
+impl BlockSource for RpcBlockSource {
+    fn next_block(&mut self) -> Result<Option<SourceBlock>, String> {
+        let pipeline = &*self.pipeline;
+        let mut state = pipeline.state.lock();
+        if state.next >= pipeline.end {
+            return Ok(None);
+        }
+        let ready = (state.next..pipeline.end).take_while(|number| state.ready.contains_key(number)).count();
+        let started = Instant::now();
+        let block = loop {
+            let next = state.next;
+            if let Some(block) = state.ready.remove(&next) {
+                break block;
+            }
+            if let Some((_, e)) = state.failed.as_ref().filter(|(number, _)| *number <= next) {
+                return Err(e.clone());
+            }
+            pipeline.changed.wait(&mut state);
+        };
+        state.next += 1;
+        let stats = &mut state.stats;
+        stats.blocks += 1;
+        stats.transactions += block.txs.len() as u64;
+        stats.lookahead += ready as u64;
+        if ready == 0 {
+            stats.stalls += 1;
+            stats.stalled += started.elapsed();
+        }
+        // A slot in the window just opened up.
+        pipeline.changed.notify_all();
+        Ok(Some(block))
+    }
+
+    fn report(&self) -> Option<String> {
+        Some(self.stats().to_string())
+    }
+}
+
+impl Drop for RpcBlockSource {
+    /// Stops the fetchers from claiming more; a request in flight finishes on its own.
+    fn drop(&mut self) {
+        self.pipeline.state.lock().closed = true;
+        self.pipeline.changed.notify_all();
+    }
+}
+
+/// The node's latest block, to check a replay's range against before fetching any of it.
+pub fn latest_block(url: &str) -> Result<u64, String> {
+    let endpoint = Endpoint::parse(url)?;
+    let failed = |e: String| format!("{}: eth_blockNumber: {}", url, e);
+    let body = match endpoint.post(r#"{"jsonrpc":"2.0","id":1,"method":"eth_blockNumber","params":[]}"#) {
+        Ok(body) => body,
+        Err(Failure::Transient(e) | Failure::Fatal(e)) => return Err(failed(e)),
+    };
+    let response: Value =
+        serde_json::from_slice(&body).map_err(|e| failed(format!("malformed JSON-RPC response: {}", e)))?;
+    if let Some(error) = response.get("error") {
+        return Err(failed(format!("JSON-RPC error {}", error)));
+    }
+    quantity(&response, "result").map_err(failed)
+}
+
+/// What the fetchers share with the source.
+struct Pipeline {
+    endpoint: Endpoint,
+    limiter: RateLimiter,
+    cache: Option<BlockCache>,
+    window: u64,
+    batch: u64,
+    /// One past the last block to fetch.
+    end: u64,
+    state: Mutex<PipelineState>,
+    /// Signalled whenever a block comes in, one is handed out, or fetching stops.
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct PipelineState {
+    /// The first block no fetcher has claimed yet.
+    claimed: u64,
+    /// The next block to hand out.
+    next: u64,
+    ready: BTreeMap<u64, SourceBlock>,
+    /// The lowest block that could not be fetched, and why. Blocks before it are still handed out.
+    failed: Option<(u64, String)>,
+    closed: bool,
+    /// Every request's round trip, retries included.
+    latencies: Vec<Duration>,
+    stats: RpcStats,
+}
+
+impl Pipeline {
+    /// Claims up to `batch` blocks within the window and fetches them, until every block is
+    /// claimed, one fails, or the source is dropped.
+    fn fetcher(&self) {
+        loop {
+            let numbers: Vec<u64> = {
+                let mut state = self.state.lock();
+                loop {
+                    if state.closed || state.failed.is_some() || state.claimed >= self.end {
+                        return;
+                    }
+                    if state.claimed < state.next + self.window {
+                        break;
+                    }
+                    self.changed.wait(&mut state);
+                }
+                let first = state.claimed;
+                state.claimed = (first + self.batch).min(state.next + self.window).min(self.end);
+                (first..state.claimed).collect()
+            };
+            let fetched = self.fetch(&numbers);
+            let mut state = self.state.lock();
+            match fetched {
+                Ok(blocks) => state.ready.extend(numbers.into_iter().zip(blocks)),
+                Err((number, e)) => {
+                    if state.failed.as_ref().is_none_or(|(failed, _)| number < *failed) {
+                        state.failed = Some((number, e));
+                    }
+                }
+            }
+            self.changed.notify_all();
+        }
+    }
+
+    /// Fetches `numbers`, consecutive blocks, in as few requests as retries allow: a retry asks
+    /// only for the blocks still missing. Cached blocks are not asked for at all.
+    fn fetch(&self, numbers: &[u64]) -> Result<Vec<SourceBlock>, (u64, String)> {
+        let mut blocks: Vec<Option<SourceBlock>> = vec![None; numbers.len()];
+        if let Some(cache) = &self.cache {
+            for (number, block) in numbers.iter().zip(&mut blocks) {
+                match cache.load(*number, decode_block) {
+                    Ok(cached) => *block = cached,
+                    Err(e) => println!("[FLUX] RPC cache: block {}: {}, fetching it again", number, e),
+                }
+            }
+            self.state.lock().stats.cached += blocks.iter().flatten().count() as u64;
+        }
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempts = 0;
+        loop {
+            let missing: Vec<u64> = numbers
+                .iter()
+                .zip(&blocks)
+                .filter(|(_, block)| block.is_none())
+                .map(|(number, _)| *number)
+                .collect();
+            if missing.is_empty() {
+                return Ok(blocks.into_iter().flatten().collect());
+            }
+            attempts += 1;
+            self.limiter.wait();
+            let started = Instant::now();
+            let response = self.endpoint.post(&request_body(&missing));
+            {
+                let mut state = self.state.lock();
+                state.latencies.push(started.elapsed());
+                state.stats.requests += 1;
+                state.stats.retries += (attempts > 1) as u64;
+                if let Ok(body) = &response {
+                    state.stats.bytes += body.len() as u64;
+                }
+            }
+            let failure = match response.and_then(|body| decode_batch(&body, &missing)) {
+                Ok(decoded) => {
+                    let mut failure = None;
+                    for (number, result) in missing.iter().zip(decoded) {
+                        match result.and_then(|block| self.accept(&block, *number)) {
+                            Ok(block) => blocks[(number - numbers[0]) as usize] = Some(block),
+                            Err(e) => {
+                                failure.get_or_insert((*number, e));
+                            }
+                        }
+                    }
+                    match failure {
+                        Some(failure) => failure,
+                        None => continue,
+                    }
+                }
+                Err(e) => (missing[0], e),
+            };
+            match failure {
+                (number, Failure::Transient(e)) if attempts < MAX_ATTEMPTS => {
+                    println!("[FLUX] RPC: block {}: {}, retrying in {:?}", number, e, backoff);
+                    std::thread::sleep(backoff);
+                    backoff *= 2;
+                }
+                (number, Failure::Transient(e)) => {
+                    return Err((number, format!("rpc: block {}: {} (gave up after {} attempts)", number, e, attempts)))
+                }
+                (number, Failure::Fatal(e)) => return Err((number, format!("rpc: block {}: {}", number, e))),
+            }
+        }
+    }
+
+
+    /// Decodes a block the node sent and, with a cache, stores it there.
+    fn accept(&self, block: &Value, number: u64) -> Result<SourceBlock, Failure> {
+        let decoded = decode_block(block, number).map_err(Failure::Fatal)?;
+        if let Some(cache) = &self.cache {
+            cache.store(number, block).map_err(Failure::Fatal)?;
+            self.state.lock().stats.cache_writes += 1;
+        }
+        Ok(decoded)
+    }
+}
+
+/// `eth_getBlockByNumber` with full transactions for each block, its number as the request id.
+/// A single block goes out as a plain request, more as a batch.
+fn request_body(numbers: &[u64]) -> String {
+    let requests: Vec<String> = numbers
+        .iter()
+        .map(|number| {
+            format!(
+                r#"{{"jsonrpc":"2.0","id":{},"method":"eth_getBlockByNumber","params":["{:#x}",true]}}"#,
+                number, number
+            )
+        })
+        .collect();
+    match requests.as_slice() {
+        [single] => single.clone(),
+        _ => format!("[{}]", requests.join(",")),
+    }
+}
+
+/// Each block's JSON, in the order of `numbers`. Batch responses may come in any order, so
+/// they are matched up by id.
+fn decode_batch(body: &[u8], numbers: &[u64]) -> Result<Vec<Result<Value, Failure>>, Failure> {
+    let response: Value =
+        serde_json::from_slice(body).map_err(|e| Failure::Fatal(format!("malformed JSON-RPC response: {}", e)))?;
+    if let [_] = numbers {
+        return Ok(vec![result(&response).cloned()]);
+    }
+    let Value::Array(responses) = response else {
+        let error = response.get("error").map_or_else(|| response.to_string(), Value::to_string);
+        return Err(Failure::Fatal(format!("batch request refused ({}); try --rpc-batch 1", error)));
+    };
+    Ok(numbers
+        .iter()
+        .map(|&number| {
+            match responses.iter().find(|response| response.get("id").and_then(Value::as_u64) == Some(number)) {
+                Some(response) => result(response).cloned(),
+                None => Err(Failure::Transient("missing from the batch response".into())),
+            }
+        })
+        .collect())
+}
+
+/// Whether asking again can help: the node being busy, down or behind is worth a retry, an
+/// answer that does not decode is not.
+pub(super) enum Failure {
+    Transient(String),
+    Fatal(String),
+}
+
+// --- HTTP ---
+
+/// An `http://host[:port][/path]` endpoint, one connection per request.
+struct Endpoint {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> Result<Self, String> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            return Err(if url.starts_with("https://") {
+                format!("--rpc-url {}: https is not supported, use a local node or a TLS-terminating proxy", url)
+            } else {
+                format!("--rpc-url {}: expected http://host[:port][/path]", url)
+            });
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| format!("--rpc-url {}: invalid port", url))?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("--rpc-url {}: no host", url));
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// POSTs a JSON body and returns the response body of a 200.
+    fn post(&self, body: &str) -> Result<Vec<u8>, Failure> {
+        let transient = |e: std::io::Error| Failure::Transient(format!("{}:{}: {}", self.host, self.port, e));
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port)).map_err(transient)?;
+        stream.set_read_timeout(Some(IO_TIMEOUT)).map_err(transient)?;
+        stream.set_write_timeout(Some(IO_TIMEOUT)).map_err(transient)?;
+        let head = format!(
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n",
+            self.path,
+            self.host,
+            self.port,
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).map_err(transient)?;
+        stream.write_all(body.as_bytes()).map_err(transient)?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).map_err(transient)?;
+        parse_response(&response)
+    }
+}
+
+/// Splits a whole HTTP/1.1 response, closed by the server, into status and body.
+fn parse_response(response: &[u8]) -> Result<Vec<u8>, Failure> {
+    let Some(split) = response.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return Err(Failure::Transient("truncated HTTP response".into()));
+    };
+    let head = String::from_utf8_lossy(&response[..split]);
+    let body = &response[split + 4..];
+    let mut lines = head.lines();
+    let status: u16 = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| Failure::Fatal("malformed HTTP status line".into()))?;
+    match status {
+        200 => {}
+        429 | 500..=599 => return Err(Failure::Transient(format!("HTTP {}", status))),
+        _ => return Err(Failure::Fatal(format!("HTTP {}", status))),
+    }
+
+    let chunked = lines.any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("transfer-encoding") && value.trim().eq_ignore_ascii_case("chunked")
+        })
+    });
+    if chunked {
+        dechunk(body).ok_or_else(|| Failure::Transient("truncated chunked body".into()))
+    } else {
+        Ok(body.to_vec())
+    }
+}
+
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(body.len());
+    loop {
+        let line_end = body.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Some(out);
+        }
+        out.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
+
+/// Spaces requests at least `1 / rate` seconds apart, across every fetcher thread.
+struct RateLimiter {
+    interval: Option<Duration>,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(rate: Option<f64>) -> Result<Self, String> {
+        let interval = match rate {
+            Some(rate) if !(rate > 0.0 && rate.is_finite()) => {
+                return Err(format!("--rpc-rate must be a positive number, got {}", rate))
+            }
+            rate => rate.map(|rate| Duration::from_secs_f64(1.0 / rate)),
+        };
+        Ok(Self {
+            interval,
+            next_slot: Mutex::new(Instant::now()),
+        })
+    }
+
+    fn wait(&self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let slot = {
+            let mut next_slot = self.next_slot.lock();
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + interval;
+            slot
+        };
+        std::thread::sleep(slot.saturating_duration_since(Instant::now()));
+    }
+}
+
+// --- DECODING ---
+
+pub(super) fn decode_response(body: &[u8], number: u64) -> Result<SourceBlock, Failure> {
+    let response: Value =
+        serde_json::from_slice(body).map_err(|e| Failure::Fatal(format!("malformed JSON-RPC response: {}", e)))?;
+    decode_value(&response, number)
+}
+
+fn decode_value(response: &Value, number: u64) -> Result<SourceBlock, Failure> {
+    decode_block(result(response)?, number).map_err(Failure::Fatal)
+}
+
+/// The block a response carries.
+fn result(response: &Value) -> Result<&Value, Failure> {
+    if let Some(error) = response.get("error") {
+        return Err(Failure::Transient(format!("JSON-RPC error {}", error)));
+    }
+    match response.get("result") {
+        // Past the node's head, or not synced that far yet.
+        None | Some(Value::Null) => Err(Failure::Transient("block not available".into())),
+        Some(block) => Ok(block),
+    }
+}
+
+fn decode_block(block: &Value, number: u64) -> Result<SourceBlock, String> {
+    if quantity(block, "number")? != number {
+        return Err(format!("node answered with block {}", quantity(block, "number")?));
+    }
+    let header = BlockHeader {
+        number,
+        timestamp: quantity(block, "timestamp")?,
+        coinbase: parsed(block, "miner")?,
+        gas_limit: quantity(block, "gasLimit")?,
+        base_fee_per_gas: optional(block, "baseFeePerGas", quantity)?.unwrap_or(0),
+        excess_blob_gas: optional(block, "excessBlobGas", quantity)?.unwrap_or(0),
+        parent_beacon_block_root: optional(block, "parentBeaconBlockRoot", parsed)?,
+        withdrawals: match block.get("withdrawals").and_then(Value::as_array) {
+            Some(withdrawals) => withdrawals.iter().map(decode_withdrawal).collect::<Result<_, _>>()?,
+            None => vec![],
+        },
+        gas_used: Some(quantity(block, "gasUsed")?),
+        receipts_root: Some(parsed(block, "receiptsRoot")?),
+        state_root: Some(parsed(block, "stateRoot")?),
+    };
+    let txs = block
+        .get("transactions")
+        .and_then(Value::as_array)
+        .ok_or("transactions: missing")?
+        .iter()
+        .enumerate()
+        .map(|(i, tx)| decode_tx(i, tx).map_err(|e| format!("tx {}: {}", i, e)))
+        .collect::<Result<_, _>>()?;
+    Ok(SourceBlock {
+        header: Some(header),
+        txs,
+    })
+}
+
+fn decode_withdrawal(withdrawal: &Value) -> Result<Withdrawal, String> {
+    Ok(Withdrawal {
+        index: quantity(withdrawal, "index")?,
+        validator_index: quantity(withdrawal, "validatorIndex")?,
+        address: parsed(withdrawal, "address")?,
+        amount: quantity(withdrawal, "amount")?,
+    })
+}
+
+fn decode_tx(id: usize, tx: &Value) -> Result<FluxTransaction, String> {
+    let tx_type = optional(tx, "type", quantity)?.unwrap_or(0);
+    if tx_type > 3 {
+        return Err(format!("type {} transactions are not supported", tx_type));
+    }
+    let to = optional(tx, "to", parsed::<Address>)?;
+    let access_list = match tx.get("accessList").and_then(Value::as_array) {
+        Some(entries) => entries
+            .iter()
+            .map(|entry| {
+                let slots = entry
+                    .get("storageKeys")
+                    .and_then(Value::as_array)
+                    .ok_or("storageKeys: missing")?
+                    .iter()
+                    .map(|key| key.as_str().and_then(hex_word).ok_or(format!("storageKeys: {}", key)))
+                    .collect::<Result<_, _>>()?;
+                Ok((parsed(entry, "address")?, slots))
+            })
+            .collect::<Result<_, String>>()?,
+        None => vec![],
+    };
+    let blob_hashes = match tx.get("blobVersionedHashes").and_then(Value::as_array) {
+        Some(hashes) => hashes
+            .iter()
+            .map(|hash| hash.as_str().and_then(|h| B256::from_str(h).ok()).ok_or(format!("blob hash {}", hash)))
+            .collect::<Result<_, _>>()?,
+        None => vec![],
+    };
+
+    Ok(FluxTransaction {
+        id,
+        caller: parsed(tx, "from")?,
+        to: to.unwrap_or_default(),
+        create: to.is_none(),
+        value: word(tx, "value")?,
+        data: bytes(tx, "input")?,
+        gas_limit: quantity(tx, "gas")?,
+        nonce: Some(quantity(tx, "nonce")?),
+        access_list,
+        max_fee_per_gas: if tx_type >= 2 {
+            word(tx, "maxFeePerGas")?
+        } else {
+            word(tx, "gasPrice")?
+        },
+        max_priority_fee_per_gas: if tx_type >= 2 {
+            Some(word(tx, "maxPriorityFeePerGas")?)
+        } else {
+            None
+        },
+        blob_hashes,
+        max_fee_per_blob_gas: if tx_type == 3 {
+            Some(word(tx, "maxFeePerBlobGas")?)
+        } else {
+            None
+        },
+        signature: signature(tx, tx_type)?,
+        skipped: false,
+    })
+}
+
+/// Typed transactions carry `yParity` (older nodes only `v`, as 0 or 1). Legacy `v` is 27/28
+/// before EIP-155: such a signature covers no chain id, so the node's `from` is kept as is.
+fn signature(tx: &Value, tx_type: u64) -> Result<Option<Signature>, String> {
+    let y_parity = if tx_type == 0 {
+        let v = quantity(tx, "v")?;
+        if v < 35 {
+            return Ok(None);
+        }
+        (v - 35) % 2 == 1
+    } else {
+        match optional(tx, "yParity", quantity)? {
+            Some(parity) => parity == 1,
+            None => quantity(tx, "v")? == 1,
+        }
+    };
+    Ok(Some(Signature {
+        y_parity,
+        r: word(tx, "r")?,
+        s: word(tx, "s")?,
+    }))
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> Result<&'a str, String> {
+    value
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("{}: missing", name))
+}
+
+/// A field that is absent or null before the fork that introduced it.
+pub(super) fn optional<T>(
+    value: &Value,
+    name: &str,
+    decode: impl Fn(&Value, &str) -> Result<T, String>,
+) -> Result<Option<T>, String> {
+    match value.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(_) => decode(value, name).map(Some),
+    }
+}
+
+pub(super) fn quantity(value: &Value, name: &str) -> Result<u64, String> {
+    let raw = field(value, name)?;
+    u64::from_str_radix(raw.trim_start_matches("0x"), 16).map_err(|_| format!("{}: {:?}", name, raw))
+}
+
+pub(super) fn word(value: &Value, name: &str) -> Result<U256, String> {
+    let raw = field(value, name)?;
+    hex_word(raw).ok_or_else(|| format!("{}: {:?}", name, raw))
+}
+
+fn hex_word(raw: &str) -> Option<U256> {
+    U256::from_str_radix(raw.trim_start_matches("0x"), 16).ok()
+}
+
+pub(super) fn parsed<T: FromStr>(value: &Value, name: &str) -> Result<T, String> {
+    let raw = field(value, name)?;
+    raw.parse().map_err(|_| format!("{}: {:?}", name, raw))
+}
+
+pub(super) fn bytes(value: &Value, name: &str) -> Result<Vec<u8>, String> {
+    let raw = field(value, name)?;
+    hex::decode(raw.trim_start_matches("0x")).map_err(|e| format!("{}: {}", name, e))
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RpcStats {
+    /// Handed to the engine so far.
+    pub blocks: u64,
+    pub transactions: u64,
+    /// HTTP requests sent, retries included.
+    pub requests: u64,
+    pub retries: u64,
+    /// Response bodies, as received.
+    pub bytes: u64,
+    /// Blocks read from `--rpc-cache` instead of the node, and fetched blocks stored there.
+    pub cached: u64,
+    pub cache_writes: u64,
+    /// Blocks ready ahead of each handed-out one, summed over them.
+    pub lookahead: u64,
+    /// Hand-outs that found nothing ready, and the time the engine spent waiting on them.
+    pub stalls: u64,
+    pub stalled: Duration,
+    /// Request round trips.
+    pub latency_p50: Duration,
+    pub latency_p95: Duration,
+    pub latency_max: Duration,
+}
+
+impl fmt::Display for RpcStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "RPC Source:         {} blocks ({} txs) in {} requests, {} retried, {:.1} MiB; latency p50 {:?}, \
+             p95 {:?}, max {:?}",
+            self.blocks,
+            self.transactions,
+            self.requests,
+            self.retries,
+            self.bytes as f64 / (1024.0 * 1024.0),
+            self.latency_p50,
+            self.latency_p95,
+            self.latency_max
+        )?;
+        if self.cached + self.cache_writes > 0 {
+            writeln!(
+                f,
+                "RPC Cache:          {} blocks read from disk ({:.1}%), {} fetched blocks stored",
+                self.cached,
+                self.cached as f64 / self.blocks.max(1) as f64 * 100.0,
+                self.cache_writes
+            )?;
+        }
+        writeln!(
+            f,
+            "RPC Lookahead:      {:.1} blocks ready on average, {} stalls ({:?} waiting)",
+            self.lookahead as f64 / self.blocks.max(1) as f64,
+            self.stalls,
+            self.stalled
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunked(body: &str) -> Vec<u8> {
+        format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nTransfer-Encoding: chunked\r\n\r\n{}", body)
+            .into_bytes()
+    }
+
+    #[test]
+    fn dechunks_with_extensions_and_trailers() {
+        assert_eq!(dechunk(b"5\r\nhello\r\n0\r\n\r\n").unwrap(), b"hello");
+        // Extensions after the size are ignored, as is anything after the last chunk.
+        let body = b"4;name=value\r\n{\"a\"\r\nB ; x\r\n:1234567890\r\n0;last\r\nX-Trailer: 1\r\n\r\n";
+        assert_eq!(dechunk(body).unwrap(), b"{\"a\":1234567890");
+    }
+
+    #[test]
+    fn rejects_truncated_chunks() {
+        for body in [
+            &b""[..],
+            b"5",
+            b"5\r\nhel",
+            b"5\r\nhello",
+            b"5\r\nhello\r\n",
+            b"5\r\nhello\r\n3\r\nab",
+            b"zz\r\nhello\r\n0\r\n\r\n",
+        ] {
+            assert_eq!(dechunk(body), None, "{:?}", String::from_utf8_lossy(body));
+        }
+    }
+
+    #[test]
+    fn parses_responses() {
+        assert!(matches!(parse_response(&chunked("2;x=y\r\n{}\r\n0\r\n\r\n")), Ok(body) if body == b"{}"));
+        assert!(matches!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}"), Ok(body) if body == b"{}"));
+        // A cut connection is retried; a request the node refuses is not.
+        assert!(matches!(parse_response(&chunked("5\r\nhel")), Err(Failure::Transient(_))));
+        assert!(matches!(parse_response(b"HTTP/1.1 200 OK\r\nContent-"), Err(Failure::Transient(_))));
+        assert!(matches!(parse_response(b"HTTP/1.1 503 Busy\r\n\r\n"), Err(Failure::Transient(_))));
+        assert!(matches!(parse_response(b"HTTP/1.1 404 Not Found\r\n\r\n"), Err(Failure::Fatal(_))));
+    }
+}
@@ -0,0 +1,161 @@
+/*
+ * FLUX ENGINE - SNAPPY DECOMPRESSION
+ * era1 stores every header, body and receipt list snappy-compressed in the framing format. Only
+ * decompression is needed, so it is done here rather than pulling in a codec. Chunk checksums
+ * are not verified: a corrupt block fails to decode as RLP instead.
+ */
+
+const STREAM_IDENTIFIER: u8 = 0xff;
+const COMPRESSED: u8 = 0x00;
+const UNCOMPRESSED: u8 = 0x01;
+/// Most data one chunk of the framing format may decompress to.
+const MAX_CHUNK_DATA: usize = 65536;
+
+/// Decompresses a snappy framed stream.
+pub fn decompress_framed(mut input: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(input.len() * 3);
+    while !input.is_empty() {
+        if input.len() < 4 {
+            return Err("snappy: truncated chunk header".into());
+        }
+        let chunk_type = input[0];
+        let length = u32::from_le_bytes([input[1], input[2], input[3], 0]) as usize;
+        let chunk = input.get(4..4 + length).ok_or("snappy: truncated chunk")?;
+        input = &input[4 + length..];
+        match chunk_type {
+            STREAM_IDENTIFIER if chunk != b"sNaPpY" => return Err("snappy: bad stream identifier".into()),
+            STREAM_IDENTIFIER => {}
+            // Both carry a 4-byte masked CRC-32C ahead of the data.
+            COMPRESSED => decompress_block(chunk.get(4..).ok_or("snappy: chunk without checksum")?, &mut out)?,
+            UNCOMPRESSED => out.extend_from_slice(chunk.get(4..).ok_or("snappy: chunk without checksum")?),
+            0x02..=0x7f => return Err(format!("snappy: reserved unskippable chunk type {:#x}", chunk_type)),
+            // Padding and reserved skippable chunks.
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+/// Decompresses one raw snappy block, appending to `out`.
+fn decompress_block(mut input: &[u8], out: &mut Vec<u8>) -> Result<(), String> {
+    let expected = read_varint(&mut input)?;
+    if expected > MAX_CHUNK_DATA as u64 {
+        return Err(format!("snappy: block length {} is over the {} byte chunk limit", expected, MAX_CHUNK_DATA));
+    }
+    let expected = expected as usize;
+    let start = out.len();
+    out.reserve(expected);
+    while let Some((&tag, rest)) = input.split_first() {
+        input = rest;
+        match tag & 0b11 {
+            // Literal: the length is in the tag, or in the 1-4 bytes after it.
+            0 => {
+                let mut length = (tag >> 2) as usize;
+                if length >= 60 {
+                    let bytes = length - 59;
+                    let raw = input.get(..bytes).ok_or("snappy: truncated literal length")?;
+                    length = raw.iter().rev().fold(0, |acc, &b| (acc << 8) | b as usize);
+                    input = &input[bytes..];
+                }
+                let literal = input.get(..length + 1).ok_or("snappy: truncated literal")?;
+                out.extend_from_slice(literal);
+                input = &input[length + 1..];
+            }
+            // Copies of earlier output, with a 1, 2 or 4 byte offset.
+            kind => {
+                let (length, offset) = match kind {
+                    1 => {
+                        let low = *input.first().ok_or("snappy: truncated copy")?;
+                        input = &input[1..];
+                        (4 + ((tag >> 2) & 0b111) as usize, ((tag as usize >> 5) << 8) | low as usize)
+                    }
+                    2 => {
+                        let raw = input.get(..2).ok_or("snappy: truncated copy")?;
+                        let offset = u16::from_le_bytes([raw[0], raw[1]]) as usize;
+                        input = &input[2..];
+                        ((tag >> 2) as usize + 1, offset)
+                    }
+                    _ => {
+                        let raw = input.get(..4).ok_or("snappy: truncated copy")?;
+                        let offset = u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as usize;
+                        input = &input[4..];
+                        ((tag >> 2) as usize + 1, offset)
+                    }
+                };
+                if offset == 0 || offset > out.len() - start {
+                    return Err(format!("snappy: copy offset {} out of range", offset));
+                }
+                // The source may overlap what is being written, so copy a byte at a time.
+                let from = out.len() - offset;
+                for i in 0..length {
+                    out.push(out[from + i]);
+                }
+            }
+        }
+    }
+    if out.len() - start != expected {
+        return Err(format!(
+            "snappy: block decompressed to {} bytes, header says {}",
+            out.len() - start,
+            expected
+        ));
+    }
+    Ok(())
+}
+
+fn read_varint(input: &mut &[u8]) -> Result<u64, String> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = input.split_first().ok_or("snappy: truncated length")?;
+        *input = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("snappy: length overflows".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A framed stream: the stream identifier, then `block` as one compressed chunk (zero CRC).
+    fn framed(block: &[u8]) -> Vec<u8> {
+        let mut stream = vec![STREAM_IDENTIFIER, 6, 0, 0];
+        stream.extend_from_slice(b"sNaPpY");
+        let length = (block.len() + 4) as u32;
+        stream.push(COMPRESSED);
+        stream.extend_from_slice(&length.to_le_bytes()[..3]);
+        stream.extend_from_slice(&[0; 4]);
+        stream.extend_from_slice(block);
+        stream
+    }
+
+    #[test]
+    fn decodes_literals_and_overlapping_copies() {
+        // 9 bytes: literal "abc", then 6 bytes copied from 3 back.
+        let block = [0x09, 0x08, b'a', b'b', b'c', 0x09, 0x03];
+        assert_eq!(decompress_framed(&framed(&block)).unwrap(), b"abcabcabc");
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        for block in [&[0x80][..], &[0x09, 0x08, b'a'], &[0x09, 0x08, b'a', b'b', b'c', 0x09]] {
+            assert!(decompress_framed(&framed(block)).is_err(), "{:?}", block);
+        }
+        let stream = framed(&[0x09, 0x08, b'a', b'b', b'c', 0x09, 0x03]);
+        assert!(decompress_framed(&stream[..stream.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_lengths_without_allocating() {
+        // 2^63, then one over the chunk limit.
+        let huge = [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01];
+        let error = decompress_framed(&framed(&huge)).unwrap_err();
+        assert!(error.contains("chunk limit"), "{}", error);
+        assert!(decompress_framed(&framed(&[0x81, 0x80, 0x04])).is_err());
+        // A length the data does not add up to.
+        assert!(decompress_framed(&framed(&[0x0a, 0x08, b'a', b'b', b'c', 0x09, 0x03])).is_err());
+    }
+}
@@ -0,0 +1,718 @@
+/*
+ * FLUX ENGINE - CHAIN FOLLOWING
+ * `flux follow --ws-url ws://node:8546`: subscribes to `newHeads` and, for every head, fetches
+ * the block (full transactions) over the same connection, so blocks are executed as the chain
+ * produces them. Heads that skip ahead have the blocks in between fetched first.
+ *
+ * Every block fetched is checked against the hash of the one before it. A parent that does not
+ * match, or a new head at or below a block already returned, means the chain reorganised: the
+ * node's blocks are walked back until one matches a block already returned, or the parent of the
+ * first one, the fork point, and the new branch from there is returned in order, its first block
+ * flagged so the caller can unwind its state to the fork point first. Only the last
+ * `MAX_REORG_DEPTH` hashes are kept.
+ *
+ * A minimal WebSocket client (RFC 6455): plain `ws://` only, like `--rpc-url`, and the server's
+ * handshake accept key is not verified, since it only guards against caching proxies.
+ */
+
+use super::rpc::{decode_response, parsed, Failure, INITIAL_BACKOFF, IO_TIMEOUT, MAX_ATTEMPTS};
+use super::{BlockSource, SourceBlock};
+use crate::workload::Rng;
+use revm::primitives::B256;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Longest wait for the next message, heads included. Several missed slots mean the node or the
+/// connection is gone.
+const HEAD_TIMEOUT: Duration = Duration::from_secs(120);
+/// Frames above this are refused rather than buffered: a full block is a few MiB at most.
+const MAX_MESSAGE: usize = 64 << 20;
+/// Blocks whose hashes are kept to find a fork point in: the deepest reorg that can be followed.
+pub const MAX_REORG_DEPTH: u64 = 64;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+pub struct WsBlockSource {
+    socket: WebSocket,
+    next_id: u64,
+    /// The next block to fetch, once the first head has said where the chain is.
+    next: Option<u64>,
+    /// Heads announced and not fetched yet, oldest first.
+    heads: VecDeque<(u64, Instant)>,
+    /// Number and hash of the blocks returned, newest last, as far back as `MAX_REORG_DEPTH`.
+    canonical: VecDeque<(u64, B256)>,
+    /// The first block followed. `canonical` starts at its parent, which was never returned but
+    /// is where a reorg of it forks.
+    first: Option<u64>,
+    /// Fetched and not returned yet: the rest of a branch, or the one block a head brought.
+    branch: VecDeque<Fetched>,
+    /// The reorg the first block of `branch` starts, and the arrival of the head behind it.
+    branch_reorg: Option<Reorg>,
+    branch_arrival: Option<Instant>,
+    /// When the head of the block `next_block` last returned arrived.
+    last_arrival: Option<Instant>,
+    last_reorg: Option<Reorg>,
+    stats: FollowStats,
+}
+
+struct Fetched {
+    number: u64,
+    hash: B256,
+    parent: B256,
+    block: SourceBlock,
+}
+
+/// Blocks already returned that are no longer on the chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reorg {
+    /// The last block the old and new branches share.
+    pub fork: u64,
+    /// Blocks of the old branch after it.
+    pub depth: u64,
+}
+
+impl WsBlockSource {
+    /// Connects and subscribes; blocks come from the first head announced after this.
+    pub fn connect(url: &str) -> Result<Self, String> {
+        let mut source = Self {
+            socket: WebSocket::connect(url)?,
+            next_id: 1,
+            next: None,
+            heads: VecDeque::new(),
+            canonical: VecDeque::new(),
+            first: None,
+            branch: VecDeque::new(),
+            branch_reorg: None,
+            branch_arrival: None,
+            last_arrival: None,
+            last_reorg: None,
+            stats: FollowStats::default(),
+        };
+        let response = source.call("eth_subscribe", r#"["newHeads"]"#)?;
+        if let Some(error) = response.get("error") {
+            return Err(format!("ws: eth_subscribe: {}", error));
+        }
+        Ok(source)
+    }
+
+    /// When the head of the last block returned by `next_block` was announced.
+    pub fn last_arrival(&self) -> Option<Instant> {
+        self.last_arrival
+    }
+
+    /// If the last block returned by `next_block` is the first of a new branch, the reorg that
+    /// brought it: state must be unwound to `fork` before it executes.
+    pub fn last_reorg(&self) -> Option<Reorg> {
+        self.last_reorg
+    }
+
+    pub fn stats(&self) -> FollowStats {
+        self.stats
+    }
+
+    /// Sends a request and waits for its response, queueing any heads that arrive meanwhile.
+    fn call(&mut self, method: &str, params: &str) -> Result<Value, String> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = format!(
+            r#"{{"jsonrpc":"2.0","id":{},"method":"{}","params":{}}}"#,
+            id, method, params
+        );
+        self.socket.send_text(request.as_bytes())?;
+        loop {
+            if let Some(message) = self.receive()? {
+                if message.get("id").and_then(Value::as_u64) == Some(id) {
+                    return Ok(message);
+                }
+            }
+        }
+    }
+
+    /// Waits for the next message. A head notification is queued instead of returned.
+    fn receive(&mut self) -> Result<Option<Value>, String> {
+        let raw = self.socket.receive()?.ok_or("ws: the node closed the connection")?;
+        self.stats.bytes += raw.len() as u64;
+        let message: Value = serde_json::from_slice(&raw).map_err(|e| format!("ws: malformed message: {}", e))?;
+        if message.get("method").and_then(Value::as_str) != Some("eth_subscription") {
+            return Ok(Some(message));
+        }
+        let number = message["params"]["result"]["number"]
+            .as_str()
+            .and_then(|raw| u64::from_str_radix(raw.trim_start_matches("0x"), 16).ok())
+            .ok_or("ws: head notification without a number")?;
+        self.stats.heads += 1;
+        self.heads.push_back((number, Instant::now()));
+        Ok(None)
+    }
+
+    /// Waits for a head that moves the chain, fetches its block (or the next one, if the head
+    /// skipped ahead) and, if the chain reorganised, the rest of the new branch back to the
+    /// fork point, all into `branch`.
+    fn fetch_next(&mut self) -> Result<(), String> {
+        let (tip, arrival) = loop {
+            while self.heads.is_empty() {
+                if let Some(message) = self.receive()? {
+                    println!("[FLUX] Follow: ignoring unexpected message {}", message);
+                }
+            }
+            let (head, arrival) = self.heads.pop_front().expect("a head was just queued");
+            let number = match self.next {
+                // Catch up on the blocks the head skipped first, at this head's arrival.
+                Some(next) if head > next => {
+                    self.heads.push_front((head, arrival));
+                    next
+                }
+                _ => head,
+            };
+            let started = Instant::now();
+            let tip = self.fetch_block(number)?;
+            self.stats.fetching += started.elapsed();
+            // A head announced twice; anything else at a height already returned is a reorg.
+            if !self.canonical.contains(&(tip.number, tip.hash)) {
+                break (tip, arrival);
+            }
+        };
+
+        if self.first.is_none() {
+            self.first = Some(tip.number);
+            if let Some(parent) = tip.number.checked_sub(1) {
+                self.canonical.push_back((parent, tip.parent));
+            }
+        }
+
+        let started = Instant::now();
+        let mut depth = 0;
+        while self.canonical.back().is_some_and(|(returned, _)| *returned >= tip.number) {
+            self.canonical.pop_back();
+            depth += 1;
+        }
+        let tip_number = tip.number;
+        let mut branch = VecDeque::from([tip]);
+        loop {
+            let first = &branch[0];
+            match self.canonical.back() {
+                Some((_, hash)) if *hash == first.parent => break,
+                Some(&(returned, _)) if self.first.is_some_and(|followed| returned < followed) => {
+                    return Err(format!(
+                        "ws: reorg at block {} replaces block {}, from before the first block followed",
+                        tip_number, returned
+                    ))
+                }
+                Some(&(returned, _)) => {
+                    self.canonical.pop_back();
+                    depth += 1;
+                    let block = self.fetch_block(returned)?;
+                    branch.push_front(block);
+                }
+                // The first block followed was genesis, which has no parent to seed with.
+                None if depth == 0 => break,
+                None => {
+                    return Err(format!(
+                        "ws: reorg at block {} goes deeper than the last {} blocks",
+                        first.number, MAX_REORG_DEPTH
+                    ))
+                }
+            }
+        }
+        self.stats.fetching += started.elapsed();
+        self.stats.blocks += branch.len() as u64;
+        self.stats.transactions += branch.iter().map(|fetched| fetched.block.txs.len() as u64).sum::<u64>();
+        if depth > 0 {
+            let fork = branch[0].number - 1;
+            println!(
+                "[FLUX] Follow: reorg after block {}: {} block(s) replaced by a {}-block branch",
+                fork,
+                depth,
+                branch.len()
+            );
+            self.stats.reorgs += 1;
+            self.stats.reorged_blocks += depth;
+            self.stats.deepest_reorg = self.stats.deepest_reorg.max(depth);
+            self.branch_reorg = Some(Reorg { fork, depth });
+        }
+        self.branch = branch;
+        self.branch_arrival = Some(arrival);
+        Ok(())
+    }
+
+    fn fetch_block(&mut self, number: u64) -> Result<Fetched, String> {
+        let params = format!(r#"["{:#x}",true]"#, number);
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            self.stats.requests += 1;
+            let response = self.call("eth_getBlockByNumber", &params)?;
+            let body = response.to_string();
+            match decode_response(body.as_bytes(), number) {
+                Ok(block) => {
+                    let hashes =
+                        |name| parsed(&response["result"], name).map_err(|e| format!("ws: block {}: {}", number, e));
+                    return Ok(Fetched {
+                        number,
+                        hash: hashes("hash")?,
+                        parent: hashes("parentHash")?,
+                        block,
+                    });
+                }
+                // The node may announce a head a moment before it serves the block.
+                Err(Failure::Transient(e)) if attempts < MAX_ATTEMPTS => {
+                    println!("[FLUX] Follow: block {}: {}, retrying in {:?}", number, e, backoff);
+                    std::thread::sleep(backoff);
+                    backoff *= 2;
+                }
+                Err(Failure::Transient(e)) => {
+                    return Err(format!("ws: block {}: {} (gave up after {} attempts)", number, e, attempts))
+                }
+                Err(Failure::Fatal(e)) => return Err(format!("ws: block {}: {}", number, e)),
+            }
+        }
+    }
+}
+
+impl BlockSource for WsBlockSource {
+    /// Waits for the chain to produce the next block. Never `None`: following ends on an error.
+    fn next_block(&mut self) -> Result<Option<SourceBlock>, String> {
+        if self.branch.is_empty() {
+            self.fetch_next()?;
+        }
+        let fetched = self.branch.pop_front().expect("fetch_next leaves a block to return");
+        self.canonical.push_back((fetched.number, fetched.hash));
+        if self.canonical.len() as u64 > MAX_REORG_DEPTH {
+            self.canonical.pop_front();
+        }
+        self.next = Some(fetched.number + 1);
+        self.last_arrival = self.branch_arrival;
+        self.last_reorg = self.branch_reorg.take();
+        Ok(Some(fetched.block))
+    }
+
+    fn report(&self) -> Option<String> {
+        Some(self.stats.to_string())
+    }
+}
+
+// --- WEBSOCKET ---
+
+struct WebSocket {
+    stream: TcpStream,
+    /// Masks every client frame, as the protocol requires; nothing here needs it unpredictable.
+    rng: Rng,
+}
+
+impl WebSocket {
+    fn connect(url: &str) -> Result<Self, String> {
+        let Some(rest) = url.strip_prefix("ws://") else {
+            return Err(if url.starts_with("wss://") {
+                format!("--ws-url {}: wss is not supported, use a local node or a TLS-terminating proxy", url)
+            } else {
+                format!("--ws-url {}: expected ws://host[:port][/path]", url)
+            });
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| format!("--ws-url {}: invalid port", url))?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("--ws-url {}: no host", url));
+        }
+
+        let io = |e: std::io::Error| format!("ws: {}:{}: {}", host, port, e);
+        let mut stream = TcpStream::connect((host, port)).map_err(io)?;
+        stream.set_read_timeout(Some(HEAD_TIMEOUT)).map_err(io)?;
+        stream.set_write_timeout(Some(IO_TIMEOUT)).map_err(io)?;
+        let seed = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos() as u64);
+        let mut rng = Rng::new(seed);
+        let nonce: Vec<u8> = (0..2).flat_map(|_| rng.next_u64().to_le_bytes()).collect();
+        let handshake = format!(
+            "GET {} HTTP/1.1\r\nHost: {}:{}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            path,
+            host,
+            port,
+            base64(&nonce)
+        );
+        stream.write_all(handshake.as_bytes()).map_err(io)?;
+
+        // Byte at a time, so nothing after the response head is consumed with it.
+        let mut head = Vec::new();
+        let mut byte = [0u8];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).map_err(io)?;
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8_lossy(&head);
+        let status = head.lines().next().and_then(|line| line.split_whitespace().nth(1));
+        if status != Some("101") {
+            return Err(format!("ws: {}: upgrade refused ({})", url, head.lines().next().unwrap_or_default()));
+        }
+        Ok(Self { stream, rng })
+    }
+
+    fn send_text(&mut self, payload: &[u8]) -> Result<(), String> {
+        self.send(OP_TEXT, payload)
+    }
+
+    fn send(&mut self, opcode: u8, payload: &[u8]) -> Result<(), String> {
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            len if len < 126 => frame.push(0x80 | len as u8),
+            len if len <= u16::MAX as usize => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        let mask = (self.rng.next_u64() as u32).to_be_bytes();
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        self.stream.write_all(&frame).map_err(|e| format!("ws: {}", e))
+    }
+
+    /// The next whole data message, reassembled from its fragments, answering pings on the way.
+    /// `None` once the server closes the connection.
+    fn receive(&mut self) -> Result<Option<Vec<u8>>, String> {
+        let mut message = Vec::new();
+        loop {
+            let (fin, opcode, payload) = self.read_frame()?;
+            match opcode {
+                OP_PING => self.send(OP_PONG, &payload)?,
+                OP_PONG => {}
+                OP_CLOSE => return Ok(None),
+                OP_TEXT | OP_BINARY | OP_CONTINUATION => {
+                    message.extend_from_slice(&payload);
+                    if message.len() > MAX_MESSAGE {
+                        return Err(format!("ws: message over {} MiB", MAX_MESSAGE >> 20));
+                    }
+                    if fin {
+                        return Ok(Some(message));
+                    }
+                }
+                _ => return Err(format!("ws: unknown opcode {:#x}", opcode)),
+            }
+        }
+    }
+
+    fn read_frame(&mut self) -> Result<(bool, u8, Vec<u8>), String> {
+        let io = |e: std::io::Error| match e.kind() {
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+                format!("ws: nothing from the node for {:?}", HEAD_TIMEOUT)
+            }
+            _ => format!("ws: {}", e),
+        };
+        let mut head = [0u8; 2];
+        self.stream.read_exact(&mut head).map_err(io)?;
+        let (fin, opcode, masked) = (head[0] & 0x80 != 0, head[0] & 0x0f, head[1] & 0x80 != 0);
+        let len = match head[1] & 0x7f {
+            126 => {
+                let mut len = [0u8; 2];
+                self.stream.read_exact(&mut len).map_err(io)?;
+                u16::from_be_bytes(len) as usize
+            }
+            127 => {
+                let mut len = [0u8; 8];
+                self.stream.read_exact(&mut len).map_err(io)?;
+                u64::from_be_bytes(len) as usize
+            }
+            len => len as usize,
+        };
+        if len > MAX_MESSAGE {
+            return Err(format!("ws: frame over {} MiB", MAX_MESSAGE >> 20));
+        }
+        let mut mask = [0u8; 4];
+        if masked {
+            self.stream.read_exact(&mut mask).map_err(io)?;
+        }
+        let mut payload = vec![0u8; len];
+        self.stream.read_exact(&mut payload).map_err(io)?;
+        if masked {
+            payload.iter_mut().enumerate().for_each(|(i, byte)| *byte ^= mask[i % 4]);
+        }
+        Ok((fin, opcode, payload))
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let word = chunk.iter().enumerate().fold(0u32, |word, (i, &b)| word | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            out.push(if i <= chunk.len() {
+                ALPHABET[(word >> (18 - 6 * i) & 0x3f) as usize] as char
+            } else {
+                '='
+            });
+        }
+    }
+    out
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FollowStats {
+    /// Head notifications received, reorged ones included.
+    pub heads: u64,
+    /// Blocks fetched, both branches of a reorg included.
+    pub blocks: u64,
+    pub transactions: u64,
+    pub reorgs: u64,
+    /// Blocks returned and then reorged out.
+    pub reorged_blocks: u64,
+    pub deepest_reorg: u64,
+    /// Block requests sent, retries included.
+    pub requests: u64,
+    /// Messages received, as received.
+    pub bytes: u64,
+    /// Time spent fetching blocks once their head had arrived.
+    pub fetching: Duration,
+}
+
+impl fmt::Display for FollowStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "WS Source:          {} heads, {} blocks ({} txs) in {} requests, {:.1} MiB, {:?} per block fetched",
+            self.heads,
+            self.blocks,
+            self.transactions,
+            self.requests,
+            self.bytes as f64 / (1024.0 * 1024.0),
+            self.fetching / self.blocks.max(1) as u32
+        )?;
+        writeln!(
+            f,
+            "WS Reorgs:          {} reorgs, {} blocks unwound and replaced, deepest {}",
+            self.reorgs, self.reorged_blocks, self.deepest_reorg
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    /// A node that serves whatever blocks `chain` holds at the time they are asked for, and
+    /// announces heads when told to.
+    struct Node {
+        chain: Arc<Mutex<HashMap<u64, Value>>>,
+        writer: Arc<Mutex<TcpStream>>,
+    }
+
+    impl Node {
+        /// Starts the node and connects a source to it.
+        fn start() -> (Node, WsBlockSource) {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("ws://{}", listener.local_addr().unwrap());
+            let chain = Arc::new(Mutex::new(HashMap::new()));
+            let (accepted, writer) = std::sync::mpsc::channel();
+            let served = chain.clone();
+            std::thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut head = Vec::new();
+                let mut byte = [0u8];
+                while !head.ends_with(b"\r\n\r\n") {
+                    stream.read_exact(&mut byte).unwrap();
+                    head.push(byte[0]);
+                }
+                stream
+                    .write_all(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n")
+                    .unwrap();
+                let writer = Arc::new(Mutex::new(stream.try_clone().unwrap()));
+                accepted.send(writer.clone()).unwrap();
+                while let Some(request) = read_client_frame(&mut stream) {
+                    let request: Value = serde_json::from_slice(&request).unwrap();
+                    let result = match request["method"].as_str() {
+                        Some("eth_subscribe") => json!("0x1"),
+                        _ => {
+                            let number = request["params"][0].as_str().unwrap().trim_start_matches("0x");
+                            let number = u64::from_str_radix(number, 16).unwrap();
+                            served.lock().unwrap().get(&number).cloned().unwrap_or(Value::Null)
+                        }
+                    };
+                    let response = json!({"jsonrpc": "2.0", "id": request["id"], "result": result});
+                    send_server_frame(&writer, &response);
+                }
+            });
+            let source = WsBlockSource::connect(&url).unwrap();
+            let writer = writer.recv().unwrap();
+            (Node { chain, writer }, source)
+        }
+
+        /// Serves `blocks` from now on, in place of any at the same heights.
+        fn serve(&self, blocks: &[Value]) {
+            let mut chain = self.chain.lock().unwrap();
+            for block in blocks {
+                let number = u64::from_str_radix(block["number"].as_str().unwrap().trim_start_matches("0x"), 16);
+                chain.insert(number.unwrap(), block.clone());
+            }
+        }
+
+        fn announce(&self, number: u64) {
+            let head = json!({
+                "jsonrpc": "2.0",
+                "method": "eth_subscription",
+                "params": {"subscription": "0x1", "result": {"number": format!("{:#x}", number)}},
+            });
+            send_server_frame(&self.writer, &head);
+        }
+    }
+
+    fn read_client_frame(stream: &mut TcpStream) -> Option<Vec<u8>> {
+        let mut head = [0u8; 2];
+        stream.read_exact(&mut head).ok()?;
+        let len = match head[1] & 0x7f {
+            126 => {
+                let mut len = [0u8; 2];
+                stream.read_exact(&mut len).ok()?;
+                u16::from_be_bytes(len) as usize
+            }
+            len => len as usize,
+        };
+        let mut mask = [0u8; 4];
+        stream.read_exact(&mut mask).ok()?;
+        let mut payload = vec![0u8; len];
+        stream.read_exact(&mut payload).ok()?;
+        payload.iter_mut().enumerate().for_each(|(i, byte)| *byte ^= mask[i % 4]);
+        Some(payload)
+    }
+
+    fn send_server_frame(writer: &Mutex<TcpStream>, message: &Value) {
+        let payload = message.to_string().into_bytes();
+        let mut frame = vec![0x80 | OP_TEXT];
+        match payload.len() {
+            len if len < 126 => frame.push(len as u8),
+            len => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&payload);
+        // The client may be gone already, once a test has what it needs.
+        let _ = writer.lock().unwrap().write_all(&frame);
+    }
+
+    /// Block hashes name their branch and height.
+    fn hash(number: u64, branch: u8) -> String {
+        let mut bytes = [branch; 32];
+        bytes[24..].copy_from_slice(&number.to_be_bytes());
+        format!("{:#x}", B256::new(bytes))
+    }
+
+    /// `eth_getBlockByNumber` as a post-merge node answers it, for a block of `branch` whose
+    /// parent is on `parent_branch`.
+    fn block(number: u64, branch: u8, parent_branch: u8) -> Value {
+        json!({
+            "number": format!("{:#x}", number),
+            "hash": hash(number, branch),
+            "parentHash": hash(number - 1, parent_branch),
+            "timestamp": format!("{:#x}", 1_700_000_000 + 12 * number),
+            "miner": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+            "gasLimit": "0x1c9c380",
+            "gasUsed": "0x0",
+            "baseFeePerGas": "0x3b9aca00",
+            "stateRoot": "0xd7f8974fb5ac78d9ac099b9ad5018bedc2ce0a72dad1827a1709da30580f0544",
+            "receiptsRoot": "0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421",
+            "transactions": [],
+        })
+    }
+
+    fn follow(source: &mut WsBlockSource) -> (u64, Option<Reorg>) {
+        let block = source.next_block().unwrap().unwrap();
+        (block.header.unwrap().number, source.last_reorg())
+    }
+
+    #[test]
+    fn follows_heads_and_fills_skipped_blocks() {
+        let (node, mut source) = Node::start();
+        node.serve(&[block(100, 0, 0), block(101, 0, 0), block(102, 0, 0)]);
+        node.announce(100);
+        assert_eq!(follow(&mut source), (100, None));
+        // A repeated head is ignored; one that skips a block has it fetched first.
+        node.announce(100);
+        node.announce(102);
+        assert_eq!(follow(&mut source), (101, None));
+        assert_eq!(follow(&mut source), (102, None));
+    }
+
+    #[test]
+    fn unwinds_a_reorg_at_the_same_height() {
+        let (node, mut source) = Node::start();
+        node.serve(&[block(100, 0, 0), block(101, 0, 0)]);
+        node.announce(100);
+        node.announce(101);
+        follow(&mut source);
+        assert_eq!(follow(&mut source), (101, None));
+
+        node.serve(&[block(101, 1, 0)]);
+        node.announce(101);
+        assert_eq!(follow(&mut source), (101, Some(Reorg { fork: 100, depth: 1 })));
+        assert_eq!(source.stats().reorged_blocks, 1);
+    }
+
+    #[test]
+    fn unwinds_a_reorg_of_the_first_block() {
+        let (node, mut source) = Node::start();
+        node.serve(&[block(100, 0, 0)]);
+        node.announce(100);
+        assert_eq!(follow(&mut source), (100, None));
+
+        node.serve(&[block(100, 1, 0)]);
+        node.announce(100);
+        assert_eq!(follow(&mut source), (100, Some(Reorg { fork: 99, depth: 1 })));
+    }
+
+    #[test]
+    fn refuses_a_reorg_from_before_the_first_block() {
+        let (node, mut source) = Node::start();
+        node.serve(&[block(100, 0, 0)]);
+        node.announce(100);
+        follow(&mut source);
+
+        node.serve(&[block(99, 1, 1), block(100, 1, 1)]);
+        node.announce(100);
+        let err = source.next_block().err().unwrap();
+        assert!(err.contains("replaces block 99, from before the first block followed"), "{}", err);
+    }
+
+    #[test]
+    fn unwinds_a_reorg_to_a_shorter_chain() {
+        let (node, mut source) = Node::start();
+        node.serve(&[block(100, 0, 0), block(101, 0, 0), block(102, 0, 0), block(103, 0, 0)]);
+        node.announce(100);
+        node.announce(103);
+        for number in 100..=103 {
+            assert_eq!(follow(&mut source), (number, None));
+        }
+
+        // The new branch forks after 100 and is one block shorter than the old one.
+        node.serve(&[block(101, 1, 0), block(102, 1, 1)]);
+        node.announce(102);
+        assert_eq!(follow(&mut source), (101, Some(Reorg { fork: 100, depth: 3 })));
+        assert_eq!(follow(&mut source), (102, None));
+        assert_eq!(source.stats().deepest_reorg, 3);
+    }
+}
@@ -0,0 +1,243 @@
+/*
+ * FLUX ENGINE - STATE BACKEND
+ * The store underneath the shard caches: nothing (every run starts from genesis fixtures), or a
+ * persistent database in a state directory.
+ */
+
+use super::flatlog::{FlatLog, FlatLogStats};
+use super::mmap::{MmapState, MmapStats};
+use super::sharded::StateChange;
+use super::snapshot::{ImportStats, SnapshotAccount};
+use crate::mvcc::Location;
+use revm::db::{DatabaseRef, EmptyDB};
+use revm::primitives::{AccountInfo, Address, Bytecode, B256, U256};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+#[cfg(feature = "rocksdb")]
+use super::rocks::RocksBackend;
+
+/// Which persistent store a state directory holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackendKind {
+    /// Append-only log; committed blocks are written back, so state carries over between runs.
+    #[default]
+    FlatLog,
+    /// Imported snapshot, never written by runs, so every run starts from the same state.
+    RocksDb,
+    /// Preprocessed read-only file, memory-mapped instead of held on the heap.
+    Mmap,
+}
+
+impl FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "flatlog" | "flat-log" => Ok(BackendKind::FlatLog),
+            "rocksdb" => Ok(BackendKind::RocksDb),
+            "mmap" => Ok(BackendKind::Mmap),
+            _ => Err(format!("unknown state backend {:?} (expected flatlog, rocksdb or mmap)", s)),
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BackendKind::FlatLog => "flatlog",
+            BackendKind::RocksDb => "rocksdb",
+            BackendKind::Mmap => "mmap",
+        })
+    }
+}
+
+pub enum StateBackend {
+    Memory(EmptyDB),
+    FlatLog(Box<FlatLog>),
+    Mmap(MmapState),
+    #[cfg(feature = "rocksdb")]
+    RocksDb(RocksBackend),
+}
+
+impl StateBackend {
+    /// `None` keeps everything in memory; a directory opens (or creates) a `kind` store there.
+    pub fn open(kind: BackendKind, state_dir: Option<&Path>) -> Result<Self, String> {
+        let Some(dir) = state_dir else {
+            return Ok(StateBackend::Memory(EmptyDB::default()));
+        };
+        match kind {
+            BackendKind::FlatLog => FlatLog::open(dir).map(|log| StateBackend::FlatLog(Box::new(log))),
+            BackendKind::Mmap => MmapState::open(dir).map(StateBackend::Mmap),
+            #[cfg(feature = "rocksdb")]
+            BackendKind::RocksDb => RocksBackend::open(dir).map(StateBackend::RocksDb),
+            #[cfg(not(feature = "rocksdb"))]
+            BackendKind::RocksDb => Err(format!(
+                "{}: the rocksdb backend needs a build with `--features rocksdb`",
+                dir.display()
+            )),
+        }
+    }
+
+    /// Loads a snapshot into a `kind` store in `state_dir`.
+    pub fn import(
+        kind: BackendKind,
+        state_dir: &Path,
+        accounts: impl Iterator<Item = Result<SnapshotAccount, String>>,
+    ) -> Result<ImportStats, String> {
+        match kind {
+            BackendKind::Mmap => MmapState::build(state_dir, accounts),
+            BackendKind::FlatLog => FlatLog::open(state_dir)?.import(accounts),
+            #[cfg(feature = "rocksdb")]
+            BackendKind::RocksDb => RocksBackend::open(state_dir)?.import(accounts),
+            #[cfg(not(feature = "rocksdb"))]
+            BackendKind::RocksDb => Err(format!(
+                "{}: the rocksdb backend needs a build with `--features rocksdb`",
+                state_dir.display()
+            )),
+        }
+    }
+
+    /// Whether committed blocks are handed to `commit_batch`.
+    pub fn accepts_writes(&self) -> bool {
+        matches!(self, StateBackend::FlatLog(_))
+    }
+
+    /// Whether a deleted account or zeroed slot reads back as absent once its block is persisted,
+    /// so the shard caches may forget it. The read-only stores would serve the snapshot's value.
+    pub fn reflects_deletions(&self) -> bool {
+        matches!(self, StateBackend::Memory(_) | StateBackend::FlatLog(_))
+    }
+
+    /// Writes one block's changes atomically: a crash leaves either all of them or none.
+    pub fn commit_batch(&self, block: u64, changes: &[StateChange]) -> Result<(), String> {
+        match self {
+            StateBackend::FlatLog(db) => db.commit_batch(block, changes),
+            _ => Ok(()),
+        }
+    }
+
+    /// Hints at accounts and slots about to be read. Only the mapped file acts on it.
+    pub fn prefetch(&self, locations: impl Iterator<Item = Location>) {
+        if let StateBackend::Mmap(db) = self {
+            db.prefetch(locations);
+        }
+    }
+
+    /// Every account the store holds, without code, for whole-state work like the state root.
+    pub fn dump(&self) -> Result<Vec<SnapshotAccount>, String> {
+        match self {
+            StateBackend::Memory(_) => Ok(Vec::new()),
+            StateBackend::FlatLog(db) => db.dump(),
+            StateBackend::Mmap(db) => db.dump(),
+            #[cfg(feature = "rocksdb")]
+            StateBackend::RocksDb(_) => Err("the rocksdb backend cannot enumerate its state yet".into()),
+        }
+    }
+
+    pub fn stats(&self) -> Option<BackendStats> {
+        match self {
+            StateBackend::FlatLog(db) => Some(BackendStats::FlatLog(db.stats())),
+            StateBackend::Mmap(db) => Some(BackendStats::Mmap(db.stats())),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum BackendStats {
+    FlatLog(FlatLogStats),
+    Mmap(MmapStats),
+}
+
+impl fmt::Display for BackendStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendStats::FlatLog(stats) => stats.fmt(f),
+            BackendStats::Mmap(stats) => stats.fmt(f),
+        }
+    }
+}
+
+impl fmt::Debug for StateBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateBackend::Memory(_) => f.write_str("Memory"),
+            StateBackend::FlatLog(_) => f.write_str("FlatLog"),
+            StateBackend::Mmap(_) => f.write_str("Mmap"),
+            #[cfg(feature = "rocksdb")]
+            StateBackend::RocksDb(_) => f.write_str("RocksDb"),
+        }
+    }
+}
+
+impl DatabaseRef for StateBackend {
+    type Error = String;
+
+    fn basic(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        match self {
+            StateBackend::Memory(db) => db.basic(address).map_err(|e| match e {}),
+            StateBackend::FlatLog(db) => db.basic(address),
+            StateBackend::Mmap(db) => db.basic(address),
+            #[cfg(feature = "rocksdb")]
+            StateBackend::RocksDb(db) => db.basic(address),
+        }
+    }
+
+    fn code_by_hash(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        match self {
+            StateBackend::Memory(db) => db.code_by_hash(code_hash).map_err(|e| match e {}),
+            StateBackend::FlatLog(db) => db.code_by_hash(code_hash),
+            StateBackend::Mmap(db) => db.code_by_hash(code_hash),
+            #[cfg(feature = "rocksdb")]
+            StateBackend::RocksDb(db) => db.code_by_hash(code_hash),
+        }
+    }
+
+    fn storage(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        match self {
+            StateBackend::Memory(db) => db.storage(address, index).map_err(|e| match e {}),
+            StateBackend::FlatLog(db) => db.storage(address, index),
+            StateBackend::Mmap(db) => db.storage(address, index),
+            #[cfg(feature = "rocksdb")]
+            StateBackend::RocksDb(db) => db.storage(address, index),
+        }
+    }
+
+    fn block_hash(&self, number: U256) -> Result<B256, Self::Error> {
+        match self {
+            StateBackend::Memory(db) => db.block_hash(number).map_err(|e| match e {}),
+            StateBackend::FlatLog(db) => db.block_hash(number),
+            StateBackend::Mmap(db) => db.block_hash(number),
+            #[cfg(feature = "rocksdb")]
+            StateBackend::RocksDb(db) => db.block_hash(number),
+        }
+    }
+}
+
+// --- ACCOUNT ENCODING ---
+
+/// On-disk account record shared by the persistent backends.
+pub(crate) const ACCOUNT_BYTES: usize = 72;
+
+/// `balance (32) | nonce (8) | code_hash (32)`, all big-endian. Code is stored by hash, separately.
+pub(crate) fn encode_account(info: &AccountInfo) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(ACCOUNT_BYTES);
+    raw.extend_from_slice(&info.balance.to_be_bytes::<32>());
+    raw.extend_from_slice(&info.nonce.to_be_bytes());
+    raw.extend_from_slice(info.code_hash.as_slice());
+    raw
+}
+
+pub(crate) fn decode_account(raw: &[u8]) -> Option<AccountInfo> {
+    if raw.len() != ACCOUNT_BYTES {
+        return None;
+    }
+    Some(AccountInfo {
+        balance: U256::from_be_slice(&raw[..32]),
+        nonce: u64::from_be_bytes(raw[32..40].try_into().ok()?),
+        code_hash: B256::from_slice(&raw[40..]),
+        code: None,
+    })
+}
@@ -0,0 +1,154 @@
+/*
+ * FLUX ENGINE - WRITE BUFFER
+ * Block-scoped write buffer for the ordered applier. Committed transactions land here instead of
+ * in the shards; later writes to the same account or slot replace earlier ones in place, and the
+ * block's net changes reach the shards (and from there the backend) in one commit at the end.
+ * Reads see the buffer first, so serial re-execution and the epilogue observe every write.
+ */
+
+use super::sharded::ShardedState;
+use revm::db::DatabaseRef;
+use revm::primitives::{Account, AccountInfo, Address, Bytecode, HashMap, B256, U256};
+use revm::DatabaseCommit;
+use std::fmt;
+
+pub struct WriteBuffer<'a, DB> {
+    state: &'a ShardedState<DB>,
+    accounts: HashMap<Address, Account>,
+    /// Account and slot writes committed into the buffer, duplicates included.
+    writes: u64,
+}
+
+impl<'a, DB> WriteBuffer<'a, DB> {
+    pub fn new(state: &'a ShardedState<DB>) -> Self {
+        Self {
+            state,
+            accounts: HashMap::default(),
+            writes: 0,
+        }
+    }
+
+    /// Commits the coalesced writes to the shards and reports how much they shrank.
+    pub fn flush(self) -> BufferStats {
+        let flushed = self
+            .accounts
+            .values()
+            .map(|account| 1 + account.storage.values().filter(|slot| slot.is_changed()).count() as u64)
+            .sum();
+        let stats = BufferStats {
+            flushes: 1,
+            writes: self.writes,
+            flushed,
+        };
+        let mut state = self.state;
+        state.commit(self.accounts);
+        stats
+    }
+}
+
+/// Folds `later` into `earlier`, the same account's writes from a previous transaction.
+pub(crate) fn coalesce(earlier: &mut Account, mut later: Account) {
+    if later.is_selfdestructed() || later.is_created() {
+        // Everything before is gone either way.
+        *earlier = later;
+        return;
+    }
+    if earlier.is_selfdestructed() {
+        // Touched again after it was destroyed: it starts over from empty storage.
+        later.mark_created();
+        *earlier = later;
+        return;
+    }
+    earlier.info = later.info;
+    for (slot, value) in later.storage {
+        match earlier.storage.get_mut(&slot) {
+            // The earlier original stays: it is what the shards hold.
+            Some(earlier) => earlier.present_value = value.present_value,
+            None => {
+                earlier.storage.insert(slot, value);
+            }
+        }
+    }
+}
+
+impl<DB> DatabaseCommit for WriteBuffer<'_, DB> {
+    fn commit(&mut self, changes: HashMap<Address, Account>) {
+        for (address, mut account) in changes {
+            if !account.is_touched() {
+                continue;
+            }
+            self.writes += 1 + account.storage.values().filter(|slot| slot.is_changed()).count() as u64;
+            // New code goes straight to the shared contract map: reads by hash never see the buffer.
+            self.state.insert_contract(&mut account.info);
+            match self.accounts.get_mut(&address) {
+                Some(earlier) => coalesce(earlier, account),
+                None => {
+                    self.accounts.insert(address, account);
+                }
+            }
+        }
+    }
+}
+
+impl<DB: DatabaseRef> DatabaseRef for WriteBuffer<'_, DB> {
+    type Error = DB::Error;
+
+    fn basic(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        match self.accounts.get(&address) {
+            Some(account) if account.is_selfdestructed() => Ok(None),
+            Some(account) => Ok(Some(account.info.clone())),
+            None => self.state.basic(address),
+        }
+    }
+
+    fn code_by_hash(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        self.state.code_by_hash(code_hash)
+    }
+
+    fn storage(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        if let Some(account) = self.accounts.get(&address) {
+            if let Some(slot) = account.storage.get(&index) {
+                return Ok(slot.present_value);
+            }
+            if account.is_selfdestructed() || account.is_created() {
+                return Ok(U256::ZERO);
+            }
+        }
+        self.state.storage(address, index)
+    }
+
+    fn block_hash(&self, number: U256) -> Result<B256, Self::Error> {
+        self.state.block_hash(number)
+    }
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BufferStats {
+    pub flushes: u64,
+    pub writes: u64,
+    /// Writes left once duplicates were folded together (changed-then-reverted slots drop out too).
+    pub flushed: u64,
+}
+
+impl std::ops::AddAssign for BufferStats {
+    fn add_assign(&mut self, other: Self) {
+        self.flushes += other.flushes;
+        self.writes += other.writes;
+        self.flushed += other.flushed;
+    }
+}
+
+impl fmt::Display for BufferStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Write Buffer:       {} writes coalesced into {} over {} blocks ({:.2}% absorbed)",
+            self.writes,
+            self.flushed,
+            self.flushes,
+            self.writes.saturating_sub(self.flushed) as f64 / self.writes.max(1) as f64 * 100.0
+        )
+    }
+}
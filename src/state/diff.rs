@@ -0,0 +1,127 @@
+/*
+ * FLUX ENGINE - STATE DIFFS
+ * Per-block state diffs: every account and slot a block changed, with its value before and after,
+ * written as JSON lines (one account per line) for auditing what the engine mutated.
+ */
+
+use super::history::Changeset;
+use revm::db::DatabaseRef;
+use revm::primitives::{AccountInfo, Address, U256};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct AccountDiff {
+    pub address: Address,
+    /// `None` where the account did not exist before / no longer exists after the block.
+    pub pre: Option<AccountInfo>,
+    pub post: Option<AccountInfo>,
+    /// (slot, before, after), by slot.
+    pub storage: Vec<(U256, U256, U256)>,
+}
+
+impl AccountDiff {
+    fn info_changed(&self) -> bool {
+        let key = |info: &Option<AccountInfo>| info.as_ref().map(|i| (i.balance, i.nonce, i.code_hash));
+        key(&self.pre) != key(&self.post)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StateDiff {
+    pub block: u64,
+    /// By address; accounts whose writes left everything as it was are not included.
+    pub accounts: Vec<AccountDiff>,
+}
+
+impl StateDiff {
+    /// Pairs the pre-block values in `changeset` with what `state` holds now.
+    pub fn compute<DB>(block: u64, changeset: &Changeset, state: &DB) -> Result<Self, String>
+    where
+        DB: DatabaseRef<Error = String>,
+    {
+        // Everything the block wrote, grouped by account and ordered by address.
+        let mut touched: BTreeMap<Address, Vec<(U256, U256)>> =
+            changeset.accounts.keys().map(|address| (*address, Vec::new())).collect();
+        for ((address, slot), before) in &changeset.storage {
+            touched.entry(*address).or_default().push((*slot, *before));
+        }
+
+        let mut accounts = Vec::new();
+        for (address, slots) in touched {
+            let pre = match changeset.accounts.get(&address) {
+                Some(pre) => pre.clone(),
+                None => state.basic(address)?,
+            };
+            let mut diff = AccountDiff {
+                address,
+                pre,
+                post: state.basic(address)?,
+                storage: Vec::new(),
+            };
+            for (slot, before) in slots {
+                let after = state.storage(address, slot)?;
+                if after != before {
+                    diff.storage.push((slot, before, after));
+                }
+            }
+            diff.storage.sort_unstable_by_key(|(slot, _, _)| *slot);
+            if diff.info_changed() || !diff.storage.is_empty() {
+                accounts.push(diff);
+            }
+        }
+        Ok(Self { block, accounts })
+    }
+
+    pub fn slots(&self) -> usize {
+        self.accounts.iter().map(|account| account.storage.len()).sum()
+    }
+
+    /// Writes `block-<n>.jsonl` under `dir` and returns its path. Each account line carries only
+    /// the fields that changed, as `{"from": .., "to": ..}`; `null` marks a missing account.
+    pub fn write(&self, dir: &Path) -> Result<PathBuf, String> {
+        let path = dir.join(format!("block-{}.jsonl", self.block));
+        let err = |e: std::io::Error| format!("{}: {}", path.display(), e);
+        fs::create_dir_all(dir).map_err(err)?;
+        let mut out = BufWriter::new(File::create(&path).map_err(err)?);
+        for account in &self.accounts {
+            let field = |get: fn(&AccountInfo) -> Value| {
+                json!({
+                    "from": account.pre.as_ref().map(get).unwrap_or(Value::Null),
+                    "to": account.post.as_ref().map(get).unwrap_or(Value::Null),
+                })
+            };
+            let mut line = json!({ "block": self.block, "address": account.address.to_string() });
+            let pre = account.pre.as_ref();
+            let post = account.post.as_ref();
+            if pre.map(|i| i.balance) != post.map(|i| i.balance) {
+                line["balance"] = field(|info| Value::String(info.balance.to_string()));
+            }
+            if pre.map(|i| i.nonce) != post.map(|i| i.nonce) {
+                line["nonce"] = field(|info| json!(info.nonce));
+            }
+            if pre.map(|i| i.code_hash) != post.map(|i| i.code_hash) {
+                line["code_hash"] = field(|info| Value::String(info.code_hash.to_string()));
+            }
+            if !account.storage.is_empty() {
+                line["storage"] = account
+                    .storage
+                    .iter()
+                    .map(|(slot, before, after)| {
+                        (
+                            format!("{:#066x}", slot),
+                            json!({ "from": format!("{:#x}", before), "to": format!("{:#x}", after) }),
+                        )
+                    })
+                    .collect::<serde_json::Map<_, _>>()
+                    .into();
+            }
+            writeln!(out, "{}", line).map_err(err)?;
+        }
+        out.flush().map_err(err)?;
+        Ok(path)
+    }
+}
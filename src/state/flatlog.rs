@@ -0,0 +1,698 @@
+/*
+ * FLUX ENGINE - FLAT-LOG STATE STORE
+ * Append-only flat-log state store. Committed changes are appended as records to the active
+ * segment file; an in-memory index maps every account, slot and code hash to where its latest
+ * value sits on disk, so memory grows with the number of live keys rather than with history.
+ * Each block's records are written as one batch closed by a `COMMIT` marker, and only marked
+ * batches are replayed, so a block is on disk whole or not at all. Segments rotate between
+ * batches at `SEGMENT_BYTES`, and once more than half the log is superseded records the live
+ * values are rewritten into fresh segments and the old ones deleted.
+ */
+
+use super::backend::{decode_account, encode_account, ACCOUNT_BYTES};
+use super::sharded::StateChange;
+use super::snapshot::{ImportStats, SnapshotAccount};
+use parking_lot::RwLock;
+use revm::db::DatabaseRef;
+use revm::primitives::{keccak256, AccountInfo, Address, Bytecode, Bytes, HashMap, B256, KECCAK_EMPTY, U256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+/// Active segment size at which a new one is started.
+const SEGMENT_BYTES: u64 = 64 << 20;
+/// Logs smaller than this are never compacted, whatever their dead ratio.
+const COMPACT_MIN_BYTES: u64 = SEGMENT_BYTES;
+/// Changes committed per batch during `import`.
+const IMPORT_BATCH: usize = 10_000;
+
+// Record: kind (1) | payload length (4, LE) | payload | FNV-1a of kind + payload (4, LE).
+const HEADER: usize = 5;
+const TRAILER: usize = 4;
+
+const ACCOUNT: u8 = 1; // address | account
+const ACCOUNT_DELETED: u8 = 2; // address
+const STORAGE: u8 = 3; // address | slot | value
+const STORAGE_WIPE: u8 = 4; // address
+const CODE: u8 = 5; // code hash | bytecode
+const COMMIT: u8 = 6; // block number (8, LE)
+
+// --- INDEX ---
+
+/// Where a value lives: `len` bytes at `offset` in `segment`, inside a record of `record` bytes.
+#[derive(Debug, Clone, Copy)]
+struct Pointer {
+    segment: u32,
+    offset: u64,
+    len: u32,
+    record: u32,
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    accounts: HashMap<Address, Pointer>,
+    storage: HashMap<Address, HashMap<U256, Pointer>>,
+    code: HashMap<B256, Pointer>,
+}
+
+impl Index {
+    /// Applies the record whose payload starts at `offset`. Returns the bytes it made dead,
+    /// including its own when it carries no value (deletions and wipes).
+    fn apply(&mut self, kind: u8, payload: &[u8], segment: u32, offset: u64) -> Result<u64, String> {
+        let record = (HEADER + payload.len() + TRAILER) as u32;
+        let value = |key_len: usize| Pointer {
+            segment,
+            offset: offset + key_len as u64,
+            len: (payload.len() - key_len) as u32,
+            record,
+        };
+        let superseded = |old: Option<Pointer>| old.map_or(0, |p| p.record as u64);
+
+        match (kind, payload.len()) {
+            (ACCOUNT, len) if len == 20 + ACCOUNT_BYTES => {
+                Ok(superseded(self.accounts.insert(Address::from_slice(&payload[..20]), value(20))))
+            }
+            (ACCOUNT_DELETED, 20) => {
+                let address = Address::from_slice(payload);
+                Ok(superseded(self.accounts.remove(&address)) + self.wipe(&address) + record as u64)
+            }
+            (STORAGE, 84) => {
+                let slots = self.storage.entry(Address::from_slice(&payload[..20])).or_default();
+                Ok(superseded(slots.insert(U256::from_be_slice(&payload[20..52]), value(52))))
+            }
+            (STORAGE_WIPE, 20) => Ok(self.wipe(&Address::from_slice(payload)) + record as u64),
+            (CODE, len) if len >= 32 => Ok(superseded(self.code.insert(B256::from_slice(&payload[..32]), value(32)))),
+            (COMMIT, 8) => Ok(record as u64),
+            _ => Err(format!("malformed record (kind {}, {} bytes)", kind, payload.len())),
+        }
+    }
+
+    fn wipe(&mut self, address: &Address) -> u64 {
+        self.storage
+            .remove(address)
+            .map_or(0, |slots| slots.values().map(|p| p.record as u64).sum())
+    }
+
+    fn keys(&self) -> usize {
+        self.accounts.len() + self.code.len() + self.storage.values().map(HashMap::len).sum::<usize>()
+    }
+}
+
+// --- SEGMENTS ---
+
+struct SegmentWriter {
+    id: u32,
+    file: File,
+    /// Bytes in the segment, not counting `pending`.
+    len: u64,
+    /// The open batch's encoded records, not yet written or indexed.
+    pending: Vec<u8>,
+}
+
+struct Inner {
+    dir: PathBuf,
+    /// Read handles for every segment, the active one included.
+    segments: BTreeMap<u32, File>,
+    writer: SegmentWriter,
+    index: Index,
+    total_bytes: u64,
+    dead_bytes: u64,
+    /// Block of the last committed batch.
+    block: u64,
+}
+
+impl Inner {
+    /// Adds a record to the open batch. Nothing reaches the disk or the index until `commit`.
+    fn append(&mut self, kind: u8, payload: &[u8]) {
+        encode_record(&mut self.writer.pending, kind, payload);
+    }
+
+    /// Closes the open batch with a `COMMIT` marker, writes and syncs it, then indexes it. On a
+    /// failed write the batch is cut off the segment again and the index is left as it was.
+    fn commit(&mut self, block: u64) -> Result<(), String> {
+        self.append(COMMIT, &block.to_le_bytes());
+        let written = self.write_batch();
+        let pending = std::mem::take(&mut self.writer.pending);
+        written?;
+
+        let (id, start) = (self.writer.id, self.writer.len);
+        let mut pos = 0;
+        while let Some((kind, payload)) = decode_record(&pending[pos..]) {
+            self.dead_bytes += self.index.apply(kind, payload, id, start + (pos + HEADER) as u64)?;
+            pos += HEADER + payload.len() + TRAILER;
+        }
+        self.writer.len += pending.len() as u64;
+        self.total_bytes += pending.len() as u64;
+        self.block = block;
+        Ok(())
+    }
+
+    fn write_batch(&mut self) -> Result<(), String> {
+        // A batch never spans segments; one larger than a segment gets a segment of its own.
+        if self.writer.len > 0 && self.writer.len + self.writer.pending.len() as u64 > SEGMENT_BYTES {
+            self.rotate()?;
+        }
+        let writer = &self.writer;
+        writer
+            .file
+            .write_all_at(&writer.pending, writer.len)
+            .and_then(|_| writer.file.sync_data())
+            .or_else(|e| writer.file.set_len(writer.len).and(Err(e)))
+            .map_err(|e| format!("{}: {}", segment_path(&self.dir, writer.id).display(), e))
+    }
+
+    fn rotate(&mut self) -> Result<(), String> {
+        let id = self.writer.id + 1;
+        let file = create_segment(&self.dir, id)?;
+        self.segments.insert(id, reopen(&self.dir, id, &file)?);
+        self.writer = SegmentWriter {
+            id,
+            file,
+            len: 0,
+            pending: std::mem::take(&mut self.writer.pending),
+        };
+        Ok(())
+    }
+
+    fn read(&self, pointer: Pointer) -> Result<Vec<u8>, String> {
+        let mut buf = vec![0u8; pointer.len as usize];
+        self.segments
+            .get(&pointer.segment)
+            .ok_or_else(|| format!("segment {} missing", pointer.segment))?
+            .read_exact_at(&mut buf, pointer.offset)
+            .map_err(|e| format!("{}: {}", segment_path(&self.dir, pointer.segment).display(), e))?;
+        Ok(buf)
+    }
+
+    fn maybe_compact(&mut self) -> Result<(), String> {
+        if self.total_bytes >= COMPACT_MIN_BYTES && self.dead_bytes * 2 > self.total_bytes {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites every live value into fresh segments, a segment's worth per batch, then deletes
+    /// the old ones. A crash part-way leaves old and new segments side by side, which replay to the
+    /// same index.
+    fn compact(&mut self) -> Result<(), String> {
+        self.rotate()?;
+        let first_new = self.writer.id;
+        let block = self.block;
+        let old = std::mem::take(&mut self.index);
+        self.total_bytes = 0;
+        self.dead_bytes = 0;
+
+        for (hash, pointer) in &old.code {
+            let code = self.read(*pointer)?;
+            self.append(CODE, &[hash.as_slice(), &code].concat());
+            self.commit_full(block)?;
+        }
+        for (address, pointer) in &old.accounts {
+            let account = self.read(*pointer)?;
+            self.append(ACCOUNT, &[address.as_slice(), &account].concat());
+            self.commit_full(block)?;
+        }
+        for (address, slots) in &old.storage {
+            for (slot, pointer) in slots {
+                let value = self.read(*pointer)?;
+                // A zero slot reads the same as an absent one.
+                if value.iter().any(|b| *b != 0) {
+                    self.append(STORAGE, &[address.as_slice(), &slot.to_be_bytes::<32>(), &value].concat());
+                    self.commit_full(block)?;
+                }
+            }
+        }
+        self.commit(block)?;
+
+        let stale: Vec<u32> = self.segments.range(..first_new).map(|(id, _)| *id).collect();
+        for id in stale {
+            self.segments.remove(&id);
+            let path = segment_path(&self.dir, id);
+            fs::remove_file(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+
+    /// Commits the open batch once it would fill the active segment.
+    fn commit_full(&mut self, block: u64) -> Result<(), String> {
+        if self.writer.len + self.writer.pending.len() as u64 >= SEGMENT_BYTES {
+            self.commit(block)?;
+        }
+        Ok(())
+    }
+}
+
+// --- THE LOG ---
+
+pub struct FlatLog {
+    inner: RwLock<Inner>,
+}
+
+impl FlatLog {
+    /// Opens (or creates) the log in `dir` and rebuilds the index by replaying every committed
+    /// batch. A batch with no `COMMIT` marker (interrupted write) is dropped, and cut off if it
+    /// ends the log.
+    pub fn open(dir: &Path) -> Result<Self, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        let mut ids: Vec<u32> = fs::read_dir(dir)
+            .map_err(|e| format!("{}: {}", dir.display(), e))?
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name().into_string().ok()?;
+                name.strip_suffix(".log")?.parse().ok()
+            })
+            .collect();
+        ids.sort_unstable();
+
+        let mut index = Index::default();
+        let mut segments = BTreeMap::new();
+        let (mut total_bytes, mut dead_bytes, mut last_len, mut block) = (0, 0, 0, 0);
+        for (i, &id) in ids.iter().enumerate() {
+            let path = segment_path(dir, id);
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            let data = fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let last = i + 1 == ids.len();
+            // Records since the segment's last marker, indexed once the next one turns up.
+            let mut batch = Vec::new();
+            let apply = |index: &mut Index, batch: &mut Vec<usize>| -> Result<u64, String> {
+                let mut dead = 0;
+                for start in batch.drain(..) {
+                    let (kind, payload) = decode_record(&data[start..]).expect("decoded before");
+                    dead += index
+                        .apply(kind, payload, id, (start + HEADER) as u64)
+                        .map_err(|e| format!("{}: {}", path.display(), e))?;
+                }
+                Ok(dead)
+            };
+            let mut pos = 0;
+            while pos < data.len() {
+                match decode_record(&data[pos..]) {
+                    Some((kind, payload)) => {
+                        batch.push(pos);
+                        if kind == COMMIT && payload.len() == 8 {
+                            dead_bytes += apply(&mut index, &mut batch)?;
+                            block = u64::from_le_bytes(payload.try_into().expect("checked length"));
+                        }
+                        pos += HEADER + payload.len() + TRAILER;
+                    }
+                    None if last => {
+                        println!("[FLUX] {}: dropping torn record at offset {}", path.display(), pos);
+                        file.set_len(pos as u64).map_err(|e| format!("{}: {}", path.display(), e))?;
+                        break;
+                    }
+                    None => return Err(format!("{}: corrupt record at offset {}", path.display(), pos)),
+                }
+            }
+            if let Some(&start) = batch.first() {
+                println!(
+                    "[FLUX] {}: dropping uncommitted batch of {} records at offset {}",
+                    path.display(),
+                    batch.len(),
+                    start
+                );
+                if last {
+                    file.set_len(start as u64).map_err(|e| format!("{}: {}", path.display(), e))?;
+                    pos = start;
+                } else {
+                    dead_bytes += (pos - start) as u64;
+                }
+            }
+            total_bytes += pos as u64;
+            last_len = pos as u64;
+            segments.insert(id, file);
+        }
+
+        let writer = match ids.last() {
+            Some(&id) => SegmentWriter {
+                id,
+                file: reopen(dir, id, &segments[&id])?,
+                len: last_len,
+                pending: Vec::new(),
+            },
+            None => {
+                let file = create_segment(dir, 0)?;
+                segments.insert(0, reopen(dir, 0, &file)?);
+                SegmentWriter {
+                    id: 0,
+                    file,
+                    len: 0,
+                    pending: Vec::new(),
+                }
+            }
+        };
+
+        Ok(Self {
+            inner: RwLock::new(Inner {
+                dir: dir.to_path_buf(),
+                segments,
+                writer,
+                index,
+                total_bytes,
+                dead_bytes,
+                block,
+            }),
+        })
+    }
+
+    /// Writes one block's committed changes as a single batch and syncs it: after a crash the
+    /// log holds all of them or none. Compacts afterwards if the log has become mostly dead
+    /// records.
+    pub fn commit_batch(&self, block: u64, changes: &[StateChange]) -> Result<(), String> {
+        let mut inner = self.inner.write();
+        for change in changes {
+            let address = change.address.as_slice();
+            if change.storage_cleared {
+                inner.append(STORAGE_WIPE, address);
+            }
+            match &change.info {
+                None => inner.append(ACCOUNT_DELETED, address),
+                Some(info) => {
+                    if let Some(code) = &info.code {
+                        if info.code_hash != KECCAK_EMPTY && !inner.index.code.contains_key(&info.code_hash) {
+                            inner.append(CODE, &[info.code_hash.as_slice(), &code.original_bytes()].concat());
+                        }
+                    }
+                    inner.append(ACCOUNT, &[address, &encode_account(info)].concat());
+                }
+            }
+            for (slot, value) in &change.storage {
+                let record = [address, &slot.to_be_bytes::<32>(), &value.to_be_bytes::<32>()].concat();
+                inner.append(STORAGE, &record);
+            }
+        }
+        inner.commit(block)?;
+        inner.maybe_compact()
+    }
+
+    /// Loads a snapshot in batches, each committed as block 0.
+    pub fn import(
+        &self,
+        accounts: impl Iterator<Item = Result<SnapshotAccount, String>>,
+    ) -> Result<ImportStats, String> {
+        let mut stats = ImportStats::default();
+        let mut batch = Vec::with_capacity(IMPORT_BATCH);
+        for account in accounts {
+            let account = account?;
+            stats.accounts += 1;
+            stats.contracts += account.info.code.is_some() as usize;
+            stats.slots += account.storage.len();
+            batch.push(StateChange {
+                address: account.address,
+                info: Some(account.info),
+                storage_cleared: false,
+                storage: account.storage,
+            });
+            if batch.len() == IMPORT_BATCH {
+                self.commit_batch(0, &batch)?;
+                batch.clear();
+            }
+        }
+        self.commit_batch(0, &batch)?;
+        Ok(stats)
+    }
+
+    /// Every live account and non-zero slot, without code.
+    pub fn dump(&self) -> Result<Vec<SnapshotAccount>, String> {
+        let inner = self.inner.read();
+        let mut accounts = Vec::with_capacity(inner.index.accounts.len());
+        for (address, pointer) in &inner.index.accounts {
+            let info = decode_account(&inner.read(*pointer)?)
+                .ok_or_else(|| format!("corrupt account record for {}", address))?;
+            let mut storage = Vec::new();
+            for (slot, pointer) in inner.index.storage.get(address).into_iter().flatten() {
+                let value = U256::from_be_slice(&inner.read(*pointer)?);
+                if value != U256::ZERO {
+                    storage.push((*slot, value));
+                }
+            }
+            accounts.push(SnapshotAccount {
+                address: *address,
+                info,
+                storage,
+            });
+        }
+        Ok(accounts)
+    }
+
+    pub fn stats(&self) -> FlatLogStats {
+        let inner = self.inner.read();
+        FlatLogStats {
+            segments: inner.segments.len(),
+            keys: inner.index.keys(),
+            total_bytes: inner.total_bytes,
+            dead_bytes: inner.dead_bytes,
+        }
+    }
+}
+
+impl DatabaseRef for FlatLog {
+    type Error = String;
+
+    fn basic(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        let inner = self.inner.read();
+        let Some(pointer) = inner.index.accounts.get(&address) else {
+            return Ok(None);
+        };
+        decode_account(&inner.read(*pointer)?)
+            .map(Some)
+            .ok_or_else(|| format!("corrupt account record for {}", address))
+    }
+
+    fn code_by_hash(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        if code_hash == KECCAK_EMPTY {
+            return Ok(Bytecode::new());
+        }
+        let inner = self.inner.read();
+        let pointer = inner
+            .index
+            .code
+            .get(&code_hash)
+            .ok_or_else(|| format!("code {} missing from state", code_hash))?;
+        Ok(Bytecode::new_raw(Bytes::from(inner.read(*pointer)?)))
+    }
+
+    fn storage(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        let inner = self.inner.read();
+        match inner.index.storage.get(&address).and_then(|slots| slots.get(&index)) {
+            Some(pointer) => Ok(U256::from_be_slice(&inner.read(*pointer)?)),
+            None => Ok(U256::ZERO),
+        }
+    }
+
+    fn block_hash(&self, number: U256) -> Result<B256, Self::Error> {
+        // The log stores no header chain; same stand-in EmptyDB uses.
+        Ok(keccak256(number.to_string().as_bytes()))
+    }
+}
+
+fn encode_record(buf: &mut Vec<u8>, kind: u8, payload: &[u8]) {
+    buf.push(kind);
+    buf.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    buf.extend_from_slice(payload);
+    buf.extend_from_slice(&checksum(kind, payload).to_le_bytes());
+}
+
+/// `None` for a truncated record or a checksum mismatch.
+fn decode_record(buf: &[u8]) -> Option<(u8, &[u8])> {
+    let len = u32::from_le_bytes(buf.get(1..HEADER)?.try_into().ok()?) as usize;
+    let payload = buf.get(HEADER..HEADER + len)?;
+    let stored = u32::from_le_bytes(buf.get(HEADER + len..HEADER + len + TRAILER)?.try_into().ok()?);
+    (stored == checksum(buf[0], payload)).then_some((buf[0], payload))
+}
+
+fn checksum(kind: u8, payload: &[u8]) -> u32 {
+    std::iter::once(&kind)
+        .chain(payload)
+        .fold(0x811C_9DC5, |hash, byte| (hash ^ *byte as u32).wrapping_mul(0x0100_0193))
+}
+
+fn segment_path(dir: &Path, id: u32) -> PathBuf {
+    dir.join(format!("{:08}.log", id))
+}
+
+fn create_segment(dir: &Path, id: u32) -> Result<File, String> {
+    let path = segment_path(dir, id);
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn reopen(dir: &Path, id: u32, file: &File) -> Result<File, String> {
+    file.try_clone()
+        .map_err(|e| format!("{}: {}", segment_path(dir, id).display(), e))
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone, Default)]
+pub struct FlatLogStats {
+    pub segments: usize,
+    pub keys: usize,
+    pub total_bytes: u64,
+    pub dead_bytes: u64,
+}
+
+impl fmt::Display for FlatLogStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Flat Log:           {} segments, {} keys, {} bytes ({:.1}% dead)",
+            self.segments,
+            self.keys,
+            self.total_bytes,
+            self.dead_bytes as f64 / self.total_bytes.max(1) as f64 * 100.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("flux-flatlog-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn change(byte: u8, balance: u64, storage: Vec<(U256, U256)>) -> StateChange {
+        StateChange {
+            address: Address::repeat_byte(byte),
+            info: Some(AccountInfo {
+                balance: U256::from(balance),
+                ..Default::default()
+            }),
+            storage_cleared: false,
+            storage,
+        }
+    }
+
+    fn balance(log: &FlatLog, byte: u8) -> Option<U256> {
+        log.basic(Address::repeat_byte(byte)).unwrap().map(|info| info.balance)
+    }
+
+    fn active_segment(dir: &Path) -> PathBuf {
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        paths.sort();
+        paths.pop().unwrap()
+    }
+
+    fn append_to(path: &Path, bytes: &[u8]) -> u64 {
+        let file = OpenOptions::new().append(true).open(path).unwrap();
+        let len = file.metadata().unwrap().len();
+        std::io::Write::write_all(&mut &file, bytes).unwrap();
+        len
+    }
+
+    #[test]
+    fn drops_a_torn_record() {
+        let dir = scratch("torn");
+        let log = FlatLog::open(&dir).unwrap();
+        log.commit_batch(1, &[change(1, 10, vec![(U256::from(1), U256::from(7))])]).unwrap();
+        drop(log);
+
+        let mut record = Vec::new();
+        encode_record(&mut record, ACCOUNT, &[[2u8; 20].as_slice(), &[0u8; ACCOUNT_BYTES]].concat());
+        let path = active_segment(&dir);
+        let committed = append_to(&path, &record[..record.len() - 3]);
+
+        let log = FlatLog::open(&dir).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), committed);
+        assert_eq!(log.inner.read().block, 1);
+        assert_eq!(balance(&log, 1), Some(U256::from(10)));
+        assert_eq!(log.storage(Address::repeat_byte(1), U256::from(1)).unwrap(), U256::from(7));
+        assert_eq!(balance(&log, 2), None);
+
+        // The log carries on from the cut.
+        log.commit_batch(2, &[change(2, 20, Vec::new())]).unwrap();
+        drop(log);
+        let log = FlatLog::open(&dir).unwrap();
+        assert_eq!((balance(&log, 1), balance(&log, 2)), (Some(U256::from(10)), Some(U256::from(20))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn discards_an_unmarked_tail() {
+        let dir = scratch("unmarked");
+        let log = FlatLog::open(&dir).unwrap();
+        log.commit_batch(1, &[change(1, 10, Vec::new())]).unwrap();
+        drop(log);
+
+        // Whole records, but the batch's COMMIT marker never made it.
+        let mut batch = Vec::new();
+        let address = Address::repeat_byte(1);
+        let info = AccountInfo {
+            balance: U256::from(99),
+            ..Default::default()
+        };
+        encode_record(&mut batch, ACCOUNT, &[address.as_slice(), &encode_account(&info)].concat());
+        encode_record(&mut batch, STORAGE, &[address.as_slice(), &[0u8; 32], &[1u8; 32]].concat());
+        let path = active_segment(&dir);
+        let committed = append_to(&path, &batch);
+
+        let log = FlatLog::open(&dir).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), committed);
+        assert_eq!(log.inner.read().block, 1);
+        assert_eq!(balance(&log, 1), Some(U256::from(10)));
+        assert_eq!(log.storage(address, U256::ZERO).unwrap(), U256::ZERO);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compaction_keeps_live_values() {
+        let dir = scratch("compact");
+        let log = FlatLog::open(&dir).unwrap();
+        let code = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x00, 0x56]));
+        let contract = StateChange {
+            address: Address::repeat_byte(9),
+            info: Some(AccountInfo::new(U256::ZERO, 1, code.hash_slow(), code.clone())),
+            storage_cleared: false,
+            storage: vec![(U256::from(1), U256::from(1))],
+        };
+        log.commit_batch(1, &[contract]).unwrap();
+        for block in 2..20u64 {
+            let slots = vec![(U256::from(1), U256::from(block)), (U256::from(2), U256::from(block % 2))];
+            log.commit_batch(block, &[change(1, block, slots), change(2, 5, Vec::new())]).unwrap();
+        }
+        let mut wiped = change(3, 1, Vec::new());
+        wiped.info = None;
+        log.commit_batch(20, &[change(3, 1, vec![(U256::from(1), U256::from(1))]), wiped]).unwrap();
+
+        let read = |log: &FlatLog| {
+            let mut dump = log.dump().unwrap();
+            dump.sort_by_key(|account| account.address);
+            dump.iter_mut().for_each(|account| account.storage.sort());
+            let code = log.code_by_hash(code.hash_slow()).unwrap().original_bytes();
+            let dump: Vec<_> = dump.into_iter().map(|a| (a.address, a.info.balance, a.info.nonce, a.storage)).collect();
+            (dump, code)
+        };
+        let before = read(&log);
+        let dead = log.stats().dead_bytes;
+        assert!(dead > 0);
+
+        log.inner.write().compact().unwrap();
+        let stats = log.stats();
+        // All that is dead is the one COMMIT marker closing the rewrite.
+        assert_eq!((stats.segments, stats.dead_bytes), (1, (HEADER + 8 + TRAILER) as u64));
+        assert!(stats.total_bytes < dead);
+        assert_eq!(read(&log), before);
+        assert_eq!(log.inner.read().block, 20);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        drop(log);
+        let log = FlatLog::open(&dir).unwrap();
+        assert_eq!(read(&log), before);
+        assert_eq!(log.inner.read().block, 20);
+        assert_eq!(log.storage(Address::repeat_byte(1), U256::from(1)).unwrap(), U256::from(19));
+        assert_eq!(balance(&log, 3), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,373 @@
+/*
+ * FLUX ENGINE - STATE HISTORY
+ * Block-versioned state retained next to the shard caches, and the policy for how much of it
+ * (and of the caches' dead entries) survives each block.
+ *
+ * History is kept as changesets: for every block, the value each account and slot had before
+ * the block wrote it. Walking them back from the latest state reconstructs any retained height.
+ */
+
+use crate::mvcc::Location;
+use revm::primitives::{AccountInfo, Address, HashMap, KECCAK_EMPTY, U256};
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::mem::size_of;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PruneMode {
+    /// Only the latest state: no changesets, and deleted accounts / zeroed slots are dropped
+    /// from memory once the backend would read them back as absent anyway.
+    #[default]
+    KeepLatest,
+    /// Changesets for the last N blocks, dead entries dropped as with `KeepLatest`.
+    KeepBlocks(u64),
+    /// Every changeset and every cached entry, forever.
+    Archive,
+}
+
+impl PruneMode {
+    /// Whether the committer records changesets at all.
+    pub fn records_history(&self) -> bool {
+        !matches!(self, PruneMode::KeepLatest)
+    }
+
+    pub fn prunes_dead_entries(&self) -> bool {
+        !matches!(self, PruneMode::Archive)
+    }
+}
+
+impl FromStr for PruneMode {
+    type Err = String;
+
+    /// `keep-latest`, `keep-last:<N>` or `archive`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unknown = || format!("unknown prune mode {:?} (keep-latest|keep-last:<N>|archive)", s);
+        match s {
+            "keep-latest" => Ok(PruneMode::KeepLatest),
+            "archive" => Ok(PruneMode::Archive),
+            other => {
+                let blocks: u64 = other
+                    .strip_prefix("keep-last:")
+                    .and_then(|n| n.parse().ok())
+                    .ok_or_else(unknown)?;
+                if blocks == 0 {
+                    return Err("keep-last needs at least 1 block (keep-latest keeps none)".into());
+                }
+                Ok(PruneMode::KeepBlocks(blocks))
+            }
+        }
+    }
+}
+
+impl fmt::Display for PruneMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PruneMode::KeepLatest => f.write_str("keep-latest"),
+            PruneMode::KeepBlocks(n) => write!(f, "keep-last:{}", n),
+            PruneMode::Archive => f.write_str("archive"),
+        }
+    }
+}
+
+// --- CHANGESETS ---
+
+/// What one block overwrote. `accounts` maps to `None` where the account did not exist yet.
+/// Code is not kept; it stays in the contract cache under its hash.
+#[derive(Debug, Clone, Default)]
+pub struct Changeset {
+    pub accounts: HashMap<Address, Option<AccountInfo>>,
+    pub storage: HashMap<(Address, U256), U256>,
+    /// Accounts whose whole storage was wiped (self-destruct or re-creation).
+    pub wiped: HashSet<Address>,
+}
+
+impl Changeset {
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty() && self.storage.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len() + self.storage.len()
+    }
+
+    /// Approximate heap footprint, for the reclaimed-bytes metric.
+    pub fn bytes(&self) -> u64 {
+        (self.accounts.len() * size_of::<(Address, Option<AccountInfo>)>()
+            + self.storage.len() * size_of::<((Address, U256), U256)>()) as u64
+    }
+
+    /// Folds a later part of the same block in; the value from before the block wins.
+    pub fn merge(&mut self, other: Changeset) {
+        for (address, info) in other.accounts {
+            self.accounts.entry(address).or_insert(info);
+        }
+        for (key, value) in other.storage {
+            self.storage.entry(key).or_insert(value);
+        }
+        self.wiped.extend(other.wiped);
+    }
+}
+
+/// An account or slot as of some height. Accounts carry no code, as in changesets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HistoricalValue {
+    Account(Option<AccountInfo>),
+    Storage(U256),
+}
+
+#[derive(Debug, Default)]
+pub struct StateHistory {
+    mode: PruneMode,
+    /// Oldest first, one entry per executed block.
+    blocks: VecDeque<(u64, Changeset)>,
+    counters: PruneCounters,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct PruneCounters {
+    changesets_pruned: u64,
+    entries_pruned: u64,
+    dead_entries_pruned: u64,
+    bytes_reclaimed: u64,
+}
+
+impl StateHistory {
+    pub fn new(mode: PruneMode) -> Self {
+        Self {
+            mode,
+            ..Self::default()
+        }
+    }
+
+    pub fn mode(&self) -> PruneMode {
+        self.mode
+    }
+
+    /// Retains `block`'s changeset, then drops whatever the mode no longer covers.
+    pub fn push(&mut self, block: u64, changeset: Changeset) {
+        if !self.mode.records_history() {
+            return;
+        }
+        self.blocks.push_back((block, changeset));
+        if let PruneMode::KeepBlocks(keep) = self.mode {
+            while self.blocks.len() as u64 > keep {
+                let (_, pruned) = self.blocks.pop_front().expect("more blocks than the limit");
+                self.counters.changesets_pruned += 1;
+                self.counters.entries_pruned += pruned.len() as u64;
+                self.counters.bytes_reclaimed += pruned.bytes();
+            }
+        }
+    }
+
+    /// What `location` held once `block` was executed, if a later retained block overwrote it.
+    /// `None` means nothing has written it since, so the latest state has the answer. Heights
+    /// from just before the oldest retained block up to the latest one can be queried.
+    pub fn lookup(&self, block: u64, location: Location) -> Result<Option<HistoricalValue>, String> {
+        let (Some((oldest, _)), Some((latest, _))) = (self.blocks.front(), self.blocks.back()) else {
+            return Err(format!("no state history retained (prune mode {})", self.mode));
+        };
+        if block > *latest {
+            return Err(format!("block {} has not been executed (latest is {})", block, latest));
+        }
+        if block + 1 < *oldest {
+            return Err(format!("block {} is pruned (history starts after block {})", block, oldest - 1));
+        }
+        // The first block after `block` to write the location recorded what it held before.
+        for (number, changeset) in self.blocks.iter().filter(|(number, _)| *number > block) {
+            match location {
+                Location::Account(address) => {
+                    if let Some(info) = changeset.accounts.get(&address) {
+                        return Ok(Some(HistoricalValue::Account(info.clone())));
+                    }
+                }
+                Location::Storage(address, slot) => {
+                    if let Some(value) = changeset.storage.get(&(address, slot)) {
+                        return Ok(Some(HistoricalValue::Storage(*value)));
+                    }
+                    if changeset.wiped.contains(&address) {
+                        return Err(format!(
+                            "slot {} of {} was wiped by block {} before it was read; its earlier value was not kept",
+                            slot, address, number
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Drops the changesets of every block after `block` and returns what those blocks
+    /// overwrote, merged: wherever the latest state differs from the state once `block` was
+    /// executed, the value it had then. Nothing is dropped if any of them cannot be undone.
+    pub fn unwind(&mut self, block: u64) -> Result<Changeset, String> {
+        let (Some((oldest, _)), Some((latest, _))) = (self.blocks.front(), self.blocks.back()) else {
+            return Err(format!("no state history retained (prune mode {})", self.mode));
+        };
+        if block > *latest {
+            return Err(format!("block {} has not been executed (latest is {})", block, latest));
+        }
+        if block + 1 < *oldest {
+            return Err(format!("block {} is pruned (history starts after block {})", block, oldest - 1));
+        }
+        let keep = self.blocks.iter().take_while(|(number, _)| *number <= block).count();
+        let mut reverted = Changeset::default();
+        // Oldest first, so the value from before the earliest unwound block wins.
+        for (_, changeset) in self.blocks.range(keep..) {
+            reverted.merge(changeset.clone());
+        }
+        // A wipe only records the slots the caches held. A contract created within the unwound
+        // blocks goes away whole; one from before them may have had others.
+        for address in &reverted.wiped {
+            if let Some(Some(info)) = reverted.accounts.get(address) {
+                if info.code_hash != KECCAK_EMPTY {
+                    return Err(format!(
+                        "the storage of contract {} was wiped after block {}; its earlier storage was not kept",
+                        address, block
+                    ));
+                }
+            }
+        }
+        self.blocks.truncate(keep);
+        Ok(reverted)
+    }
+
+    /// Accounts for dead cache entries the shards dropped under this mode.
+    pub fn record_dead_pruned(&mut self, entries: u64, bytes: u64) {
+        self.counters.dead_entries_pruned += entries;
+        self.counters.bytes_reclaimed += bytes;
+    }
+
+    pub fn stats(&self) -> PruneStats {
+        PruneStats {
+            mode: self.mode,
+            retained_blocks: self.blocks.len(),
+            retained_entries: self.blocks.iter().map(|(_, changeset)| changeset.len() as u64).sum(),
+            retained_bytes: self.blocks.iter().map(|(_, changeset)| changeset.bytes()).sum(),
+            changesets_pruned: self.counters.changesets_pruned,
+            entries_pruned: self.counters.entries_pruned,
+            dead_entries_pruned: self.counters.dead_entries_pruned,
+            bytes_reclaimed: self.counters.bytes_reclaimed,
+        }
+    }
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone)]
+pub struct PruneStats {
+    pub mode: PruneMode,
+    pub retained_blocks: usize,
+    pub retained_entries: u64,
+    pub retained_bytes: u64,
+    pub changesets_pruned: u64,
+    /// Changeset entries dropped with their blocks.
+    pub entries_pruned: u64,
+    /// Deleted accounts and zeroed slots dropped from the shard caches.
+    pub dead_entries_pruned: u64,
+    pub bytes_reclaimed: u64,
+}
+
+impl fmt::Display for PruneStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "State Pruning:      {}, {} blocks of history retained ({} entries, {} bytes)",
+            self.mode, self.retained_blocks, self.retained_entries, self.retained_bytes
+        )?;
+        writeln!(
+            f,
+            "                    {} changesets pruned ({} entries), {} dead entries pruned, {} bytes reclaimed",
+            self.changesets_pruned, self.entries_pruned, self.dead_entries_pruned, self.bytes_reclaimed
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use revm::primitives::B256;
+
+    fn account(balance: u64, code_hash: B256) -> Option<AccountInfo> {
+        Some(AccountInfo {
+            balance: U256::from(balance),
+            code_hash,
+            ..Default::default()
+        })
+    }
+
+    fn changeset(accounts: &[(u8, Option<AccountInfo>)], storage: &[(u8, u64, u64)], wiped: &[u8]) -> Changeset {
+        Changeset {
+            accounts: accounts.iter().map(|(byte, info)| (Address::repeat_byte(*byte), info.clone())).collect(),
+            storage: storage
+                .iter()
+                .map(|&(byte, slot, value)| ((Address::repeat_byte(byte), U256::from(slot)), U256::from(value)))
+                .collect(),
+            wiped: wiped.iter().map(|byte| Address::repeat_byte(*byte)).collect(),
+        }
+    }
+
+    #[test]
+    fn unwind_returns_the_earliest_overwritten_values() {
+        let mut history = StateHistory::new(PruneMode::Archive);
+        history.push(1, changeset(&[(1, None)], &[(1, 0, 0)], &[]));
+        history.push(2, changeset(&[(1, account(10, KECCAK_EMPTY)), (2, None)], &[(1, 0, 5), (2, 0, 0)], &[]));
+        history.push(3, changeset(&[(1, account(20, KECCAK_EMPTY))], &[(1, 0, 6), (1, 1, 0)], &[]));
+
+        let reverted = history.unwind(1).unwrap();
+        assert_eq!(reverted.accounts[&Address::repeat_byte(1)], account(10, KECCAK_EMPTY));
+        assert_eq!(reverted.accounts[&Address::repeat_byte(2)], None);
+        assert_eq!(reverted.storage[&(Address::repeat_byte(1), U256::ZERO)], U256::from(5));
+        assert_eq!(reverted.len(), 5);
+        assert_eq!(history.stats().retained_blocks, 1);
+
+        // Unwinding to the latest block is a no-op.
+        assert!(history.unwind(1).unwrap().is_empty());
+        assert_eq!(history.unwind(2).unwrap_err(), "block 2 has not been executed (latest is 1)");
+    }
+
+    #[test]
+    fn unwind_refuses_what_it_cannot_undo() {
+        assert_eq!(
+            StateHistory::new(PruneMode::KeepLatest).unwind(0).unwrap_err(),
+            "no state history retained (prune mode keep-latest)"
+        );
+
+        let mut history = StateHistory::new(PruneMode::KeepBlocks(2));
+        (1..=4).for_each(|block| history.push(block, Changeset::default()));
+        assert_eq!(history.unwind(1).unwrap_err(), "block 1 is pruned (history starts after block 2)");
+        assert!(history.unwind(2).is_ok());
+    }
+
+    #[test]
+    fn unwind_over_a_wiped_contract_needs_it_created_within() {
+        let contract = Address::repeat_byte(7);
+        let code_hash = B256::repeat_byte(0xcc);
+
+        // Created in block 2, wiped in block 3: unwinding to 1 drops it whole.
+        let mut history = StateHistory::new(PruneMode::Archive);
+        history.push(1, Changeset::default());
+        history.push(2, changeset(&[(7, None)], &[(7, 0, 0)], &[]));
+        history.push(3, changeset(&[(7, account(0, code_hash))], &[(7, 0, 9)], &[7]));
+        let reverted = history.unwind(1).unwrap();
+        assert_eq!(reverted.accounts[&contract], None);
+        assert!(reverted.wiped.contains(&contract));
+
+        // Already there at block 1: its unread slots are gone, so nothing is unwound.
+        let mut history = StateHistory::new(PruneMode::Archive);
+        history.push(1, Changeset::default());
+        history.push(2, changeset(&[(7, account(0, code_hash))], &[(7, 0, 9)], &[7]));
+        assert_eq!(
+            history.unwind(1).unwrap_err(),
+            format!(
+                "the storage of contract {} was wiped after block 1; its earlier storage was not kept",
+                contract
+            )
+        );
+        assert_eq!(history.stats().retained_blocks, 2);
+
+        // A wiped account without code had no storage to lose.
+        history.push(3, changeset(&[(8, account(1, KECCAK_EMPTY))], &[], &[8]));
+        assert!(history.unwind(2).is_ok());
+    }
+}
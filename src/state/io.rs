@@ -0,0 +1,198 @@
+/*
+ * FLUX ENGINE - I/O THREADS
+ * Dedicated I/O threads for backend reads (`--features async-io`, Linux only). An executor that
+ * misses the shard caches hands the read to the pool instead of blocking in the syscall (or the
+ * simulated device wait) itself. While the read is in flight the executor runs other queued
+ * rayon work, such as the block's remaining transactions, and it takes the value back when the
+ * read completes.
+ */
+
+use parking_lot::{Condvar, Mutex};
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Reads one executor thread may have in flight through nested hand-offs. Every level is
+/// another transaction's stack on top of the waiting one, so this bounds stack growth.
+const MAX_NESTED: usize = 4;
+
+thread_local! {
+    static NESTED: Cell<usize> = const { Cell::new(0) };
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct Queue {
+    /// Pending jobs, and whether the pool is shutting down.
+    jobs: Mutex<(VecDeque<Job>, bool)>,
+    ready: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct IoCounters {
+    reads: AtomicU64,
+    queued_nanos: AtomicU64,
+    /// Rayon jobs executors ran while one of their reads was in flight.
+    handoffs: AtomicU64,
+    /// Reads an executor blocked on because it had nothing else to run.
+    blocked: AtomicU64,
+}
+
+/// Where an I/O thread leaves a finished read (or its panic) for the executor.
+struct Completion<T> {
+    value: Mutex<Option<Result<T, Box<dyn Any + Send>>>>,
+    done: Condvar,
+}
+
+pub struct IoPool {
+    queue: Arc<Queue>,
+    counters: Arc<IoCounters>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl IoPool {
+    pub fn new(threads: usize) -> Self {
+        let queue = Arc::new(Queue::default());
+        let threads = (0..threads.max(1))
+            .map(|i| {
+                let queue = queue.clone();
+                thread::Builder::new()
+                    .name(format!("flux-io-{}", i))
+                    .spawn(move || serve(&queue))
+                    .expect("failed to spawn I/O thread")
+            })
+            .collect();
+        Self {
+            queue,
+            counters: Arc::new(IoCounters::default()),
+            threads,
+        }
+    }
+
+    /// Runs `read` on an I/O thread and returns its result. The caller is never idle while it
+    /// waits if its rayon pool has other work queued; a panic in `read` resumes on the caller.
+    pub fn read<T: Send + 'static>(&self, read: impl FnOnce() -> T + Send + 'static) -> T {
+        let completion = Arc::new(Completion {
+            value: Mutex::new(None),
+            done: Condvar::new(),
+        });
+        let filled = completion.clone();
+        let counters = self.counters.clone();
+        let submitted = Instant::now();
+        self.submit(Box::new(move || {
+            counters
+                .queued_nanos
+                .fetch_add(submitted.elapsed().as_nanos() as u64, Ordering::Relaxed);
+            let value = panic::catch_unwind(AssertUnwindSafe(read));
+            *filled.value.lock() = Some(value);
+            filled.done.notify_one();
+        }));
+        self.counters.reads.fetch_add(1, Ordering::Relaxed);
+
+        // Hand the thread back to rayon until the read lands or there is nothing left to run.
+        let depth = NESTED.with(Cell::get);
+        if depth < MAX_NESTED {
+            NESTED.with(|nested| nested.set(depth + 1));
+            while completion.value.lock().is_none() {
+                match rayon::yield_now() {
+                    Some(rayon::Yield::Executed) => self.counters.handoffs.fetch_add(1, Ordering::Relaxed),
+                    _ => break,
+                };
+            }
+            NESTED.with(|nested| nested.set(depth));
+        }
+
+        let mut value = completion.value.lock();
+        if value.is_none() {
+            self.counters.blocked.fetch_add(1, Ordering::Relaxed);
+        }
+        while value.is_none() {
+            completion.done.wait(&mut value);
+        }
+        match value.take().expect("checked above") {
+            Ok(value) => value,
+            Err(panic) => panic::resume_unwind(panic),
+        }
+    }
+
+    pub fn stats(&self) -> IoStats {
+        IoStats {
+            threads: self.threads.len(),
+            reads: self.counters.reads.load(Ordering::Relaxed),
+            queued: Duration::from_nanos(self.counters.queued_nanos.load(Ordering::Relaxed)),
+            handoffs: self.counters.handoffs.load(Ordering::Relaxed),
+            blocked: self.counters.blocked.load(Ordering::Relaxed),
+        }
+    }
+
+    fn submit(&self, job: Job) {
+        self.queue.jobs.lock().0.push_back(job);
+        self.queue.ready.notify_one();
+    }
+}
+
+fn serve(queue: &Queue) {
+    loop {
+        let mut jobs = queue.jobs.lock();
+        let job = loop {
+            if let Some(job) = jobs.0.pop_front() {
+                break job;
+            }
+            if jobs.1 {
+                return;
+            }
+            queue.ready.wait(&mut jobs);
+        };
+        drop(jobs);
+        job();
+    }
+}
+
+impl Drop for IoPool {
+    fn drop(&mut self) {
+        self.queue.jobs.lock().1 = true;
+        self.queue.ready.notify_all();
+        for handle in self.threads.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl fmt::Debug for IoPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IoPool")
+            .field("threads", &self.threads.len())
+            .field("counters", &self.counters)
+            .finish()
+    }
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone)]
+pub struct IoStats {
+    pub threads: usize,
+    pub reads: u64,
+    /// Total time reads sat in the queue before an I/O thread picked them up.
+    pub queued: Duration,
+    pub handoffs: u64,
+    pub blocked: u64,
+}
+
+impl fmt::Display for IoStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mean = Duration::from_nanos((self.queued.as_nanos() / self.reads.max(1) as u128) as u64);
+        writeln!(
+            f,
+            "Async I/O:          {} threads, {} reads (mean queue wait {:?}), {} jobs run while waiting, {} blocked",
+            self.threads, self.reads, mean, self.handoffs, self.blocked
+        )
+    }
+}
@@ -0,0 +1,231 @@
+/*
+ * FLUX ENGINE - SIMULATED DISK LATENCY
+ * Simulation-mode backend wrapper that charges every backend read a sampled latency, so we can
+ * predict pipeline behavior once state no longer fits in RAM without owning the hardware. With
+ * an I/O pool attached, reads (and their injected latency) run on its threads instead.
+ */
+
+use revm::db::DatabaseRef;
+use revm::primitives::{AccountInfo, Address, Bytecode, B256, U256};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(all(feature = "async-io", target_os = "linux"))]
+use super::io::{IoPool, IoStats};
+
+// --- LATENCY MODELS ---
+
+/// Per-read latency drawn from a log-normal distribution (median, sigma). Log-normal gives the
+/// long right tail real storage has, which is what actually hurts a pinned executor.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LatencyModel {
+    /// No injected latency; reads go straight through.
+    #[default]
+    None,
+    /// Local NVMe, 4K random read at low queue depth.
+    Nvme,
+    /// SATA SSD.
+    Sata,
+    /// Network-attached block storage (EBS/PD style).
+    Network,
+    Custom { median: Duration, sigma: f64 },
+}
+
+impl LatencyModel {
+    fn params(&self) -> Option<(Duration, f64)> {
+        match *self {
+            LatencyModel::None => None,
+            LatencyModel::Nvme => Some((Duration::from_micros(80), 0.35)),
+            LatencyModel::Sata => Some((Duration::from_micros(250), 0.5)),
+            LatencyModel::Network => Some((Duration::from_micros(900), 0.8)),
+            LatencyModel::Custom { median, sigma } => Some((median, sigma)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.params().is_some()
+    }
+}
+
+impl FromStr for LatencyModel {
+    type Err = String;
+
+    /// `none`, `nvme`, `sata`, `network`, or `<median_us>[:<sigma>]` for a custom distribution.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(LatencyModel::None),
+            "nvme" => Ok(LatencyModel::Nvme),
+            "sata" => Ok(LatencyModel::Sata),
+            "network" => Ok(LatencyModel::Network),
+            custom => {
+                let (median, sigma) = custom.split_once(':').unwrap_or((custom, "0.5"));
+                let median: u64 = median
+                    .parse()
+                    .map_err(|_| format!("unknown latency model {:?} (none|nvme|sata|network|<median_us>[:<sigma>])", s))?;
+                let sigma: f64 = sigma
+                    .parse()
+                    .ok()
+                    .filter(|sigma: &f64| *sigma >= 0.0)
+                    .ok_or_else(|| format!("invalid sigma in latency model {:?}", s))?;
+                Ok(LatencyModel::Custom { median: Duration::from_micros(median), sigma })
+            }
+        }
+    }
+}
+
+// --- THE WRAPPER ---
+
+#[derive(Debug, Default)]
+struct DiskCounters {
+    rng: AtomicU64,
+    reads: AtomicU64,
+    injected_nanos: AtomicU64,
+}
+
+/// Wraps any `DatabaseRef` and sleeps before each read. The store and counters are shared with
+/// the I/O threads, so reads handed off to them feed the same statistics.
+#[derive(Debug)]
+pub struct SimulatedDisk<DB> {
+    inner: Arc<DB>,
+    model: LatencyModel,
+    counters: Arc<DiskCounters>,
+    #[cfg(all(feature = "async-io", target_os = "linux"))]
+    io: Option<IoPool>,
+}
+
+impl<DB> SimulatedDisk<DB> {
+    pub fn new(inner: DB, model: LatencyModel) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            model,
+            counters: Arc::new(DiskCounters::default()),
+            #[cfg(all(feature = "async-io", target_os = "linux"))]
+            io: None,
+        }
+    }
+
+    /// Serves every read from `io`'s threads.
+    #[cfg(all(feature = "async-io", target_os = "linux"))]
+    pub fn with_io_pool(mut self, io: IoPool) -> Self {
+        self.io = Some(io);
+        self
+    }
+
+    /// `None` when reads run on the caller's thread.
+    #[cfg(all(feature = "async-io", target_os = "linux"))]
+    pub fn io_stats(&self) -> Option<IoStats> {
+        self.io.as_ref().map(IoPool::stats)
+    }
+
+    pub fn inner(&self) -> &DB {
+        &self.inner
+    }
+
+    pub fn stats(&self) -> DiskStats {
+        DiskStats {
+            model: self.model,
+            reads: self.counters.reads.load(Ordering::Relaxed),
+            injected: Duration::from_nanos(self.counters.injected_nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Charges the read and runs it, on an I/O thread when a pool is attached.
+    fn load<T>(&self, read: impl FnOnce(&DB) -> T + Send + 'static) -> T
+    where
+        DB: Send + Sync + 'static,
+        T: Send + 'static,
+    {
+        #[cfg(all(feature = "async-io", target_os = "linux"))]
+        if let Some(io) = &self.io {
+            let (inner, model, counters) = (self.inner.clone(), self.model, self.counters.clone());
+            return io.read(move || {
+                counters.charge_read(model);
+                read(&inner)
+            });
+        }
+        self.counters.charge_read(self.model);
+        read(&self.inner)
+    }
+}
+
+impl DiskCounters {
+    fn charge_read(&self, model: LatencyModel) {
+        let Some((median, sigma)) = model.params() else {
+            return;
+        };
+
+        // Box-Muller on two splitmix64 draws -> standard normal -> log-normal around the median.
+        let u1 = unit_f64(self.next_random()).max(f64::MIN_POSITIVE);
+        let u2 = unit_f64(self.next_random());
+        let z = (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos();
+        // Cap the tail at 50x median; beyond that we're modelling a failing disk, not a slow one.
+        let factor = (sigma * z).exp().min(50.0);
+        let delay = median.mul_f64(factor);
+
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.injected_nanos.fetch_add(delay.as_nanos() as u64, Ordering::Relaxed);
+        std::thread::sleep(delay);
+    }
+
+    fn next_random(&self) -> u64 {
+        // splitmix64: every fetch_add hands out a distinct state, so no lock is needed.
+        let mut z = self
+            .rng
+            .fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed)
+            .wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+fn unit_f64(bits: u64) -> f64 {
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+impl<DB> DatabaseRef for SimulatedDisk<DB>
+where
+    DB: DatabaseRef + Send + Sync + 'static,
+    DB::Error: Send + 'static,
+{
+    type Error = DB::Error;
+
+    fn basic(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        self.load(move |db| db.basic(address))
+    }
+
+    fn code_by_hash(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        self.load(move |db| db.code_by_hash(code_hash))
+    }
+
+    fn storage(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        self.load(move |db| db.storage(address, index))
+    }
+
+    fn block_hash(&self, number: U256) -> Result<B256, Self::Error> {
+        self.load(move |db| db.block_hash(number))
+    }
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone)]
+pub struct DiskStats {
+    pub model: LatencyModel,
+    pub reads: u64,
+    pub injected: Duration,
+}
+
+impl fmt::Display for DiskStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mean = Duration::from_nanos((self.injected.as_nanos() / self.reads.max(1) as u128) as u64);
+        writeln!(
+            f,
+            "Simulated Disk:     {:?}, {} backend reads, {:?} injected (mean {:?}/read)",
+            self.model, self.reads, self.injected, mean
+        )
+    }
+}
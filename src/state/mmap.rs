@@ -0,0 +1,448 @@
+/*
+ * FLUX ENGINE - MEMORY-MAPPED STATE
+ * Read-only state from a preprocessed, memory-mapped file. Nothing is loaded onto the heap: the
+ * kernel pages the tables in on first touch, and the block's prefetch hints turn those page
+ * faults into `MADV_WILLNEED` readahead issued before the executors get there.
+ *
+ * Layout: a header, then three sorted fixed-width tables (accounts, storage, code index), the
+ * code blob, and a fence per table holding every `FENCE_STRIDE`th key. Lookups search the small,
+ * always-hot fences first, so a miss touches at most a couple of table pages.
+ */
+
+use super::backend::{decode_account, encode_account, ACCOUNT_BYTES};
+use super::snapshot::{ImportStats, SnapshotAccount};
+use crate::mvcc::Location;
+use revm::db::DatabaseRef;
+use revm::primitives::{keccak256, AccountInfo, Address, Bytecode, Bytes, B256, KECCAK_EMPTY, U256};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// File name inside the state directory.
+pub const STATE_FILE: &str = "state.mmap";
+
+const MAGIC: &[u8; 8] = b"FLUXMAP1";
+const HEADER_BYTES: usize = 64;
+const FENCE_STRIDE: usize = 64;
+
+const ACCOUNT_KEY: usize = 20;
+const STORAGE_KEY: usize = 20 + 32;
+const CODE_KEY: usize = 32;
+const ACCOUNT_RECORD: usize = ACCOUNT_KEY + ACCOUNT_BYTES;
+const STORAGE_RECORD: usize = STORAGE_KEY + 32;
+/// Hash, then offset (8) and length (4) of the code in the blob.
+const CODE_RECORD: usize = CODE_KEY + 12;
+
+// --- LAYOUT ---
+
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    accounts: usize,
+    slots: usize,
+    codes: usize,
+    code_bytes: usize,
+}
+
+impl Layout {
+    fn accounts(&self) -> Table {
+        Table {
+            at: HEADER_BYTES,
+            count: self.accounts,
+            record: ACCOUNT_RECORD,
+            key: ACCOUNT_KEY,
+            fence_at: self.code_blob() + self.code_bytes,
+        }
+    }
+
+    fn storage(&self) -> Table {
+        let accounts = self.accounts();
+        Table {
+            at: accounts.end(),
+            count: self.slots,
+            record: STORAGE_RECORD,
+            key: STORAGE_KEY,
+            fence_at: accounts.fence_end(),
+        }
+    }
+
+    fn code(&self) -> Table {
+        let storage = self.storage();
+        Table {
+            at: storage.end(),
+            count: self.codes,
+            record: CODE_RECORD,
+            key: CODE_KEY,
+            fence_at: storage.fence_end(),
+        }
+    }
+
+    fn code_blob(&self) -> usize {
+        HEADER_BYTES + self.accounts * ACCOUNT_RECORD + self.slots * STORAGE_RECORD + self.codes * CODE_RECORD
+    }
+
+    fn file_len(&self) -> usize {
+        self.code().fence_end()
+    }
+
+    fn encode(&self) -> [u8; HEADER_BYTES] {
+        let mut header = [0u8; HEADER_BYTES];
+        header[..8].copy_from_slice(MAGIC);
+        for (i, n) in [self.accounts, self.slots, self.codes, self.code_bytes].into_iter().enumerate() {
+            header[8 + i * 8..16 + i * 8].copy_from_slice(&(n as u64).to_le_bytes());
+        }
+        header
+    }
+
+    fn decode(header: &[u8]) -> Option<Self> {
+        if header.len() < HEADER_BYTES || &header[..8] != MAGIC {
+            return None;
+        }
+        let field = |i: usize| u64::from_le_bytes(header[8 + i * 8..16 + i * 8].try_into().unwrap()) as usize;
+        Some(Self {
+            accounts: field(0),
+            slots: field(1),
+            codes: field(2),
+            code_bytes: field(3),
+        })
+    }
+}
+
+/// A sorted table of fixed-width records, keyed by their first `key` bytes.
+#[derive(Debug, Clone, Copy)]
+struct Table {
+    at: usize,
+    count: usize,
+    record: usize,
+    key: usize,
+    fence_at: usize,
+}
+
+impl Table {
+    fn end(&self) -> usize {
+        self.at + self.count * self.record
+    }
+
+    fn fence_end(&self) -> usize {
+        self.fence_at + self.count.div_ceil(FENCE_STRIDE) * self.key
+    }
+
+    /// Records that can hold `key`, found from the fence alone (no table page is touched).
+    fn window(&self, data: &[u8], key: &[u8]) -> Range<usize> {
+        let fence = |i: usize| &data[self.fence_at + i * self.key..][..self.key];
+        let after = partition_point(self.count.div_ceil(FENCE_STRIDE), |i| fence(i) <= key);
+        if after == 0 {
+            return 0..0;
+        }
+        let start = (after - 1) * FENCE_STRIDE;
+        start..(start + FENCE_STRIDE).min(self.count)
+    }
+
+    /// The value bytes (everything after the key) of the record for `key`.
+    fn find<'a>(&self, data: &'a [u8], key: &[u8]) -> Option<&'a [u8]> {
+        let window = self.window(data, key);
+        let record = |i: usize| &data[self.at + i * self.record..][..self.record];
+        let i = window.start + partition_point(window.len(), |i| &record(window.start + i)[..self.key] < key);
+        (i < window.end && &record(i)[..self.key] == key).then(|| &record(i)[self.key..])
+    }
+
+    fn byte_range(&self, records: Range<usize>) -> Range<usize> {
+        self.at + records.start * self.record..self.at + records.end * self.record
+    }
+}
+
+/// First index in `0..len` for which `pred` is false; `pred` must be true-then-false.
+fn partition_point(len: usize, pred: impl Fn(usize) -> bool) -> usize {
+    let (mut lo, mut hi) = (0, len);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if pred(mid) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+// --- MAPPING ---
+
+struct Mapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// The mapping is read-only for its whole lifetime.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+
+    fn advise(&self, range: Range<usize>, advice: libc::c_int) {
+        unsafe {
+            libc::madvise(self.ptr.add(range.start), range.len(), advice);
+        }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+// --- THE STATE FILE ---
+
+pub struct MmapState {
+    map: Mapping,
+    layout: Layout,
+    page_size: usize,
+    hinted_pages: AtomicU64,
+}
+
+impl MmapState {
+    pub fn open(dir: &Path) -> Result<Self, String> {
+        let path = dir.join(STATE_FILE);
+        let err = |e: &dyn fmt::Display| format!("{}: {}", path.display(), e);
+        let file = File::open(&path).map_err(|e| err(&e))?;
+        let len = file.metadata().map_err(|e| err(&e))?.len() as usize;
+
+        let mut header = [0u8; HEADER_BYTES];
+        std::os::unix::fs::FileExt::read_exact_at(&file, &mut header, 0).map_err(|e| err(&e))?;
+        let layout = Layout::decode(&header).ok_or_else(|| err(&"not a flux state file (build it with import-state)"))?;
+        if layout.file_len() != len {
+            return Err(err(&format!("expected {} bytes, found {}", layout.file_len(), len)));
+        }
+
+        let fd = file.as_raw_fd();
+        let ptr = unsafe { libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_SHARED, fd, 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(err(&std::io::Error::last_os_error()));
+        }
+        let map = Mapping { ptr, len };
+        // Lookups are point reads; kernel readahead would only drag in neighbouring records.
+        map.advise(0..len, libc::MADV_RANDOM);
+
+        Ok(Self {
+            map,
+            layout,
+            page_size: unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as usize,
+            hinted_pages: AtomicU64::new(0),
+        })
+    }
+
+    /// Preprocesses a snapshot into `dir`'s state file. Everything is sorted in memory first, so
+    /// this needs room for the whole snapshot; the runs that map the result do not.
+    pub fn build(
+        dir: &Path,
+        accounts: impl Iterator<Item = Result<SnapshotAccount, String>>,
+    ) -> Result<ImportStats, String> {
+        let mut stats = ImportStats::default();
+        let mut account_rows: Vec<(Address, Vec<u8>)> = Vec::new();
+        let mut storage_rows: Vec<(Address, U256, U256)> = Vec::new();
+        let mut code: BTreeMap<B256, Bytes> = BTreeMap::new();
+        for account in accounts {
+            let account = account?;
+            if let Some(bytecode) = &account.info.code {
+                code.insert(account.info.code_hash, bytecode.original_bytes());
+                stats.contracts += 1;
+            }
+            account_rows.push((account.address, encode_account(&account.info)));
+            stats.slots += account.storage.len();
+            storage_rows.extend(account.storage.iter().map(|(slot, value)| (account.address, *slot, *value)));
+            stats.accounts += 1;
+        }
+        account_rows.sort_unstable_by_key(|(address, _)| *address);
+        storage_rows.sort_unstable_by_key(|(address, slot, _)| (*address, *slot));
+
+        let layout = Layout {
+            accounts: account_rows.len(),
+            slots: storage_rows.len(),
+            codes: code.len(),
+            code_bytes: code.values().map(|bytes| bytes.len()).sum(),
+        };
+
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        let path = dir.join(STATE_FILE);
+        let tmp = path.with_extension("tmp");
+        let err = |e: std::io::Error| format!("{}: {}", tmp.display(), e);
+        let file = File::create(&tmp).map_err(err)?;
+        let mut out = BufWriter::new(&file);
+
+        out.write_all(&layout.encode()).map_err(err)?;
+        for (address, account) in &account_rows {
+            out.write_all(address.as_slice()).and_then(|_| out.write_all(account)).map_err(err)?;
+        }
+        for (address, slot, value) in &storage_rows {
+            out.write_all(&storage_key(*address, *slot)).map_err(err)?;
+            out.write_all(&value.to_be_bytes::<32>()).map_err(err)?;
+        }
+        let mut offset = 0u64;
+        for (hash, bytes) in &code {
+            out.write_all(hash.as_slice()).map_err(err)?;
+            out.write_all(&offset.to_le_bytes()).map_err(err)?;
+            out.write_all(&(bytes.len() as u32).to_le_bytes()).map_err(err)?;
+            offset += bytes.len() as u64;
+        }
+        for bytes in code.values() {
+            out.write_all(bytes).map_err(err)?;
+        }
+        for (address, _) in account_rows.iter().step_by(FENCE_STRIDE) {
+            out.write_all(address.as_slice()).map_err(err)?;
+        }
+        for (address, slot, _) in storage_rows.iter().step_by(FENCE_STRIDE) {
+            out.write_all(&storage_key(*address, *slot)).map_err(err)?;
+        }
+        for hash in code.keys().step_by(FENCE_STRIDE) {
+            out.write_all(hash.as_slice()).map_err(err)?;
+        }
+        out.flush().map_err(err)?;
+        drop(out);
+        file.sync_all().map_err(err)?;
+        fs::rename(&tmp, &path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(stats)
+    }
+
+    /// Asks the kernel to start reading the pages that could hold `locations`. Cheap and
+    /// non-blocking: only fences are searched, and each page is advised at most once per call.
+    pub fn prefetch(&self, locations: impl Iterator<Item = Location>) {
+        let data = self.map.bytes();
+        let mut pages = HashSet::new();
+        for location in locations {
+            let (table, key) = match location {
+                Location::Account(address) => (self.layout.accounts(), address.to_vec()),
+                Location::Storage(address, slot) => (self.layout.storage(), storage_key(address, slot).to_vec()),
+            };
+            let range = table.byte_range(table.window(data, &key));
+            if range.is_empty() {
+                continue;
+            }
+            let first = range.start / self.page_size;
+            let last = (range.end - 1) / self.page_size;
+            for page in first..=last {
+                if pages.insert(page) {
+                    let start = page * self.page_size;
+                    self.map.advise(start..(start + self.page_size).min(self.map.len), libc::MADV_WILLNEED);
+                }
+            }
+        }
+        self.hinted_pages.fetch_add(pages.len() as u64, Ordering::Relaxed);
+    }
+
+    /// Every account and slot in the file, without code. Walks both tables front to back.
+    pub fn dump(&self) -> Result<Vec<SnapshotAccount>, String> {
+        let data = self.map.bytes();
+        let (accounts, storage) = (self.layout.accounts(), self.layout.storage());
+        let mut slots = (0..storage.count).map(|i| &data[storage.at + i * storage.record..][..storage.record]).peekable();
+        let mut dumped = Vec::with_capacity(accounts.count);
+        for i in 0..accounts.count {
+            let record = &data[accounts.at + i * accounts.record..][..accounts.record];
+            let address = Address::from_slice(&record[..ACCOUNT_KEY]);
+            let info = decode_account(&record[ACCOUNT_KEY..])
+                .ok_or_else(|| format!("corrupt account record for {}", address))?;
+            let mut account_storage = Vec::new();
+            // Both tables are sorted by address; storage of accounts without a record is skipped.
+            while let Some(slot) = slots.next_if(|slot| slot[..ACCOUNT_KEY] <= *address.as_slice()) {
+                if slot[..ACCOUNT_KEY] == *address.as_slice() {
+                    let value = U256::from_be_slice(&slot[STORAGE_KEY..]);
+                    account_storage.push((U256::from_be_slice(&slot[ACCOUNT_KEY..STORAGE_KEY]), value));
+                }
+            }
+            dumped.push(SnapshotAccount {
+                address,
+                info,
+                storage: account_storage,
+            });
+        }
+        Ok(dumped)
+    }
+
+    pub fn stats(&self) -> MmapStats {
+        MmapStats {
+            accounts: self.layout.accounts,
+            slots: self.layout.slots,
+            contracts: self.layout.codes,
+            mapped_bytes: self.map.len,
+            hinted_pages: self.hinted_pages.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl DatabaseRef for MmapState {
+    type Error = String;
+
+    fn basic(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        self.layout
+            .accounts()
+            .find(self.map.bytes(), address.as_slice())
+            .map(|raw| decode_account(raw).ok_or_else(|| format!("corrupt account record for {}", address)))
+            .transpose()
+    }
+
+    fn code_by_hash(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        if code_hash == KECCAK_EMPTY {
+            return Ok(Bytecode::new());
+        }
+        let data = self.map.bytes();
+        let entry = self
+            .layout
+            .code()
+            .find(data, code_hash.as_slice())
+            .ok_or_else(|| format!("code {} missing from state", code_hash))?;
+        let offset = u64::from_le_bytes(entry[..8].try_into().unwrap()) as usize;
+        let len = u32::from_le_bytes(entry[8..].try_into().unwrap()) as usize;
+        let start = self.layout.code_blob() + offset;
+        Ok(Bytecode::new_raw(Bytes::copy_from_slice(&data[start..start + len])))
+    }
+
+    fn storage(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        Ok(self
+            .layout
+            .storage()
+            .find(self.map.bytes(), &storage_key(address, index))
+            .map(U256::from_be_slice)
+            .unwrap_or_default())
+    }
+
+    fn block_hash(&self, number: U256) -> Result<B256, Self::Error> {
+        // The state file carries no header chain; same stand-in EmptyDB uses.
+        Ok(keccak256(number.to_string().as_bytes()))
+    }
+}
+
+fn storage_key(address: Address, slot: U256) -> [u8; STORAGE_KEY] {
+    let mut key = [0u8; STORAGE_KEY];
+    key[..20].copy_from_slice(address.as_slice());
+    key[20..].copy_from_slice(&slot.to_be_bytes::<32>());
+    key
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone, Default)]
+pub struct MmapStats {
+    pub accounts: usize,
+    pub slots: usize,
+    pub contracts: usize,
+    pub mapped_bytes: usize,
+    pub hinted_pages: u64,
+}
+
+impl fmt::Display for MmapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Mapped State:       {} accounts, {} slots, {} contracts, {} bytes mapped, {} pages prefetched",
+            self.accounts, self.slots, self.contracts, self.mapped_bytes, self.hinted_pages
+        )
+    }
+}
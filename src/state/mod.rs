@@ -0,0 +1,19 @@
+/*
+ * FLUX ENGINE - STATE BACKENDS
+ * Everything that sits underneath the CacheDB the executors read from.
+ */
+
+pub mod backend;
+pub mod buffer;
+pub mod diff;
+pub mod flatlog;
+pub mod history;
+#[cfg(all(feature = "async-io", target_os = "linux"))]
+pub mod io;
+pub mod latency;
+pub mod mmap;
+pub mod preload;
+#[cfg(feature = "rocksdb")]
+pub mod rocks;
+pub mod sharded;
+pub mod snapshot;
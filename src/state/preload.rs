@@ -0,0 +1,92 @@
+/*
+ * FLUX ENGINE - PRELOAD SETS
+ * Binary account/storage sets for `--preload-state`: the same accounts a JSON-lines dump holds,
+ * packed so a large warm-up set loads in one read instead of a parse per line.
+ *
+ * Layout: `FLUXPRE1` | account count (8, LE), then per account: address (20) | balance (32, BE)
+ * | nonce (8, BE) | code length (4, LE) | code | slot count (4, LE) | (slot (32, BE) | value
+ * (32, BE)) per slot.
+ */
+
+use super::snapshot::{ImportStats, SnapshotAccount};
+use revm::primitives::{AccountInfo, Address, Bytecode, Bytes, KECCAK_EMPTY, U256};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"FLUXPRE1";
+
+/// Writes `accounts` in the format `read_preload` reads.
+pub fn write_preload(path: &Path, accounts: &[SnapshotAccount]) -> Result<ImportStats, String> {
+    let err = |e: std::io::Error| format!("{}: {}", path.display(), e);
+    let mut out = BufWriter::new(File::create(path).map_err(err)?);
+    let mut stats = ImportStats::default();
+    out.write_all(MAGIC).map_err(err)?;
+    out.write_all(&(accounts.len() as u64).to_le_bytes()).map_err(err)?;
+    for account in accounts {
+        let code = account.info.code.as_ref().map(Bytecode::original_bytes).unwrap_or_default();
+        out.write_all(account.address.as_slice()).map_err(err)?;
+        out.write_all(&account.info.balance.to_be_bytes::<32>()).map_err(err)?;
+        out.write_all(&account.info.nonce.to_be_bytes()).map_err(err)?;
+        out.write_all(&(code.len() as u32).to_le_bytes()).map_err(err)?;
+        out.write_all(&code).map_err(err)?;
+        out.write_all(&(account.storage.len() as u32).to_le_bytes()).map_err(err)?;
+        for (slot, value) in &account.storage {
+            out.write_all(&slot.to_be_bytes::<32>()).map_err(err)?;
+            out.write_all(&value.to_be_bytes::<32>()).map_err(err)?;
+        }
+        stats.accounts += 1;
+        stats.contracts += usize::from(!code.is_empty());
+        stats.slots += account.storage.len();
+    }
+    out.flush().map_err(err)?;
+    Ok(stats)
+}
+
+/// Every account in `path`, read in one go.
+pub fn read_preload(path: &Path) -> Result<Vec<SnapshotAccount>, String> {
+    let data = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let truncated = || format!("{}: truncated preload file", path.display());
+    if data.get(..MAGIC.len()) != Some(MAGIC.as_slice()) {
+        return Err(format!("{}: not a preload file", path.display()));
+    }
+    let mut rest = &data[MAGIC.len()..];
+    let mut take = |len: usize| -> Result<&[u8], String> {
+        if rest.len() < len {
+            return Err(truncated());
+        }
+        let (bytes, tail) = rest.split_at(len);
+        rest = tail;
+        Ok(bytes)
+    };
+
+    let count = u64::from_le_bytes(take(8)?.try_into().expect("8 bytes"));
+    let mut accounts = Vec::with_capacity(count.min(1 << 20) as usize);
+    for _ in 0..count {
+        let address = Address::from_slice(take(20)?);
+        let balance = U256::from_be_slice(take(32)?);
+        let nonce = u64::from_be_bytes(take(8)?.try_into().expect("8 bytes"));
+        let code_len = u32::from_le_bytes(take(4)?.try_into().expect("4 bytes")) as usize;
+        let code = take(code_len)?;
+        let mut info = AccountInfo {
+            balance,
+            nonce,
+            code_hash: KECCAK_EMPTY,
+            code: None,
+        };
+        if !code.is_empty() {
+            let code = Bytecode::new_raw(Bytes::copy_from_slice(code));
+            info.code_hash = code.hash_slow();
+            info.code = Some(code);
+        }
+        let slots = u32::from_le_bytes(take(4)?.try_into().expect("4 bytes"));
+        let storage = (0..slots)
+            .map(|_| Ok((U256::from_be_slice(take(32)?), U256::from_be_slice(take(32)?))))
+            .collect::<Result<_, String>>()?;
+        accounts.push(SnapshotAccount { address, info, storage });
+    }
+    if !rest.is_empty() {
+        return Err(format!("{}: {} trailing bytes after {} accounts", path.display(), rest.len(), count));
+    }
+    Ok(accounts)
+}
@@ -0,0 +1,112 @@
+/*
+ * FLUX ENGINE - ROCKSDB STATE
+ * Persistent state in RocksDB (`--features rocksdb`). Read-only during execution: the shard
+ * caches above it absorb repeated reads, and committed blocks never write back, so every run
+ * starts from the same imported snapshot.
+ */
+
+use super::backend::{decode_account, encode_account};
+use super::snapshot::{ImportStats, SnapshotAccount};
+use revm::db::DatabaseRef;
+use revm::primitives::{keccak256, AccountInfo, Address, Bytecode, Bytes, B256, KECCAK_EMPTY, U256};
+use rocksdb::{Options, WriteBatch, DB};
+use std::path::Path;
+
+const ACCOUNT: u8 = b'a';
+const CODE: u8 = b'c';
+const STORAGE: u8 = b's';
+const BLOCK_HASH: u8 = b'h';
+
+/// Accounts written per RocksDB batch during import.
+const IMPORT_BATCH: usize = 10_000;
+
+pub struct RocksBackend {
+    db: DB,
+}
+
+impl RocksBackend {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        DB::open(&options, path)
+            .map(|db| Self { db })
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn import(
+        &self,
+        accounts: impl Iterator<Item = Result<SnapshotAccount, String>>,
+    ) -> Result<ImportStats, String> {
+        let mut stats = ImportStats::default();
+        let mut batch = WriteBatch::default();
+        for account in accounts {
+            let account = account?;
+            if let Some(code) = &account.info.code {
+                batch.put(key(CODE, &[account.info.code_hash.as_slice()]), code.original_bytes());
+                stats.contracts += 1;
+            }
+            batch.put(key(ACCOUNT, &[account.address.as_slice()]), encode_account(&account.info));
+            for (slot, value) in &account.storage {
+                let slot = slot.to_be_bytes::<32>();
+                batch.put(key(STORAGE, &[account.address.as_slice(), &slot]), value.to_be_bytes::<32>());
+            }
+            stats.slots += account.storage.len();
+            stats.accounts += 1;
+
+            if stats.accounts % IMPORT_BATCH == 0 {
+                self.db.write(std::mem::take(&mut batch)).map_err(|e| e.to_string())?;
+            }
+        }
+        self.db.write(batch).map_err(|e| e.to_string())?;
+        Ok(stats)
+    }
+
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>, String> {
+        self.db.get(key).map_err(|e| e.to_string())
+    }
+}
+
+impl DatabaseRef for RocksBackend {
+    type Error = String;
+
+    fn basic(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        self.get(key(ACCOUNT, &[address.as_slice()]))?
+            .map(|raw| decode_account(&raw).ok_or_else(|| format!("corrupt account record for {}", address)))
+            .transpose()
+    }
+
+    fn code_by_hash(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        if code_hash == KECCAK_EMPTY {
+            return Ok(Bytecode::new());
+        }
+        self.get(key(CODE, &[code_hash.as_slice()]))?
+            .map(|raw| Bytecode::new_raw(Bytes::from(raw)))
+            .ok_or_else(|| format!("code {} missing from state", code_hash))
+    }
+
+    fn storage(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        let slot = index.to_be_bytes::<32>();
+        Ok(self
+            .get(key(STORAGE, &[address.as_slice(), &slot]))?
+            .map(|raw| U256::from_be_slice(&raw))
+            .unwrap_or_default())
+    }
+
+    fn block_hash(&self, number: U256) -> Result<B256, Self::Error> {
+        let number_bytes = number.to_be_bytes::<32>();
+        Ok(self
+            .get(key(BLOCK_HASH, &[&number_bytes]))?
+            .map(|raw| B256::from_slice(&raw))
+            // Same stand-in EmptyDB uses when the snapshot carries no header chain.
+            .unwrap_or_else(|| keccak256(number.to_string().as_bytes())))
+    }
+}
+
+fn key(prefix: u8, parts: &[&[u8]]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(1 + parts.iter().map(|p| p.len()).sum::<usize>());
+    key.push(prefix);
+    for part in parts {
+        key.extend_from_slice(part);
+    }
+    key
+}
@@ -0,0 +1,720 @@
+/*
+ * FLUX ENGINE - SHARDED STATE STORE
+ * Global state split across independently locked shards, read-through to a backend.
+ * Executors read it concurrently through per-transaction CacheDB overlays instead of cloning
+ * one big map under one big lock. Optionally size-limited: between blocks, the least recently
+ * read accounts the backend can serve again are evicted.
+ */
+
+use super::history::Changeset;
+use super::snapshot::SnapshotAccount;
+use crate::mvcc::Location;
+use dashmap::DashMap;
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use rayon::prelude::*;
+use revm::db::{AccountState, DatabaseRef, DbAccount};
+use revm::primitives::{Account, AccountInfo, Address, Bytecode, HashMap, B256, KECCAK_EMPTY, U256};
+use revm::DatabaseCommit;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+struct Shard {
+    accounts: HashMap<Address, DbAccount>,
+    /// Committed since the last `take_changes`.
+    dirty: HashMap<Address, Dirty>,
+    /// Pre-block values of everything committed since the last `take_changeset`.
+    changeset: Changeset,
+    /// Block each cached account was last read in. Only kept when the cache has a limit.
+    last_read: HashMap<Address, AtomicU64>,
+    /// Cached state the backend does not hold (seeded, or committed and never persisted), which
+    /// eviction would lose.
+    pinned: HashSet<Address>,
+}
+
+#[derive(Debug, Default)]
+struct Dirty {
+    storage_cleared: bool,
+    slots: HashSet<U256>,
+}
+
+/// One account's committed changes, as handed to a persistent backend.
+#[derive(Debug, Clone)]
+pub struct StateChange {
+    pub address: Address,
+    /// `None` once the account no longer exists (self-destructed). Carries the code when it has any.
+    pub info: Option<AccountInfo>,
+    /// Earlier storage is gone (self-destruct or re-creation); `storage` is everything written since.
+    pub storage_cleared: bool,
+    pub storage: Vec<(U256, U256)>,
+}
+
+#[derive(Debug, Default)]
+struct ContentionCounters {
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
+    wait_nanos: AtomicU64,
+}
+
+/// What the engine is doing when it reads state, for the cache hit rates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ReadStage {
+    /// Seeding, prologue system calls, reporting: anything outside the stages below.
+    Other = 0,
+    Execution = 1,
+    /// Validation, re-execution, ordered apply and the block epilogue.
+    Commit = 2,
+    /// Folding the block into the state trie and diffing it.
+    StateRoot = 3,
+    /// Warming the caches with the block's declared locations, and pre-validating against them,
+    /// before execution.
+    Prefetch = 4,
+}
+
+const READ_STAGES: [ReadStage; 5] = [
+    ReadStage::Prefetch,
+    ReadStage::Execution,
+    ReadStage::Commit,
+    ReadStage::StateRoot,
+    ReadStage::Other,
+];
+
+#[derive(Debug, Default)]
+struct CacheCounters {
+    /// Indexed by `ReadStage`.
+    hits: [AtomicU64; 5],
+    misses: [AtomicU64; 5],
+    evicted_accounts: AtomicU64,
+    evicted_entries: AtomicU64,
+    /// Accounts plus changed slots committed.
+    writes: AtomicU64,
+}
+
+#[derive(Debug)]
+pub struct ShardedState<DB> {
+    shards: Box<[RwLock<Shard>]>,
+    contracts: DashMap<B256, Bytecode>,
+    backend: DB,
+    counters: ContentionCounters,
+    record_changesets: AtomicBool,
+    /// Accounts plus slots kept across blocks. `None` = unbounded.
+    capacity: Option<usize>,
+    /// Blocks evicted so far; the clock `last_read` is kept in.
+    epoch: AtomicU64,
+    stage: AtomicU8,
+    cache: CacheCounters,
+}
+
+impl<DB> ShardedState<DB> {
+    pub fn new(backend: DB, shards: usize) -> Self {
+        Self {
+            shards: (0..shards.max(1)).map(|_| RwLock::new(Shard::default())).collect(),
+            contracts: DashMap::new(),
+            backend,
+            counters: ContentionCounters::default(),
+            record_changesets: AtomicBool::new(false),
+            capacity: None,
+            epoch: AtomicU64::new(0),
+            stage: AtomicU8::new(ReadStage::Other as u8),
+            cache: CacheCounters::default(),
+        }
+    }
+
+    /// Caps the accounts plus slots held across blocks; see `evict`.
+    pub fn with_capacity_limit(mut self, entries: Option<usize>) -> Self {
+        self.capacity = entries;
+        self
+    }
+
+    pub fn set_read_stage(&self, stage: ReadStage) {
+        self.stage.store(stage as u8, Ordering::Relaxed);
+    }
+
+    /// Has the committer keep each block's overwritten values for `take_changeset`.
+    pub fn record_changesets(&self, record: bool) {
+        self.record_changesets.store(record, Ordering::Relaxed);
+    }
+
+    pub fn backend(&self) -> &DB {
+        &self.backend
+    }
+
+    /// Seeds an account (genesis alloc / fixtures). Cached storage is kept.
+    pub fn insert_account_info(&self, address: Address, mut info: AccountInfo) {
+        self.insert_contract(&mut info);
+        let mut shard = self.write(&address);
+        shard.pinned.insert(address);
+        shard.accounts.entry(address).or_default().info = info;
+    }
+
+    /// Seeds a storage slot. Creates the account entry if needed, leaving its info to the backend.
+    pub fn insert_account_storage(&self, address: Address, slot: U256, value: U256) {
+        let mut shard = self.write(&address);
+        shard.pinned.insert(address);
+        shard.accounts.entry(address).or_default().storage.insert(slot, value);
+    }
+
+    /// Every existing account held in memory (committed or read through), with code attached.
+    /// Zero slots are left out; they read the same as absent ones.
+    pub fn dump(&self) -> Vec<SnapshotAccount> {
+        let mut accounts = Vec::new();
+        for shard in self.shards.iter() {
+            for (address, account) in &shard.read().accounts {
+                let Some(mut info) = account.info() else {
+                    continue;
+                };
+                if info.code.is_none() && info.code_hash != KECCAK_EMPTY {
+                    info.code = self.contracts.get(&info.code_hash).map(|code| code.clone());
+                }
+                let storage = account
+                    .storage
+                    .iter()
+                    .filter(|(_, value)| **value != U256::ZERO)
+                    .map(|(slot, value)| (*slot, *value))
+                    .collect();
+                accounts.push(SnapshotAccount {
+                    address: *address,
+                    info,
+                    storage,
+                });
+            }
+        }
+        accounts
+    }
+
+    /// `persisted` (the backend's accounts) as seen through the caches: cached accounts and slots
+    /// replace the backend's, wiped storage and deleted accounts drop out. Zero slots are left out.
+    pub fn overlay(&self, persisted: Vec<SnapshotAccount>) -> Vec<SnapshotAccount> {
+        let mut merged: BTreeMap<Address, (AccountInfo, HashMap<U256, U256>)> = persisted
+            .into_iter()
+            .map(|account| (account.address, (account.info, account.storage.into_iter().collect())))
+            .collect();
+        for shard in self.shards.iter() {
+            for (address, account) in &shard.read().accounts {
+                let Some(info) = account.info() else {
+                    merged.remove(address);
+                    continue;
+                };
+                let (merged_info, storage) = merged.entry(*address).or_default();
+                *merged_info = info;
+                if account.account_state.is_storage_cleared() {
+                    storage.clear();
+                }
+                storage.extend(account.storage.iter().map(|(slot, value)| (*slot, *value)));
+            }
+        }
+        merged
+            .into_iter()
+            .map(|(address, (info, storage))| SnapshotAccount {
+                address,
+                info,
+                storage: storage.into_iter().filter(|(_, value)| *value != U256::ZERO).collect(),
+            })
+            .collect()
+    }
+
+    /// Drains everything committed since the last call, for writing back to the backend.
+    pub fn take_changes(&self) -> Vec<StateChange> {
+        let mut changes = Vec::new();
+        for shard in self.shards.iter() {
+            let mut shard = shard.write();
+            let dirty = std::mem::take(&mut shard.dirty);
+            for (address, dirty) in dirty {
+                let account = &shard.accounts[&address];
+                let info = account.info().map(|mut info| {
+                    if info.code.is_none() && info.code_hash != KECCAK_EMPTY {
+                        info.code = self.contracts.get(&info.code_hash).map(|code| code.clone());
+                    }
+                    info
+                });
+                let storage = dirty
+                    .slots
+                    .into_iter()
+                    .map(|slot| (slot, account.storage.get(&slot).copied().unwrap_or_default()))
+                    .collect();
+                changes.push(StateChange {
+                    address,
+                    info,
+                    storage_cleared: dirty.storage_cleared,
+                    storage,
+                });
+            }
+        }
+        changes
+    }
+
+    /// Forgets what was committed since the last `take_changes`, for backends nothing is written to.
+    /// The accounts it touched now exist only here, so they are pinned.
+    pub fn discard_changes(&self) {
+        for shard in self.shards.iter() {
+            let mut shard = shard.write();
+            let dirty = std::mem::take(&mut shard.dirty);
+            shard.pinned.extend(dirty.into_keys());
+        }
+    }
+
+    /// Keeps these accounts cached for good: the backend does not have their current state.
+    pub fn pin(&self, addresses: impl IntoIterator<Item = Address>) {
+        for address in addresses {
+            self.write(&address).pinned.insert(address);
+        }
+    }
+
+    /// Drains the pre-block values recorded since the last call (see `with_changesets`).
+    pub fn take_changeset(&self) -> Changeset {
+        let mut changeset = Changeset::default();
+        for shard in self.shards.iter() {
+            changeset.merge(std::mem::take(&mut shard.write().changeset));
+        }
+        changeset
+    }
+
+    /// Drops accounts that no longer exist and zero slots from the caches. Only sound once the
+    /// backend reads both back as absent, i.e. the block is persisted or there is no backend.
+    /// Returns the entries dropped and roughly how many bytes they held.
+    pub fn prune_dead(&self) -> (u64, u64) {
+        const ACCOUNT_BYTES: usize = size_of::<(Address, DbAccount)>();
+        const SLOT_BYTES: usize = size_of::<(U256, U256)>();
+        let (mut entries, mut bytes) = (0, 0);
+        for shard in self.shards.iter() {
+            let mut shard = shard.write();
+            let Shard {
+                accounts,
+                dirty,
+                last_read,
+                ..
+            } = &mut *shard;
+            accounts.retain(|address, account| {
+                // Still owed to the backend; take_changes looks these up.
+                if dirty.contains_key(address) {
+                    return true;
+                }
+                if matches!(account.account_state, AccountState::NotExisting) {
+                    entries += 1 + account.storage.len() as u64;
+                    bytes += ACCOUNT_BYTES + account.storage.len() * SLOT_BYTES;
+                    return false;
+                }
+                let before = account.storage.len();
+                account.storage.retain(|_, value| *value != U256::ZERO);
+                entries += (before - account.storage.len()) as u64;
+                bytes += (before - account.storage.len()) * SLOT_BYTES;
+                true
+            });
+            last_read.retain(|address, _| accounts.contains_key(address));
+        }
+        (entries, bytes as u64)
+    }
+
+    /// Brings every shard back under its share of the capacity limit by dropping whole accounts,
+    /// least recently read first. Only accounts the backend would read back identically go:
+    /// nothing committed but not yet persisted, nothing pinned. Call between blocks, after the
+    /// block's changes were persisted or discarded. Returns the accounts and entries (accounts
+    /// plus slots) evicted.
+    pub fn evict(&self) -> (u64, u64) {
+        let epoch = self.epoch.fetch_add(1, Ordering::Relaxed);
+        let Some(capacity) = self.capacity else {
+            return (0, 0);
+        };
+        let limit = capacity.div_ceil(self.shards.len());
+        let (mut evicted, mut entries) = (0, 0);
+        for shard in self.shards.iter() {
+            let mut shard = shard.write();
+            let Shard {
+                accounts,
+                dirty,
+                last_read,
+                pinned,
+                ..
+            } = &mut *shard;
+            let mut held: usize = accounts.values().map(|account| 1 + account.storage.len()).sum();
+            if held <= limit {
+                continue;
+            }
+            let mut candidates: Vec<(u64, Address, usize)> = accounts
+                .iter()
+                .filter(|(address, _)| !dirty.contains_key(*address) && !pinned.contains(*address))
+                .map(|(address, account)| {
+                    let read = last_read.get(address).map_or(epoch, |read| read.load(Ordering::Relaxed));
+                    (read, *address, 1 + account.storage.len())
+                })
+                .collect();
+            candidates.sort_unstable_by_key(|(read, _, _)| *read);
+            for (_, address, size) in candidates {
+                if held <= limit {
+                    break;
+                }
+                accounts.remove(&address);
+                last_read.remove(&address);
+                held -= size;
+                evicted += 1;
+                entries += size as u64;
+            }
+        }
+        self.cache.evicted_accounts.fetch_add(evicted, Ordering::Relaxed);
+        self.cache.evicted_entries.fetch_add(entries, Ordering::Relaxed);
+        (evicted, entries)
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        let held = self
+            .shards
+            .iter()
+            .map(|shard| shard.read().accounts.values().map(|account| 1 + account.storage.len()).sum::<usize>())
+            .sum();
+        CacheStats {
+            capacity: self.capacity,
+            held,
+            stages: READ_STAGES
+                .iter()
+                .map(|stage| {
+                    let index = *stage as usize;
+                    (
+                        *stage,
+                        self.cache.hits[index].load(Ordering::Relaxed),
+                        self.cache.misses[index].load(Ordering::Relaxed),
+                    )
+                })
+                .collect(),
+            evicted_accounts: self.cache.evicted_accounts.load(Ordering::Relaxed),
+            evicted_entries: self.cache.evicted_entries.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn stats(&self) -> ShardStats {
+        let (mut accounts, mut slots) = (0, 0);
+        for shard in self.shards.iter() {
+            let shard = shard.read();
+            accounts += shard.accounts.len();
+            slots += shard.accounts.values().map(|account| account.storage.len()).sum::<usize>();
+        }
+        let code_bytes: usize = self.contracts.iter().map(|code| code.len()).sum();
+        ShardStats {
+            shards: self.shards.len(),
+            acquisitions: self.counters.acquisitions.load(Ordering::Relaxed),
+            contended: self.counters.contended.load(Ordering::Relaxed),
+            waited: Duration::from_nanos(self.counters.wait_nanos.load(Ordering::Relaxed)),
+            accounts,
+            slots,
+            contracts: self.contracts.len(),
+            bytes: (accounts * size_of::<(Address, DbAccount)>() + slots * size_of::<(U256, U256)>() + code_bytes) as u64,
+            reads: (0..READ_STAGES.len())
+                .map(|i| self.cache.hits[i].load(Ordering::Relaxed) + self.cache.misses[i].load(Ordering::Relaxed))
+                .sum(),
+            writes: self.cache.writes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Same normalisation as `CacheDB::insert_contract`: code lives in `contracts`, keyed by hash.
+    pub(super) fn insert_contract(&self, info: &mut AccountInfo) {
+        if let Some(code) = &info.code {
+            if !code.is_empty() {
+                if info.code_hash == KECCAK_EMPTY {
+                    info.code_hash = code.hash_slow();
+                }
+                self.contracts.entry(info.code_hash).or_insert_with(|| code.clone());
+            }
+        }
+        if info.code_hash == B256::ZERO {
+            info.code_hash = KECCAK_EMPTY;
+        }
+    }
+
+    /// Counts a read served from the shards and, with a capacity limit, marks the account used.
+    fn hit(&self, shard: &Shard, address: &Address) {
+        self.cache.hits[self.stage.load(Ordering::Relaxed) as usize].fetch_add(1, Ordering::Relaxed);
+        if let Some(read) = shard.last_read.get(address) {
+            // Skip the store when it would not change anything: hot accounts are read from
+            // every executor at once.
+            let epoch = self.epoch.load(Ordering::Relaxed);
+            if read.load(Ordering::Relaxed) != epoch {
+                read.store(epoch, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn miss(&self) {
+        self.cache.misses[self.stage.load(Ordering::Relaxed) as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn mark_read(&self, shard: &mut Shard, address: Address) {
+        if self.capacity.is_some() {
+            let epoch = self.epoch.load(Ordering::Relaxed);
+            shard.last_read.entry(address).or_default().store(epoch, Ordering::Relaxed);
+        }
+    }
+
+    fn shard_of(&self, address: &Address) -> usize {
+        // Synthetic workloads vary only the low bytes, so mix before reducing.
+        let mut low = [0u8; 8];
+        low.copy_from_slice(&address[12..]);
+        let mixed = u64::from_be_bytes(low).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        (mixed >> 32) as usize % self.shards.len()
+    }
+
+    fn read(&self, address: &Address) -> RwLockReadGuard<'_, Shard> {
+        let shard = &self.shards[self.shard_of(address)];
+        self.counters.acquisitions.fetch_add(1, Ordering::Relaxed);
+        if let Some(guard) = shard.try_read() {
+            return guard;
+        }
+        let started = Instant::now();
+        let guard = shard.read();
+        self.record_wait(started);
+        guard
+    }
+
+    fn write(&self, address: &Address) -> RwLockWriteGuard<'_, Shard> {
+        let shard = &self.shards[self.shard_of(address)];
+        self.counters.acquisitions.fetch_add(1, Ordering::Relaxed);
+        if let Some(guard) = shard.try_write() {
+            return guard;
+        }
+        let started = Instant::now();
+        let guard = shard.write();
+        self.record_wait(started);
+        guard
+    }
+
+    fn record_wait(&self, started: Instant) {
+        self.counters.contended.fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .wait_nanos
+            .fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+impl<DB: DatabaseRef + Sync> ShardedState<DB> {
+    /// Reads `locations` through to the caches, accounts in parallel, so execution finds them
+    /// warm. Errors are dropped: the same read fails again, and is reported, when a tx makes it.
+    pub fn prefetch(&self, locations: impl IntoIterator<Item = Location>) {
+        by_account(locations)
+            .into_par_iter()
+            .for_each(|(address, slots)| self.warm_account(address, slots));
+    }
+
+    /// Like `prefetch`, on the calling thread only, so it can run beside execution without
+    /// taking executor threads. A read racing a commit never replaces what the commit cached.
+    pub fn prefetch_serial(&self, locations: impl IntoIterator<Item = Location>) {
+        for (address, slots) in by_account(locations) {
+            self.warm_account(address, slots);
+        }
+    }
+
+    fn warm_account(&self, address: Address, slots: Vec<U256>) {
+        // Slots are only cached under a cached account, so it goes first.
+        let _ = self.basic(address);
+        for slot in slots {
+            let _ = self.storage(address, slot);
+        }
+    }
+}
+
+fn by_account(locations: impl IntoIterator<Item = Location>) -> BTreeMap<Address, Vec<U256>> {
+    let mut accounts: BTreeMap<Address, Vec<U256>> = BTreeMap::new();
+    for location in locations {
+        match location {
+            Location::Account(address) => accounts.entry(address).or_default(),
+            Location::Storage(address, slot) => {
+                let slots = accounts.entry(address).or_default();
+                slots.push(slot);
+                slots
+            }
+        };
+    }
+    accounts
+}
+
+/// Read-through: misses go to the backend (without holding a shard lock) and are cached.
+impl<DB: DatabaseRef> DatabaseRef for ShardedState<DB> {
+    type Error = DB::Error;
+
+    fn basic(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        let shard = self.read(&address);
+        if let Some(account) = shard.accounts.get(&address) {
+            self.hit(&shard, &address);
+            return Ok(account.info());
+        }
+        drop(shard);
+        self.miss();
+        let mut info = self.backend.basic(address)?;
+        if let Some(info) = info.as_mut() {
+            self.insert_contract(info);
+        }
+        let mut shard = self.write(&address);
+        self.mark_read(&mut shard, address);
+        Ok(shard.accounts.entry(address).or_insert_with(|| info.into()).info())
+    }
+
+    fn code_by_hash(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        if let Some(code) = self.contracts.get(&code_hash) {
+            return Ok(code.clone());
+        }
+        let code = self.backend.code_by_hash(code_hash)?;
+        Ok(self.contracts.entry(code_hash).or_insert(code).clone())
+    }
+
+    fn storage(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        let shard = self.read(&address);
+        if let Some(account) = shard.accounts.get(&address) {
+            if let Some(value) = account.storage.get(&index) {
+                self.hit(&shard, &address);
+                return Ok(*value);
+            }
+            if matches!(account.account_state, AccountState::StorageCleared | AccountState::NotExisting) {
+                self.hit(&shard, &address);
+                return Ok(U256::ZERO);
+            }
+        }
+        drop(shard);
+        self.miss();
+        let value = self.backend.storage(address, index)?;
+        match self.write(&address).accounts.get_mut(&address) {
+            Some(account) => Ok(*account.storage.entry(index).or_insert(value)),
+            None => Ok(value),
+        }
+    }
+
+    fn block_hash(&self, number: U256) -> Result<B256, Self::Error> {
+        self.backend.block_hash(number)
+    }
+}
+
+/// `CacheDB::commit`, one shard lock per account. Implemented on the shared reference so the
+/// committer never needs exclusive access to the whole store.
+impl<DB> DatabaseCommit for &ShardedState<DB> {
+    fn commit(&mut self, changes: HashMap<Address, Account>) {
+        for (address, mut account) in changes {
+            if !account.is_touched() {
+                continue;
+            }
+            let changed = account.storage.values().filter(|slot| slot.is_changed()).count();
+            self.cache.writes.fetch_add(1 + changed as u64, Ordering::Relaxed);
+            let mut shard = self.write(&address);
+            self.mark_read(&mut shard, address);
+            let Shard {
+                accounts,
+                dirty,
+                changeset,
+                ..
+            } = &mut *shard;
+            let dirty = dirty.entry(address).or_default();
+            let db_account = accounts.entry(address).or_default();
+            if self.record_changesets.load(Ordering::Relaxed) {
+                // First write in the block wins: the cache still holds the pre-block value.
+                changeset.accounts.entry(address).or_insert_with(|| {
+                    db_account.info().map(|info| AccountInfo { code: None, ..info })
+                });
+                for (slot, value) in account.storage.iter().filter(|(_, value)| value.is_changed()) {
+                    let pre = db_account.storage.get(slot).copied().unwrap_or(value.original_value());
+                    changeset.storage.entry((address, *slot)).or_insert(pre);
+                }
+                // A wipe also removes every cached slot. Ones never read are not recorded.
+                if account.is_selfdestructed() || account.is_created() {
+                    changeset.wiped.insert(address);
+                    for (slot, value) in &db_account.storage {
+                        changeset.storage.entry((address, *slot)).or_insert(*value);
+                    }
+                }
+            }
+            if account.is_selfdestructed() {
+                dirty.storage_cleared = true;
+                dirty.slots.clear();
+                db_account.storage.clear();
+                db_account.account_state = AccountState::NotExisting;
+                db_account.info = AccountInfo::default();
+                continue;
+            }
+            let is_newly_created = account.is_created();
+            self.insert_contract(&mut account.info);
+            db_account.info = account.info;
+            db_account.account_state = if is_newly_created {
+                db_account.storage.clear();
+                dirty.storage_cleared = true;
+                dirty.slots.clear();
+                AccountState::StorageCleared
+            } else if db_account.account_state.is_storage_cleared() {
+                AccountState::StorageCleared
+            } else {
+                AccountState::Touched
+            };
+            dirty
+                .slots
+                .extend(account.storage.iter().filter(|(_, value)| value.is_changed()).map(|(key, _)| *key));
+            db_account
+                .storage
+                .extend(account.storage.into_iter().map(|(key, value)| (key, value.present_value())));
+        }
+    }
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone, Default)]
+pub struct ShardStats {
+    pub shards: usize,
+    pub acquisitions: u64,
+    pub contended: u64,
+    pub waited: Duration,
+    /// Resident right now. `bytes` is approximate: entry sizes plus contract code.
+    pub accounts: usize,
+    pub slots: usize,
+    pub contracts: usize,
+    pub bytes: u64,
+    /// Reads served (from the shards or through to the backend) and writes committed, run-wide.
+    pub reads: u64,
+    pub writes: u64,
+}
+
+impl fmt::Display for ShardStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "State Shards:       {} shards, {} lock acquisitions, {} contended ({:.2}%), {:?} waiting",
+            self.shards,
+            self.acquisitions,
+            self.contended,
+            self.contended as f64 / self.acquisitions.max(1) as f64 * 100.0,
+            self.waited
+        )?;
+        writeln!(
+            f,
+            "                    {} accounts, {} slots, {} contracts resident (~{} bytes), {} reads, {} writes",
+            self.accounts, self.slots, self.contracts, self.bytes, self.reads, self.writes
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheStats {
+    pub capacity: Option<usize>,
+    /// Accounts plus slots cached right now.
+    pub held: usize,
+    /// (stage, hits, misses); a miss is a read that went to the backend.
+    pub stages: Vec<(ReadStage, u64, u64)>,
+    pub evicted_accounts: u64,
+    pub evicted_entries: u64,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let capacity = self.capacity.map_or_else(|| "unbounded".to_string(), |entries| format!("{} entries max", entries));
+        writeln!(
+            f,
+            "State Cache:        {} ({} held), {} accounts evicted ({} entries)",
+            capacity, self.held, self.evicted_accounts, self.evicted_entries
+        )?;
+        let rates: Vec<String> = self
+            .stages
+            .iter()
+            .map(|(stage, hits, misses)| {
+                let rate = *hits as f64 / (hits + misses).max(1) as f64 * 100.0;
+                format!("{:?} {:.2}% ({}/{})", stage, rate, hits, hits + misses)
+            })
+            .collect();
+        writeln!(f, "                    hit rate: {}", rates.join(", "))
+    }
+}
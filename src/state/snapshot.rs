@@ -0,0 +1,132 @@
+/*
+ * FLUX ENGINE - STATE SNAPSHOTS
+ * State snapshot import: `geth dump --iterative` style JSON lines, one account per line.
+ * Storage keys must be plain slots (dump with preimages), since the engine addresses storage
+ * by slot, not by slot hash. Dumps may be zstd-compressed, which `read_dump` detects.
+ */
+
+use revm::primitives::{AccountInfo, Address, Bytecode, Bytes, KECCAK_EMPTY, U256};
+use crate::zstd;
+use serde_json::Value;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct SnapshotAccount {
+    pub address: Address,
+    pub info: AccountInfo,
+    pub storage: Vec<(U256, U256)>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImportStats {
+    pub accounts: usize,
+    pub contracts: usize,
+    pub slots: usize,
+}
+
+/// Streams the accounts in `path`. Lines without an `address` (the leading `{"root": ..}`) are skipped.
+pub fn read_dump(path: &Path) -> Result<impl Iterator<Item = Result<SnapshotAccount, String>>, String> {
+    let file = zstd::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let display = path.display().to_string();
+    Ok(file
+        .lines()
+        .enumerate()
+        .filter_map(move |(lineno, line)| {
+            let at = |e: String| format!("{}:{}: {}", display, lineno + 1, e);
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Some(Err(at(e.to_string()))),
+            };
+            if line.trim().is_empty() {
+                return None;
+            }
+            parse_account(&line).transpose().map(|res| res.map_err(at))
+        }))
+}
+
+/// Writes `accounts` in the format `read_dump` reads, one per line, zstd-compressed at
+/// `compression` if given.
+pub fn write_dump(path: &Path, accounts: &[SnapshotAccount], compression: Option<u32>) -> Result<(), String> {
+    let err = |e: std::io::Error| format!("{}: {}", path.display(), e);
+    let mut out = zstd::Writer::create(path, compression).map_err(err)?;
+    for account in accounts {
+        writeln!(out, "{}", dump_line(account)).map_err(err)?;
+    }
+    out.finish().map_err(err)
+}
+
+/// `account` as one line of a dump. Storage is keyed by slot, so equal states give equal lines.
+pub fn dump_line(account: &SnapshotAccount) -> String {
+    let storage: serde_json::Map<String, Value> = account
+        .storage
+        .iter()
+        .map(|(slot, value)| (format!("{:#066x}", slot), Value::String(format!("{:x}", value))))
+        .collect();
+    let mut line = serde_json::json!({
+        "address": account.address.to_string(),
+        "balance": account.info.balance.to_string(),
+        "nonce": account.info.nonce,
+        "storage": storage,
+    });
+    if let Some(code) = account.info.code.as_ref().filter(|code| !code.is_empty()) {
+        line["code"] = Value::String(format!("0x{}", hex::encode(code.original_bytes())));
+    }
+    line.to_string()
+}
+
+fn parse_account(line: &str) -> Result<Option<SnapshotAccount>, String> {
+    let value: Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    let Some(address) = value.get("address").and_then(Value::as_str) else {
+        return Ok(None);
+    };
+
+    let mut info = AccountInfo {
+        balance: number(&value, "balance")?,
+        nonce: number(&value, "nonce")?.try_into().map_err(|_| "nonce does not fit in u64".to_string())?,
+        code_hash: KECCAK_EMPTY,
+        // `AccountInfo::default()` carries empty code; `None` is what marks an account codeless here.
+        code: None,
+    };
+    if let Some(code) = value.get("code").and_then(Value::as_str) {
+        let code = hex::decode(code.trim_start_matches("0x")).map_err(|e| format!("code: {}", e))?;
+        if !code.is_empty() {
+            let code = Bytecode::new_raw(Bytes::from(code));
+            info.code_hash = code.hash_slow();
+            info.code = Some(code);
+        }
+    }
+
+    let mut storage = Vec::new();
+    if let Some(slots) = value.get("storage").and_then(Value::as_object) {
+        for (slot, value) in slots {
+            let key = hex_word(slot).ok_or_else(|| format!("storage slot {:?}", slot))?;
+            let value = value
+                .as_str()
+                .and_then(hex_word)
+                .ok_or_else(|| format!("storage value for slot {:?}", slot))?;
+            storage.push((key, value));
+        }
+    }
+
+    Ok(Some(SnapshotAccount {
+        address: address.parse().map_err(|_| format!("address {:?}", address))?,
+        info,
+        storage,
+    }))
+}
+
+/// geth writes balances as decimal strings and nonces as JSON numbers; accept either for both.
+fn number(value: &Value, field: &str) -> Result<U256, String> {
+    match value.get(field) {
+        None | Some(Value::Null) => Ok(U256::ZERO),
+        Some(Value::Number(n)) => n.as_u64().map(U256::from).ok_or_else(|| format!("{}: {}", field, n)),
+        Some(Value::String(s)) => s.parse().map_err(|_| format!("{}: {:?}", field, s)),
+        Some(other) => Err(format!("{}: unexpected {}", field, other)),
+    }
+}
+
+/// Storage words are hex, with or without `0x` (geth omits it on values) and with leading zeros trimmed.
+fn hex_word(raw: &str) -> Option<U256> {
+    U256::from_str_radix(raw.trim_start_matches("0x"), 16).ok()
+}
@@ -0,0 +1,77 @@
+/*
+ * FLUX ENGINE - SYSTEM OPERATIONS
+ * State changes a block makes outside its transactions: the EIP-4788 beacon root call in the
+ * prologue and EIP-4895 withdrawals in the epilogue. Neither uses block gas or pays fees.
+ */
+
+use crate::block::Withdrawal;
+use crate::executor;
+use crate::state::sharded::ShardedState;
+use revm::db::{DatabaseRef, WrapDatabaseRef};
+use revm::precompile::Precompiles;
+use revm::primitives::{address, Account, Address, Env, HashMap, TransactTo, B256, U256};
+use revm::DatabaseCommit;
+use std::fmt::Debug;
+
+/// Caller of system contract calls (EIP-4788).
+pub const SYSTEM_ADDRESS: Address = address!("fffffffffffffffffffffffffffffffffffffffe");
+pub const BEACON_ROOTS_ADDRESS: Address = address!("000F3df6D732807Ef1319fB7B8bB8522d0Beac02");
+
+const SYSTEM_CALL_GAS_LIMIT: u64 = 30_000_000;
+const GWEI_TO_WEI: u64 = 1_000_000_000;
+
+/// Stores `parent_beacon_block_root` in the beacon roots contract. Only the contract's own
+/// storage is kept; the system caller and coinbase stay untouched.
+pub fn apply_beacon_root_call<ExtDB>(
+    mut state: &ShardedState<ExtDB>,
+    block_env: &Env,
+    parent_beacon_block_root: B256,
+    precompiles: &Precompiles,
+) -> Result<(), String>
+where
+    ExtDB: DatabaseRef,
+    ExtDB::Error: Debug,
+{
+    let mut env = block_env.clone();
+    env.tx.caller = SYSTEM_ADDRESS;
+    env.tx.transact_to = TransactTo::Call(BEACON_ROOTS_ADDRESS);
+    env.tx.data = parent_beacon_block_root.0.to_vec().into();
+    env.tx.gas_limit = SYSTEM_CALL_GAS_LIMIT;
+    env.tx.gas_price = U256::ZERO;
+    env.tx.gas_priority_fee = None;
+    env.cfg.disable_base_fee = true;
+    env.cfg.disable_block_gas_limit = true;
+
+    let mut executed = executor::transact(&mut WrapDatabaseRef(state), &mut env, precompiles)
+        .map_err(|e| format!("beacon root call: {:?}", e))?;
+    if !executed.result.is_success() {
+        return Err(format!("beacon root call did not succeed: {:?}", executed.result));
+    }
+    executed.state.retain(|address, _| *address == BEACON_ROOTS_ADDRESS);
+    executor::commit(&mut state, &env, precompiles, executed);
+    Ok(())
+}
+
+/// Credits every withdrawal (gwei on the consensus layer, wei here) to its recipient.
+pub fn apply_withdrawals<DB>(state: &mut DB, withdrawals: &[Withdrawal]) -> Result<(), String>
+where
+    DB: DatabaseRef + DatabaseCommit,
+    DB::Error: Debug,
+{
+    let mut credited: HashMap<Address, Account> = HashMap::new();
+    for withdrawal in withdrawals.iter().filter(|w| w.amount > 0) {
+        if !credited.contains_key(&withdrawal.address) {
+            let info = state
+                .basic(withdrawal.address)
+                .map_err(|e| format!("withdrawal {}: {:?}", withdrawal.index, e))?
+                .unwrap_or_default();
+            let mut account = Account::from(info);
+            account.mark_touch();
+            credited.insert(withdrawal.address, account);
+        }
+        let account = credited.get_mut(&withdrawal.address).expect("inserted above");
+        account.info.balance += U256::from(withdrawal.amount) * U256::from(GWEI_TO_WEI);
+    }
+    state.commit(credited);
+    Ok(())
+}
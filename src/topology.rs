@@ -0,0 +1,184 @@
+/*
+ * FLUX ENGINE - CORE TOPOLOGY
+ * `--topology exec=2-27,commit=28-29`: which cores each thread pool runs on, one thread pinned
+ * per listed core. The exec cores carry the process-wide rayon pool (speculation, prefetch,
+ * Block-STM and lock workers, sender recovery); the commit cores carry commit validation and
+ * state-root hashing. Cores are checked against the ones this process may run on. Without a
+ * topology nothing is pinned and the pools are sized as configured.
+ */
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Topology {
+    pub exec: Vec<usize>,
+    pub commit: Vec<usize>,
+}
+
+impl Topology {
+    /// Fails on any core this process is not allowed to run on.
+    pub fn validate(&self) -> Result<(), String> {
+        let available = available_cores();
+        for (stage, cores) in [("exec", &self.exec), ("commit", &self.commit)] {
+            if let Some(core) = cores.iter().find(|core| !available.contains(core)) {
+                return Err(format!(
+                    "topology: {} core {} is not available (this process may run on {})",
+                    stage,
+                    core,
+                    CoreList(&available)
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds the global rayon pool with a thread pinned to each exec core. Must run before
+    /// anything else uses rayon; without exec cores the default pool is left alone.
+    pub fn install_executor_pool(&self) -> Result<(), String> {
+        if self.exec.is_empty() {
+            return Ok(());
+        }
+        let cores = self.exec.clone();
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(cores.len())
+            .thread_name(|i| format!("flux-exec-{}", i))
+            .start_handler(move |i| {
+                if !pin_current_thread(cores[i]) {
+                    eprintln!("[FLUX] Topology: could not pin executor {} to core {}", i, cores[i]);
+                }
+            })
+            .build_global()
+            .map_err(|e| format!("topology: executor pool: {}", e))
+    }
+}
+
+impl FromStr for Topology {
+    type Err = String;
+
+    /// `<stage>=<cores>[,<stage>=<cores>...]`, stages `exec` and `commit`, cores as single
+    /// numbers or ranges. A bare core or range extends the stage before it: `exec=0-3,8-11`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut topology = Topology::default();
+        let mut stage: Option<&mut Vec<usize>> = None;
+        for item in s.split(',') {
+            let cores = match item.split_once('=') {
+                Some((name, cores)) => {
+                    stage = Some(match name {
+                        "exec" => &mut topology.exec,
+                        "commit" => &mut topology.commit,
+                        "prefetch" => return Err("topology: prefetch shares the exec cores".into()),
+                        _ => return Err(format!("topology: unknown stage {:?} (expected exec or commit)", name)),
+                    });
+                    cores
+                }
+                None => item,
+            };
+            let Some(stage) = stage.as_deref_mut() else {
+                return Err(format!("topology: {:?} names no stage (expected e.g. exec=2-27)", item));
+            };
+            let parse = |core: &str| {
+                core.trim()
+                    .parse::<usize>()
+                    .map_err(|_| format!("topology: invalid core {:?}", core))
+            };
+            match cores.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (parse(first)?, parse(last)?);
+                    if first > last {
+                        return Err(format!("topology: empty core range {:?}", cores));
+                    }
+                    stage.extend(first..=last);
+                }
+                None => stage.push(parse(cores)?),
+            }
+        }
+
+        for (name, cores) in [("exec", &mut topology.exec), ("commit", &mut topology.commit)] {
+            let listed = cores.len();
+            cores.sort_unstable();
+            cores.dedup();
+            if cores.len() != listed {
+                return Err(format!("topology: {} lists a core twice", name));
+            }
+        }
+        if topology.exec.is_empty() && topology.commit.is_empty() {
+            return Err("topology: no cores given".into());
+        }
+        Ok(topology)
+    }
+}
+
+impl fmt::Display for Topology {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stages: Vec<String> = [("exec", &self.exec), ("commit", &self.commit)]
+            .into_iter()
+            .filter(|(_, cores)| !cores.is_empty())
+            .map(|(name, cores)| format!("{}={}", name, CoreList(cores)))
+            .collect();
+        f.write_str(&stages.join(","))
+    }
+}
+
+/// Sorted cores, printed with consecutive runs as ranges: `0-3,8`.
+struct CoreList<'a>(&'a [usize]);
+
+impl fmt::Display for CoreList<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        for &core in self.0 {
+            match runs.last_mut() {
+                Some((_, last)) if *last + 1 == core => *last = core,
+                _ => runs.push((core, core)),
+            }
+        }
+        let runs: Vec<String> = runs
+            .into_iter()
+            .map(|(first, last)| {
+                if first == last {
+                    first.to_string()
+                } else {
+                    format!("{}-{}", first, last)
+                }
+            })
+            .collect();
+        f.write_str(&runs.join(","))
+    }
+}
+
+/// The cores this process may be scheduled on: its affinity mask on Linux, otherwise the first
+/// `available_parallelism` core ids.
+pub fn available_cores() -> Vec<usize> {
+    #[cfg(target_os = "linux")]
+    {
+        // SAFETY: `set` is a plain bitmask owned by this frame, filled in by the kernel.
+        let allowed = unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            (libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) == 0).then(|| {
+                (0..libc::CPU_SETSIZE as usize)
+                    .filter(|&core| libc::CPU_ISSET(core, &set))
+                    .collect::<Vec<usize>>()
+            })
+        };
+        if let Some(allowed) = allowed {
+            return allowed;
+        }
+    }
+    (0..std::thread::available_parallelism().map_or(1, |n| n.get())).collect()
+}
+
+/// Restricts the calling thread to `core`. Returns false where pinning is unsupported or refused.
+pub fn pin_current_thread(core: usize) -> bool {
+    #[cfg(target_os = "linux")]
+    // SAFETY: `set` is a plain bitmask owned by this frame; the call only reads it.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) == 0
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = core;
+        false
+    }
+}
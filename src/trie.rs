@@ -0,0 +1,619 @@
+/*
+ * FLUX ENGINE - MERKLE PATRICIA TRIE
+ * Just enough of the Ethereum MPT to compute the root of an index-keyed list (receipts,
+ * transactions) and of the secure (keccak-keyed) state and storage tries. Nothing is persisted:
+ * list roots are built, hashed and dropped; the state trie lives in memory between blocks.
+ */
+
+use crate::commitment::CommitmentScheme;
+use crate::keccak::{keccak256_batch, Implementation};
+use crate::state::snapshot::SnapshotAccount;
+use alloy_primitives::{keccak256, Address, B256, U256};
+use alloy_rlp::{Header, EMPTY_STRING_CODE};
+use rayon::prelude::*;
+use revm::primitives::{AccountInfo, HashMap};
+use std::fmt;
+use std::time::Duration;
+
+/// Root of the trie mapping `rlp(i) -> items[i]`.
+pub fn ordered_trie_root<T: AsRef<[u8]>>(items: &[T]) -> B256 {
+    if items.is_empty() {
+        return keccak256([EMPTY_STRING_CODE]);
+    }
+    let mut entries: Vec<(Vec<u8>, &[u8])> = items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| (nibbles(&alloy_rlp::encode(i)), item.as_ref()))
+        .collect();
+    entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    keccak256(encode_node(&entries, 0))
+}
+
+/// Root of an account's storage trie: `keccak(slot) -> rlp(value)`. Zero slots are not in the trie.
+pub fn storage_root(slots: impl IntoIterator<Item = (U256, U256)>) -> B256 {
+    let (keys, values): (Vec<_>, Vec<_>) = slots
+        .into_iter()
+        .filter(|(_, value)| *value != U256::ZERO)
+        .map(|(slot, value)| (slot.to_be_bytes::<32>(), alloy_rlp::encode(value)))
+        .unzip();
+    secure_root(keccak256_batch(&keys).into_iter().zip(values))
+}
+
+/// Root of the state trie: `keccak(address) -> rlp([nonce, balance, storage_root, code_hash])`.
+/// Takes every existing account with the root of its storage.
+pub fn state_root<'a>(accounts: impl IntoIterator<Item = (Address, &'a AccountInfo, B256)>) -> B256 {
+    let (keys, values): (Vec<_>, Vec<_>) = accounts
+        .into_iter()
+        .map(|(address, info, storage_root)| (address, account_rlp(info, storage_root)))
+        .unzip();
+    secure_root(keccak256_batch(&keys).into_iter().zip(values))
+}
+
+fn account_rlp(info: &AccountInfo, storage_root: B256) -> Vec<u8> {
+    rlp_list(&[
+        alloy_rlp::encode(info.nonce),
+        alloy_rlp::encode(info.balance),
+        rlp_bytes(storage_root.as_slice()),
+        rlp_bytes(info.code_hash.as_slice()),
+    ])
+}
+
+fn secure_root(entries: impl Iterator<Item = (B256, Vec<u8>)>) -> B256 {
+    let mut entries: Vec<(Vec<u8>, Vec<u8>)> = entries.map(|(key, value)| (nibbles(key.as_slice()), value)).collect();
+    if entries.is_empty() {
+        return keccak256([EMPTY_STRING_CODE]);
+    }
+    entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    keccak256(encode_node(&entries, 0))
+}
+
+/// RLP of the node covering `entries` (sorted, all sharing `key[..depth]`).
+fn encode_node<V: AsRef<[u8]>>(entries: &[(Vec<u8>, V)], depth: usize) -> Vec<u8> {
+    if let [(key, value)] = entries {
+        return rlp_list(&[rlp_bytes(&hex_prefix(&key[depth..], true)), rlp_bytes(value.as_ref())]);
+    }
+
+    // Sorted, so the first and last key bound the prefix every entry shares.
+    let (first, last) = (&entries[0].0[depth..], &entries[entries.len() - 1].0[depth..]);
+    let shared = first.iter().zip(last).take_while(|(a, b)| a == b).count();
+    if shared > 0 {
+        let child = encode_node(entries, depth + shared);
+        return rlp_list(&[rlp_bytes(&hex_prefix(&first[..shared], false)), reference(child)]);
+    }
+
+    // A key ending exactly here sorts first and lands in the branch's value slot.
+    let mut rest = entries;
+    let value = match rest.first() {
+        Some((key, value)) if key.len() == depth => {
+            rest = &rest[1..];
+            rlp_bytes(value.as_ref())
+        }
+        _ => rlp_bytes(&[]),
+    };
+    let mut children = Vec::with_capacity(16);
+    for nibble in 0..16u8 {
+        let end = rest.iter().position(|(key, _)| key[depth] != nibble).unwrap_or(rest.len());
+        children.push((end > 0).then(|| encode_node(&rest[..end], depth + 1)));
+        rest = &rest[end..];
+    }
+    let mut items = references(children);
+    items.push(value);
+    rlp_list(&items)
+}
+
+/// Children under 32 bytes are inlined; larger ones are referenced by hash.
+fn reference(node: Vec<u8>) -> Vec<u8> {
+    if node.len() < 32 {
+        node
+    } else {
+        rlp_bytes(keccak256(&node).as_slice())
+    }
+}
+
+/// `reference` for a branch's children at once, so their hashes go through one batch. Absent
+/// children become the empty string.
+fn references(children: Vec<Option<Vec<u8>>>) -> Vec<Vec<u8>> {
+    let large: Vec<&[u8]> = children.iter().flatten().filter(|node| node.len() >= 32).map(Vec::as_slice).collect();
+    let mut hashes = keccak256_batch(&large).into_iter();
+    children
+        .into_iter()
+        .map(|child| match child {
+            None => rlp_bytes(&[]),
+            Some(node) if node.len() < 32 => node,
+            Some(_) => rlp_bytes(hashes.next().expect("one hash per large child").as_slice()),
+        })
+        .collect()
+}
+
+fn nibbles(bytes: &[u8]) -> Vec<u8> {
+    bytes.iter().flat_map(|b| [b >> 4, b & 0x0f]).collect()
+}
+
+/// Compact (hex-prefix) path encoding from the yellow paper, appendix C.
+fn hex_prefix(path: &[u8], leaf: bool) -> Vec<u8> {
+    let flag = if leaf { 2 } else { 0 };
+    let mut out = Vec::with_capacity(path.len() / 2 + 1);
+    let rest = if path.len() % 2 == 1 {
+        out.push(((flag + 1) << 4) | path[0]);
+        &path[1..]
+    } else {
+        out.push(flag << 4);
+        path
+    };
+    out.extend(rest.chunks(2).map(|pair| (pair[0] << 4) | pair[1]));
+    out
+}
+
+fn rlp_bytes(bytes: &[u8]) -> Vec<u8> {
+    alloy_rlp::encode(bytes)
+}
+
+fn rlp_list(items: &[Vec<u8>]) -> Vec<u8> {
+    let payload_length = items.iter().map(Vec::len).sum();
+    let mut out = Vec::with_capacity(payload_length + 9);
+    Header { list: true, payload_length }.encode(&mut out);
+    for item in items {
+        out.extend_from_slice(item);
+    }
+    out
+}
+
+// --- INCREMENTAL TRIE ---
+
+/// Branch levels whose children are hashed as parallel tasks. Below that, subtrees are too small
+/// to be worth scheduling.
+const PARALLEL_BRANCH_LEVELS: usize = 2;
+
+/// A secure trie kept between blocks. Every node caches its reference (inline RLP or hash);
+/// an update clears the caches along the path it touched, so `root` rehashes only those nodes.
+/// All keys are 32-byte hashes, hence equally long, so branches never carry a value.
+#[derive(Debug, Default)]
+pub struct Trie {
+    root: Option<Box<Cached>>,
+}
+
+#[derive(Debug)]
+struct Cached {
+    node: Node,
+    /// `None` while dirty.
+    reference: Option<Vec<u8>>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Leaf { path: Vec<u8>, value: Vec<u8> },
+    Extension { path: Vec<u8>, child: Box<Cached> },
+    Branch { children: [Option<Box<Cached>>; 16] },
+}
+
+fn dirty(node: Node) -> Box<Cached> {
+    Box::new(Cached { node, reference: None })
+}
+
+fn leaf(path: &[u8], value: Vec<u8>) -> Box<Cached> {
+    dirty(Node::Leaf {
+        path: path.to_vec(),
+        value,
+    })
+}
+
+/// `node` hung under `path`: merged into it where it has a path of its own, else an extension.
+fn prefixed(path: &[u8], node: Box<Cached>) -> Box<Cached> {
+    if path.is_empty() {
+        return node;
+    }
+    match node.node {
+        Node::Leaf { path: rest, value } => leaf(&[path, &rest].concat(), value),
+        Node::Extension { path: rest, child } => dirty(Node::Extension {
+            path: [path, &rest].concat(),
+            child,
+        }),
+        branch => dirty(Node::Extension {
+            path: path.to_vec(),
+            child: Box::new(Cached {
+                node: branch,
+                reference: node.reference,
+            }),
+        }),
+    }
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+fn insert(slot: Option<Box<Cached>>, path: &[u8], value: Vec<u8>) -> Box<Cached> {
+    let Some(cached) = slot else {
+        return leaf(path, value);
+    };
+    match cached.node {
+        Node::Leaf { path: existing, value: old } => {
+            if existing == path {
+                return leaf(path, value);
+            }
+            let shared = common_prefix(&existing, path);
+            let mut children: [Option<Box<Cached>>; 16] = Default::default();
+            children[existing[shared] as usize] = Some(leaf(&existing[shared + 1..], old));
+            children[path[shared] as usize] = Some(leaf(&path[shared + 1..], value));
+            prefixed(&path[..shared], dirty(Node::Branch { children }))
+        }
+        Node::Extension { path: existing, child } => {
+            let shared = common_prefix(&existing, path);
+            if shared == existing.len() {
+                return dirty(Node::Extension {
+                    child: insert(Some(child), &path[shared..], value),
+                    path: existing,
+                });
+            }
+            let mut children: [Option<Box<Cached>>; 16] = Default::default();
+            children[existing[shared] as usize] = Some(prefixed(&existing[shared + 1..], child));
+            children[path[shared] as usize] = Some(leaf(&path[shared + 1..], value));
+            prefixed(&path[..shared], dirty(Node::Branch { children }))
+        }
+        Node::Branch { mut children } => {
+            let nibble = path[0] as usize;
+            children[nibble] = Some(insert(children[nibble].take(), &path[1..], value));
+            dirty(Node::Branch { children })
+        }
+    }
+}
+
+/// Removes `path` below `cached`. The flag is false (and the subtree untouched) if it was absent.
+fn remove(mut cached: Box<Cached>, path: &[u8]) -> (Option<Box<Cached>>, bool) {
+    match &mut cached.node {
+        Node::Leaf { path: existing, .. } => {
+            if existing == path {
+                (None, true)
+            } else {
+                (Some(cached), false)
+            }
+        }
+        Node::Extension { path: existing, .. } if !path.starts_with(existing) => (Some(cached), false),
+        Node::Extension { .. } => {
+            let Node::Extension { path: existing, child } = cached.node else {
+                unreachable!()
+            };
+            match remove(child, &path[existing.len()..]) {
+                (Some(child), false) => (
+                    Some(Box::new(Cached {
+                        node: Node::Extension { path: existing, child },
+                        reference: cached.reference,
+                    })),
+                    false,
+                ),
+                (Some(child), true) => (Some(prefixed(&existing, child)), true),
+                (None, removed) => (None, removed),
+            }
+        }
+        Node::Branch { children } => {
+            let nibble = path[0] as usize;
+            let Some(child) = children[nibble].take() else {
+                return (Some(cached), false);
+            };
+            let (child, removed) = remove(child, &path[1..]);
+            children[nibble] = child;
+            if !removed {
+                return (Some(cached), false);
+            }
+            let mut remaining = children.iter().enumerate().filter(|(_, child)| child.is_some());
+            match (remaining.next(), remaining.next()) {
+                (None, _) => (None, true),
+                (Some((only, _)), None) => {
+                    let child = children[only].take().expect("counted above");
+                    (Some(prefixed(&[only as u8], child)), true)
+                }
+                _ => {
+                    cached.reference = None;
+                    (Some(cached), true)
+                }
+            }
+        }
+    }
+}
+
+/// The node's reference, re-encoding it (and any dirty children) first. Counts re-encoded nodes.
+fn cached_reference<'a>(cached: &'a mut Cached, branches: usize, rehashed: &mut u64) -> &'a [u8] {
+    if cached.reference.is_none() {
+        let encoded = encode(cached, branches, rehashed);
+        cached.reference = Some(reference(encoded));
+    }
+    cached.reference.as_deref().expect("encoded above")
+}
+
+/// Re-encodes a dirty node. A branch encodes its dirty children first and hashes them as one batch.
+/// `branches` is how many branch levels lie above the node: the top `PARALLEL_BRANCH_LEVELS`
+/// encode their children as parallel tasks, up to 256 subtrees at once.
+fn encode(cached: &mut Cached, branches: usize, rehashed: &mut u64) -> Vec<u8> {
+    *rehashed += 1;
+    match &mut cached.node {
+        Node::Leaf { path, value } => rlp_list(&[rlp_bytes(&hex_prefix(path, true)), rlp_bytes(value)]),
+        Node::Extension { path, child } => {
+            let child = cached_reference(child, branches, rehashed).to_vec();
+            rlp_list(&[rlp_bytes(&hex_prefix(path, false)), child])
+        }
+        Node::Branch { children } => {
+            let encode_child = |child: &mut Option<Box<Cached>>| match child {
+                Some(child) if child.reference.is_none() => {
+                    let mut rehashed = 0;
+                    (Some(encode(child, branches + 1, &mut rehashed)), rehashed)
+                }
+                _ => (None, 0),
+            };
+            let encoded: Vec<(Option<Vec<u8>>, u64)> = if branches < PARALLEL_BRANCH_LEVELS {
+                children[..].par_iter_mut().map(encode_child).collect()
+            } else {
+                children.iter_mut().map(encode_child).collect()
+            };
+            let fresh = references(
+                encoded
+                    .into_iter()
+                    .map(|(node, child_rehashed)| {
+                        *rehashed += child_rehashed;
+                        node
+                    })
+                    .collect(),
+            );
+            let mut items = Vec::with_capacity(17);
+            for (child, fresh) in children.iter_mut().zip(fresh) {
+                items.push(match child {
+                    Some(child) => child.reference.get_or_insert(fresh).clone(),
+                    None => fresh,
+                });
+            }
+            items.push(rlp_bytes(&[]));
+            rlp_list(&items)
+        }
+    }
+}
+
+impl Trie {
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    pub fn insert(&mut self, key: B256, value: Vec<u8>) {
+        self.root = Some(insert(self.root.take(), &nibbles(key.as_slice()), value));
+    }
+
+    pub fn remove(&mut self, key: B256) {
+        if let Some(root) = self.root.take() {
+            self.root = remove(root, &nibbles(key.as_slice())).0;
+        }
+    }
+
+    /// The root hash and how many nodes had to be re-encoded for it.
+    pub fn root(&mut self) -> (B256, u64) {
+        let mut rehashed = 0;
+        let Some(root) = self.root.as_mut() else {
+            return (keccak256([EMPTY_STRING_CODE]), 0);
+        };
+        let reference = cached_reference(root, 0, &mut rehashed);
+        // A root small enough to be inlined still gets hashed.
+        let hash = if reference.len() < 32 {
+            keccak256(reference)
+        } else {
+            B256::from_slice(&reference[1..])
+        };
+        (hash, rehashed)
+    }
+}
+
+/// The state trie plus one storage trie per account with storage, kept up to date block by block.
+/// Updates only edit the tries; hashing waits for `root`, which roots every changed account's
+/// storage trie in parallel before hashing the state trie (itself split across subtrees).
+#[derive(Debug, Default)]
+pub struct StateTrie {
+    accounts: Trie,
+    storage: HashMap<Address, Trie>,
+    /// Accounts updated since the last `root`, whose leaves wait on their storage roots.
+    pending: HashMap<Address, AccountInfo>,
+}
+
+impl StateTrie {
+    pub fn build(accounts: &[SnapshotAccount]) -> Self {
+        let mut trie = Self::default();
+        for account in accounts {
+            trie.update(account.address, Some(&account.info), false, account.storage.iter().copied());
+        }
+        trie
+    }
+
+    /// Applies one account's changes: its info now (`None` once it is gone), whether its storage
+    /// was wiped first, and the current value of every slot written since the last update.
+    pub fn update(
+        &mut self,
+        address: Address,
+        info: Option<&AccountInfo>,
+        wiped: bool,
+        slots: impl IntoIterator<Item = (U256, U256)>,
+    ) {
+        let Some(info) = info else {
+            self.storage.remove(&address);
+            self.pending.remove(&address);
+            self.accounts.remove(keccak256(address));
+            return;
+        };
+        if wiped {
+            self.storage.remove(&address);
+        }
+        let storage = self.storage.entry(address).or_default();
+        let (keys, values): (Vec<_>, Vec<_>) = slots
+            .into_iter()
+            .map(|(slot, value)| (slot.to_be_bytes::<32>(), value))
+            .unzip();
+        for (slot, value) in keccak256_batch(&keys).into_iter().zip(values) {
+            if value == U256::ZERO {
+                storage.remove(slot);
+            } else {
+                storage.insert(slot, alloy_rlp::encode(value));
+            }
+        }
+        self.pending.insert(address, info.clone());
+    }
+
+    /// The state root and how many nodes (state and storage tries) were re-encoded since the last
+    /// call. Runs on the current rayon pool.
+    pub fn root(&mut self) -> (B256, u64) {
+        let mut changed: Vec<(Address, AccountInfo, Trie)> = self
+            .pending
+            .drain()
+            .map(|(address, info)| {
+                let storage = self.storage.remove(&address).unwrap_or_default();
+                (address, info, storage)
+            })
+            .collect();
+        let storage_roots: Vec<(B256, u64)> = changed.par_iter_mut().map(|(_, _, storage)| storage.root()).collect();
+
+        let keys = keccak256_batch(&changed.iter().map(|(address, _, _)| *address).collect::<Vec<_>>());
+
+        let mut rehashed = 0;
+        for (((address, info, storage), (storage_root, storage_rehashed)), key) in
+            changed.into_iter().zip(storage_roots).zip(keys)
+        {
+            rehashed += storage_rehashed;
+            self.accounts.insert(key, account_rlp(&info, storage_root));
+            if !storage.is_empty() {
+                self.storage.insert(address, storage);
+            }
+        }
+        let (root, accounts_rehashed) = self.accounts.root();
+        (root, rehashed + accounts_rehashed)
+    }
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone, Default)]
+pub struct TrieStats {
+    /// For the Verkle tree, "nodes" below are scalar multiplications.
+    pub scheme: CommitmentScheme,
+    /// Roots computed by walking the whole state (the first one, and any after state was seeded).
+    pub full_builds: u64,
+    pub full_build_nodes: u64,
+    /// Roots computed incrementally, and the nodes they re-encoded.
+    pub incremental: u64,
+    pub nodes_rehashed: u64,
+    pub last_rehashed: u64,
+    /// Wall time spent hashing, builds included, and the threads it was spread across.
+    pub hashing: Duration,
+    pub threads: usize,
+}
+
+impl fmt::Display for TrieStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "State Trie:         {} full builds ({} nodes), {} incremental roots, {} nodes rehashed ({:.1} per root, {} last)",
+            self.full_builds,
+            self.full_build_nodes,
+            self.incremental,
+            self.nodes_rehashed,
+            self.nodes_rehashed as f64 / self.incremental.max(1) as f64,
+            self.last_rehashed
+        )?;
+        writeln!(
+            f,
+            "                    {} commitment, {:?} hashing on {} threads, {} keccak",
+            self.scheme,
+            self.hashing,
+            self.threads,
+            Implementation::detect()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workload::Rng;
+    use alloy_primitives::b256;
+    use revm::primitives::KECCAK_EMPTY;
+
+    const EMPTY_ROOT: B256 = b256!("56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421");
+
+    /// Root of a plain (unhashed keys) trie, as the ethereum/tests trie vectors are given.
+    fn plain_root(pairs: &[(&str, &str)]) -> B256 {
+        let mut entries: Vec<(Vec<u8>, &[u8])> =
+            pairs.iter().map(|(key, value)| (nibbles(key.as_bytes()), value.as_bytes())).collect();
+        entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        keccak256(encode_node(&entries, 0))
+    }
+
+    fn random_key(rng: &mut Rng) -> B256 {
+        let mut key = [0u8; 32];
+        key.chunks_mut(8).for_each(|chunk| chunk.copy_from_slice(&rng.next_u64().to_be_bytes()));
+        B256::new(key)
+    }
+
+    #[test]
+    fn empty_tries_have_the_empty_root() {
+        assert_eq!(ordered_trie_root::<Vec<u8>>(&[]), EMPTY_ROOT);
+        assert_eq!(storage_root([(U256::from(1), U256::ZERO)]), EMPTY_ROOT);
+        assert_eq!(state_root([]), EMPTY_ROOT);
+        assert_eq!(Trie::default().root().0, EMPTY_ROOT);
+        assert_eq!(StateTrie::default().root().0, EMPTY_ROOT);
+    }
+
+    #[test]
+    fn matches_the_reference_trie_vectors() {
+        // ethereum/tests TrieTests/trieanyorder.json, "dogs" and "puppy".
+        let dogs = [("doe", "reindeer"), ("dog", "puppy"), ("dogglesworth", "cat")];
+        assert_eq!(plain_root(&dogs), b256!("8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3"));
+        let puppy = [("do", "verb"), ("horse", "stallion"), ("doge", "coin"), ("dog", "puppy")];
+        assert_eq!(plain_root(&puppy), b256!("5991bb8c6514148a29db676a14ac506cd2cd5775ace63c30a4fe457715e9ac84"));
+    }
+
+    #[test]
+    fn incremental_trie_matches_a_rebuilt_one() {
+        let mut rng = Rng::new(7);
+        let mut trie = Trie::default();
+        let mut entries = std::collections::BTreeMap::new();
+        for round in 0..4 {
+            for _ in 0..300 {
+                let (key, value) = (random_key(&mut rng), alloy_rlp::encode(rng.next_u64()));
+                trie.insert(key, value.clone());
+                entries.insert(key, value);
+            }
+            // Removals collapse branches back into extensions and leaves.
+            let removed: Vec<B256> = entries.keys().copied().filter(|_| rng.below(3) == 0).collect();
+            for key in removed {
+                trie.remove(key);
+                entries.remove(&key);
+            }
+            let rebuilt = secure_root(entries.iter().map(|(key, value)| (*key, value.clone())));
+            assert_eq!(trie.root().0, rebuilt, "round {}", round);
+        }
+    }
+
+    #[test]
+    fn state_trie_matches_the_state_root() {
+        let mut rng = Rng::new(11);
+        let mut accounts: Vec<SnapshotAccount> = (0..50u64)
+            .map(|k| SnapshotAccount {
+                address: Address::with_last_byte(k as u8),
+                info: AccountInfo {
+                    balance: U256::from(rng.next_u64()),
+                    nonce: k,
+                    code_hash: KECCAK_EMPTY,
+                    code: None,
+                },
+                storage: (0..k % 4).map(|slot| (U256::from(slot), U256::from(rng.next_u64()))).collect(),
+            })
+            .collect();
+        let mut trie = StateTrie::build(&accounts);
+        let expected = |accounts: &[SnapshotAccount]| {
+            let roots: Vec<B256> = accounts.iter().map(|account| storage_root(account.storage.clone())).collect();
+            state_root(accounts.iter().zip(roots).map(|(account, root)| (account.address, &account.info, root)))
+        };
+        assert_eq!(trie.root().0, expected(&accounts));
+
+        // A slot zeroed, one written, and an account gone.
+        accounts[5].storage[0].1 = U256::ZERO;
+        accounts[6].storage.push((U256::from(9), U256::from(1)));
+        trie.update(accounts[5].address, Some(&accounts[5].info), false, [accounts[5].storage[0]]);
+        trie.update(accounts[6].address, Some(&accounts[6].info), false, [(U256::from(9), U256::from(1))]);
+        let gone = accounts.remove(7);
+        trie.update(gone.address, None, false, []);
+        assert_eq!(trie.root().0, expected(&accounts));
+    }
+}
@@ -0,0 +1,250 @@
+/*
+ * FLUX ENGINE - PRE-VALIDATION
+ * `--prevalidate`: a cheap pass between prefetch and execution that turns away the transactions
+ * revm is certain to reject (intrinsic gas, fee caps, nonce, balance), so no executor runs them.
+ * Only what is invalid whatever the rest of the block does gets rejected here: a nonce outside
+ * the range the sender's earlier transactions can reach, or a cost above a balance nothing
+ * earlier in the block can have topped up. Everything else is left to revm, in block order.
+ */
+
+use crate::FluxTransaction;
+use revm::db::DatabaseRef;
+use revm::interpreter::gas::initial_tx_gas;
+use revm::primitives::{
+    Address, BerlinSpec, ByzantiumSpec, CancunSpec, Env, FrontierSpec, HashMap, HashSet, HomesteadSpec,
+    InvalidTransaction, IstanbulSpec, LatestSpec, LondonSpec, MergeSpec, PetersburgSpec, ShanghaiSpec, SpecId,
+    SpuriousDragonSpec, TangerineSpec, KECCAK_EMPTY, U256,
+};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// What the sender's account looked like before the block, and what its earlier transactions
+/// in the block can have done to it.
+struct Sender {
+    nonce: u64,
+    balance: U256,
+    /// Earlier transactions of this sender that passed; each may bump the nonce once.
+    pending: u64,
+}
+
+/// Checks each transaction of a block in order against the state before it. `verdicts[i]` is
+/// why transaction `i` can never be included, or `None` if it may be.
+pub fn prevalidate<DB: DatabaseRef<Error = String>>(
+    state: &DB,
+    block_env: &Env,
+    txs: &[FluxTransaction],
+) -> (Vec<Option<InvalidTransaction>>, ValidationStats) {
+    let started = Instant::now();
+    let mut verdicts: Vec<Option<InvalidTransaction>> = vec![None; txs.len()];
+    // An invalid block environment fails every transaction in revm; there is nothing to add.
+    if validate_block(block_env).is_ok() {
+        check_block(state, block_env, txs, &mut verdicts);
+    }
+
+    let mut stats = ValidationStats {
+        blocks: 1,
+        transactions: txs.len() as u64,
+        duration: started.elapsed(),
+        ..ValidationStats::default()
+    };
+    for verdict in verdicts.iter().flatten() {
+        match verdict {
+            InvalidTransaction::NonceTooLow { .. } | InvalidTransaction::NonceTooHigh { .. } => stats.nonce += 1,
+            InvalidTransaction::LackOfFundForMaxFee { .. } | InvalidTransaction::OverflowPaymentInTransaction => {
+                stats.funds += 1
+            }
+            InvalidTransaction::CallGasCostMoreThanGasLimit | InvalidTransaction::CallerGasLimitMoreThanBlock => {
+                stats.gas += 1
+            }
+            _ => stats.other += 1,
+        }
+    }
+    (verdicts, stats)
+}
+
+fn check_block<DB: DatabaseRef<Error = String>>(
+    state: &DB,
+    block_env: &Env,
+    txs: &[FluxTransaction],
+    verdicts: &mut [Option<InvalidTransaction>],
+) {
+    let coinbase = block_env.block.coinbase;
+    let mut senders: HashMap<Address, Option<Sender>> = HashMap::default();
+    // Accounts an earlier transaction may have sent value to. Once one has called code, any
+    // account may have been paid, and balances stop being an upper bound.
+    let mut credited: HashSet<Address> = HashSet::default();
+    let mut code_called = false;
+
+    for (i, tx) in txs.iter().enumerate() {
+        let env = tx.env(block_env);
+        let verdict = validate_tx(&env).err().or_else(|| {
+            // A sender that could not be read is left for revm to report.
+            let sender = senders
+                .entry(tx.caller)
+                .or_insert_with(|| {
+                    state.basic(tx.caller).ok().map(|info| {
+                        let info = info.unwrap_or_default();
+                        Sender {
+                            nonce: info.nonce,
+                            balance: info.balance,
+                            pending: 0,
+                        }
+                    })
+                })
+                .as_ref()?;
+            let bounded = !code_called && tx.caller != coinbase && !credited.contains(&tx.caller);
+            check_against_sender(&env, sender, bounded).err()
+        });
+
+        match verdict {
+            Some(invalid) => verdicts[i] = Some(invalid),
+            None => {
+                if let Some(Some(sender)) = senders.get_mut(&tx.caller) {
+                    sender.pending += 1;
+                }
+                credited.insert(tx.to);
+                // Init code runs like any other; a target that could not be read may be code too.
+                code_called |= tx.create;
+                if !code_called {
+                    code_called = state
+                        .basic(tx.to)
+                        .map_or(true, |info| info.is_some_and(|info| info.code_hash != KECCAK_EMPTY));
+                }
+            }
+        }
+    }
+}
+
+/// Nonce against every value the sender's earlier transactions can leave behind; with `bounded`,
+/// cost against the balance before the block, which nothing earlier can have raised.
+fn check_against_sender(env: &Env, sender: &Sender, bounded: bool) -> Result<(), InvalidTransaction> {
+    if let Some(tx) = env.tx.nonce {
+        if tx < sender.nonce {
+            return Err(InvalidTransaction::NonceTooLow { tx, state: sender.nonce });
+        }
+        if tx > sender.nonce + sender.pending {
+            return Err(InvalidTransaction::NonceTooHigh {
+                tx,
+                state: sender.nonce + sender.pending,
+            });
+        }
+    }
+    if !bounded {
+        return Ok(());
+    }
+
+    // revm's own balance check: the full gas limit at the fee cap, the value and the blob fee.
+    let mut cost = U256::from(env.tx.gas_limit)
+        .checked_mul(env.tx.gas_price)
+        .and_then(|gas_cost| gas_cost.checked_add(env.tx.value))
+        .ok_or(InvalidTransaction::OverflowPaymentInTransaction)?;
+    if SpecId::enabled(env.cfg.spec_id, SpecId::CANCUN) {
+        cost = env
+            .calc_data_fee()
+            .and_then(|data_fee| cost.checked_add(data_fee))
+            .ok_or(InvalidTransaction::OverflowPaymentInTransaction)?;
+    }
+    if cost > sender.balance {
+        return Err(InvalidTransaction::LackOfFundForMaxFee {
+            fee: env.tx.gas_limit,
+            balance: sender.balance,
+        });
+    }
+    Ok(())
+}
+
+/// Expands `$check!(Spec)` for the revm spec type of `$spec_id`, as revm picks its EVM.
+macro_rules! spec_dispatch {
+    ($spec_id:expr, $check:ident) => {
+        match $spec_id {
+            SpecId::FRONTIER | SpecId::FRONTIER_THAWING => $check!(FrontierSpec),
+            SpecId::HOMESTEAD | SpecId::DAO_FORK => $check!(HomesteadSpec),
+            SpecId::TANGERINE => $check!(TangerineSpec),
+            SpecId::SPURIOUS_DRAGON => $check!(SpuriousDragonSpec),
+            SpecId::BYZANTIUM => $check!(ByzantiumSpec),
+            SpecId::PETERSBURG | SpecId::CONSTANTINOPLE => $check!(PetersburgSpec),
+            SpecId::ISTANBUL | SpecId::MUIR_GLACIER => $check!(IstanbulSpec),
+            SpecId::BERLIN => $check!(BerlinSpec),
+            SpecId::LONDON | SpecId::ARROW_GLACIER | SpecId::GRAY_GLACIER => $check!(LondonSpec),
+            SpecId::MERGE => $check!(MergeSpec),
+            SpecId::SHANGHAI => $check!(ShanghaiSpec),
+            SpecId::CANCUN => $check!(CancunSpec),
+            SpecId::LATEST => $check!(LatestSpec),
+        }
+    };
+}
+
+/// revm's checks that need no state, under the block's spec: fee caps, block gas limit, chain
+/// id, blobs, and the intrinsic gas against the gas limit.
+fn validate_tx(env: &Env) -> Result<(), InvalidTransaction> {
+    macro_rules! validate {
+        ($spec:ty) => {{
+            env.validate_tx::<$spec>()?;
+            let is_create = env.tx.transact_to.is_create();
+            let intrinsic = initial_tx_gas::<$spec>(&env.tx.data, is_create, &env.tx.access_list);
+            if intrinsic > env.tx.gas_limit {
+                return Err(InvalidTransaction::CallGasCostMoreThanGasLimit);
+            }
+            Ok(())
+        }};
+    }
+    spec_dispatch!(env.cfg.spec_id, validate)
+}
+
+fn validate_block(env: &Env) -> Result<(), ()> {
+    macro_rules! validate {
+        ($spec:ty) => {
+            env.validate_block_env::<$spec>().map_err(|_| ())
+        };
+    }
+    spec_dispatch!(env.cfg.spec_id, validate)
+}
+
+// --- REPORTING ---
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidationStats {
+    pub blocks: u64,
+    pub transactions: u64,
+    /// Rejections by cause: nonce out of reach, cost above the balance, gas limit below the
+    /// intrinsic gas (or above the block's), anything else (fee caps, chain id, blobs).
+    pub nonce: u64,
+    pub funds: u64,
+    pub gas: u64,
+    pub other: u64,
+    pub duration: Duration,
+}
+
+impl ValidationStats {
+    pub fn rejected(&self) -> u64 {
+        self.nonce + self.funds + self.gas + self.other
+    }
+}
+
+impl std::ops::AddAssign for ValidationStats {
+    fn add_assign(&mut self, other: Self) {
+        self.blocks += other.blocks;
+        self.transactions += other.transactions;
+        self.nonce += other.nonce;
+        self.funds += other.funds;
+        self.gas += other.gas;
+        self.other += other.other;
+        self.duration += other.duration;
+    }
+}
+
+impl fmt::Display for ValidationStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Pre-Validation:     {} of {} txs rejected before execution ({} nonce, {} funds, {} gas, {} other) in {:?}",
+            self.rejected(),
+            self.transactions,
+            self.nonce,
+            self.funds,
+            self.gas,
+            self.other,
+            self.duration
+        )
+    }
+}
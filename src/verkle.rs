@@ -0,0 +1,366 @@
+/*
+ * FLUX ENGINE - VERKLE TREE (EXPERIMENTAL, `--features verkle`)
+ * The EIP-6800 tree shape (31-byte stems, 256-wide internal nodes, account header and the first
+ * storage slots sharing one leaf) with Pedersen vector commitments updated homomorphically, so a
+ * block pays the Verkle-era cost of one scalar multiplication per changed value and per level.
+ *
+ * This is a cost model, not the canonical tree: commitments are over secp256k1 (via k256) instead
+ * of Bandersnatch, stems are keccak- rather than Pedersen-hashed, code is not chunked into the
+ * tree, and the generators' discrete logs are known. Roots are only comparable with each other.
+ */
+
+use crate::state::snapshot::SnapshotAccount;
+use alloy_primitives::{keccak256, Address, B256, U256};
+use k256::elliptic_curve::ops::Reduce;
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use k256::{FieldBytes, ProjectivePoint, Scalar};
+use rayon::prelude::*;
+use revm::primitives::{AccountInfo, HashMap};
+use std::collections::{BTreeMap, HashSet};
+use std::mem;
+use std::sync::OnceLock;
+
+const WIDTH: usize = 256;
+
+type Stem = [u8; 31];
+
+// Account header suffixes (EIP-6800). Code size is left out with the code chunks.
+const VERSION_LEAF_KEY: u8 = 0;
+const BALANCE_LEAF_KEY: u8 = 1;
+const NONCE_LEAF_KEY: u8 = 2;
+const CODE_HASH_LEAF_KEY: u8 = 3;
+/// Slots below this share the account header's leaf, from suffix 64 on.
+const HEADER_STORAGE_SLOTS: u64 = 64;
+
+// --- COMMITMENTS ---
+
+/// One generator per vector position, `keccak("flux-verkle", i) * G`.
+fn generators() -> &'static [ProjectivePoint] {
+    static GENERATORS: OnceLock<Vec<ProjectivePoint>> = OnceLock::new();
+    GENERATORS.get_or_init(|| {
+        (0..WIDTH)
+            .map(|i| keccak256([b"flux-verkle".as_slice(), &[i as u8]].concat()))
+            .map(|seed| ProjectivePoint::GENERATOR * to_scalar(&seed))
+            .collect()
+    })
+}
+
+fn to_scalar(bytes: &B256) -> Scalar {
+    <Scalar as Reduce<k256::U256>>::reduce_bytes(&FieldBytes::from(bytes.0))
+}
+
+/// Maps a commitment into the scalar field, so the parent can commit to it in turn.
+fn hash_commitment(point: &ProjectivePoint) -> Scalar {
+    to_scalar(&keccak256(point.to_encoded_point(true).as_bytes()))
+}
+
+/// The two field elements a 32-byte value is committed as: the low half plus 2^128 (telling a
+/// stored zero from an absent value), and the high half.
+fn value_halves(value: Option<&B256>) -> (Scalar, Scalar) {
+    let Some(value) = value else {
+        return (Scalar::ZERO, Scalar::ZERO);
+    };
+    let half = |bytes: &[u8]| Scalar::from(u128::from_le_bytes(bytes.try_into().expect("16 bytes")));
+    let marker = Scalar::from(u128::MAX) + Scalar::ONE;
+    (half(&value[..16]) + marker, half(&value[16..]))
+}
+
+// --- KEYS ---
+
+/// `keccak(address || tree_index)[..31]`, in place of EIP-6800's Pedersen hash.
+fn stem(address: Address, tree_index: U256) -> Stem {
+    let mut input = [0u8; 64];
+    input[12..32].copy_from_slice(address.as_slice());
+    input[32..].copy_from_slice(&tree_index.to_le_bytes::<32>());
+    keccak256(input)[..31].try_into().expect("31 bytes")
+}
+
+fn storage_key(address: Address, slot: U256) -> (Stem, u8) {
+    if slot < U256::from(HEADER_STORAGE_SLOTS) {
+        return (stem(address, U256::ZERO), (HEADER_STORAGE_SLOTS + slot.to::<u64>()) as u8);
+    }
+    // MAIN_STORAGE_OFFSET (256^31) + slot, split into tree index and suffix.
+    let tree_index = (slot >> 8) + (U256::from(1) << 240);
+    (stem(address, tree_index), slot.byte(0))
+}
+
+// --- TREE ---
+
+#[derive(Debug, Default)]
+enum Node {
+    #[default]
+    Empty,
+    Internal(Box<Internal>),
+    Leaf(Box<Leaf>),
+}
+
+#[derive(Debug)]
+struct Internal {
+    children: Vec<Node>,
+    /// Each child's scalar as last folded into `commitment`.
+    folded: Vec<Scalar>,
+    /// Children changed since the last commit (unsorted, may repeat).
+    dirty: Vec<u8>,
+    commitment: ProjectivePoint,
+}
+
+#[derive(Debug)]
+struct Leaf {
+    stem: Stem,
+    values: BTreeMap<u8, B256>,
+    /// Suffix -> its value before the first change since the last commit.
+    changed: HashMap<u8, Option<B256>>,
+    /// Commitments to suffixes 0..128 and 128..256, two positions per value.
+    c1: ProjectivePoint,
+    c2: ProjectivePoint,
+    commitment: ProjectivePoint,
+}
+
+impl Node {
+    fn scalar(&self) -> Scalar {
+        match self {
+            Node::Empty => Scalar::ZERO,
+            Node::Internal(node) => hash_commitment(&node.commitment),
+            Node::Leaf(leaf) => hash_commitment(&leaf.commitment),
+        }
+    }
+
+    fn set(&mut self, stem: &Stem, depth: usize, suffix: u8, value: Option<B256>) {
+        match self {
+            Node::Empty => {
+                if value.is_some() {
+                    let mut leaf = Leaf::new(*stem);
+                    leaf.set(suffix, value);
+                    *self = Node::Leaf(Box::new(leaf));
+                }
+            }
+            Node::Leaf(leaf) if leaf.stem == *stem => leaf.set(suffix, value),
+            Node::Leaf(_) => {
+                if value.is_none() {
+                    return;
+                }
+                // Two stems meet here: push the existing leaf one level down.
+                let Node::Leaf(existing) = mem::take(self) else {
+                    unreachable!("matched a leaf")
+                };
+                let mut node = Internal::new();
+                let index = existing.stem[depth];
+                node.children[index as usize] = Node::Leaf(existing);
+                node.dirty.push(index);
+                node.set(stem, depth, suffix, value);
+                *self = Node::Internal(Box::new(node));
+            }
+            Node::Internal(node) => node.set(stem, depth, suffix, value),
+        }
+    }
+
+    /// Brings the commitments under this node up to date, collapsing what no longer needs an
+    /// internal node, and returns the scalar multiplications it took.
+    fn commit(&mut self, depth: usize) -> u64 {
+        match self {
+            Node::Empty => 0,
+            Node::Leaf(leaf) if leaf.values.is_empty() => {
+                *self = Node::Empty;
+                0
+            }
+            Node::Leaf(leaf) => leaf.commit(),
+            Node::Internal(node) => {
+                let dirty = node.take_dirty();
+                let ops = node.commit_children(&dirty, depth, false);
+                let mut live = node.children.iter().enumerate().filter(|(_, child)| !matches!(child, Node::Empty));
+                match (live.next(), live.next()) {
+                    (None, _) => *self = Node::Empty,
+                    (Some((index, Node::Leaf(_))), None) => *self = mem::take(&mut node.children[index]),
+                    _ => return ops + node.fold(&dirty),
+                }
+                ops
+            }
+        }
+    }
+}
+
+impl Internal {
+    fn new() -> Self {
+        Self {
+            children: (0..WIDTH).map(|_| Node::Empty).collect(),
+            folded: vec![Scalar::ZERO; WIDTH],
+            dirty: Vec::new(),
+            commitment: ProjectivePoint::IDENTITY,
+        }
+    }
+
+    fn set(&mut self, stem: &Stem, depth: usize, suffix: u8, value: Option<B256>) {
+        let index = stem[depth];
+        self.dirty.push(index);
+        self.children[index as usize].set(stem, depth + 1, suffix, value);
+    }
+
+    fn take_dirty(&mut self) -> Vec<u8> {
+        let mut dirty = mem::take(&mut self.dirty);
+        dirty.sort_unstable();
+        dirty.dedup();
+        dirty
+    }
+
+    fn commit_children(&mut self, dirty: &[u8], depth: usize, parallel: bool) -> u64 {
+        let mut touched = [false; WIDTH];
+        for index in dirty {
+            touched[*index as usize] = true;
+        }
+        let children = self.children.iter_mut().enumerate().filter(|(index, _)| touched[*index]);
+        if parallel {
+            children.par_bridge().map(|(_, child)| child.commit(depth + 1)).sum()
+        } else {
+            children.map(|(_, child)| child.commit(depth + 1)).sum()
+        }
+    }
+
+    /// `C += (new - old) * G_i` for every child whose scalar moved.
+    fn fold(&mut self, dirty: &[u8]) -> u64 {
+        let generators = generators();
+        let mut ops = 0;
+        for index in dirty.iter().map(|index| *index as usize) {
+            let scalar = self.children[index].scalar();
+            if scalar != self.folded[index] {
+                self.commitment += generators[index] * (scalar - self.folded[index]);
+                self.folded[index] = scalar;
+                ops += 1;
+            }
+        }
+        ops
+    }
+}
+
+impl Leaf {
+    fn new(stem: Stem) -> Self {
+        Self {
+            stem,
+            values: BTreeMap::new(),
+            changed: HashMap::default(),
+            c1: ProjectivePoint::IDENTITY,
+            c2: ProjectivePoint::IDENTITY,
+            commitment: ProjectivePoint::IDENTITY,
+        }
+    }
+
+    fn set(&mut self, suffix: u8, value: Option<B256>) {
+        let old = match value {
+            Some(value) => self.values.insert(suffix, value),
+            None => self.values.remove(&suffix),
+        };
+        if old != value {
+            self.changed.entry(suffix).or_insert(old);
+        }
+    }
+
+    /// `C = G_0 + stem * G_1 + h(C1) * G_2 + h(C2) * G_3`, after updating C1 / C2 by delta.
+    fn commit(&mut self) -> u64 {
+        if self.changed.is_empty() {
+            return 0;
+        }
+        let generators = generators();
+        let mut ops = 0;
+        for (suffix, old) in self.changed.drain() {
+            let (old_low, old_high) = value_halves(old.as_ref());
+            let (low, high) = value_halves(self.values.get(&suffix));
+            let half = if suffix < 128 { &mut self.c1 } else { &mut self.c2 };
+            let position = 2 * (suffix as usize % 128);
+            if low != old_low {
+                *half += generators[position] * (low - old_low);
+                ops += 1;
+            }
+            if high != old_high {
+                *half += generators[position + 1] * (high - old_high);
+                ops += 1;
+            }
+        }
+
+        let mut stem = [0u8; 32];
+        stem[..31].copy_from_slice(&self.stem);
+        self.commitment = generators[0]
+            + generators[1] * to_scalar(&B256::from(stem))
+            + generators[2] * hash_commitment(&self.c1)
+            + generators[3] * hash_commitment(&self.c2);
+        ops + 3
+    }
+}
+
+/// The whole state in one Verkle tree, kept between blocks like `trie::StateTrie`: updates only
+/// set values, `root` folds them into the commitments.
+#[derive(Debug)]
+pub struct VerkleTree {
+    root: Internal,
+    /// Slots each account holds, so a wipe or deletion can clear them (stems don't group them).
+    slots: HashMap<Address, HashSet<U256>>,
+}
+
+impl Default for VerkleTree {
+    fn default() -> Self {
+        Self {
+            root: Internal::new(),
+            slots: HashMap::default(),
+        }
+    }
+}
+
+impl VerkleTree {
+    pub fn build(accounts: &[SnapshotAccount]) -> Self {
+        let mut tree = Self::default();
+        for account in accounts {
+            tree.update(account.address, Some(&account.info), false, account.storage.iter().copied());
+        }
+        tree
+    }
+
+    /// Same contract as `StateTrie::update`.
+    pub fn update(
+        &mut self,
+        address: Address,
+        info: Option<&AccountInfo>,
+        wiped: bool,
+        slots: impl IntoIterator<Item = (U256, U256)>,
+    ) {
+        if wiped || info.is_none() {
+            for slot in self.slots.remove(&address).unwrap_or_default() {
+                self.set_slot(address, slot, U256::ZERO);
+            }
+        }
+        let header = stem(address, U256::ZERO);
+        let Some(info) = info else {
+            for suffix in [VERSION_LEAF_KEY, BALANCE_LEAF_KEY, NONCE_LEAF_KEY, CODE_HASH_LEAF_KEY] {
+                self.root.set(&header, 0, suffix, None);
+            }
+            return;
+        };
+        self.root.set(&header, 0, VERSION_LEAF_KEY, Some(B256::ZERO));
+        self.root
+            .set(&header, 0, BALANCE_LEAF_KEY, Some(B256::from(info.balance.to_le_bytes::<32>())));
+        self.root
+            .set(&header, 0, NONCE_LEAF_KEY, Some(B256::from(U256::from(info.nonce).to_le_bytes::<32>())));
+        self.root.set(&header, 0, CODE_HASH_LEAF_KEY, Some(info.code_hash));
+
+        for (slot, value) in slots {
+            self.set_slot(address, slot, value);
+            let held = self.slots.entry(address).or_default();
+            if value == U256::ZERO {
+                held.remove(&slot);
+            } else {
+                held.insert(slot);
+            }
+        }
+    }
+
+    fn set_slot(&mut self, address: Address, slot: U256, value: U256) {
+        let (stem, suffix) = storage_key(address, slot);
+        let value = (value != U256::ZERO).then(|| B256::from(value.to_be_bytes::<32>()));
+        self.root.set(&stem, 0, suffix, value);
+    }
+
+    /// The root commitment (hashed into 32 bytes) and the scalar multiplications it took since the
+    /// last call. The root's children are committed in parallel on the current rayon pool.
+    pub fn root(&mut self) -> (B256, u64) {
+        let dirty = self.root.take_dirty();
+        let ops = self.root.commit_children(&dirty, 0, true) + self.root.fold(&dirty);
+        (B256::from_slice(&hash_commitment(&self.root.commitment).to_bytes()), ops)
+    }
+}
@@ -0,0 +1,139 @@
+/*
+ * FLUX ENGINE - WATCHDOG
+ * Turns silent hangs in the execute/commit phases into a loud diagnostic abort.
+ */
+
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// --- PROGRESS (shared with the engine) ---
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Stage {
+    Idle = 0,
+    Speculative = 1,
+    Commit = 2,
+    /// Persist, state root, diff and prune: whole-block steps that tick no counter, so unwatched.
+    Finalize = 3,
+}
+
+impl Stage {
+    fn from_u8(v: u8) -> Self {
+        match v {
+            1 => Stage::Speculative,
+            2 => Stage::Commit,
+            3 => Stage::Finalize,
+            _ => Stage::Idle,
+        }
+    }
+
+    fn is_watched(self) -> bool {
+        matches!(self, Stage::Speculative | Stage::Commit)
+    }
+}
+
+/// Heartbeats published by the engine. Every executed or committed transaction bumps a counter,
+/// so the watchdog only needs to see *some* counter move to know the block is alive.
+#[derive(Debug, Default)]
+pub struct Progress {
+    stage: AtomicU8,
+    block_size: AtomicUsize,
+    executed: AtomicUsize,
+    committed: AtomicUsize,
+}
+
+impl Progress {
+    pub fn begin_block(&self, block_size: usize) {
+        self.block_size.store(block_size, Ordering::Relaxed);
+        self.executed.store(0, Ordering::Relaxed);
+        self.committed.store(0, Ordering::Relaxed);
+        self.set_stage(Stage::Speculative);
+    }
+
+    pub fn set_stage(&self, stage: Stage) {
+        self.stage.store(stage as u8, Ordering::Release);
+    }
+
+    pub fn tx_executed(&self) {
+        self.executed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn tx_committed(&self) {
+        self.committed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::from_u8(self.stage.load(Ordering::Acquire))
+    }
+
+    fn ticks(&self) -> usize {
+        self.executed.load(Ordering::Relaxed) + self.committed.load(Ordering::Relaxed)
+    }
+}
+
+// --- WATCHDOG THREAD ---
+
+pub struct Watchdog {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    /// Spawns the monitor. If the engine is inside a block and no transaction executes or commits
+    /// for `timeout`, the stage state is dumped to stderr and the process aborts (so a core dump /
+    /// attached debugger still has every thread's stack).
+    pub fn spawn(timeout: Duration, progress: Arc<Progress>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_flag = stop.clone();
+        let poll = (timeout / 10).max(Duration::from_millis(10));
+
+        let handle = thread::Builder::new()
+            .name("flux-watchdog".into())
+            .spawn(move || {
+                let mut last_ticks = progress.ticks();
+                let mut last_change = Instant::now();
+
+                while !stop_flag.load(Ordering::Acquire) {
+                    thread::park_timeout(poll);
+
+                    let ticks = progress.ticks();
+                    if ticks != last_ticks || !progress.stage().is_watched() {
+                        last_ticks = ticks;
+                        last_change = Instant::now();
+                        continue;
+                    }
+
+                    let stalled_for = last_change.elapsed();
+                    if stalled_for >= timeout {
+                        dump_and_abort(&progress, stalled_for);
+                    }
+                }
+            })
+            .expect("failed to spawn watchdog thread");
+
+        Self { stop, handle: Some(handle) }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+fn dump_and_abort(progress: &Progress, stalled_for: Duration) -> ! {
+    let block_size = progress.block_size.load(Ordering::Relaxed);
+    eprintln!("[FLUX][WATCHDOG] No transaction progress for {:?}. Pipeline is deadlocked.", stalled_for);
+    eprintln!("       Stage:     {:?}", progress.stage());
+    eprintln!("       Executed:  {}/{}", progress.executed.load(Ordering::Relaxed), block_size);
+    eprintln!("       Committed: {}/{}", progress.committed.load(Ordering::Relaxed), block_size);
+    eprintln!("       Aborting so the core dump captures every thread's backtrace.");
+    std::process::abort();
+}
@@ -0,0 +1,374 @@
+/*
+ * FLUX ENGINE - SYNTHETIC WORKLOAD
+ * The generated blocks: how many transactions each holds, how many accounts they are spread
+ * over and how evenly, how many call contract code instead of moving value, and how many pile
+ * onto one hot account. Every random choice comes from a generator seeded with `seed` and the
+ * block number, so a configuration always generates the same blocks. The defaults are the
+ * original benchmark: 10k transfers per block, round-robin over 100 accounts; the profiles are
+ * standard scenarios to compare strategies on.
+ */
+
+use crate::FluxTransaction;
+use revm::primitives::{AccountInfo, Address, Bytecode, Bytes, U256};
+use std::fmt;
+use std::str::FromStr;
+
+/// A counter: `slot 0 += 1`. Every call reads and writes the contract's one slot, so two calls
+/// to the same contract always conflict.
+const COUNTER_CODE: [u8; 10] = [0x60, 0x01, 0x60, 0x00, 0x54, 0x01, 0x60, 0x00, 0x55, 0x00];
+/// Enough for a cold counter increment, with room to spare.
+const CALL_GAS: u64 = 100_000;
+const TRANSFER_GAS: u64 = 21_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntheticWorkload {
+    pub txs_per_block: TxCount,
+    /// Accounts sending, round-robin. With one, every transaction conflicts with every other on
+    /// the sender's balance, whatever it is sent to.
+    pub senders: usize,
+    /// Accounts transfers are spread over, and as many contracts for the complex transactions.
+    pub contracts: usize,
+    /// How transactions pick among those accounts.
+    pub popularity: Popularity,
+    /// Share of transactions calling a contract instead of transferring value.
+    pub complexity: f64,
+    /// Share of transactions sent to the first account (or contract) instead of their own, all
+    /// of them conflicting with each other.
+    pub conflict_rate: f64,
+    pub seed: u64,
+}
+
+impl Default for SyntheticWorkload {
+    fn default() -> Self {
+        Self {
+            txs_per_block: TxCount::Fixed(10_000),
+            senders: 1,
+            contracts: 100,
+            popularity: Popularity::RoundRobin,
+            complexity: 0.0,
+            conflict_rate: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+impl SyntheticWorkload {
+    /// Accounts that must exist before the first block: the contracts complex transactions call.
+    pub fn genesis_accounts(&self) -> Vec<(Address, AccountInfo)> {
+        if self.complexity == 0.0 {
+            return vec![];
+        }
+        let code = Bytecode::new_raw(Bytes::from_static(&COUNTER_CODE));
+        let info = AccountInfo {
+            code_hash: code.hash_slow(),
+            code: Some(code),
+            ..AccountInfo::default()
+        };
+        (0..self.contracts).map(|k| (contract_address(k), info.clone())).collect()
+    }
+
+    /// The sending accounts: `first`, then `0x5e..` accounts for the rest.
+    pub fn sender_addresses(&self, first: Address) -> Vec<Address> {
+        std::iter::once(first)
+            .chain((1..self.senders).map(|k| indexed_address(0x5e, k)))
+            .collect()
+    }
+
+    /// Block `number`'s transactions, sent round-robin by `senders`. Every tx bids 30 gwei max
+    /// with a 1 gwei tip; before London the same bid goes out as a legacy gas price.
+    pub fn generate(&self, number: u64, senders: &[Address], london: bool) -> Vec<FluxTransaction> {
+        let gwei = U256::from(1_000_000_000u64);
+        let mut rng = Rng::new(self.seed ^ number.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        let count = match self.txs_per_block {
+            TxCount::Fixed(count) => count,
+            TxCount::Uniform { min, max } => min + rng.below((max - min + 1) as u64) as usize,
+        };
+        let zipf = match self.popularity {
+            Popularity::RoundRobin => None,
+            Popularity::Zipf { skew } => Some(Zipf::new(self.contracts, skew)),
+        };
+
+        (0..count)
+            .map(|i| {
+                let complex = rng.next_f64() < self.complexity;
+                let index = if rng.next_f64() < self.conflict_rate {
+                    0
+                } else {
+                    match &zipf {
+                        Some(zipf) => zipf.sample(&mut rng),
+                        None => i % self.contracts,
+                    }
+                };
+                let (to, value, gas_limit) = if complex {
+                    (contract_address(index), U256::ZERO, CALL_GAS)
+                } else {
+                    (account_address(index), U256::from(100), TRANSFER_GAS)
+                };
+                FluxTransaction {
+                    id: i,
+                    caller: senders[i % senders.len()],
+                    to,
+                    create: false,
+                    value,
+                    data: vec![],
+                    gas_limit,
+                    nonce: None,
+                    access_list: vec![],
+                    max_fee_per_gas: gwei * U256::from(30),
+                    max_priority_fee_per_gas: london.then_some(gwei),
+                    blob_hashes: vec![],
+                    max_fee_per_blob_gas: None,
+                    signature: None,
+                    skipped: false,
+                }
+            })
+            .collect()
+    }
+}
+
+/// The `k`th plain account: `k` in the low bytes, so the first 256 are `0x00..00` to `0x00..ff`.
+pub fn account_address(k: usize) -> Address {
+    indexed_address(0x00, k)
+}
+
+/// The `k`th counter contract, in its own `0xcc..` range.
+pub fn contract_address(k: usize) -> Address {
+    indexed_address(0xcc, k)
+}
+
+fn indexed_address(prefix: u8, k: usize) -> Address {
+    let mut bytes = [0u8; 20];
+    bytes[0] = prefix;
+    bytes[12..].copy_from_slice(&(k as u64).to_be_bytes());
+    Address::from(bytes)
+}
+
+/// Named starting points for `--workload`. All of them send each of a block's 10k txs from its
+/// own account, so conflicts come from what the transactions touch, not who sends them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkloadProfile {
+    /// Value transfers between many accounts: embarrassingly parallel.
+    Transfers,
+    /// Swaps against a handful of pools, the largest taking most of the volume.
+    DexHeavy,
+    /// A mint everyone piles onto: nearly every transaction increments the same counter.
+    NftMint,
+    /// Transfers and contract calls over a mainnet-like long tail.
+    Mixed,
+}
+
+impl WorkloadProfile {
+    pub fn workload(self) -> SyntheticWorkload {
+        let base = SyntheticWorkload {
+            senders: 10_000,
+            ..SyntheticWorkload::default()
+        };
+        match self {
+            WorkloadProfile::Transfers => SyntheticWorkload { contracts: 10_000, ..base },
+            WorkloadProfile::DexHeavy => SyntheticWorkload {
+                contracts: 20,
+                popularity: Popularity::Zipf { skew: 1.2 },
+                complexity: 0.8,
+                conflict_rate: 0.1,
+                ..base
+            },
+            WorkloadProfile::NftMint => SyntheticWorkload {
+                contracts: 4,
+                popularity: Popularity::Zipf { skew: 2.0 },
+                complexity: 0.95,
+                conflict_rate: 0.6,
+                ..base
+            },
+            WorkloadProfile::Mixed => SyntheticWorkload {
+                contracts: 1_000,
+                popularity: Popularity::Zipf { skew: 1.0 },
+                complexity: 0.4,
+                conflict_rate: 0.05,
+                ..base
+            },
+        }
+    }
+}
+
+impl FromStr for WorkloadProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "transfers" => Ok(WorkloadProfile::Transfers),
+            "dex-heavy" => Ok(WorkloadProfile::DexHeavy),
+            "nft-mint" => Ok(WorkloadProfile::NftMint),
+            "mixed" => Ok(WorkloadProfile::Mixed),
+            _ => Err(format!("unknown workload {:?} (transfers|dex-heavy|nft-mint|mixed)", s)),
+        }
+    }
+}
+
+impl fmt::Display for WorkloadProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            WorkloadProfile::Transfers => "transfers",
+            WorkloadProfile::DexHeavy => "dex-heavy",
+            WorkloadProfile::NftMint => "nft-mint",
+            WorkloadProfile::Mixed => "mixed",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Popularity {
+    /// Transaction `i` goes to account `i % contracts`: every account equally busy, and the
+    /// transactions kept apart as far as the account count allows.
+    #[default]
+    RoundRobin,
+    /// Account `k` is drawn with probability proportional to `1 / (k + 1)^skew`: a few hot
+    /// accounts take most of the traffic and the rest a long tail, as on mainnet.
+    Zipf { skew: f64 },
+}
+
+impl FromStr for Popularity {
+    type Err = String;
+
+    /// `round-robin` or `zipf[:<skew>]` (zipf = zipf:1.0).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unknown = || format!("unknown popularity {:?} (round-robin|zipf[:<skew>])", s);
+        match s {
+            "round-robin" => return Ok(Popularity::RoundRobin),
+            "zipf" => return Ok(Popularity::Zipf { skew: 1.0 }),
+            _ => {}
+        }
+        let skew: f64 = s
+            .strip_prefix("zipf:")
+            .ok_or_else(unknown)?
+            .parse()
+            .map_err(|_| unknown())?;
+        if !(skew > 0.0 && skew.is_finite()) {
+            return Err(format!("zipf skew must be a positive number, got {}", skew));
+        }
+        Ok(Popularity::Zipf { skew })
+    }
+}
+
+impl fmt::Display for Popularity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Popularity::RoundRobin => f.write_str("round-robin"),
+            Popularity::Zipf { skew } => write!(f, "zipf:{}", skew),
+        }
+    }
+}
+
+/// Draws ranks `0..n` with Zipf weights by binary search over the cumulative distribution.
+struct Zipf {
+    cdf: Vec<f64>,
+}
+
+impl Zipf {
+    fn new(n: usize, skew: f64) -> Self {
+        let mut total = 0.0;
+        let mut cdf: Vec<f64> = (1..=n)
+            .map(|rank| {
+                total += 1.0 / (rank as f64).powf(skew);
+                total
+            })
+            .collect();
+        for weight in &mut cdf {
+            *weight /= total;
+        }
+        Self { cdf }
+    }
+
+    fn sample(&self, rng: &mut Rng) -> usize {
+        let target = rng.next_f64();
+        self.cdf.partition_point(|&weight| weight <= target).min(self.cdf.len() - 1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxCount {
+    Fixed(usize),
+    /// Drawn uniformly from `min..=max` for every block.
+    Uniform { min: usize, max: usize },
+}
+
+impl FromStr for TxCount {
+    type Err = String;
+
+    /// `<N>` or `<min>-<max>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |raw: &str| {
+            raw.trim()
+                .parse::<usize>()
+                .map_err(|_| format!("invalid txs per block {:?} (expected <N> or <min>-<max>)", s))
+        };
+        let count = match s.split_once('-') {
+            Some((min, max)) => TxCount::Uniform {
+                min: parse(min)?,
+                max: parse(max)?,
+            },
+            None => TxCount::Fixed(parse(s)?),
+        };
+        match count {
+            TxCount::Fixed(0) => Err("txs per block must be at least 1".into()),
+            TxCount::Uniform { min, max } if min == 0 || min > max => {
+                Err(format!("txs per block range needs 1 <= min <= max, got {}-{}", min, max))
+            }
+            count => Ok(count),
+        }
+    }
+}
+
+impl fmt::Display for TxCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxCount::Fixed(count) => write!(f, "{}", count),
+            TxCount::Uniform { min, max } => write!(f, "{}-{}", min, max),
+        }
+    }
+}
+
+impl fmt::Display for SyntheticWorkload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} txs/block from {} senders to {} accounts ({}), {:.0}% contract calls, {:.0}% to the hot account, \
+             seed {}",
+            self.txs_per_block,
+            self.senders,
+            self.contracts,
+            self.popularity,
+            self.complexity * 100.0,
+            self.conflict_rate * 100.0,
+            self.seed
+        )
+    }
+}
+
+/// SplitMix64: tiny, fast, and the same on every platform and toolchain, which is all a
+/// reproducible workload asks of a generator.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in `[0, n)`.
+    pub fn below(&mut self, n: u64) -> u64 {
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+}
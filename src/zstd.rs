@@ -0,0 +1,1679 @@
+/*
+ * FLUX ENGINE - ZSTD
+ * Zstandard (RFC 8878) for the files long replays pile up: the RPC block cache, access traces and
+ * checkpoint state dumps. As with snappy, it is done here rather than pulling in a codec.
+ *
+ * The decoder reads any zstd stream, concatenated and skippable frames included. Dictionaries are
+ * not supported. Where a frame carries a content checksum (XXH64 of what it holds, low 32 bits),
+ * it is checked at the end of the frame, so damage that still decodes is caught there. The
+ * encoder is simpler than the reference one: hash-chain match finding whose window, chain depth
+ * and laziness grow with the level, Huffman-coded literals when their symbols fit a directly
+ * described table (text does), and the predefined FSE tables for sequences. Any zstd decoder
+ * reads what it writes.
+ */
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+
+const MAGIC: u32 = 0xfd2f_b528;
+/// Skippable frames use any of 16 magic numbers, this one with the low nibble cleared.
+const SKIPPABLE_MAGIC: u32 = 0x184d_2a50;
+const MAX_BLOCK: usize = 128 * 1024;
+/// Largest window the decoder keeps history for.
+const MAX_WINDOW: usize = 1 << 27;
+const MIN_MATCH: usize = 4;
+/// Blocks shorter than this are stored raw.
+const MIN_COMPRESS: usize = 32;
+/// What a match has to save, in `Encoder::gain` terms, to be worth a sequence.
+const MIN_GAIN: i64 = 8;
+pub const MAX_LEVEL: u32 = 22;
+
+const RAW_BLOCK: u8 = 0;
+const RLE_BLOCK: u8 = 1;
+const COMPRESSED_BLOCK: u8 = 2;
+
+const RAW_LITERALS: u8 = 0;
+const RLE_LITERALS: u8 = 1;
+const COMPRESSED_LITERALS: u8 = 2;
+const TREELESS_LITERALS: u8 = 3;
+
+const MAX_HUFFMAN_BITS: u32 = 11;
+
+// Literal lengths, match lengths and offsets are coded as a symbol plus extra bits.
+const LL_BASE: [u32; 36] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 18, 20, 22, 24, 28, 32, 40, 48, 64, 128, 256, 512,
+    1024, 2048, 4096, 8192, 16384, 32768, 65536,
+];
+const LL_BITS: [u8; 36] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 4, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+];
+const ML_BASE: [u32; 53] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32,
+    33, 34, 35, 37, 39, 41, 43, 47, 51, 59, 67, 83, 99, 131, 259, 515, 1027, 2051, 4099, 8195, 16387, 32771, 65539,
+];
+const ML_BITS: [u8; 53] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2,
+    3, 3, 4, 4, 5, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+];
+const MAX_OF_CODE: u8 = 31;
+
+// The predefined distributions, for blocks whose sequences do not describe their own.
+const LL_DEFAULT: [i16; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1, -1, -1, -1, -1,
+];
+const LL_DEFAULT_LOG: u32 = 6;
+const ML_DEFAULT: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+];
+const ML_DEFAULT_LOG: u32 = 6;
+const OF_DEFAULT: [i16; 29] =
+    [1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1];
+const OF_DEFAULT_LOG: u32 = 5;
+
+/// Whether `data` starts like a zstd stream.
+pub fn is_zstd(data: &[u8]) -> bool {
+    match data.get(..4) {
+        Some(magic) => {
+            let magic = u32::from_le_bytes(magic.try_into().expect("four bytes"));
+            magic == MAGIC || magic & !0xf == SKIPPABLE_MAGIC
+        }
+        None => false,
+    }
+}
+
+/// `data` as one frame, at `level` (1 to `MAX_LEVEL`).
+pub fn compress(data: &[u8], level: u32) -> Vec<u8> {
+    let mut encoder = Encoder::with_size_hint(Vec::new(), level, Some(data.len()));
+    encoder.write_all(data).expect("writing to a Vec");
+    encoder.finish().expect("writing to a Vec")
+}
+
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(data.len() * 4);
+    Decoder::new(data).read_to_end(&mut out).map_err(|e| e.to_string())?;
+    Ok(out)
+}
+
+/// Opens `path` for reading, decompressing it on the fly if it is a zstd stream.
+pub fn open(path: &Path) -> io::Result<Box<dyn BufRead + Send>> {
+    let mut file = BufReader::new(File::open(path)?);
+    if is_zstd(file.fill_buf()?) {
+        Ok(Box::new(BufReader::new(Decoder::new(file))))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+/// A file being written plain, or as one zstd frame when given a level. `finish` completes it.
+pub enum Writer {
+    Plain(BufWriter<File>),
+    Compressed(Encoder<BufWriter<File>>),
+}
+
+impl Writer {
+    pub fn create(path: &Path, level: Option<u32>) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(match level {
+            Some(level) => Writer::Compressed(Encoder::new(file, level)),
+            None => Writer::Plain(file),
+        })
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Writer::Plain(mut file) => file.flush(),
+            Writer::Compressed(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Writer::Plain(file) => file.write(buf),
+            Writer::Compressed(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Writer::Plain(file) => file.flush(),
+            Writer::Compressed(encoder) => encoder.flush(),
+        }
+    }
+}
+
+// --- BITSTREAMS ---
+
+fn highbit(n: u32) -> u32 {
+    31 - n.leading_zeros()
+}
+
+/// Little-endian bits, least significant first: how every zstd bitstream is written.
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u64, bits: u32) {
+        if bits == 0 {
+            return;
+        }
+        self.acc |= (value & ((1 << bits) - 1)) << self.bits;
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    /// Ends the stream with the 1 bit a backward reader starts from.
+    fn close(mut self) -> Vec<u8> {
+        self.put(1, 1);
+        if self.bits > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+/// Reads a closed stream from its end back, as Huffman and FSE streams are decoded. Reads past
+/// the start yield zeros and leave `overflowed` set.
+struct BackwardBits<'a> {
+    data: &'a [u8],
+    /// Bits not read yet.
+    pos: isize,
+}
+
+impl<'a> BackwardBits<'a> {
+    fn new(data: &'a [u8]) -> Result<Self, String> {
+        match data.last() {
+            Some(&last) if last != 0 => Ok(Self {
+                data,
+                pos: (data.len() * 8 - 8) as isize + highbit(last as u32) as isize,
+            }),
+            _ => Err("bitstream without an end mark".into()),
+        }
+    }
+
+    fn read(&mut self, bits: u32) -> u64 {
+        let value = self.peek(bits);
+        self.pos -= bits as isize;
+        value
+    }
+
+    fn peek(&self, bits: u32) -> u64 {
+        if bits == 0 {
+            return 0;
+        }
+        let start = self.pos - bits as isize;
+        if start < 0 {
+            let available = self.pos.max(0) as u32;
+            return self.bits_at(0, available) << (bits - available);
+        }
+        self.bits_at(start as usize, bits)
+    }
+
+    fn bits_at(&self, start: usize, bits: u32) -> u64 {
+        if bits == 0 {
+            return 0;
+        }
+        let mut word = [0u8; 8];
+        let bytes = self.data.get(start / 8..).unwrap_or_default();
+        let n = bytes.len().min(8);
+        word[..n].copy_from_slice(&bytes[..n]);
+        (u64::from_le_bytes(word) >> (start % 8)) & ((1 << bits) - 1)
+    }
+
+    fn overflowed(&self) -> bool {
+        self.pos < 0
+    }
+}
+
+/// Reads forward, least significant bit first, as table descriptions are stored.
+struct ForwardBits<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl ForwardBits<'_> {
+    fn read(&mut self, bits: u32) -> u64 {
+        let value = self.peek(bits);
+        self.pos += bits as usize;
+        value
+    }
+
+    fn peek(&self, bits: u32) -> u64 {
+        let mut word = [0u8; 8];
+        let bytes = self.data.get(self.pos / 8..).unwrap_or_default();
+        let n = bytes.len().min(8);
+        word[..n].copy_from_slice(&bytes[..n]);
+        (u64::from_le_bytes(word) >> (self.pos % 8)) & ((1 << bits) - 1)
+    }
+}
+
+// --- FSE ---
+
+#[derive(Debug, Clone, Copy, Default)]
+struct FseEntry {
+    symbol: u8,
+    bits: u8,
+    base: u16,
+}
+
+#[derive(Debug, Clone)]
+struct FseTable {
+    log: u32,
+    entries: Vec<FseEntry>,
+}
+
+/// Where each symbol's states go in a table: the same spread for encoding and decoding.
+/// Symbols of probability "less than 1" (-1) take the last cells, one each.
+fn spread(norm: &[i16], log: u32) -> Result<(Vec<u8>, usize), String> {
+    let size = 1usize << log;
+    let mut symbols = vec![0u8; size];
+    let mut high = size;
+    for (symbol, &count) in norm.iter().enumerate() {
+        if count == -1 {
+            high -= 1;
+            symbols[high] = symbol as u8;
+        }
+    }
+    let step = (size >> 1) + (size >> 3) + 3;
+    let mut position = 0;
+    for (symbol, &count) in norm.iter().enumerate() {
+        for _ in 0..count.max(0) {
+            symbols[position] = symbol as u8;
+            position = (position + step) & (size - 1);
+            while position >= high {
+                position = (position + step) & (size - 1);
+            }
+        }
+    }
+    if position != 0 {
+        return Err("FSE table does not add up".into());
+    }
+    Ok((symbols, high))
+}
+
+impl FseTable {
+    fn new(norm: &[i16], log: u32) -> Result<Self, String> {
+        let (symbols, _) = spread(norm, log)?;
+        let mut next: Vec<u32> = norm.iter().map(|&count| if count == -1 { 1 } else { count.max(0) as u32 }).collect();
+        let entries = symbols
+            .iter()
+            .map(|&symbol| {
+                let state = next[symbol as usize];
+                next[symbol as usize] += 1;
+                let bits = log - highbit(state);
+                FseEntry {
+                    symbol,
+                    bits: bits as u8,
+                    base: ((state << bits) - (1 << log)) as u16,
+                }
+            })
+            .collect();
+        Ok(Self { log, entries })
+    }
+
+    /// A table that always decodes `symbol` and reads no bits.
+    fn rle(symbol: u8) -> Self {
+        Self {
+            log: 0,
+            entries: vec![FseEntry { symbol, bits: 0, base: 0 }],
+        }
+    }
+
+    /// Parses a table description, returning it and the bytes it took.
+    fn read(data: &[u8], max_log: u32, max_symbol: usize) -> Result<(Self, usize), String> {
+        let mut bits = ForwardBits { data, pos: 0 };
+        let log = bits.read(4) as u32 + 5;
+        if log > max_log {
+            return Err(format!("FSE accuracy {} over the limit of {}", log, max_log));
+        }
+        let mut remaining = (1i32 << log) + 1;
+        let mut threshold = 1i32 << log;
+        let mut width = log + 1;
+        let mut norm: Vec<i16> = Vec::new();
+        while remaining > 1 && norm.len() <= max_symbol {
+            let max = 2 * threshold - 1 - remaining;
+            let value = bits.peek(width) as i32;
+            let mut count = if value & (threshold - 1) < max {
+                bits.pos += width as usize - 1;
+                value & (threshold - 1)
+            } else {
+                bits.pos += width as usize;
+                let count = value & (2 * threshold - 1);
+                if count >= threshold {
+                    count - max
+                } else {
+                    count
+                }
+            };
+            count -= 1;
+            remaining -= count.abs();
+            norm.push(count as i16);
+            if count == 0 {
+                loop {
+                    let repeat = bits.read(2);
+                    norm.resize(norm.len() + repeat as usize, 0);
+                    if repeat != 3 {
+                        break;
+                    }
+                }
+            }
+            while remaining < threshold {
+                width -= 1;
+                threshold >>= 1;
+            }
+        }
+        let used = bits.pos.div_ceil(8);
+        if remaining != 1 || norm.len() > max_symbol + 1 || used > data.len() {
+            return Err("corrupt FSE table description".into());
+        }
+        Ok((Self::new(&norm, log)?, used))
+    }
+
+    fn symbol(&self, state: usize) -> u8 {
+        self.entries[state].symbol
+    }
+
+    fn update(&self, state: usize, bits: &mut BackwardBits) -> usize {
+        let entry = self.entries[state];
+        entry.base as usize + bits.read(entry.bits as u32) as usize
+    }
+}
+
+/// The encoding side of an FSE table.
+struct FseEncoder {
+    log: u32,
+    states: Vec<u16>,
+    /// Per symbol: where its states start, relative to its first cell, and the bit count offset.
+    symbols: Vec<(i32, u32)>,
+}
+
+impl FseEncoder {
+    fn new(norm: &[i16], log: u32) -> Self {
+        let size = 1u32 << log;
+        let (symbols, _) = spread(norm, log).expect("predefined tables add up");
+        let mut cumulative = Vec::with_capacity(norm.len());
+        let mut total = 0i32;
+        let mut transforms = Vec::with_capacity(norm.len());
+        for &count in norm {
+            cumulative.push(total as usize);
+            transforms.push(match count {
+                0 => (0, 0),
+                -1 | 1 => {
+                    total += 1;
+                    (total - 2, (log << 16) - size)
+                }
+                count => {
+                    let count = count as u32;
+                    let max_bits_out = log - highbit(count - 1);
+                    let transform = (total - count as i32, (max_bits_out << 16) - (count << max_bits_out));
+                    total += count as i32;
+                    transform
+                }
+            });
+        }
+        let mut states = vec![0u16; size as usize];
+        for (cell, &symbol) in symbols.iter().enumerate() {
+            let slot = &mut cumulative[symbol as usize];
+            states[*slot] = (size as usize + cell) as u16;
+            *slot += 1;
+        }
+        Self {
+            log,
+            states,
+            symbols: transforms,
+        }
+    }
+
+    fn init(&self, symbol: u8) -> u32 {
+        let (find, delta_bits) = self.symbols[symbol as usize];
+        let bits_out = (delta_bits + (1 << 15)) >> 16;
+        let value = (bits_out << 16) - delta_bits;
+        self.states[((value >> bits_out) as i32 + find) as usize] as u32
+    }
+
+    fn encode(&self, state: &mut u32, symbol: u8, out: &mut BitWriter) {
+        let (find, delta_bits) = self.symbols[symbol as usize];
+        let bits_out = (*state + delta_bits) >> 16;
+        out.put(*state as u64, bits_out);
+        *state = self.states[((*state >> bits_out) as i32 + find) as usize] as u32;
+    }
+
+    fn flush(&self, state: u32, out: &mut BitWriter) {
+        out.put(state as u64, self.log);
+    }
+}
+
+struct DefaultTables {
+    ll: FseTable,
+    ml: FseTable,
+    of: FseTable,
+    ll_encoder: FseEncoder,
+    ml_encoder: FseEncoder,
+    of_encoder: FseEncoder,
+}
+
+fn default_tables() -> &'static DefaultTables {
+    static TABLES: OnceLock<DefaultTables> = OnceLock::new();
+    TABLES.get_or_init(|| DefaultTables {
+        ll: FseTable::new(&LL_DEFAULT, LL_DEFAULT_LOG).expect("predefined tables add up"),
+        ml: FseTable::new(&ML_DEFAULT, ML_DEFAULT_LOG).expect("predefined tables add up"),
+        of: FseTable::new(&OF_DEFAULT, OF_DEFAULT_LOG).expect("predefined tables add up"),
+        ll_encoder: FseEncoder::new(&LL_DEFAULT, LL_DEFAULT_LOG),
+        ml_encoder: FseEncoder::new(&ML_DEFAULT, ML_DEFAULT_LOG),
+        of_encoder: FseEncoder::new(&OF_DEFAULT, OF_DEFAULT_LOG),
+    })
+}
+
+// --- HUFFMAN ---
+
+#[derive(Debug, Clone)]
+struct HuffmanTable {
+    max_bits: u32,
+    /// Indexed by the next `max_bits` bits: the symbol and its code length.
+    entries: Vec<(u8, u8)>,
+}
+
+impl HuffmanTable {
+    /// Parses a tree description, returning it and the bytes it took.
+    fn read(data: &[u8]) -> Result<(Self, usize), String> {
+        let header = *data.first().ok_or("missing Huffman tree")? as usize;
+        let mut weights: Vec<u8> = Vec::new();
+        let used = if header < 128 {
+            let stream = data.get(1..1 + header).ok_or("truncated Huffman tree")?;
+            let (table, table_len) = FseTable::read(stream, 6, 255)?;
+            let mut bits = BackwardBits::new(&stream[table_len..])?;
+            let mut states = [bits.read(table.log) as usize, bits.read(table.log) as usize];
+            // Two states take turns; once the stream runs out, the other one has a last symbol.
+            'decode: loop {
+                for turn in 0..2 {
+                    weights.push(table.symbol(states[turn]));
+                    states[turn] = table.update(states[turn], &mut bits);
+                    if bits.overflowed() {
+                        weights.push(table.symbol(states[1 - turn]));
+                        break 'decode;
+                    }
+                    if weights.len() > 255 {
+                        return Err("too many Huffman weights".into());
+                    }
+                }
+            }
+            1 + header
+        } else {
+            let count = header - 127;
+            let packed = data.get(1..1 + count.div_ceil(2)).ok_or("truncated Huffman tree")?;
+            weights.extend((0..count).map(|i| if i % 2 == 0 { packed[i / 2] >> 4 } else { packed[i / 2] & 0xf }));
+            1 + count.div_ceil(2)
+        };
+        Ok((Self::from_weights(weights)?, used))
+    }
+
+    /// The last symbol's weight is left out; it is whatever fills the code space up.
+    fn from_weights(mut weights: Vec<u8>) -> Result<Self, String> {
+        if weights.len() > 255 || weights.iter().any(|&weight| weight as u32 > MAX_HUFFMAN_BITS + 1) {
+            return Err("corrupt Huffman weights".into());
+        }
+        let total: u32 = weights.iter().filter(|&&weight| weight > 0).map(|&weight| 1 << (weight - 1)).sum();
+        if total == 0 {
+            return Err("empty Huffman tree".into());
+        }
+        let max_bits = highbit(total) + 1;
+        let rest = (1 << max_bits) - total;
+        if max_bits > MAX_HUFFMAN_BITS || !rest.is_power_of_two() {
+            return Err("corrupt Huffman weights".into());
+        }
+        weights.push(highbit(rest) as u8 + 1);
+        let mut entries = vec![(0u8, 0u8); 1 << max_bits];
+        let mut position = 0;
+        for weight in 1..=max_bits as u8 {
+            for (symbol, _) in weights.iter().enumerate().filter(|(_, &w)| w == weight) {
+                let span = 1 << (weight - 1);
+                entries[position..position + span].fill((symbol as u8, (max_bits + 1 - weight as u32) as u8));
+                position += span;
+            }
+        }
+        Ok(Self { max_bits, entries })
+    }
+
+    fn decode_stream(&self, data: &[u8], count: usize, out: &mut Vec<u8>) -> Result<(), String> {
+        let mut bits = BackwardBits::new(data)?;
+        for _ in 0..count {
+            let (symbol, length) = self.entries[bits.peek(self.max_bits) as usize];
+            bits.pos -= length as isize;
+            out.push(symbol);
+        }
+        if bits.pos != 0 {
+            return Err("corrupt Huffman stream".into());
+        }
+        Ok(())
+    }
+}
+
+/// Code lengths for `counts`, at most `limit` bits, filling the code space exactly.
+fn huffman_lengths(counts: &[u32], limit: u32) -> Vec<u32> {
+    let mut parents: Vec<usize> = vec![usize::MAX; counts.len()];
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = counts
+        .iter()
+        .enumerate()
+        .filter(|(_, &count)| count > 0)
+        .map(|(symbol, &count)| Reverse((count as u64, symbol)))
+        .collect();
+    while heap.len() > 1 {
+        let Reverse((a, left)) = heap.pop().expect("two nodes");
+        let Reverse((b, right)) = heap.pop().expect("two nodes");
+        let node = parents.len();
+        parents.push(usize::MAX);
+        parents[left] = node;
+        parents[right] = node;
+        heap.push(Reverse((a + b, node)));
+    }
+    let mut lengths: Vec<u32> = (0..counts.len())
+        .map(|symbol| {
+            if counts[symbol] == 0 {
+                return 0;
+            }
+            let (mut node, mut depth) = (symbol, 0);
+            while parents[node] != usize::MAX {
+                node = parents[node];
+                depth += 1;
+            }
+            depth.min(limit)
+        })
+        .collect();
+
+    // Clamping overfilled the code space: lengthen the longest codes still under the limit, then
+    // hand back whatever that freed up to the longest codes it fits.
+    let unit = |length: u32| 1u64 << (limit - length);
+    let mut used: u64 = lengths.iter().filter(|&&length| length > 0).map(|&length| unit(length)).sum();
+    let full = 1u64 << limit;
+    while used > full {
+        let symbol = (0..lengths.len())
+            .filter(|&symbol| lengths[symbol] > 0 && lengths[symbol] < limit)
+            .max_by_key(|&symbol| (lengths[symbol], Reverse(counts[symbol])))
+            .expect("a code under the limit");
+        lengths[symbol] += 1;
+        used -= unit(lengths[symbol]);
+    }
+    while used < full {
+        let symbol = (0..lengths.len())
+            .filter(|&symbol| lengths[symbol] > 1 && unit(lengths[symbol]) <= full - used)
+            .max_by_key(|&symbol| (lengths[symbol], counts[symbol]))
+            .expect("the longest code fits");
+        used += unit(lengths[symbol]);
+        lengths[symbol] -= 1;
+    }
+    lengths
+}
+
+// --- XXH64 ---
+
+const PRIME64_1: u64 = 0x9e37_79b1_85eb_ca87;
+const PRIME64_2: u64 = 0xc2b2_ae3d_27d4_eb4f;
+const PRIME64_3: u64 = 0x1656_67b1_9e37_79f9;
+const PRIME64_4: u64 = 0x85eb_ca77_c2b2_ae63;
+const PRIME64_5: u64 = 0x27d4_eb2f_1656_67c5;
+
+/// XXH64 with seed 0, over data fed in pieces.
+struct Xxh64 {
+    lanes: [u64; 4],
+    /// Input not yet making up a whole 32-byte stripe.
+    pending: Vec<u8>,
+    total: u64,
+}
+
+impl Xxh64 {
+    fn new() -> Self {
+        Self {
+            lanes: [PRIME64_1.wrapping_add(PRIME64_2), PRIME64_2, 0, PRIME64_1.wrapping_neg()],
+            pending: Vec::with_capacity(32),
+            total: 0,
+        }
+    }
+
+    fn round(lane: u64, input: u64) -> u64 {
+        lane.wrapping_add(input.wrapping_mul(PRIME64_2)).rotate_left(31).wrapping_mul(PRIME64_1)
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (lane, word) in self.lanes.iter_mut().zip(stripe.chunks_exact(8)) {
+            *lane = Self::round(*lane, u64::from_le_bytes(word.try_into().expect("eight bytes")));
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.total += data.len() as u64;
+        if !self.pending.is_empty() {
+            let take = (32 - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.pending.len() < 32 {
+                return;
+            }
+            let stripe: [u8; 32] = self.pending[..].try_into().expect("a whole stripe");
+            self.pending.clear();
+            self.stripe(&stripe);
+        }
+        let mut stripes = data.chunks_exact(32);
+        for stripe in &mut stripes {
+            self.stripe(stripe);
+        }
+        self.pending.extend_from_slice(stripes.remainder());
+    }
+
+    fn digest(&self) -> u64 {
+        let mut hash = if self.total >= 32 {
+            let [a, b, c, d] = self.lanes;
+            let mut hash = a
+                .rotate_left(1)
+                .wrapping_add(b.rotate_left(7))
+                .wrapping_add(c.rotate_left(12))
+                .wrapping_add(d.rotate_left(18));
+            for lane in self.lanes {
+                hash = (hash ^ Self::round(0, lane)).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4);
+            }
+            hash
+        } else {
+            PRIME64_5
+        };
+        hash = hash.wrapping_add(self.total);
+        let mut rest = self.pending.as_slice();
+        while let Some((word, tail)) = rest.split_first_chunk::<8>() {
+            hash ^= Self::round(0, u64::from_le_bytes(*word));
+            hash = hash.rotate_left(27).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4);
+            rest = tail;
+        }
+        if let Some((word, tail)) = rest.split_first_chunk::<4>() {
+            hash ^= u64::from(u32::from_le_bytes(*word)).wrapping_mul(PRIME64_1);
+            hash = hash.rotate_left(23).wrapping_mul(PRIME64_2).wrapping_add(PRIME64_3);
+            rest = tail;
+        }
+        for &byte in rest {
+            hash ^= u64::from(byte).wrapping_mul(PRIME64_5);
+            hash = hash.rotate_left(11).wrapping_mul(PRIME64_1);
+        }
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(PRIME64_2);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(PRIME64_3);
+        hash ^ hash >> 32
+    }
+}
+
+// --- DECODING ---
+
+/// Decompresses a zstd stream as it is read.
+pub struct Decoder<R: Read> {
+    input: R,
+    /// Decoded bytes: up to a window of history, then what has not been read yet.
+    out: Vec<u8>,
+    read: usize,
+    frame: Option<Frame>,
+}
+
+struct Frame {
+    window: usize,
+    /// What the frame has decoded so far, hashed, if it ends with a content checksum.
+    checksum: Option<Xxh64>,
+    last_block: bool,
+    /// The three most recent offsets, most recent first.
+    offsets: [usize; 3],
+    huffman: Option<HuffmanTable>,
+    ll: Option<FseTable>,
+    of: Option<FseTable>,
+    ml: Option<FseTable>,
+}
+
+impl<R: Read> Decoder<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            out: Vec::new(),
+            read: 0,
+            frame: None,
+        }
+    }
+
+    /// Reads `buf` whole; false if the input ended right before it.
+    fn read_exact_or_end(&mut self, buf: &mut [u8]) -> Result<bool, String> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.input.read(&mut buf[filled..]) {
+                Ok(0) if filled == 0 => return Ok(false),
+                Ok(0) => return Err("truncated zstd stream".into()),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        Ok(true)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), String> {
+        match self.read_exact_or_end(buf)? {
+            true => Ok(()),
+            false => Err("truncated zstd stream".into()),
+        }
+    }
+
+    /// Decodes the next block (or frame header), false once the input is used up.
+    fn advance(&mut self) -> Result<bool, String> {
+        let Some(frame) = &self.frame else {
+            return self.start_frame();
+        };
+        if frame.last_block {
+            // The frame is done once its checksum, if it has one, is read.
+            if let Some(hash) = self.frame.take().and_then(|frame| frame.checksum) {
+                let mut expected = [0u8; 4];
+                self.read_exact(&mut expected)?;
+                let (actual, expected) = (hash.digest() as u32, u32::from_le_bytes(expected));
+                if actual != expected {
+                    return Err(format!(
+                        "zstd content checksum mismatch: frame says {:08x}, content hashes to {:08x}",
+                        expected, actual
+                    ));
+                }
+            }
+            return Ok(true);
+        }
+        // Everything read before the last window is no longer needed.
+        if self.out.len() > 2 * frame.window.max(MAX_BLOCK) {
+            let drop = self.out.len() - frame.window;
+            self.out.drain(..drop);
+            self.read -= drop;
+        }
+
+        let mut header = [0u8; 3];
+        self.read_exact(&mut header)?;
+        let header = u32::from_le_bytes([header[0], header[1], header[2], 0]);
+        let size = (header >> 3) as usize;
+        let kind = (header >> 1 & 3) as u8;
+        if size > MAX_BLOCK {
+            return Err(format!("zstd block of {} bytes", size));
+        }
+        let start = self.out.len();
+        match kind {
+            RAW_BLOCK => {
+                self.out.resize(start + size, 0);
+                let mut raw = std::mem::take(&mut self.out);
+                let read = self.read_exact(&mut raw[start..]);
+                self.out = raw;
+                read?;
+            }
+            RLE_BLOCK => {
+                let mut byte = [0u8];
+                self.read_exact(&mut byte)?;
+                self.out.resize(self.out.len() + size, byte[0]);
+            }
+            COMPRESSED_BLOCK => {
+                let mut block = vec![0u8; size];
+                self.read_exact(&mut block)?;
+                let frame = self.frame.as_mut().expect("inside a frame");
+                decode_block(frame, &block, &mut self.out)?;
+            }
+            _ => return Err("reserved zstd block type".into()),
+        }
+        let frame = self.frame.as_mut().expect("inside a frame");
+        if let Some(hash) = &mut frame.checksum {
+            hash.update(&self.out[start..]);
+        }
+        frame.last_block = header & 1 == 1;
+        Ok(true)
+    }
+
+    fn start_frame(&mut self) -> Result<bool, String> {
+        let mut magic = [0u8; 4];
+        if !self.read_exact_or_end(&mut magic)? {
+            return Ok(false);
+        }
+        let magic = u32::from_le_bytes(magic);
+        if magic & !0xf == SKIPPABLE_MAGIC {
+            let mut size = [0u8; 4];
+            self.read_exact(&mut size)?;
+            let size = u32::from_le_bytes(size) as u64;
+            let skipped = io::copy(&mut (&mut self.input).take(size), &mut io::sink()).map_err(|e| e.to_string())?;
+            if skipped < size {
+                return Err("truncated skippable frame".into());
+            }
+            return Ok(true);
+        }
+        if magic != MAGIC {
+            return Err("not a zstd stream".into());
+        }
+        let mut descriptor = [0u8];
+        self.read_exact(&mut descriptor)?;
+        let descriptor = descriptor[0];
+        let single_segment = descriptor & 0x20 != 0;
+        if descriptor & 0x08 != 0 {
+            return Err("reserved zstd frame header bit set".into());
+        }
+        let mut window = 0;
+        if !single_segment {
+            let mut byte = [0u8];
+            self.read_exact(&mut byte)?;
+            let base = 1usize << (10 + (byte[0] >> 3));
+            window = base + (base / 8) * (byte[0] & 7) as usize;
+        }
+        let dictionary_bytes = [0, 1, 2, 4][(descriptor & 3) as usize];
+        let mut dictionary = [0u8; 4];
+        self.read_exact(&mut dictionary[..dictionary_bytes])?;
+        if u32::from_le_bytes(dictionary) != 0 {
+            return Err("zstd dictionaries are not supported".into());
+        }
+        let size_bytes = match descriptor >> 6 {
+            0 => single_segment as usize,
+            1 => 2,
+            2 => 4,
+            _ => 8,
+        };
+        let mut size = [0u8; 8];
+        self.read_exact(&mut size[..size_bytes])?;
+        let content_size = u64::from_le_bytes(size) + if size_bytes == 2 { 256 } else { 0 };
+        if single_segment {
+            window = content_size.min(MAX_WINDOW as u64 + 1) as usize;
+        }
+        if window > MAX_WINDOW {
+            return Err(format!("zstd window of {} bytes is over the limit of {}", window, MAX_WINDOW));
+        }
+        // A new frame refers to nothing before it.
+        self.out.drain(..self.read);
+        self.read = 0;
+        self.frame = Some(Frame {
+            window,
+            checksum: (descriptor & 0x04 != 0).then(Xxh64::new),
+            last_block: false,
+            offsets: [1, 4, 8],
+            huffman: None,
+            ll: None,
+            of: None,
+            ml: None,
+        });
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read == self.out.len() {
+            if !self.advance().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))? {
+                return Ok(0);
+            }
+        }
+        let n = buf.len().min(self.out.len() - self.read);
+        buf[..n].copy_from_slice(&self.out[self.read..self.read + n]);
+        self.read += n;
+        Ok(n)
+    }
+}
+
+fn decode_block(frame: &mut Frame, block: &[u8], out: &mut Vec<u8>) -> Result<(), String> {
+    let (literals, used) = decode_literals(frame, block)?;
+    decode_sequences(frame, &block[used..], &literals, out)
+}
+
+/// The literals section: the literals and the bytes the section took.
+fn decode_literals(frame: &mut Frame, block: &[u8]) -> Result<(Vec<u8>, usize), String> {
+    let byte = |i: usize| block.get(i).copied().map(u32::from).ok_or("truncated literals header");
+    let first = byte(0)?;
+    let kind = (first & 3) as u8;
+    let size_format = first >> 2 & 3;
+    match kind {
+        RAW_LITERALS | RLE_LITERALS => {
+            let (size, header) = match size_format {
+                0 | 2 => (first >> 3, 1),
+                1 => (first >> 4 | byte(1)? << 4, 2),
+                _ => (first >> 4 | byte(1)? << 4 | byte(2)? << 12, 3),
+            };
+            let size = size as usize;
+            if kind == RAW_LITERALS {
+                let literals = block.get(header..header + size).ok_or("truncated literals")?;
+                Ok((literals.to_vec(), header + size))
+            } else {
+                Ok((vec![byte(header)? as u8; size], header + 1))
+            }
+        }
+        _ => {
+            let (regenerated, compressed, header) = match size_format {
+                0 | 1 => {
+                    let h = first | byte(1)? << 8 | byte(2)? << 16;
+                    (h >> 4 & 0x3ff, h >> 14 & 0x3ff, 3)
+                }
+                2 => {
+                    let h = first | byte(1)? << 8 | byte(2)? << 16 | byte(3)? << 24;
+                    (h >> 4 & 0x3fff, h >> 18, 4)
+                }
+                _ => {
+                    let h = first as u64
+                        | (byte(1)? as u64) << 8
+                        | (byte(2)? as u64) << 16
+                        | (byte(3)? as u64) << 24
+                        | (byte(4)? as u64) << 32;
+                    ((h >> 4 & 0x3ffff) as u32, (h >> 22 & 0x3ffff) as u32, 5)
+                }
+            };
+            let (regenerated, compressed) = (regenerated as usize, compressed as usize);
+            if regenerated > MAX_BLOCK {
+                return Err("literals larger than a block".into());
+            }
+            let mut payload = block.get(header..header + compressed).ok_or("truncated literals")?;
+            if kind == COMPRESSED_LITERALS {
+                let (table, used) = HuffmanTable::read(payload)?;
+                frame.huffman = Some(table);
+                payload = &payload[used..];
+            } else if kind == TREELESS_LITERALS && frame.huffman.is_none() {
+                return Err("treeless literals without an earlier Huffman tree".into());
+            }
+            let table = frame.huffman.as_ref().expect("a Huffman tree");
+            let mut literals = Vec::with_capacity(regenerated);
+            if size_format == 0 {
+                table.decode_stream(payload, regenerated, &mut literals)?;
+            } else {
+                let jump = payload.get(..6).ok_or("truncated jump table")?;
+                let size = |i: usize| u16::from_le_bytes([jump[2 * i], jump[2 * i + 1]]) as usize;
+                let mut sizes = [size(0), size(1), size(2), 0];
+                let per_stream = regenerated.div_ceil(4);
+                sizes[3] = (payload.len() - 6)
+                    .checked_sub(sizes[..3].iter().sum())
+                    .filter(|_| 3 * per_stream <= regenerated)
+                    .ok_or("corrupt jump table")?;
+                let counts = [per_stream, per_stream, per_stream, regenerated - 3 * per_stream];
+                let mut streams = &payload[6..];
+                for (size, count) in sizes.into_iter().zip(counts) {
+                    let (stream, rest) = streams.split_at(size);
+                    table.decode_stream(stream, count, &mut literals)?;
+                    streams = rest;
+                }
+            }
+            Ok((literals, header + compressed))
+        }
+    }
+}
+
+fn decode_sequences(frame: &mut Frame, data: &[u8], literals: &[u8], out: &mut Vec<u8>) -> Result<(), String> {
+    let byte = |i: usize| data.get(i).copied().map(usize::from).ok_or("truncated sequences header");
+    let (count, mut used) = match byte(0) {
+        Err(_) | Ok(0) => (0, 1),
+        Ok(first @ 1..=127) => (first, 1),
+        Ok(first @ 128..=254) => ((first - 128) << 8 | byte(1)?, 2),
+        Ok(_) => (byte(1)? | byte(2)? << 8 | 0x7f00, 3),
+    };
+    if count == 0 {
+        out.extend_from_slice(literals);
+        return Ok(());
+    }
+    let modes = byte(used)?;
+    used += 1;
+    if modes & 3 != 0 {
+        return Err("reserved sequence mode bits set".into());
+    }
+    let defaults = default_tables();
+    let slots = [
+        (modes >> 6, &mut frame.ll, &defaults.ll, 9, 35),
+        (modes >> 4 & 3, &mut frame.of, &defaults.of, 8, MAX_OF_CODE as usize),
+        (modes >> 2 & 3, &mut frame.ml, &defaults.ml, 9, 52),
+    ];
+    for (mode, table, default, max_log, max_symbol) in slots {
+        let (parsed, size) = match mode {
+            0 => (default.clone(), 0),
+            1 => (FseTable::rle(byte(used)? as u8), 1),
+            2 => FseTable::read(data.get(used..).unwrap_or_default(), max_log, max_symbol)?,
+            _ => (table.take().ok_or("repeated sequence table without an earlier one")?, 0),
+        };
+        *table = Some(parsed);
+        used += size;
+    }
+    let (ll, of, ml) = (
+        frame.ll.as_ref().expect("set above"),
+        frame.of.as_ref().expect("set above"),
+        frame.ml.as_ref().expect("set above"),
+    );
+
+    let mut bits = BackwardBits::new(data.get(used..).unwrap_or_default())?;
+    let mut ll_state = bits.read(ll.log) as usize;
+    let mut of_state = bits.read(of.log) as usize;
+    let mut ml_state = bits.read(ml.log) as usize;
+    let mut literal = 0;
+    let offsets = &mut frame.offsets;
+    for n in 0..count {
+        let (ll_code, of_code, ml_code) = (ll.symbol(ll_state), of.symbol(of_state), ml.symbol(ml_state));
+        if ll_code as usize >= LL_BASE.len() || ml_code as usize >= ML_BASE.len() || of_code > MAX_OF_CODE {
+            return Err("corrupt sequence codes".into());
+        }
+        let offset_value = (1u64 << of_code) + bits.read(of_code as u32);
+        let match_length = ML_BASE[ml_code as usize] as usize + bits.read(ML_BITS[ml_code as usize] as u32) as usize;
+        let literal_length = LL_BASE[ll_code as usize] as usize + bits.read(LL_BITS[ll_code as usize] as u32) as usize;
+
+        let offset = if offset_value > 3 {
+            let offset = offset_value as usize - 3;
+            *offsets = [offset, offsets[0], offsets[1]];
+            offset
+        } else {
+            // Repeat codes shift by one when the sequence has no literals.
+            let index = offset_value as usize - 1 + (literal_length == 0) as usize;
+            match index {
+                0 => offsets[0],
+                1 => {
+                    offsets.swap(0, 1);
+                    offsets[0]
+                }
+                2 => {
+                    *offsets = [offsets[2], offsets[0], offsets[1]];
+                    offsets[0]
+                }
+                _ => {
+                    let offset = offsets[0].wrapping_sub(1);
+                    *offsets = [offset, offsets[0], offsets[1]];
+                    offset
+                }
+            }
+        };
+        if n + 1 < count {
+            ll_state = ll.update(ll_state, &mut bits);
+            ml_state = ml.update(ml_state, &mut bits);
+            of_state = of.update(of_state, &mut bits);
+        }
+
+        let copied = literals.get(literal..literal + literal_length).ok_or("sequence past the literals")?;
+        out.extend_from_slice(copied);
+        literal += literal_length;
+        if offset == 0 || offset > out.len() {
+            return Err("match offset outside the window".into());
+        }
+        let start = out.len() - offset;
+        if offset >= match_length {
+            out.extend_from_within(start..start + match_length);
+        } else {
+            for i in 0..match_length {
+                out.push(out[start + i]);
+            }
+        }
+    }
+    if bits.pos != 0 {
+        return Err("corrupt sequence stream".into());
+    }
+    out.extend_from_slice(&literals[literal..]);
+    Ok(())
+}
+
+// --- ENCODING ---
+
+/// Match finding effort for a level.
+#[derive(Debug, Clone, Copy)]
+struct Params {
+    window_log: u32,
+    hash_log: u32,
+    /// Candidates tried per position.
+    depth: usize,
+    /// Whether a match is put off by a byte when the next position has a longer one.
+    lazy: bool,
+}
+
+impl Params {
+    fn for_level(level: u32) -> Self {
+        let (window_log, hash_log, depth, lazy) = match level {
+            0..=1 => (17, 15, 1, false),
+            2 => (18, 16, 2, false),
+            3 => (19, 17, 4, false),
+            4..=5 => (20, 17, 8, true),
+            6..=9 => (21, 18, 16, true),
+            10..=15 => (22, 19, 48, true),
+            _ => (22, 20, 128, true),
+        };
+        Self {
+            window_log,
+            hash_log,
+            depth,
+            lazy,
+        }
+    }
+}
+
+/// Compresses what is written to it into one frame, a block at a time. `flush` ends the current
+/// block early; `finish` (or, failing that, dropping it) ends the frame.
+pub struct Encoder<W: Write> {
+    out: Option<W>,
+    params: Params,
+    /// Input not compressed yet, after up to a window of history it can refer back to.
+    buf: Vec<u8>,
+    /// Stream position of `buf[0]`.
+    base: u64,
+    /// `buf[..pending]` is compressed already.
+    pending: usize,
+    /// Per hash of four bytes, the last position (plus one) they were seen at.
+    head: Vec<u64>,
+    /// Per position, the one before it with the same hash (plus one).
+    chain: Vec<u64>,
+    /// The first position not hashed yet.
+    next_insert: u64,
+    /// The offset of the last match, which the next one can repeat for next to nothing.
+    last_offset: usize,
+    started: bool,
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(out: W, level: u32) -> Self {
+        Self::with_size_hint(out, level, None)
+    }
+
+    /// With the input's size known, tables and window are no bigger than it needs.
+    fn with_size_hint(out: W, level: u32, size: Option<usize>) -> Self {
+        let mut params = Params::for_level(level);
+        if let Some(size) = size {
+            let log = (size.max(1).next_power_of_two().trailing_zeros()).max(17);
+            params.window_log = params.window_log.min(log);
+            params.hash_log = params.hash_log.min(log + 1);
+        }
+        Self {
+            out: Some(out),
+            params,
+            buf: Vec::new(),
+            base: 0,
+            pending: 0,
+            head: vec![0; 1 << params.hash_log],
+            chain: vec![0; 1 << params.window_log],
+            next_insert: 0,
+            last_offset: 1,
+            started: false,
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.end_frame()?;
+        Ok(self.out.take().expect("not finished yet"))
+    }
+
+    fn end_frame(&mut self) -> io::Result<()> {
+        self.emit_block(self.buf.len() - self.pending, true)
+    }
+
+    fn emit_block(&mut self, len: usize, last: bool) -> io::Result<()> {
+        let out = self.out.as_mut().expect("not finished yet");
+        if !self.started {
+            let mut header = MAGIC.to_le_bytes().to_vec();
+            // No content size or checksum, not a single segment: just the window.
+            header.extend_from_slice(&[0, ((self.params.window_log - 10) << 3) as u8]);
+            out.write_all(&header)?;
+            self.started = true;
+        }
+        let (start, end) = (self.pending, self.pending + len);
+        let compressed = if len >= MIN_COMPRESS { self.compress_block(start, end) } else { None };
+        let (kind, payload) = match &compressed {
+            Some(payload) => (COMPRESSED_BLOCK, payload.as_slice()),
+            None => (RAW_BLOCK, &self.buf[start..end]),
+        };
+        let header = (payload.len() as u32) << 3 | (kind as u32) << 1 | last as u32;
+        let out = self.out.as_mut().expect("not finished yet");
+        out.write_all(&header.to_le_bytes()[..3])?;
+        out.write_all(payload)?;
+        self.pending = end;
+
+        let window = 1usize << self.params.window_log;
+        if self.pending >= 2 * window {
+            let drop = self.pending - window;
+            self.buf.drain(..drop);
+            self.base += drop as u64;
+            self.pending -= drop;
+        }
+        Ok(())
+    }
+
+    fn hash(&self, i: usize) -> usize {
+        let word = u32::from_le_bytes(self.buf[i..i + 4].try_into().expect("four bytes"));
+        (word.wrapping_mul(0x9e37_79b1) >> (32 - self.params.hash_log)) as usize
+    }
+
+    fn insert(&mut self, i: usize, end: usize) {
+        let position = self.base + i as u64;
+        if position < self.next_insert || i + MIN_MATCH > end {
+            return;
+        }
+        let hash = self.hash(i);
+        let mask = self.chain.len() - 1;
+        self.chain[position as usize & mask] = self.head[hash];
+        self.head[hash] = position + 1;
+        self.next_insert = position + 1;
+    }
+
+    /// What a match saves, roughly, in bits: four per byte (about what a Huffman-coded literal
+    /// costs in text) less the offset's. Repeating the last offset costs next to nothing.
+    fn gain(&self, length: usize, offset: usize) -> i64 {
+        let offset_bits = if offset == self.last_offset { 0 } else { highbit(offset as u32 + 3) };
+        4 * length as i64 - offset_bits as i64
+    }
+
+    /// The most worthwhile match for `buf[i..end]` among earlier positions, as (length, offset),
+    /// or a length of 0.
+    fn find(&mut self, i: usize, end: usize) -> (usize, usize) {
+        let position = self.base + i as u64;
+        let reach = (self.chain.len() as u64).min(1 << self.params.window_log) - 1;
+        let lowest = position.saturating_sub(reach).max(self.base);
+        let length_at = |j: usize| self.buf[j..end].iter().zip(&self.buf[i..end]).take_while(|(a, b)| a == b).count();
+
+        let (mut best, mut offset, mut best_gain) = (0, 0, MIN_GAIN);
+        if position.checked_sub(self.last_offset as u64).is_some_and(|earlier| earlier >= lowest) {
+            let length = length_at(i - self.last_offset);
+            if length >= MIN_MATCH && self.gain(length, self.last_offset) > best_gain {
+                (best, offset, best_gain) = (length, self.last_offset, self.gain(length, self.last_offset));
+            }
+        }
+        let mask = self.chain.len() - 1;
+        let mut candidate = self.head[self.hash(i)];
+        for _ in 0..self.params.depth {
+            if candidate == 0 || candidate - 1 < lowest {
+                break;
+            }
+            let earlier = candidate - 1;
+            let j = (earlier - self.base) as usize;
+            if self.buf.get(j + best) == self.buf.get(i + best) {
+                let length = length_at(j);
+                let distance = (position - earlier) as usize;
+                if length >= MIN_MATCH && self.gain(length, distance) > best_gain {
+                    (best, offset, best_gain) = (length, distance, self.gain(length, distance));
+                    if i + best == end {
+                        break;
+                    }
+                }
+            }
+            candidate = self.chain[earlier as usize & mask];
+        }
+        self.insert(i, end);
+        (best, offset)
+    }
+
+    /// `buf[start..end]` as a compressed block's payload, unless that comes out no smaller.
+    fn compress_block(&mut self, start: usize, end: usize) -> Option<Vec<u8>> {
+        let mut sequences: Vec<(usize, usize, usize)> = Vec::new();
+        let mut literals: Vec<u8> = Vec::with_capacity(end - start);
+        let (mut anchor, mut i) = (start, start);
+        while i + MIN_MATCH <= end {
+            let (mut length, mut offset) = self.find(i, end);
+            if length < MIN_MATCH {
+                i += 1;
+                continue;
+            }
+            if self.params.lazy && i + 1 + MIN_MATCH <= end {
+                let (next_length, next_offset) = self.find(i + 1, end);
+                // Worth a literal more only if it gains more than the literal costs.
+                if next_length > 0 && self.gain(next_length, next_offset) > self.gain(length, offset) + 4 {
+                    (length, offset) = (next_length, next_offset);
+                    i += 1;
+                }
+            }
+            literals.extend_from_slice(&self.buf[anchor..i]);
+            // Offset values 1 to 3 are repeat codes: 1 repeats the last offset, given literals
+            // before it. New offsets are sent as offset + 3.
+            let literal_length = i - anchor;
+            let offset_value = if offset == self.last_offset && literal_length > 0 { 1 } else { offset + 3 };
+            sequences.push((literal_length, length, offset_value));
+            self.last_offset = offset;
+            for j in i + 1..i + length {
+                self.insert(j, end);
+            }
+            i += length;
+            anchor = i;
+        }
+        literals.extend_from_slice(&self.buf[anchor..end]);
+
+        let mut payload = encode_literals(&literals);
+        encode_sequences(&sequences, &mut payload);
+        (payload.len() < end - start).then_some(payload)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        while self.buf.len() - self.pending >= MAX_BLOCK {
+            self.emit_block(MAX_BLOCK, false)?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.len() > self.pending {
+            self.emit_block(self.buf.len() - self.pending, false)?;
+        }
+        self.out.as_mut().expect("not finished yet").flush()
+    }
+}
+
+impl<W: Write> Drop for Encoder<W> {
+    /// Ends the frame if `finish` was not called, ignoring errors as `BufWriter` does.
+    fn drop(&mut self) {
+        if self.out.is_some() {
+            let _ = self.end_frame();
+        }
+    }
+}
+
+fn encode_literals(literals: &[u8]) -> Vec<u8> {
+    let n = literals.len();
+    let raw_header = |kind: u8| -> Vec<u8> {
+        match n {
+            0..=31 => vec![kind | (n << 3) as u8],
+            32..=4095 => vec![kind | 1 << 2 | ((n & 0xf) << 4) as u8, (n >> 4) as u8],
+            _ => vec![kind | 3 << 2 | ((n & 0xf) << 4) as u8, (n >> 4) as u8, (n >> 12) as u8],
+        }
+    };
+    if n > 0 && literals.iter().all(|&byte| byte == literals[0]) {
+        let mut section = raw_header(RLE_LITERALS);
+        section.push(literals[0]);
+        return section;
+    }
+    let mut section = raw_header(RAW_LITERALS);
+    let raw_len = section.len() + n;
+    if let Some(huffman) = huffman_literals(literals).filter(|huffman| huffman.len() < raw_len) {
+        return huffman;
+    }
+    section.extend_from_slice(literals);
+    section
+}
+
+/// Huffman-coded literals, as long as their largest symbol is one a direct tree description
+/// covers (128, which every ASCII byte is under).
+fn huffman_literals(literals: &[u8]) -> Option<Vec<u8>> {
+    let mut counts = [0u32; 256];
+    for &byte in literals {
+        counts[byte as usize] += 1;
+    }
+    let last = counts.iter().rposition(|&count| count > 0)?;
+    if last > 128 || literals.len() < 64 {
+        return None;
+    }
+    let lengths = huffman_lengths(&counts[..=last], MAX_HUFFMAN_BITS);
+    let max_bits = *lengths.iter().max()?;
+    let weight = |symbol: usize| if lengths[symbol] == 0 { 0 } else { max_bits + 1 - lengths[symbol] };
+
+    let mut tree = vec![(127 + last) as u8];
+    tree.extend((0..last).step_by(2).map(|symbol| {
+        let low = if symbol + 1 < last { weight(symbol + 1) } else { 0 };
+        (weight(symbol) << 4 | low) as u8
+    }));
+    let mut codes = [(0u32, 0u32); 129];
+    let mut position = 0u32;
+    for w in 1..=max_bits {
+        for symbol in (0..=last).filter(|&symbol| weight(symbol) == w) {
+            codes[symbol] = (position >> (w - 1), lengths[symbol]);
+            position += 1 << (w - 1);
+        }
+    }
+    let stream = |part: &[u8]| {
+        let mut bits = BitWriter::default();
+        for &byte in part.iter().rev() {
+            let (code, length) = codes[byte as usize];
+            bits.put(code as u64, length);
+        }
+        bits.close()
+    };
+
+    let regenerated = literals.len();
+    let mut body = tree;
+    let single = regenerated < 1024;
+    if single {
+        body.extend(stream(literals));
+    } else {
+        let per_stream = regenerated.div_ceil(4);
+        let streams: Vec<Vec<u8>> = literals.chunks(per_stream).map(stream).collect();
+        if streams.len() != 4 || streams[..3].iter().any(|stream| stream.len() > u16::MAX as usize) {
+            return None;
+        }
+        for stream in &streams[..3] {
+            body.extend_from_slice(&(stream.len() as u16).to_le_bytes());
+        }
+        streams.iter().for_each(|stream| body.extend_from_slice(stream));
+    }
+    let compressed = body.len();
+    let mut section = match (single, regenerated.max(compressed)) {
+        (true, 0..=1023) | (false, 0..=1023) => {
+            let size_format = if single { 0 } else { 1 };
+            let h = COMPRESSED_LITERALS as u32 | size_format << 2 | (regenerated as u32) << 4 | (compressed as u32) << 14;
+            h.to_le_bytes()[..3].to_vec()
+        }
+        (true, _) => return None,
+        (false, 1024..=16383) => {
+            let h = COMPRESSED_LITERALS as u32 | 2 << 2 | (regenerated as u32) << 4 | (compressed as u32) << 18;
+            h.to_le_bytes().to_vec()
+        }
+        (false, _) => {
+            let h = COMPRESSED_LITERALS as u64 | 3 << 2 | (regenerated as u64) << 4 | (compressed as u64) << 22;
+            h.to_le_bytes()[..5].to_vec()
+        }
+    };
+    section.extend(body);
+    Some(section)
+}
+
+/// Appends the sequences section, coded with the predefined tables.
+fn encode_sequences(sequences: &[(usize, usize, usize)], out: &mut Vec<u8>) {
+    let count = sequences.len();
+    match count {
+        0..=127 => out.push(count as u8),
+        128..=0x7eff => out.extend_from_slice(&[(count >> 8) as u8 + 128, count as u8]),
+        _ => out.extend_from_slice(&[255, (count - 0x7f00) as u8, ((count - 0x7f00) >> 8) as u8]),
+    }
+    if count == 0 {
+        return;
+    }
+    out.push(0);
+
+    let codes: Vec<SequenceCodes> = sequences.iter().map(|&sequence| SequenceCodes::new(sequence)).collect();
+    let tables = default_tables();
+    let (ll_table, ml_table, of_table) = (&tables.ll_encoder, &tables.ml_encoder, &tables.of_encoder);
+    let mut bits = BitWriter::default();
+    // Written last to first, so the decoder reading backwards meets them in order.
+    let last = codes[count - 1];
+    let mut ml_state = ml_table.init(last.ml);
+    let mut of_state = of_table.init(last.of);
+    let mut ll_state = ll_table.init(last.ll);
+    last.put_extra(&mut bits);
+    for sequence in codes[..count - 1].iter().rev() {
+        of_table.encode(&mut of_state, sequence.of, &mut bits);
+        ml_table.encode(&mut ml_state, sequence.ml, &mut bits);
+        ll_table.encode(&mut ll_state, sequence.ll, &mut bits);
+        sequence.put_extra(&mut bits);
+    }
+    ml_table.flush(ml_state, &mut bits);
+    of_table.flush(of_state, &mut bits);
+    ll_table.flush(ll_state, &mut bits);
+    out.extend(bits.close());
+}
+
+/// A sequence's three codes and the extra bits after each.
+#[derive(Clone, Copy)]
+struct SequenceCodes {
+    ll: u8,
+    ll_extra: u32,
+    ml: u8,
+    ml_extra: u32,
+    of: u8,
+    of_extra: u32,
+}
+
+impl SequenceCodes {
+    fn new((literal_length, match_length, offset_value): (usize, usize, usize)) -> Self {
+        let code = |base: &[u32], value: u32| base.iter().rposition(|&b| b <= value).expect("base 0 or 3") as u8;
+        let (literal_length, match_length) = (literal_length as u32, match_length as u32);
+        let (ll, ml) = (code(&LL_BASE, literal_length), code(&ML_BASE, match_length));
+        let offset_value = offset_value as u32;
+        let of = highbit(offset_value) as u8;
+        Self {
+            ll,
+            ll_extra: literal_length - LL_BASE[ll as usize],
+            ml,
+            ml_extra: match_length - ML_BASE[ml as usize],
+            of,
+            of_extra: offset_value - (1 << of),
+        }
+    }
+
+    fn put_extra(&self, bits: &mut BitWriter) {
+        bits.put(self.ll_extra as u64, LL_BITS[self.ll as usize] as u32);
+        bits.put(self.ml_extra as u64, ML_BITS[self.ml as usize] as u32);
+        bits.put(self.of_extra as u64, self.of as u32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workload::Rng;
+
+    /// JSON-ish lines, compressible the way cached blocks and traces are.
+    fn sample(len: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        for i in 0u64.. {
+            if out.len() >= len {
+                break;
+            }
+            let line = format!(
+                concat!(
+                    "{{\"number\":\"{:#x}\",\"gasUsed\":\"{:#x}\",",
+                    "\"miner\":\"0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5\"}}\n"
+                ),
+                i,
+                i * 2654435761 % 30_000_000
+            );
+            out.extend_from_slice(line.as_bytes());
+        }
+        out.truncate(len);
+        out
+    }
+
+    fn unhex(lines: &[&str]) -> Vec<u8> {
+        hex::decode(lines.concat()).unwrap()
+    }
+
+    // Frames written by the reference CLI (zstd 1.5.7) from `sample(2000)`, and from it repeated
+    // 150 times.
+    /// `zstd -19 --check`: single segment, content size and checksum.
+    const SMALL_19_CHECKED: &[&str] = &[
+        "28b52ffd64d006350700128e2518802979d4c1d0252a3223249d307f08616509e93910a5ac6097f6526747dabfd5b2dd8a7019a1",
+        "d3d992ee45a9147b79d27cca24c86efd8e74628961331392399f37993c8bec167eb5cea06a96422c9f78adb968e8dce8cde1b59e",
+        "33abc3bd9576395c644ee639fd9c711fd0380e49f618f399f791336b5677d76e226177513a3d140c8d240689251c81a32cca2138",
+        "10c831280e07232ea811f009127aaf5f63f05c920e21040a0142a01018e3ff4f841f008c8c613ff20d58034a79455770107b0245",
+        "b48576222710c4bfc02652428de82edf84c413ce49b341ad400d645d18aba90140f7d769",
+    ];
+    /// `zstd -1 --no-check`.
+    const SMALL_1_UNCHECKED: &[&str] = &[
+        "28b52ffd60d006350800e28d251a70499207eed1f319d5212b97d7551d0689942b4908d0ffbd9e08581f64b7c9fe95ebf5532e73",
+        "2e1cfbc819a33e5e342f91a2a8d2eda58b2aea9a796ec879df4370b5a8d25d938c5c260e2a85c7b34ed423b3277962679d6b5564",
+        "a72fca1ecb346411f7f3b5e23ec00261a5d02d6623ef9733275277932ade5cf7c89919cb4381a1cbc1c081121001c44818840061",
+        "50d680702014022d20f04211970705d7d4e00ecfa10adc998dd1f7db8818a0ea9bcc6c7a8b3e9d1850d47c3a2c5eb47657be51db",
+        "5df1a0a5bbf2899aee8a072ddd954fd474573c68e9ae7ca2a6bbe2414b77e51335dd150f5aba2b9fa8e9ae78d0d25df9444d77c5",
+        "8396ee2ed3a7849c1d53083c",
+    ];
+    /// `cat repeated | zstd -1 --check`: streamed, so a window and no content size, over several
+    /// blocks.
+    const REPEATED_1_STREAMED: &[&str] = &[
+        "28b52ffd0448a40800d24d251a70499207eed1f319d5212b97d7551d0689942b4908d0ffbd9e0803eb836e93fd2bd7eba75ce65c",
+        "38f6913346fd45f312298a2add5ebaa8a2ae99e7869cf73d04578b2add35c9c865e2a052783ceb443d327b922776d6b9564576fa",
+        "a2ecb14c4316713f5f2bee032c10560add6236f27e3973227537a9e2cd758f9c99b13c1418ba1c0c1c280111408c844108100665",
+        "0d080742213220e042114e3abaf7d3cb21cb1d900651a0bbbe1da19a1a5c84cdd107dc59cc50fd2dc266e8f32db398dea06ac706",
+        "1c5ddb449878517827e48b9aee8a375abb2b5fa8edae78d1a2bbf2418dee8a072dba2b1fd4e8ae78d0a2bbf2418dee8a072dba2b",
+        "1fd4e8ae78d0a2bbf2418dee8a072dbabb0c9e1244764cb1f04c000008630100fcff3910024d000008370100dc131d0801115f6c",
+        "0b",
+    ];
+
+    #[test]
+    fn xxh64_matches_the_reference() {
+        let digest = |data: &[u8]| {
+            let mut hash = Xxh64::new();
+            hash.update(data);
+            hash.digest()
+        };
+        assert_eq!(digest(b""), 0xef46_db37_51d8_e999);
+        assert_eq!(digest(b"abc"), 0x44bc_2cf5_ad77_0999);
+        // Fed in pieces that straddle stripes, the same as whole.
+        let data = sample(1000);
+        let mut hash = Xxh64::new();
+        for piece in data.chunks(13) {
+            hash.update(piece);
+        }
+        assert_eq!(hash.digest(), digest(&data));
+    }
+
+    #[test]
+    fn decodes_reference_frames() {
+        assert_eq!(decompress(&unhex(SMALL_19_CHECKED)).unwrap(), sample(2000));
+        assert_eq!(decompress(&unhex(SMALL_1_UNCHECKED)).unwrap(), sample(2000));
+        assert_eq!(decompress(&unhex(REPEATED_1_STREAMED)).unwrap(), sample(2000).repeat(150));
+    }
+
+    #[test]
+    fn decodes_concatenated_and_skippable_frames() {
+        let mut stream = Vec::new();
+        stream.extend_from_slice(&(SKIPPABLE_MAGIC | 7).to_le_bytes());
+        stream.extend_from_slice(&5u32.to_le_bytes());
+        stream.extend_from_slice(b"skip!");
+        stream.extend(unhex(SMALL_19_CHECKED));
+        stream.extend(unhex(SMALL_1_UNCHECKED));
+        stream.extend(compress(b"and one of ours", 3));
+        let mut expected = sample(2000).repeat(2);
+        expected.extend_from_slice(b"and one of ours");
+        assert_eq!(decompress(&stream).unwrap(), expected);
+    }
+
+    #[test]
+    fn rejects_a_wrong_checksum() {
+        let mut frame = unhex(SMALL_19_CHECKED);
+        *frame.last_mut().unwrap() ^= 1;
+        assert!(decompress(&frame).unwrap_err().contains("checksum mismatch"));
+        // Cut off before its checksum, a frame is truncated rather than unchecked.
+        let mut frame = unhex(REPEATED_1_STREAMED);
+        frame.truncate(frame.len() - 2);
+        assert!(decompress(&frame).unwrap_err().contains("truncated"));
+    }
+
+    #[test]
+    fn round_trips_at_several_levels() {
+        let mut rng = Rng::new(3);
+        let noise: Vec<u8> = (0..200_000).map(|_| rng.next_u64() as u8).collect();
+        let inputs = [
+            Vec::new(),
+            b"x".to_vec(),
+            sample(2000),
+            sample(300_000),
+            sample(2000).repeat(150),
+            noise,
+            vec![0u8; 300_000],
+        ];
+        for level in [1, 3, 9, 19, MAX_LEVEL] {
+            for input in &inputs {
+                let compressed = compress(input, level);
+                let decoded = decompress(&compressed).unwrap_or_else(|e| panic!("level {}: {}", level, e));
+                assert!(decoded == *input, "level {}, {} bytes", level, input.len());
+            }
+        }
+    }
+
+    #[test]
+    fn streams_through_the_encoder_and_decoder() {
+        let input = sample(400_000);
+        let mut encoder = Encoder::new(Vec::new(), 5);
+        for piece in input.chunks(7_777) {
+            encoder.write_all(piece).unwrap();
+        }
+        let compressed = encoder.finish().unwrap();
+        let mut decoder = Decoder::new(compressed.as_slice());
+        let (mut decoded, mut buf) = (Vec::new(), [0u8; 1000]);
+        loop {
+            match decoder.read(&mut buf).unwrap() {
+                0 => break,
+                n => decoded.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert!(decoded == input);
+    }
+}